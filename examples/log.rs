@@ -14,8 +14,9 @@
 
 #![deny(warnings)]
 
-use git2::{Commit, DiffOptions, ObjectType, Repository, Signature, Time};
-use git2::{DiffFormat, Error, Pathspec};
+use git2::{Commit, DiffOptions, ObjectType, Repository, Time};
+use git2::{DiffFormat, Error, RevwalkFilter};
+use std::path::Path;
 use std::str;
 use structopt::StructOpt;
 
@@ -66,6 +67,12 @@ struct Args {
     #[structopt(name = "min-parents")]
     /// specify a minimum number of parents for a commit
     flag_min_parents: Option<usize>,
+    #[structopt(name = "full-history", long)]
+    /// follow every parent of merges when limiting to paths
+    flag_full_history: bool,
+    #[structopt(name = "follow", long)]
+    /// follow renames of the single file given as path
+    flag_follow: bool,
     #[structopt(name = "patch", long, short)]
     /// show commit diff
     flag_patch: bool,
@@ -120,73 +127,51 @@ fn run(args: &Args) -> Result<(), Error> {
         revwalk.push_head()?;
     }
 
-    // Prepare our diff options and pathspec matcher
-    let (mut diffopts, mut diffopts2) = (DiffOptions::new(), DiffOptions::new());
-    for spec in &args.arg_spec {
-        diffopts.pathspec(spec);
-        diffopts2.pathspec(spec);
+    // Limit the revwalk to the paths, simplifying history like git does
+    if args.flag_follow {
+        if args.arg_spec.len() != 1 {
+            return Err(Error::from_str("--follow requires exactly one path"));
+        }
+        revwalk.follow(Path::new(&args.arg_spec[0]))?;
+    } else {
+        revwalk.limit_to_paths(&args.arg_spec)?;
+    }
+    revwalk.full_history(args.flag_full_history);
+
+    // Prepare our diff options
+    let mut diffopts = DiffOptions::new();
+    if !args.flag_follow {
+        for spec in &args.arg_spec {
+            diffopts.pathspec(spec);
+        }
     }
-    let ps = Pathspec::new(args.arg_spec.iter())?;
 
     // Filter our revwalk based on the CLI parameters
-    macro_rules! filter_try {
-        ($e:expr) => {
-            match $e {
-                Ok(t) => t,
-                Err(e) => return Some(Err(e)),
-            }
-        };
+    let mut filter = RevwalkFilter::new();
+    filter.min_parents(args.min_parents());
+    if let Some(n) = args.max_parents() {
+        filter.max_parents(n);
     }
-    let revwalk = revwalk
-        .filter_map(|id| {
-            let id = filter_try!(id);
-            let commit = filter_try!(repo.find_commit(id));
-            let parents = commit.parents().len();
-            if parents < args.min_parents() {
-                return None;
-            }
-            if let Some(n) = args.max_parents() {
-                if parents >= n {
-                    return None;
-                }
-            }
-            if !args.arg_spec.is_empty() {
-                match commit.parents().len() {
-                    0 => {
-                        let tree = filter_try!(commit.tree());
-                        let flags = git2::PathspecFlags::NO_MATCH_ERROR;
-                        if ps.match_tree(&tree, flags).is_err() {
-                            return None;
-                        }
-                    }
-                    _ => {
-                        let m = commit.parents().all(|parent| {
-                            match_with_parent(&repo, &commit, &parent, &mut diffopts)
-                                .unwrap_or(false)
-                        });
-                        if !m {
-                            return None;
-                        }
-                    }
-                }
-            }
-            if !sig_matches(&commit.author(), &args.flag_author) {
-                return None;
-            }
-            if !sig_matches(&commit.committer(), &args.flag_committer) {
-                return None;
-            }
-            if !log_message_matches(commit.message(), &args.flag_grep) {
-                return None;
-            }
-            Some(Ok(commit))
-        })
-        .skip(args.flag_skip.unwrap_or(0))
-        .take(args.flag_max_count.unwrap_or(!0));
+    if let Some(ref author) = args.flag_author {
+        filter.author(author);
+    }
+    if let Some(ref committer) = args.flag_committer {
+        filter.committer(committer);
+    }
+    if let Some(ref grep) = args.flag_grep {
+        filter.grep(grep);
+    }
+    if let Some(n) = args.flag_skip {
+        filter.skip(n);
+    }
+    if let Some(n) = args.flag_max_count {
+        filter.max_count(n);
+    }
+    revwalk.set_filter(Some(filter));
 
     // print!
-    for commit in revwalk {
-        let commit = commit?;
+    for id in revwalk {
+        let commit = repo.find_commit(id?)?;
         print_commit(&commit);
         if !args.flag_patch || commit.parents().len() > 1 {
             continue;
@@ -198,7 +183,7 @@ fn run(args: &Args) -> Result<(), Error> {
             None
         };
         let b = commit.tree()?;
-        let diff = repo.diff_tree_to_tree(a.as_ref(), Some(&b), Some(&mut diffopts2))?;
+        let diff = repo.diff_tree_to_tree(a.as_ref(), Some(&b), Some(&mut diffopts))?;
         diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
             match line.origin() {
                 ' ' | '+' | '-' => print!("{}", line.origin()),
@@ -212,24 +197,6 @@ fn run(args: &Args) -> Result<(), Error> {
     Ok(())
 }
 
-fn sig_matches(sig: &Signature, arg: &Option<String>) -> bool {
-    match *arg {
-        Some(ref s) => {
-            sig.name().map(|n| n.contains(s)).unwrap_or(false)
-                || sig.email().map(|n| n.contains(s)).unwrap_or(false)
-        }
-        None => true,
-    }
-}
-
-fn log_message_matches(msg: Option<&str>, grep: &Option<String>) -> bool {
-    match (grep, msg) {
-        (&None, _) => true,
-        (&Some(_), None) => false,
-        (&Some(ref s), Some(msg)) => msg.contains(s),
-    }
-}
-
 fn print_commit(commit: &Commit) {
     println!("commit {}", commit.id());
 
@@ -271,18 +238,6 @@ fn print_time(time: &Time, prefix: &str) {
     );
 }
 
-fn match_with_parent(
-    repo: &Repository,
-    commit: &Commit,
-    parent: &Commit,
-    opts: &mut DiffOptions,
-) -> Result<bool, Error> {
-    let a = parent.tree()?;
-    let b = commit.tree()?;
-    let diff = repo.diff_tree_to_tree(Some(&a), Some(&b), Some(opts))?;
-    Ok(diff.deltas().len() > 0)
-}
-
 impl Args {
     fn min_parents(&self) -> usize {
         if self.flag_no_min_parents {
@@ -15,7 +15,7 @@
 
 #![deny(warnings)]
 
-use git2::{Error, Oid, Repository, Revwalk};
+use git2::{Repository, RevSpecList};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -29,10 +29,8 @@ struct Args {
     #[structopt(name = "reverse", long)]
     /// sort commits in reverse
     flag_reverse: bool,
-    #[structopt(name = "not")]
-    /// don't show <spec>
-    flag_not: Vec<String>,
     #[structopt(name = "spec", last = true)]
+    /// revisions to show, `^<spec>`, ranges and `--not` exclude revisions
     arg_spec: Vec<String>,
 }
 
@@ -55,31 +53,8 @@ fn run(args: &Args) -> Result<(), git2::Error> {
         },
     )?;
 
-    let specs = args
-        .flag_not
-        .iter()
-        .map(|s| (s, true))
-        .chain(args.arg_spec.iter().map(|s| (s, false)))
-        .map(|(spec, hide)| {
-            if spec.starts_with('^') {
-                (&spec[1..], !hide)
-            } else {
-                (&spec[..], hide)
-            }
-        });
-    for (spec, hide) in specs {
-        let id = if spec.contains("..") {
-            let revspec = repo.revparse(spec)?;
-            if revspec.mode().contains(git2::RevparseMode::MERGE_BASE) {
-                return Err(Error::from_str("merge bases not implemented"));
-            }
-            push(&mut revwalk, revspec.from().unwrap().id(), !hide)?;
-            revspec.to().unwrap().id()
-        } else {
-            repo.revparse_single(spec)?.id()
-        };
-        push(&mut revwalk, id, hide)?;
-    }
+    let specs = RevSpecList::parse(&repo, &args.arg_spec)?;
+    specs.apply(&mut revwalk)?;
 
     for id in revwalk {
         let id = id?;
@@ -88,14 +63,6 @@ fn run(args: &Args) -> Result<(), git2::Error> {
     Ok(())
 }
 
-fn push(revwalk: &mut Revwalk, id: Oid, hide: bool) -> Result<(), Error> {
-    if hide {
-        revwalk.hide(id)
-    } else {
-        revwalk.push(id)
-    }
-}
-
 fn main() {
     let args = Args::from_args();
     match run(&args) {
@@ -4,7 +4,7 @@
 // This is required to link libz when libssh2-sys is not included.
 extern crate libz_sys as libz;
 
-use libc::{c_char, c_int, c_uchar, c_uint, c_void, size_t};
+use libc::{c_char, c_int, c_uchar, c_uint, c_ushort, c_void, size_t};
 #[cfg(feature = "ssh")]
 use libssh2_sys as libssh2;
 use std::ffi::CStr;
@@ -15,6 +15,8 @@ pub const GIT_CLONE_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_STASH_APPLY_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_CHECKOUT_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_MERGE_OPTIONS_VERSION: c_uint = 1;
+pub const GIT_MERGE_FILE_INPUT_VERSION: c_uint = 1;
+pub const GIT_MERGE_FILE_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_REMOTE_CALLBACKS_VERSION: c_uint = 1;
 pub const GIT_STATUS_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_BLAME_OPTIONS_VERSION: c_uint = 1;
@@ -93,6 +95,9 @@ pub enum git_worktree {}
 pub enum git_transaction {}
 pub enum git_mailmap {}
 pub enum git_indexer {}
+pub enum git_commit_graph {}
+pub enum git_commit_graph_writer {}
+pub enum git_midx_writer {}
 
 #[repr(C)]
 pub struct git_revspec {
@@ -841,6 +846,13 @@ pub struct git_index_time {
     pub nanoseconds: u32,
 }
 
+#[repr(C)]
+pub struct git_index_name_entry {
+    pub ancestor: *mut c_char,
+    pub ours: *mut c_char,
+    pub theirs: *mut c_char,
+}
+
 #[repr(C)]
 pub struct git_config_entry {
     pub name: *const c_char,
@@ -1317,6 +1329,35 @@ pub struct git_merge_options {
     pub file_flags: u32,
 }
 
+#[repr(C)]
+pub struct git_merge_file_input {
+    pub version: c_uint,
+    pub ptr: *const c_char,
+    pub size: size_t,
+    pub path: *const c_char,
+    pub mode: c_uint,
+}
+
+#[repr(C)]
+pub struct git_merge_file_options {
+    pub version: c_uint,
+    pub ancestor_label: *const c_char,
+    pub our_label: *const c_char,
+    pub their_label: *const c_char,
+    pub favor: git_merge_file_favor_t,
+    pub flags: u32,
+    pub marker_size: c_ushort,
+}
+
+#[repr(C)]
+pub struct git_merge_file_result {
+    pub automergeable: c_uint,
+    pub path: *const c_char,
+    pub mode: c_uint,
+    pub ptr: *const c_char,
+    pub len: size_t,
+}
+
 git_enum! {
     pub enum git_merge_flag_t {
         GIT_MERGE_FIND_RENAMES = 1 << 0,
@@ -1811,7 +1852,7 @@ pub type git_commit_create_cb = Option<
         *const c_char,
         *const git_tree,
         usize,
-        *const git_commit,
+        *const *const git_commit,
         *mut c_void,
     ) -> c_int,
 >;
@@ -1968,6 +2009,22 @@ pub struct git_worktree_prune_options {
 
 pub const GIT_WORKTREE_PRUNE_OPTIONS_VERSION: c_uint = 1;
 
+git_enum! {
+    pub enum git_commit_graph_split_strategy_t {
+        GIT_COMMIT_GRAPH_SPLIT_STRATEGY_SINGLE_FILE = 0,
+    }
+}
+
+#[repr(C)]
+pub struct git_commit_graph_writer_options {
+    pub version: c_uint,
+    pub split_strategy: git_commit_graph_split_strategy_t,
+    pub size_multiple: f32,
+    pub max_commits: size_t,
+}
+
+pub const GIT_COMMIT_GRAPH_WRITER_OPTIONS_VERSION: c_uint = 1;
+
 pub type git_repository_mergehead_foreach_cb =
     Option<extern "C" fn(oid: *const git_oid, payload: *mut c_void) -> c_int>;
 
@@ -2105,6 +2162,7 @@ extern "C" {
     pub fn git_repository_is_empty(repo: *mut git_repository) -> c_int;
     pub fn git_repository_is_shallow(repo: *mut git_repository) -> c_int;
     pub fn git_repository_path(repo: *const git_repository) -> *const c_char;
+    pub fn git_repository_commondir(repo: *const git_repository) -> *const c_char;
     pub fn git_repository_state(repo: *mut git_repository) -> c_int;
     pub fn git_repository_workdir(repo: *const git_repository) -> *const c_char;
     pub fn git_repository_set_workdir(
@@ -2622,6 +2680,7 @@ extern "C" {
         repo: *mut *mut git_repository,
         submodule: *mut git_submodule,
     ) -> c_int;
+    pub fn git_submodule_owner(submodule: *mut git_submodule) -> *mut git_repository;
     pub fn git_submodule_path(submodule: *mut git_submodule) -> *const c_char;
     pub fn git_submodule_reload(submodule: *mut git_submodule, force: c_int) -> c_int;
     pub fn git_submodule_set_ignore(
@@ -2735,6 +2794,7 @@ extern "C" {
     pub fn git_tree_entrycount(tree: *const git_tree) -> size_t;
     pub fn git_tree_free(tree: *mut git_tree);
     pub fn git_tree_id(tree: *const git_tree) -> *const git_oid;
+    pub fn git_tree_owner(tree: *const git_tree) -> *mut git_repository;
     pub fn git_tree_lookup(
         tree: *mut *mut git_tree,
         repo: *mut git_repository,
@@ -2802,6 +2862,7 @@ extern "C" {
     ) -> c_int;
     pub fn git_commit_free(commit: *mut git_commit);
     pub fn git_commit_id(commit: *const git_commit) -> *const git_oid;
+    pub fn git_commit_owner(commit: *const git_commit) -> *mut git_repository;
     pub fn git_commit_lookup(
         commit: *mut *mut git_commit,
         repo: *mut git_repository,
@@ -2997,6 +3058,11 @@ extern "C" {
     pub fn git_index_clear(index: *mut git_index) -> c_int;
     pub fn git_index_entry_stage(entry: *const git_index_entry) -> c_int;
     pub fn git_index_entrycount(entry: *const git_index) -> size_t;
+    pub fn git_index_name_entrycount(index: *mut git_index) -> size_t;
+    pub fn git_index_name_get_byindex(
+        index: *mut git_index,
+        n: size_t,
+    ) -> *const git_index_name_entry;
     pub fn git_index_find(at_pos: *mut size_t, index: *mut git_index, path: *const c_char)
         -> c_int;
     pub fn git_index_find_prefix(
@@ -3014,6 +3080,7 @@ extern "C" {
     pub fn git_index_has_conflicts(index: *const git_index) -> c_int;
     pub fn git_index_new(index: *mut *mut git_index) -> c_int;
     pub fn git_index_open(index: *mut *mut git_index, index_path: *const c_char) -> c_int;
+    pub fn git_index_owner(index: *const git_index) -> *mut git_repository;
     pub fn git_index_path(index: *const git_index) -> *const c_char;
     pub fn git_index_read(index: *mut git_index, force: c_int) -> c_int;
     pub fn git_index_read_tree(index: *mut git_index, tree: *const git_tree) -> c_int;
@@ -3242,6 +3309,7 @@ extern "C" {
     ) -> c_int;
     pub fn git_tag_free(tag: *mut git_tag);
     pub fn git_tag_id(tag: *const git_tag) -> *const git_oid;
+    pub fn git_tag_owner(tag: *const git_tag) -> *mut git_repository;
     pub fn git_tag_list(tag_names: *mut git_strarray, repo: *mut git_repository) -> c_int;
     pub fn git_tag_list_match(
         tag_names: *mut git_strarray,
@@ -3323,6 +3391,25 @@ extern "C" {
         opts: *const git_merge_options,
     ) -> c_int;
     pub fn git_repository_state_cleanup(repo: *mut git_repository) -> c_int;
+    pub fn git_merge_file_input_init(opts: *mut git_merge_file_input, version: c_uint) -> c_int;
+    pub fn git_merge_file_options_init(opts: *mut git_merge_file_options, version: c_uint)
+        -> c_int;
+    pub fn git_merge_file(
+        out: *mut git_merge_file_result,
+        ancestor: *const git_merge_file_input,
+        ours: *const git_merge_file_input,
+        theirs: *const git_merge_file_input,
+        opts: *const git_merge_file_options,
+    ) -> c_int;
+    pub fn git_merge_file_from_index(
+        out: *mut git_merge_file_result,
+        repo: *mut git_repository,
+        ancestor: *const git_index_entry,
+        ours: *const git_index_entry,
+        theirs: *const git_index_entry,
+        opts: *const git_merge_file_options,
+    ) -> c_int;
+    pub fn git_merge_file_result_free(result: *mut git_merge_file_result);
 
     // merge analysis
 
@@ -3404,6 +3491,7 @@ extern "C" {
     // revwalk
     pub fn git_revwalk_new(out: *mut *mut git_revwalk, repo: *mut git_repository) -> c_int;
     pub fn git_revwalk_free(walk: *mut git_revwalk);
+    pub fn git_revwalk_repository(walk: *mut git_revwalk) -> *mut git_repository;
 
     pub fn git_revwalk_reset(walk: *mut git_revwalk) -> c_int;
 
@@ -4186,6 +4274,47 @@ extern "C" {
     pub fn git_transaction_commit(tx: *mut git_transaction) -> c_int;
     pub fn git_transaction_free(tx: *mut git_transaction);
 
+    // commit graph
+    pub fn git_commit_graph_open(
+        cgraph_out: *mut *mut git_commit_graph,
+        objects_dir: *const c_char,
+    ) -> c_int;
+    pub fn git_commit_graph_free(cgraph: *mut git_commit_graph);
+    pub fn git_commit_graph_writer_new(
+        out: *mut *mut git_commit_graph_writer,
+        objects_info_dir: *const c_char,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_free(w: *mut git_commit_graph_writer);
+    pub fn git_commit_graph_writer_add_index_file(
+        w: *mut git_commit_graph_writer,
+        repo: *mut git_repository,
+        idx_path: *const c_char,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_add_revwalk(
+        w: *mut git_commit_graph_writer,
+        walk: *mut git_revwalk,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_options_init(
+        opts: *mut git_commit_graph_writer_options,
+        version: c_uint,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_commit(
+        w: *mut git_commit_graph_writer,
+        opts: *mut git_commit_graph_writer_options,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_dump(
+        buffer: *mut git_buf,
+        w: *mut git_commit_graph_writer,
+        opts: *mut git_commit_graph_writer_options,
+    ) -> c_int;
+
+    // multi-pack-index
+    pub fn git_midx_writer_new(out: *mut *mut git_midx_writer, pack_dir: *const c_char) -> c_int;
+    pub fn git_midx_writer_free(w: *mut git_midx_writer);
+    pub fn git_midx_writer_add(w: *mut git_midx_writer, idx_path: *const c_char) -> c_int;
+    pub fn git_midx_writer_commit(w: *mut git_midx_writer) -> c_int;
+    pub fn git_midx_writer_dump(midx: *mut git_buf, w: *mut git_midx_writer) -> c_int;
+
     // Mailmap
     pub fn git_mailmap_new(out: *mut *mut git_mailmap) -> c_int;
     pub fn git_mailmap_from_buffer(
@@ -0,0 +1,811 @@
+//! Encoding, decoding and application of git's binary patch format.
+//!
+//! When `git diff --binary` (or a diff created with
+//! [`DiffOptions::show_binary`]) encounters a binary file, the change is
+//! written as a `GIT binary patch` section. The section holds a forward hunk
+//! (turning the old file into the new one) and, usually, a reverse hunk. Each
+//! hunk is either the deflated `literal` contents of the resulting file or a
+//! deflated git `delta` against the other side, encoded as base85 lines.
+//!
+//! This module implements that format independently of libgit2's printer and
+//! applier so binary changes can be produced, inspected and applied to plain
+//! byte buffers or blobs.
+//!
+//! [`DiffOptions::show_binary`]: crate::DiffOptions::show_binary
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use libc::c_int;
+
+use crate::{Blob, DiffBinary, DiffBinaryFile, DiffBinaryKind};
+use crate::{Error, ErrorClass, ErrorCode, Oid, Repository};
+
+const HEADER: &str = "GIT binary patch";
+
+/// Number of raw bytes encoded on a single base85 line.
+const LINE_BYTES: usize = 52;
+
+/// Size of the blocks of the base used to find copies when creating a delta.
+const DELTA_BLOCK: usize = 16;
+
+/// Maximum number of base offsets remembered for a single block.
+const DELTA_CANDIDATES: usize = 64;
+
+/// Largest copy emitted by a single delta instruction.
+const DELTA_MAX_COPY: usize = 0x10000;
+
+/// Largest insert emitted by a single delta instruction.
+const DELTA_MAX_INSERT: usize = 0x7f;
+
+/// Largest ratio of inflated to deflated size zlib can achieve.
+const ZLIB_MAX_RATIO: usize = 1032;
+
+const EN85: &[u8; 85] = b"0123456789\
+    ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+    abcdefghijklmnopqrstuvwxyz\
+    !#$%&()*+-;<=>?@^_`{|}~";
+
+/// A single hunk of a binary patch.
+///
+/// The data held by a hunk is always deflated, exactly as it appears (after
+/// base85 decoding) in a patch file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryHunk {
+    kind: DiffBinaryKind,
+    inflated_len: usize,
+    data: Vec<u8>,
+}
+
+/// A `GIT binary patch` section, describing the change of one binary file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryPatch {
+    forward: BinaryHunk,
+    reverse: Option<BinaryHunk>,
+    old_path: Option<PathBuf>,
+    new_path: Option<PathBuf>,
+}
+
+impl BinaryHunk {
+    /// Create a hunk holding the literal contents of `data`.
+    pub fn literal(data: &[u8]) -> Result<BinaryHunk, Error> {
+        Ok(BinaryHunk {
+            kind: DiffBinaryKind::Literal,
+            inflated_len: data.len(),
+            data: deflate(data)?,
+        })
+    }
+
+    /// Create a hunk holding a git delta which turns `base` into `target`.
+    pub fn delta(base: &[u8], target: &[u8]) -> Result<BinaryHunk, Error> {
+        let delta = create_delta(base, target);
+        Ok(BinaryHunk {
+            kind: DiffBinaryKind::Delta,
+            inflated_len: delta.len(),
+            data: deflate(&delta)?,
+        })
+    }
+
+    /// Create a hunk from one side of a binary diff produced by libgit2.
+    ///
+    /// Returns `None` if the file carries no binary data.
+    pub fn from_diff_binary_file(file: &DiffBinaryFile<'_>) -> Option<BinaryHunk> {
+        match file.kind() {
+            DiffBinaryKind::None => None,
+            kind => Some(BinaryHunk {
+                kind,
+                inflated_len: file.inflated_len(),
+                data: file.data().to_vec(),
+            }),
+        }
+    }
+
+    /// Whether this hunk holds literal contents or a delta.
+    ///
+    /// This is never [`DiffBinaryKind::None`].
+    pub fn kind(&self) -> DiffBinaryKind {
+        self.kind
+    }
+
+    /// The length of the hunk's data after inflation.
+    pub fn inflated_len(&self) -> usize {
+        self.inflated_len
+    }
+
+    /// The deflated data of this hunk.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Inflate the data of this hunk.
+    ///
+    /// For a literal hunk this is the contents of the file, for a delta hunk
+    /// it is the raw git delta.
+    pub fn inflate(&self) -> Result<Vec<u8>, Error> {
+        inflate(&self.data, self.inflated_len)
+    }
+
+    /// Compute the contents produced by applying this hunk to `base`.
+    ///
+    /// A literal hunk ignores `base` altogether.
+    pub fn apply(&self, base: &[u8]) -> Result<Vec<u8>, Error> {
+        let data = self.inflate()?;
+        match self.kind {
+            DiffBinaryKind::Delta => apply_delta(base, &data),
+            _ => Ok(data),
+        }
+    }
+
+    fn parse(lines: &mut Lines<'_>) -> Result<Option<BinaryHunk>, Error> {
+        let header = match lines.peek() {
+            Some(line) if !line.is_empty() => line,
+            _ => return Ok(None),
+        };
+        let (kind, len) = if let Some(len) = header.strip_prefix(b"literal ") {
+            (DiffBinaryKind::Literal, len)
+        } else if let Some(len) = header.strip_prefix(b"delta ") {
+            (DiffBinaryKind::Delta, len)
+        } else {
+            return Ok(None);
+        };
+        let inflated_len = str::from_utf8(len)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("invalid binary hunk size"))?;
+        lines.next();
+
+        let mut data = Vec::new();
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+            decode_line(line, &mut data)?;
+        }
+        Ok(Some(BinaryHunk {
+            kind,
+            inflated_len,
+            data,
+        }))
+    }
+}
+
+impl fmt::Display for BinaryHunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            DiffBinaryKind::Delta => "delta",
+            _ => "literal",
+        };
+        writeln!(f, "{} {}", kind, self.inflated_len)?;
+        let mut line = String::new();
+        for chunk in self.data.chunks(LINE_BYTES) {
+            line.clear();
+            encode_line(&mut line, chunk);
+            f.write_str(&line)?;
+        }
+        f.write_str("\n")
+    }
+}
+
+impl BinaryPatch {
+    /// Create a binary patch which turns `old` into `new`.
+    ///
+    /// Like git, each hunk is written as a delta when that is smaller than
+    /// the deflated literal contents, and as a literal otherwise.
+    pub fn new(old: &[u8], new: &[u8]) -> Result<BinaryPatch, Error> {
+        Ok(BinaryPatch {
+            forward: smallest_hunk(old, new)?,
+            reverse: Some(smallest_hunk(new, old)?),
+            old_path: None,
+            new_path: None,
+        })
+    }
+
+    /// Create a binary patch from the binary data of a libgit2 diff.
+    ///
+    /// The diff must have been generated with
+    /// [`DiffOptions::show_binary`](crate::DiffOptions::show_binary) so that
+    /// it contains data.
+    pub fn from_diff_binary(binary: &DiffBinary<'_>) -> Result<BinaryPatch, Error> {
+        let forward = if binary.contains_data() {
+            BinaryHunk::from_diff_binary_file(&binary.new_file())
+        } else {
+            None
+        };
+        let forward = forward.ok_or_else(|| invalid("binary diff contains no data"))?;
+        Ok(BinaryPatch {
+            forward,
+            reverse: BinaryHunk::from_diff_binary_file(&binary.old_file()),
+            old_path: None,
+            new_path: None,
+        })
+    }
+
+    /// Parse a single `GIT binary patch` section.
+    ///
+    /// The buffer must start with the `GIT binary patch` line; anything after
+    /// the section's hunks is ignored.
+    pub fn from_buffer(buf: &[u8]) -> Result<BinaryPatch, Error> {
+        let mut lines = Lines::new(buf);
+        match lines.next() {
+            Some(line) if line == HEADER.as_bytes() => {}
+            _ => return Err(invalid("missing binary patch header")),
+        }
+        BinaryPatch::parse_hunks(&mut lines)
+    }
+
+    /// Parse every `GIT binary patch` section of a patch file.
+    ///
+    /// Text hunks are skipped. When a section is preceded by a
+    /// `diff --git a/<old> b/<new>` header, its paths are recorded in the
+    /// returned patches.
+    pub fn parse_all(buf: &[u8]) -> Result<Vec<BinaryPatch>, Error> {
+        let mut lines = Lines::new(buf);
+        let mut paths = None;
+        let mut patches = Vec::new();
+        while let Some(line) = lines.next() {
+            if let Some(header) = line.strip_prefix(b"diff --git ") {
+                paths = parse_diff_header(header);
+            } else if line == HEADER.as_bytes() {
+                let mut patch = BinaryPatch::parse_hunks(&mut lines)?;
+                if let Some((old, new)) = paths.take() {
+                    patch.old_path = Some(old);
+                    patch.new_path = Some(new);
+                }
+                patches.push(patch);
+            }
+        }
+        Ok(patches)
+    }
+
+    fn parse_hunks(lines: &mut Lines<'_>) -> Result<BinaryPatch, Error> {
+        let forward =
+            BinaryHunk::parse(lines)?.ok_or_else(|| invalid("binary patch has no hunks"))?;
+        let reverse = BinaryHunk::parse(lines)?;
+        Ok(BinaryPatch {
+            forward,
+            reverse,
+            old_path: None,
+            new_path: None,
+        })
+    }
+
+    /// The hunk turning the old file into the new file.
+    pub fn forward(&self) -> &BinaryHunk {
+        &self.forward
+    }
+
+    /// The hunk turning the new file back into the old file, if the patch
+    /// contains one.
+    pub fn reverse(&self) -> Option<&BinaryHunk> {
+        self.reverse.as_ref()
+    }
+
+    /// The path of the old file, if it was known when parsing.
+    pub fn old_path(&self) -> Option<&Path> {
+        self.old_path.as_deref()
+    }
+
+    /// The path of the new file, if it was known when parsing.
+    pub fn new_path(&self) -> Option<&Path> {
+        self.new_path.as_deref()
+    }
+
+    /// Apply this patch to the contents of the old file, returning the
+    /// contents of the new file.
+    pub fn apply(&self, old: &[u8]) -> Result<Vec<u8>, Error> {
+        self.forward.apply(old)
+    }
+
+    /// Apply this patch in reverse to the contents of the new file, returning
+    /// the contents of the old file.
+    ///
+    /// This fails if the patch has no reverse hunk.
+    pub fn apply_reverse(&self, new: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.reverse {
+            Some(ref hunk) => hunk.apply(new),
+            None => Err(invalid("binary patch has no reverse hunk")),
+        }
+    }
+
+    /// Apply this patch to a blob of `repo` and write the result as a new
+    /// blob, returning its id.
+    ///
+    /// Pass `None` for a file which is created by this patch.
+    pub fn apply_to_blob(&self, repo: &Repository, old: Option<&Blob<'_>>) -> Result<Oid, Error> {
+        let new = self.apply(old.map(|b| b.content()).unwrap_or(&[]))?;
+        repo.blob(&new)
+    }
+}
+
+impl fmt::Display for BinaryPatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        self.forward.fmt(f)?;
+        if let Some(ref reverse) = self.reverse {
+            reverse.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorCode::Invalid, ErrorClass::Patch, msg)
+}
+
+fn zlib_error(rc: c_int) -> Error {
+    let msg = format!("zlib failure ({})", rc);
+    Error::new(ErrorCode::GenericError, ErrorClass::Zlib, msg)
+}
+
+fn smallest_hunk(base: &[u8], target: &[u8]) -> Result<BinaryHunk, Error> {
+    let literal = BinaryHunk::literal(target)?;
+    if base.is_empty() || target.is_empty() {
+        return Ok(literal);
+    }
+    let delta = BinaryHunk::delta(base, target)?;
+    if delta.data.len() < literal.data.len() {
+        Ok(delta)
+    } else {
+        Ok(literal)
+    }
+}
+
+/// Split `a/<old> b/<new>` into its two paths.
+fn parse_diff_header(header: &[u8]) -> Option<(PathBuf, PathBuf)> {
+    let header = str::from_utf8(header).ok()?.strip_prefix("a/")?;
+    // When both paths are equal, splitting in the middle is unambiguous even
+    // if the path itself contains " b/".
+    if header.len() >= 3 && (header.len() - 3) % 2 == 0 {
+        let mid = (header.len() - 3) / 2;
+        if header.get(mid..mid + 3) == Some(" b/") && header[..mid] == header[mid + 3..] {
+            return Some((PathBuf::from(&header[..mid]), PathBuf::from(&header[..mid])));
+        }
+    }
+    let idx = header.find(" b/")?;
+    Some((
+        PathBuf::from(&header[..idx]),
+        PathBuf::from(&header[idx + 3..]),
+    ))
+}
+
+#[derive(Clone)]
+struct Lines<'a> {
+    rest: Option<&'a [u8]>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(buf: &'a [u8]) -> Lines<'a> {
+        Lines { rest: Some(buf) }
+    }
+
+    fn peek(&self) -> Option<&'a [u8]> {
+        self.clone().next()
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let rest = self.rest?;
+        let line = match rest.iter().position(|&b| b == b'\n') {
+            Some(idx) => {
+                self.rest = Some(&rest[idx + 1..]);
+                &rest[..idx]
+            }
+            None => {
+                self.rest = None;
+                rest
+            }
+        };
+        Some(trim_cr(line))
+    }
+}
+
+fn trim_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn encode_line(out: &mut String, chunk: &[u8]) {
+    let len = chunk.len() as u8;
+    out.push(if len <= 26 {
+        (b'A' + len - 1) as char
+    } else {
+        (b'a' + len - 27) as char
+    });
+    for group in chunk.chunks(4) {
+        let mut acc = 0u32;
+        for i in 0..4 {
+            acc = (acc << 8) | u32::from(group.get(i).copied().unwrap_or(0));
+        }
+        let mut encoded = [0u8; 5];
+        for slot in encoded.iter_mut().rev() {
+            *slot = EN85[(acc % 85) as usize];
+            acc /= 85;
+        }
+        out.extend(encoded.iter().map(|&b| b as char));
+    }
+    out.push('\n');
+}
+
+fn decode_line(line: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+    let (len, encoded) = match line.split_first() {
+        Some((&c @ b'A'..=b'Z', rest)) => ((c - b'A') as usize + 1, rest),
+        Some((&c @ b'a'..=b'z', rest)) => ((c - b'a') as usize + 27, rest),
+        _ => return Err(invalid("invalid binary patch line length")),
+    };
+    let groups = encoded.len() / 5;
+    if encoded.len() % 5 != 0 || groups * 4 < len || groups * 4 >= len + 4 {
+        return Err(invalid("corrupt binary patch line"));
+    }
+    let mut remaining = len;
+    for group in encoded.chunks(5) {
+        let mut acc = 0u32;
+        for &c in group {
+            let digit = EN85
+                .iter()
+                .position(|&e| e == c)
+                .ok_or_else(|| invalid("invalid base85 character"))?;
+            acc = acc
+                .checked_mul(85)
+                .and_then(|acc| acc.checked_add(digit as u32))
+                .ok_or_else(|| invalid("invalid base85 sequence"))?;
+        }
+        let n = remaining.min(4);
+        out.extend_from_slice(&acc.to_be_bytes()[..n]);
+        remaining -= n;
+    }
+    Ok(())
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    unsafe {
+        let mut len = libz_sys::compressBound(data.len() as libz_sys::uLong);
+        let mut out = Vec::with_capacity(len as usize);
+        let rc = libz_sys::compress2(
+            out.as_mut_ptr(),
+            &mut len,
+            data.as_ptr(),
+            data.len() as libz_sys::uLong,
+            libz_sys::Z_DEFAULT_COMPRESSION,
+        );
+        if rc != libz_sys::Z_OK {
+            return Err(zlib_error(rc));
+        }
+        out.set_len(len as usize);
+        Ok(out)
+    }
+}
+
+fn inflate(data: &[u8], inflated_len: usize) -> Result<Vec<u8>, Error> {
+    // The announced size is untrusted, so refuse sizes no deflated stream of
+    // this length can have rather than attempt to allocate them.
+    let mismatch = || invalid("binary hunk size does not match its data");
+    if inflated_len / ZLIB_MAX_RATIO > data.len() {
+        return Err(mismatch());
+    }
+    // zlib refuses a zero sized output buffer, so always leave room for one
+    // byte and check the produced length afterwards.
+    let mut out = Vec::new();
+    out.try_reserve_exact(inflated_len + 1)
+        .map_err(|_| invalid("binary hunk is too large to inflate"))?;
+    let mut len = (inflated_len + 1) as libz_sys::uLong;
+    unsafe {
+        let rc = libz_sys::uncompress(
+            out.as_mut_ptr(),
+            &mut len,
+            data.as_ptr(),
+            data.len() as libz_sys::uLong,
+        );
+        if rc != libz_sys::Z_OK {
+            return Err(zlib_error(rc));
+        }
+        out.set_len(len as usize);
+    }
+    if out.len() != inflated_len {
+        return Err(mismatch());
+    }
+    Ok(out)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize, Error> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| invalid("truncated delta header"))?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            return Err(invalid("delta header size overflow"));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Create a git delta turning `base` into `target`.
+///
+/// Blocks of the base are indexed at fixed offsets and looked up at every
+/// offset of the target; matches are then extended in both directions. This
+/// finds every common run at least twice the block size long.
+fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_varint(&mut out, base.len());
+    write_varint(&mut out, target.len());
+
+    // Copy offsets are limited to 32 bits.
+    let indexed = &base[..base.len().min(u32::MAX as usize)];
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, block) in indexed.chunks_exact(DELTA_BLOCK).enumerate() {
+        let offsets = index.entry(block).or_default();
+        if offsets.len() < DELTA_CANDIDATES {
+            offsets.push(i * DELTA_BLOCK);
+        }
+    }
+
+    let mut pending = 0;
+    let mut pos = 0;
+    while pos + DELTA_BLOCK <= target.len() {
+        let best = index
+            .get(&target[pos..pos + DELTA_BLOCK])
+            .and_then(|offsets| {
+                offsets
+                    .iter()
+                    .map(|&offset| (offset, common_prefix(&indexed[offset..], &target[pos..])))
+                    .max_by_key(|&(_, len)| len)
+            });
+        let (mut offset, mut len) = match best {
+            Some(found) => found,
+            None => {
+                pos += 1;
+                continue;
+            }
+        };
+        let mut start = pos;
+        while start > pending && offset > 0 && indexed[offset - 1] == target[start - 1] {
+            start -= 1;
+            offset -= 1;
+            len += 1;
+        }
+        emit_insert(&mut out, &target[pending..start]);
+        emit_copy(&mut out, offset, len);
+        pos = start + len;
+        pending = pos;
+    }
+    emit_insert(&mut out, &target[pending..]);
+    out
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn emit_insert(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(DELTA_MAX_INSERT) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn emit_copy(out: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(DELTA_MAX_COPY);
+        // A size of 0x10000 is encoded by omitting all size bytes.
+        let encoded_size = if size == DELTA_MAX_COPY { 0 } else { size };
+        let mut cmd = 0x80u8;
+        let mut args = Vec::with_capacity(7);
+        for i in 0..4 {
+            let byte = (offset >> (i * 8)) as u8;
+            if byte != 0 {
+                cmd |= 1 << i;
+                args.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = (encoded_size >> (i * 8)) as u8;
+            if byte != 0 {
+                cmd |= 0x10 << i;
+                args.push(byte);
+            }
+        }
+        out.push(cmd);
+        out.extend_from_slice(&args);
+        offset += size;
+        len -= size;
+    }
+}
+
+/// Apply a git delta to `base`.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut pos = 0;
+    let base_len = read_varint(delta, &mut pos)?;
+    let target_len = read_varint(delta, &mut pos)?;
+    if base_len != base.len() {
+        return Err(invalid("delta base does not match the preimage"));
+    }
+
+    let truncated = || invalid("truncated delta");
+    // Only reserve what the delta can plausibly produce; the announced size
+    // is untrusted.
+    let mut out = Vec::with_capacity(target_len.min(base.len().saturating_add(delta.len())));
+    while pos < delta.len() {
+        let cmd = delta[pos];
+        pos += 1;
+        if cmd & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for i in 0..4 {
+                if cmd & (1 << i) != 0 {
+                    offset |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (i * 8);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if cmd & (0x10 << i) != 0 {
+                    size |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (i * 8);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = DELTA_MAX_COPY;
+            }
+            let end = offset
+                .checked_add(size)
+                .filter(|&end| end <= base.len())
+                .ok_or_else(|| invalid("delta copies beyond the end of its base"))?;
+            out.extend_from_slice(&base[offset..end]);
+        } else if cmd != 0 {
+            let end = pos + cmd as usize;
+            out.extend_from_slice(delta.get(pos..end).ok_or_else(truncated)?);
+            pos = end;
+        } else {
+            return Err(invalid("unexpected delta opcode 0"));
+        }
+        if out.len() > target_len {
+            return Err(invalid("delta produces more data than announced"));
+        }
+    }
+    if out.len() != target_len {
+        return Err(invalid("delta produces less data than announced"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryHunk, BinaryPatch};
+    use crate::{ApplyLocation, Diff, DiffBinaryKind, DiffOptions, Patch};
+    use std::path::Path;
+
+    fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn smoke() {
+        let old = sample(4000, 1);
+        let mut new = old.clone();
+        new[100..140].copy_from_slice(&[0; 40]);
+        new.extend_from_slice(b"\0tail");
+
+        let patch = t!(BinaryPatch::new(&old, &new));
+        assert_eq!(patch.forward().kind(), DiffBinaryKind::Delta);
+        assert_eq!(t!(patch.apply(&old)), new);
+        assert_eq!(t!(patch.apply_reverse(&new)), old);
+
+        let parsed = t!(BinaryPatch::from_buffer(patch.to_string().as_bytes()));
+        assert_eq!(parsed, patch);
+        assert_eq!(t!(parsed.apply(&old)), new);
+    }
+
+    #[test]
+    fn literal() {
+        let hunk = t!(BinaryHunk::literal(b""));
+        assert_eq!(hunk.inflated_len(), 0);
+        assert_eq!(t!(hunk.apply(b"ignored")), b"");
+
+        let data = sample(200, 7);
+        let hunk = t!(BinaryHunk::literal(&data));
+        assert_eq!(t!(hunk.inflate()), data);
+        let text = format!("GIT binary patch\n{}", hunk);
+        let parsed = t!(BinaryPatch::from_buffer(text.as_bytes()));
+        assert!(parsed.reverse().is_none());
+        assert_eq!(parsed.forward(), &hunk);
+        assert!(parsed.apply_reverse(&data).is_err());
+    }
+
+    #[test]
+    fn corrupt() {
+        assert!(BinaryPatch::from_buffer(b"GIT binary patch\n").is_err());
+        assert!(BinaryPatch::from_buffer(b"GIT binary patch\nliteral 3\nB!!!!\n\n").is_err());
+        let patch = t!(BinaryPatch::new(&sample(100, 1), &sample(100, 2)));
+        let mut text = patch.to_string();
+        text = text.replacen("literal 100", "literal 99", 1);
+        let parsed = t!(BinaryPatch::from_buffer(text.as_bytes()));
+        assert!(parsed.apply(&sample(100, 1)).is_err());
+
+        // Sizes are not trusted to allocate.
+        for len in ["1099511627776", &usize::MAX.to_string()] {
+            let literal = format!("literal {}", len);
+            let text = patch.to_string().replacen("literal 100", &literal, 1);
+            let parsed = t!(BinaryPatch::from_buffer(text.as_bytes()));
+            assert!(parsed.apply(&sample(100, 1)).is_err());
+        }
+        let mut delta = Vec::new();
+        super::write_varint(&mut delta, 3);
+        super::write_varint(&mut delta, 1 << 40);
+        delta.extend_from_slice(b"\x03abc");
+        let hunk = BinaryHunk {
+            kind: DiffBinaryKind::Delta,
+            inflated_len: delta.len(),
+            data: t!(super::deflate(&delta)),
+        };
+        assert!(hunk.apply(b"abc").is_err());
+    }
+
+    #[test]
+    fn libgit2_roundtrip() {
+        let (td, repo) = crate::test::repo_init();
+        let old = sample(3000, 3);
+        let mut new = old.clone();
+        new.splice(1000..1000, b"\0inserted".iter().copied());
+
+        let path = Path::new("file.bin");
+        let mut opts = DiffOptions::new();
+        opts.show_binary(true);
+        let mut patch = t!(Patch::from_buffers(
+            &old,
+            Some(path),
+            &new,
+            Some(path),
+            Some(&mut opts)
+        ));
+        let buf = t!(patch.to_buf());
+
+        // Parse what libgit2 printed.
+        let parsed = t!(BinaryPatch::parse_all(&buf));
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].new_path(), Some(path));
+        assert_eq!(t!(parsed[0].apply(&old)), new);
+
+        // Have libgit2 apply what we generated.
+        std::fs::write(td.path().join(path), &old).unwrap();
+        let mut index = t!(repo.index());
+        t!(index.add_path(path));
+        t!(index.write());
+        let old_id = t!(repo.blob(&old));
+        let new_id = t!(repo.blob(&new));
+        let ours = t!(BinaryPatch::new(&old, &new));
+        let text = format!(
+            "diff --git a/file.bin b/file.bin\nindex {}..{} 100644\n{}",
+            old_id, new_id, ours
+        );
+        let diff = t!(Diff::from_buffer(text.as_bytes()));
+        t!(repo.apply(&diff, ApplyLocation::Index, None));
+        let index = t!(repo.index());
+        assert_eq!(index.get_path(path, 0).unwrap().id, new_id);
+
+        let old_blob = t!(repo.find_blob(old_id));
+        assert_eq!(t!(ours.apply_to_blob(&repo, Some(&old_blob))), new_id);
+    }
+}
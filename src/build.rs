@@ -513,6 +513,14 @@ impl<'cb> CheckoutBuilder<'cb> {
         self
     }
 
+    /// Treat the paths added with `path` as a list of exact file or
+    /// directory paths instead of pathspecs.
+    ///
+    /// Defaults to false.
+    pub fn disable_pathspec_match(&mut self, on: bool) -> &mut CheckoutBuilder<'cb> {
+        self.flag(raw::GIT_CHECKOUT_DISABLE_PATHSPEC_MATCH, on)
+    }
+
     /// Add a path to be checked out.
     ///
     /// If no paths are specified, then all files are checked out. Otherwise
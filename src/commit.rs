@@ -1,12 +1,15 @@
 use libc;
+use std::ffi::CString;
 use std::marker;
 use std::mem;
 use std::ops::Range;
 use std::ptr;
 use std::str;
 
+use crate::signing::{self, SignatureVerification, Signer, Verifier};
 use crate::util::Binding;
 use crate::{raw, signature, Buf, Error, IntoCString, Mailmap, Object, Oid, Signature, Time, Tree};
+use crate::{ErrorCode, Repository};
 
 /// A structure to represent a git [commit][1]
 ///
@@ -281,6 +284,81 @@ impl<'repo> Commit<'repo> {
         }
     }
 
+    /// Amend this existing commit like `amend()`, signing the new commit
+    /// with `signer`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn amend_with_signer(
+        &self,
+        update_ref: Option<&str>,
+        author: Option<&Signature<'_>>,
+        committer: Option<&Signature<'_>>,
+        message_encoding: Option<&str>,
+        message: Option<&str>,
+        tree: Option<&Tree<'repo>>,
+        signer: &dyn Signer,
+    ) -> Result<Oid, Error> {
+        let author = author
+            .map(|s| s.to_owned())
+            .unwrap_or_else(|| self.author());
+        let committer = committer
+            .map(|s| s.to_owned())
+            .unwrap_or_else(|| self.committer());
+        let encoding = match message_encoding {
+            Some(encoding) => Some(CString::new(encoding)?),
+            None => self.message_encoding().map(CString::new).transpose()?,
+        };
+        let message = match message {
+            Some(message) => message.as_bytes(),
+            None => self.message_raw_bytes(),
+        };
+        let summary = String::from_utf8_lossy(message.split(|b| *b == b'\n').next().unwrap_or(&[]))
+            .into_owned();
+        let message = CString::new(message)?;
+        let tree = match tree {
+            Some(tree) => tree.clone(),
+            None => self.tree()?,
+        };
+        let parents = self.parents().collect::<Vec<_>>();
+        let mut parent_ptrs = parents
+            .iter()
+            .map(|p| p.raw() as *const raw::git_commit)
+            .collect::<Vec<_>>();
+        unsafe {
+            let repo = Repository::borrow_raw(raw::git_commit_owner(&*self.raw));
+            let id = signing::create_commit(
+                repo.raw(),
+                author.raw(),
+                committer.raw(),
+                encoding.as_ref().map(|e| e.as_ptr()).unwrap_or(ptr::null()),
+                message.as_ptr(),
+                tree.raw(),
+                &mut parent_ptrs,
+                signer,
+            )?;
+            if let Some(update_ref) = update_ref {
+                let log_message = format!("commit (amend): {}", summary);
+                repo.update_ref_to_commit(update_ref, id, Some(self.id()), &log_message)?;
+            }
+            Ok(id)
+        }
+    }
+
+    /// Check the signature of this commit with `verifier`.
+    ///
+    /// A commit without a signature is reported as
+    /// [`SignatureStatus::Unsigned`](crate::SignatureStatus::Unsigned).
+    pub fn verify_signature(
+        &self,
+        verifier: &dyn Verifier,
+    ) -> Result<SignatureVerification, Error> {
+        let repo = unsafe { Repository::borrow_raw(raw::git_commit_owner(&*self.raw)) };
+        match repo.extract_signature(&self.id(), None) {
+            Ok((signature, content)) => verifier.verify(&signature, &content),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(SignatureVerification::unsigned()),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get the number of parents of this commit.
     ///
     /// Use the `parents` iterator to return an iterator over all parents.
@@ -0,0 +1,619 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::ptr;
+
+use crate::util::Binding;
+use crate::{raw, Error, ErrorClass, ErrorCode, IntoCString, Oid, Repository};
+
+/// Options for writing a commit-graph file with
+/// [`Repository::write_commit_graph`].
+pub struct CommitGraphOptions {
+    reachable: bool,
+    packs: bool,
+}
+
+/// A commit-graph, as written by `git commit-graph write` or
+/// [`Repository::write_commit_graph`].
+///
+/// The commit-graph stores the parents, tree, commit time and generation
+/// number of commits, allowing them to be looked up without parsing the
+/// commits. Both single files and split chains of files are read.
+pub struct CommitGraph {
+    layers: Vec<Layer>,
+}
+
+/// A commit stored in a [`CommitGraph`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitGraphEntry {
+    id: Oid,
+    tree_id: Oid,
+    parent_ids: Vec<Oid>,
+    generation: u32,
+    time: i64,
+}
+
+/// A single commit-graph file.
+struct Layer {
+    data: Vec<u8>,
+    /// The number of commits in the layers this one is based on.
+    base: usize,
+    count: usize,
+    fanout: usize,
+    oids: usize,
+    commits: usize,
+    edges: Option<usize>,
+}
+
+const OID_SIZE: usize = 20;
+const COMMIT_DATA_SIZE: usize = OID_SIZE + 16;
+const PARENT_NONE: u32 = 0x7000_0000;
+const PARENT_EDGES: u32 = 0x8000_0000;
+const LAST_EDGE: u32 = 0x8000_0000;
+
+impl CommitGraphOptions {
+    /// Creates a default set of options, which add the commits reachable
+    /// from the references of the repository.
+    pub fn new() -> CommitGraphOptions {
+        CommitGraphOptions {
+            reachable: true,
+            packs: false,
+        }
+    }
+
+    /// Add the commits reachable from the references and `HEAD`, like
+    /// `git commit-graph write --reachable`.
+    ///
+    /// Defaults to `true`.
+    pub fn reachable(&mut self, reachable: bool) -> &mut CommitGraphOptions {
+        self.reachable = reachable;
+        self
+    }
+
+    /// Add the commits stored in the packfiles of the repository, like
+    /// `git commit-graph write` without `--reachable`.
+    ///
+    /// Defaults to `false`.
+    pub fn packs(&mut self, packs: bool) -> &mut CommitGraphOptions {
+        self.packs = packs;
+        self
+    }
+}
+
+impl Default for CommitGraphOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write `objects/info/commit-graph` for `repo`.
+///
+/// A split commit-graph chain takes precedence over that file for git, so if
+/// there is one, it is replaced by a chain of a single file instead, like
+/// `git commit-graph write --split=replace` does.
+pub(crate) fn write(repo: &Repository, opts: &CommitGraphOptions) -> Result<(), Error> {
+    let info = repo.objects_dir().join("info");
+    let chain_dir = info.join("commit-graphs");
+    let chain = chain_dir.join("commit-graph-chain");
+    let old_chain = match fs::read_to_string(&chain) {
+        Ok(contents) => Some(contents),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(io_error(e)),
+    };
+    // libgit2 writes `commit-graph` in the directory it is given.
+    let dir = if old_chain.is_some() {
+        &chain_dir
+    } else {
+        &info
+    };
+    fs::create_dir_all(dir).map_err(io_error)?;
+    let dir = dir.as_path().into_c_string()?;
+
+    struct Writer(*mut raw::git_commit_graph_writer);
+    impl Drop for Writer {
+        fn drop(&mut self) {
+            unsafe { raw::git_commit_graph_writer_free(self.0) }
+        }
+    }
+
+    unsafe {
+        let mut writer = Writer(ptr::null_mut());
+        try_call!(raw::git_commit_graph_writer_new(&mut writer.0, dir));
+        if opts.reachable {
+            let mut walk = repo.revwalk()?;
+            walk.push_glob("*")?;
+            match walk.push_head() {
+                Ok(()) => {}
+                Err(ref e) if e.code() == ErrorCode::UnbornBranch => {}
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            try_call!(raw::git_commit_graph_writer_add_revwalk(
+                writer.0,
+                walk.raw()
+            ));
+        }
+        if opts.packs {
+            let packs = match fs::read_dir(repo.objects_dir().join("pack")) {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().is_some_and(|e| e == "idx"))
+                    .collect::<Vec<_>>(),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(io_error(e)),
+            };
+            for pack in packs {
+                let pack = pack.as_path().into_c_string()?;
+                try_call!(raw::git_commit_graph_writer_add_index_file(
+                    writer.0,
+                    repo.raw(),
+                    pack
+                ));
+            }
+        }
+
+        let mut raw_opts = mem::zeroed();
+        try_call!(raw::git_commit_graph_writer_options_init(
+            &mut raw_opts,
+            raw::GIT_COMMIT_GRAPH_WRITER_OPTIONS_VERSION
+        ));
+        try_call!(raw::git_commit_graph_writer_commit(writer.0, &mut raw_opts));
+    }
+
+    if let Some(old_chain) = old_chain {
+        replace_chain(&chain_dir, &old_chain)?;
+    }
+    // Let the object database pick up the new file.
+    repo.odb()?.refresh()
+}
+
+/// Make the `commit-graph` file just written in `dir` the only layer of the
+/// chain there, then remove the layers of `old_chain`.
+fn replace_chain(dir: &Path, old_chain: &str) -> Result<(), Error> {
+    let written = dir.join("commit-graph");
+    let data = fs::read(&written).map_err(io_error)?;
+    // Layers are named after the checksum which ends them.
+    let hash = Oid::from_bytes(&data[data.len().saturating_sub(OID_SIZE)..])?.to_string();
+    fs::rename(&written, dir.join(format!("graph-{}.graph", hash))).map_err(io_error)?;
+
+    let chain = dir.join("commit-graph-chain");
+    let lock = dir.join("commit-graph-chain.lock");
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .and_then(|mut file| file.write_all(format!("{}\n", hash).as_bytes()))
+        .and_then(|()| fs::rename(&lock, &chain));
+    if let Err(e) = result {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            let _ = fs::remove_file(&lock);
+        }
+        return Err(io_error(e));
+    }
+
+    for old in old_chain.lines().map(str::trim) {
+        if old.is_empty() || old == hash {
+            continue;
+        }
+        match fs::remove_file(dir.join(format!("graph-{}.graph", old))) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error(e)),
+        }
+    }
+    Ok(())
+}
+
+impl CommitGraph {
+    /// Read the commit-graph of the objects directory `objects_dir`.
+    ///
+    /// A split chain in `info/commit-graphs` is read if there is one, as git
+    /// does, and `info/commit-graph` otherwise. Fails with
+    /// [`ErrorCode::NotFound`] if there is neither.
+    pub fn open(objects_dir: &Path) -> Result<CommitGraph, Error> {
+        let info = objects_dir.join("info");
+        let chain = info.join("commit-graphs");
+        let files = match fs::read_to_string(chain.join("commit-graph-chain")) {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|hash| chain.join(format!("graph-{}.graph", hash.trim())))
+                .collect(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => vec![info.join("commit-graph")],
+            Err(e) => return Err(io_error(e)),
+        };
+
+        let mut layers: Vec<Layer> = Vec::new();
+        for file in files {
+            let data = match fs::read(&file) {
+                Ok(data) => data,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::new(
+                        ErrorCode::NotFound,
+                        ErrorClass::Odb,
+                        format!("no commit-graph at '{}'", file.display()),
+                    ))
+                }
+                Err(e) => return Err(io_error(e)),
+            };
+            let base = layers.last().map_or(0, |l| l.base + l.count);
+            layers.push(Layer::parse(data, base)?);
+        }
+        Ok(CommitGraph { layers })
+    }
+
+    /// The number of commits in the commit-graph.
+    pub fn len(&self) -> usize {
+        self.layers.last().map_or(0, |l| l.base + l.count)
+    }
+
+    /// Whether the commit-graph holds no commit.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the commit `id` is in the commit-graph.
+    pub fn contains(&self, id: Oid) -> bool {
+        self.position(id).is_some()
+    }
+
+    /// Look up the commit `id`, returning `None` if it is not in the
+    /// commit-graph.
+    pub fn get(&self, id: Oid) -> Result<Option<CommitGraphEntry>, Error> {
+        match self.position(id) {
+            Some(pos) => self.entry(pos).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The ids of the commits in the commit-graph.
+    pub fn ids(&self) -> impl Iterator<Item = Oid> + '_ {
+        self.layers
+            .iter()
+            .flat_map(|layer| (0..layer.count).map(move |i| layer.oid(i)))
+    }
+
+    fn position(&self, id: Oid) -> Option<usize> {
+        self.layers
+            .iter()
+            .find_map(|layer| layer.find(id).map(|i| layer.base + i))
+    }
+
+    fn layer(&self, pos: usize) -> Result<(&Layer, usize), Error> {
+        self.layers
+            .iter()
+            .find(|l| pos >= l.base && pos < l.base + l.count)
+            .map(|l| (l, pos - l.base))
+            .ok_or_else(|| corrupt("parent position out of range"))
+    }
+
+    fn entry(&self, pos: usize) -> Result<CommitGraphEntry, Error> {
+        let (layer, i) = self.layer(pos)?;
+        let data = &layer.data[layer.commits + i * COMMIT_DATA_SIZE..][..COMMIT_DATA_SIZE];
+        let tree_id = Oid::from_bytes(&data[..OID_SIZE])?;
+        let p1 = be32(&data[OID_SIZE..]);
+        let p2 = be32(&data[OID_SIZE + 4..]);
+        let high = be32(&data[OID_SIZE + 8..]);
+        let low = be32(&data[OID_SIZE + 12..]);
+
+        let mut parents = Vec::new();
+        if p1 != PARENT_NONE {
+            parents.push(p1 as usize);
+        }
+        if p2 & PARENT_EDGES != 0 {
+            let edges = layer
+                .edges
+                .ok_or_else(|| corrupt("missing extra edges chunk"))?;
+            let mut k = (p2 & !PARENT_EDGES) as usize;
+            loop {
+                let edge = layer
+                    .data
+                    .get(edges + k * 4..edges + k * 4 + 4)
+                    .map(be32)
+                    .ok_or_else(|| corrupt("extra edge out of range"))?;
+                parents.push((edge & !LAST_EDGE) as usize);
+                if edge & LAST_EDGE != 0 {
+                    break;
+                }
+                k += 1;
+            }
+        } else if p2 != PARENT_NONE {
+            parents.push(p2 as usize);
+        }
+        let parent_ids = parents
+            .into_iter()
+            .map(|p| self.layer(p).map(|(l, i)| l.oid(i)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(CommitGraphEntry {
+            id: layer.oid(i),
+            tree_id,
+            parent_ids,
+            generation: high >> 2,
+            time: (((high & 0x3) as i64) << 32) | low as i64,
+        })
+    }
+}
+
+impl Layer {
+    fn parse(data: Vec<u8>, base: usize) -> Result<Layer, Error> {
+        if data.len() < 8 || &data[..4] != b"CGPH" {
+            return Err(corrupt("bad signature"));
+        }
+        if data[4] != 1 || data[5] != 1 {
+            return Err(corrupt("unsupported version"));
+        }
+        let chunks = data[6] as usize;
+        let (mut fanout, mut oids, mut commits, mut edges) = (None, None, None, None);
+        for i in 0..chunks {
+            let entry = data
+                .get(8 + i * 12..8 + i * 12 + 12)
+                .ok_or_else(|| corrupt("truncated chunk table"))?;
+            let offset = u64::from_be_bytes(entry[4..].try_into().unwrap()) as usize;
+            if offset > data.len() {
+                return Err(corrupt("chunk out of range"));
+            }
+            match &entry[..4] {
+                b"OIDF" => fanout = Some(offset),
+                b"OIDL" => oids = Some(offset),
+                b"CDAT" => commits = Some(offset),
+                b"EDGE" => edges = Some(offset),
+                _ => {}
+            }
+        }
+        let missing = || corrupt("missing required chunk");
+        let fanout = fanout.ok_or_else(missing)?;
+        let oids = oids.ok_or_else(missing)?;
+        let commits = commits.ok_or_else(missing)?;
+        let count = data
+            .get(fanout + 255 * 4..fanout + 256 * 4)
+            .map(|b| be32(b) as usize)
+            .ok_or_else(|| corrupt("truncated fanout"))?;
+        if data.len() < oids + count * OID_SIZE || data.len() < commits + count * COMMIT_DATA_SIZE {
+            return Err(corrupt("truncated commit data"));
+        }
+        Ok(Layer {
+            data,
+            base,
+            count,
+            fanout,
+            oids,
+            commits,
+            edges,
+        })
+    }
+
+    fn oid(&self, i: usize) -> Oid {
+        Oid::from_bytes(&self.data[self.oids + i * OID_SIZE..][..OID_SIZE]).unwrap()
+    }
+
+    /// The position of `id` in this layer, found with the fanout table.
+    fn find(&self, id: Oid) -> Option<usize> {
+        let first = id.as_bytes()[0] as usize;
+        let fanout = |i: usize| be32(&self.data[self.fanout + i * 4..]) as usize;
+        let mut lo = if first == 0 { 0 } else { fanout(first - 1) };
+        let mut hi = fanout(first).min(self.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let oid = &self.data[self.oids + mid * OID_SIZE..][..OID_SIZE];
+            match oid.cmp(id.as_bytes()) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+impl CommitGraphEntry {
+    /// The id of the commit.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// The id of the tree of the commit.
+    pub fn tree_id(&self) -> Oid {
+        self.tree_id
+    }
+
+    /// The ids of the parents of the commit.
+    pub fn parent_ids(&self) -> &[Oid] {
+        &self.parent_ids
+    }
+
+    /// The generation number of the commit: 1 for commits without parents,
+    /// and one more than the largest generation of its parents otherwise.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The commit time, in seconds since the epoch.
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn corrupt(msg: &str) -> Error {
+    Error::new(
+        ErrorCode::Invalid,
+        ErrorClass::Odb,
+        format!("corrupt commit-graph: {}", msg),
+    )
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Os,
+        format!("failed to access commit-graph: {}", e),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use crate::{CommitGraphOptions, ErrorCode, Oid, Repository};
+
+    /// Commit an empty tree on top of `refs/heads/main`.
+    fn commit(repo: &Repository, message: &str) -> Oid {
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&head])
+            .unwrap()
+    }
+
+    fn chain(repo: &Repository) -> Vec<String> {
+        let path = repo
+            .path()
+            .join("objects/info/commit-graphs/commit-graph-chain");
+        let contents = fs::read_to_string(path).unwrap();
+        contents.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let err = repo.commit_graph().err().unwrap();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+
+        let sig = repo.signature().unwrap();
+        let root = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = root.tree().unwrap();
+        let a = repo.commit(None, &sig, &sig, "a", &tree, &[&root]).unwrap();
+        let b = repo.commit(None, &sig, &sig, "b", &tree, &[&root]).unwrap();
+        let c = repo.commit(None, &sig, &sig, "c", &tree, &[&root]).unwrap();
+        let parents = [a, b, c]
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        let octopus = repo
+            .commit(None, &sig, &sig, "octopus", &tree, &parents)
+            .unwrap();
+        repo.reference("refs/heads/main", octopus, true, "")
+            .unwrap();
+        // Not reachable from any reference.
+        let dangling = repo.commit(None, &sig, &sig, "x", &tree, &[&root]).unwrap();
+
+        repo.write_commit_graph(None).unwrap();
+        assert!(repo.path().join("objects/info/commit-graph").exists());
+        let graph = repo.commit_graph().unwrap();
+        assert_eq!(graph.len(), 5);
+        assert!(!graph.contains(dangling));
+        assert!(graph.get(dangling).unwrap().is_none());
+
+        let entry = graph.get(root.id()).unwrap().unwrap();
+        assert_eq!(entry.generation(), 1);
+        assert!(entry.parent_ids().is_empty());
+        assert_eq!(entry.tree_id(), tree.id());
+        assert_eq!(entry.time(), root.time().seconds());
+
+        let entry = graph.get(octopus).unwrap().unwrap();
+        assert_eq!(entry.id(), octopus);
+        assert_eq!(entry.generation(), 3);
+        assert_eq!(entry.parent_ids(), &[a, b, c]);
+
+        let mut ids = graph.ids().collect::<Vec<_>>();
+        ids.sort();
+        let mut expected = vec![root.id(), a, b, c, octopus];
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        // Revision walks still work with the commit-graph in use.
+        let mut walk = repo.revwalk().unwrap();
+        walk.push(octopus).unwrap();
+        assert_eq!(walk.count(), 5);
+        assert_eq!(repo.merge_base(a, b).unwrap(), root.id());
+
+        let mut opts = CommitGraphOptions::new();
+        opts.reachable(false).packs(true);
+        repo.write_commit_graph(Some(&opts)).unwrap();
+        assert_eq!(repo.commit_graph().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn replaces_chain() {
+        let (_td, repo) = crate::test::repo_init();
+        let a = commit(&repo, "a");
+        repo.write_commit_graph(None).unwrap();
+
+        // Turn the file into a chain of one layer, as git would write it.
+        let info = repo.path().join("objects/info");
+        let dir = info.join("commit-graphs");
+        fs::create_dir_all(&dir).unwrap();
+        let data = fs::read(info.join("commit-graph")).unwrap();
+        let old = Oid::from_bytes(&data[data.len() - 20..])
+            .unwrap()
+            .to_string();
+        fs::rename(
+            info.join("commit-graph"),
+            dir.join(format!("graph-{}.graph", old)),
+        )
+        .unwrap();
+        fs::write(dir.join("commit-graph-chain"), format!("{}\n", old)).unwrap();
+        fs::write(dir.join("unrelated"), "").unwrap();
+        assert_eq!(repo.commit_graph().unwrap().len(), 2);
+
+        let b = commit(&repo, "b");
+        repo.write_commit_graph(None).unwrap();
+        let new = chain(&repo);
+        assert_eq!(new.len(), 1);
+        assert_ne!(new[0], old);
+        assert!(dir.join(format!("graph-{}.graph", new[0])).exists());
+        assert!(!dir.join(format!("graph-{}.graph", old)).exists());
+        assert!(!dir.join("commit-graph").exists());
+        assert!(dir.join("unrelated").exists());
+        assert!(!info.join("commit-graph").exists());
+
+        let graph = repo.commit_graph().unwrap();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.get(b).unwrap().unwrap().parent_ids(), &[a]);
+    }
+
+    #[test]
+    fn git_chains() {
+        let (td, repo) = crate::test::repo_init();
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(args)
+                .current_dir(td.path())
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+        };
+        let split = ["commit-graph", "write", "--reachable", "--split=no-merge"];
+        let a = commit(&repo, "a");
+        if git(&split).is_none() {
+            return;
+        }
+        let b = commit(&repo, "b");
+        let c = commit(&repo, "c");
+        assert!(git(&split).is_some());
+        assert_eq!(chain(&repo).len(), 2);
+
+        // Parents are found across layers.
+        let graph = repo.commit_graph().unwrap();
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.get(b).unwrap().unwrap().parent_ids(), &[a]);
+        assert_eq!(graph.get(c).unwrap().unwrap().generation(), 4);
+
+        repo.write_commit_graph(None).unwrap();
+        assert_eq!(chain(&repo).len(), 1);
+        let graph = repo.commit_graph().unwrap();
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.get(c).unwrap().unwrap().parent_ids(), &[b]);
+        assert!(git(&["commit-graph", "verify"]).is_some());
+        let layers = fs::read_dir(repo.path().join("objects/info/commit-graphs")).unwrap();
+        assert_eq!(layers.count(), 2);
+    }
+}
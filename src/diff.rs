@@ -102,7 +102,7 @@ pub struct DiffBinaryFile<'a> {
 /// either the deflated full ("literal") contents of the file, or
 /// the deflated binary delta between the two sides (whichever is
 /// smaller).
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DiffBinaryKind {
     /// There is no binary delta
     None,
@@ -0,0 +1,889 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str;
+
+use crate::maintenance::{self, WorktreeRoots};
+use crate::{Error, ErrorCode, ObjectType, Oid, Repository};
+
+/// Options for [`Repository::fsck`].
+pub struct FsckOptions {
+    verify_hashes: bool,
+    connectivity: bool,
+    reflogs: bool,
+    dangling: bool,
+    strict: bool,
+}
+
+/// The findings of [`Repository::fsck`].
+#[derive(Clone, Debug)]
+pub struct FsckReport {
+    objects: usize,
+    problems: Vec<FsckProblem>,
+}
+
+/// What refers to an object found to be missing or of the wrong type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsckReferrer {
+    /// Another object, such as a commit referring to its tree.
+    Object(Oid),
+    /// A reference, or `HEAD`.
+    Reference(String),
+    /// The entry at `index` of the reflog of `reference`, from the most
+    /// recent.
+    Reflog {
+        /// The name of the reference.
+        reference: String,
+        /// The index of the entry.
+        index: usize,
+    },
+    /// The index of the repository or of one of its worktrees.
+    Index,
+}
+
+/// A problem found by [`Repository::fsck`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The contents of an object do not hash to its id.
+    HashMismatch {
+        /// The id of the object.
+        id: Oid,
+    },
+    /// An object could not be read from the object database.
+    Corrupt {
+        /// The id of the object.
+        id: Oid,
+        /// Why the object could not be read.
+        message: String,
+    },
+    /// A commit, tree or tag could not be parsed.
+    Malformed {
+        /// The id of the object.
+        id: Oid,
+        /// The type of the object.
+        kind: ObjectType,
+        /// Why the object could not be parsed.
+        message: String,
+    },
+    /// A tree entry has a mode git does not use. Mode 100664 is only
+    /// reported by [`FsckOptions::strict`] checks.
+    BadFileMode {
+        /// The id of the tree.
+        tree: Oid,
+        /// The name of the entry.
+        name: String,
+        /// The mode of the entry.
+        mode: u32,
+    },
+    /// A tree entry has an empty name, a name with a `/`, or a name which
+    /// is `.`, `..` or `.git`.
+    BadEntryName {
+        /// The id of the tree.
+        tree: Oid,
+        /// The name of the entry.
+        name: String,
+    },
+    /// A tree has several entries with the same name.
+    DuplicateEntry {
+        /// The id of the tree.
+        tree: Oid,
+        /// The duplicated name.
+        name: String,
+    },
+    /// The entries of a tree are not sorted as git sorts them.
+    UnsortedTree {
+        /// The id of the tree.
+        tree: Oid,
+    },
+    /// An object is referred to but does not exist.
+    MissingObject {
+        /// The id of the missing object.
+        id: Oid,
+        /// What refers to the object.
+        referrer: FsckReferrer,
+    },
+    /// An object is not of the type it is referred to as.
+    WrongType {
+        /// The id of the object.
+        id: Oid,
+        /// The type the object is referred to as.
+        expected: ObjectType,
+        /// The actual type of the object.
+        actual: ObjectType,
+        /// What refers to the object.
+        referrer: FsckReferrer,
+    },
+    /// A reference could not be read, or is a symbolic reference to a
+    /// reference which does not exist.
+    BrokenReference {
+        /// The name of the reference.
+        name: String,
+        /// Why the reference is broken.
+        message: String,
+    },
+    /// The reflog of a reference could not be read.
+    BrokenReflog {
+        /// The name of the reference.
+        reference: String,
+        /// Why the reflog could not be read.
+        message: String,
+    },
+    /// An unreachable object which is not referred to by any other object.
+    ///
+    /// This is not an error, unreachable objects are normally left behind
+    /// by rewriting history until they expire.
+    Dangling {
+        /// The id of the object.
+        id: Oid,
+        /// The type of the object.
+        kind: ObjectType,
+    },
+}
+
+impl FsckOptions {
+    /// Creates a default set of options, which perform every check.
+    pub fn new() -> FsckOptions {
+        FsckOptions {
+            verify_hashes: true,
+            connectivity: true,
+            reflogs: true,
+            dangling: true,
+            strict: false,
+        }
+    }
+
+    /// Check that the contents of every object hash to its id.
+    ///
+    /// Defaults to `true`.
+    pub fn verify_hashes(&mut self, verify: bool) -> &mut FsckOptions {
+        self.verify_hashes = verify;
+        self
+    }
+
+    /// Check that the objects referred to by objects, references and the
+    /// index exist and are of the right type.
+    ///
+    /// Defaults to `true`.
+    pub fn connectivity(&mut self, check: bool) -> &mut FsckOptions {
+        self.connectivity = check;
+        self
+    }
+
+    /// Check the reflog entries as part of the connectivity checks, and
+    /// consider them when looking for dangling objects.
+    ///
+    /// Defaults to `true`.
+    pub fn reflogs(&mut self, check: bool) -> &mut FsckOptions {
+        self.reflogs = check;
+        self
+    }
+
+    /// Report dangling objects.
+    ///
+    /// Defaults to `true`.
+    pub fn dangling(&mut self, report: bool) -> &mut FsckOptions {
+        self.dangling = report;
+        self
+    }
+
+    /// Also report tree entries with mode 100664, which old versions of git
+    /// wrote for group-writable files, like `git fsck --strict`.
+    ///
+    /// Defaults to `false`.
+    pub fn strict(&mut self, strict: bool) -> &mut FsckOptions {
+        self.strict = strict;
+        self
+    }
+}
+
+impl Default for FsckOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FsckReport {
+    /// The number of objects checked.
+    pub fn objects(&self) -> usize {
+        self.objects
+    }
+
+    /// The problems found, objects first, then references.
+    pub fn problems(&self) -> &[FsckProblem] {
+        &self.problems
+    }
+
+    /// Whether no problems were found, dangling objects aside.
+    pub fn is_ok(&self) -> bool {
+        self.problems.iter().all(|p| !p.is_error())
+    }
+}
+
+impl FsckProblem {
+    /// Whether this is an error, which only dangling objects are not.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, FsckProblem::Dangling { .. })
+    }
+}
+
+impl fmt::Display for FsckReferrer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckReferrer::Object(id) => write!(f, "{}", id),
+            FsckReferrer::Reference(name) => write!(f, "{}", name),
+            FsckReferrer::Reflog { reference, index } => write!(f, "{}@{{{}}}", reference, index),
+            FsckReferrer::Index => write!(f, "index"),
+        }
+    }
+}
+
+/// Formats problems like `git fsck` does.
+impl fmt::Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckProblem::HashMismatch { id } => write!(f, "error: hash mismatch for {}", id),
+            FsckProblem::Corrupt { id, message } => {
+                write!(f, "error: unable to read {}: {}", id, message)
+            }
+            FsckProblem::Malformed { id, kind, message } => {
+                write!(f, "error in {} {}: {}", kind, id, message)
+            }
+            FsckProblem::BadFileMode { tree, name, mode } => write!(
+                f,
+                "error in tree {}: badFilemode: entry '{}' has bad mode {:o}",
+                tree, name, mode
+            ),
+            FsckProblem::BadEntryName { tree, name } => write!(
+                f,
+                "error in tree {}: badEntryName: entry '{}' has a bad name",
+                tree, name
+            ),
+            FsckProblem::DuplicateEntry { tree, name } => write!(
+                f,
+                "error in tree {}: duplicateEntries: contains duplicate entry '{}'",
+                tree, name
+            ),
+            FsckProblem::UnsortedTree { tree } => {
+                write!(
+                    f,
+                    "error in tree {}: treeNotSorted: not properly sorted",
+                    tree
+                )
+            }
+            FsckProblem::MissingObject { id, referrer } => {
+                write!(f, "missing object {} referred to by {}", id, referrer)
+            }
+            FsckProblem::WrongType {
+                id,
+                expected,
+                actual,
+                referrer,
+            } => write!(
+                f,
+                "error: {} {} referred to by {} is a {}",
+                expected, id, referrer, actual
+            ),
+            FsckProblem::BrokenReference { name, message } => {
+                write!(f, "error: {}: {}", name, message)
+            }
+            FsckProblem::BrokenReflog { reference, message } => {
+                write!(f, "error: reflog of {}: {}", reference, message)
+            }
+            FsckProblem::Dangling { id, kind } => write!(f, "dangling {} {}", kind, id),
+        }
+    }
+}
+
+/// An object referred to, with the type it is expected to have.
+struct Edge {
+    id: Oid,
+    kind: Option<ObjectType>,
+    referrer: FsckReferrer,
+}
+
+pub(crate) fn fsck(repo: &Repository, opts: &FsckOptions) -> Result<FsckReport, Error> {
+    let odb = repo.odb()?;
+    let mut ids = Vec::new();
+    odb.foreach(|id| {
+        ids.push(*id);
+        true
+    })?;
+    ids.sort();
+    ids.dedup();
+
+    let mut problems = Vec::new();
+    let mut kinds = HashMap::new();
+    let mut edges = Vec::new();
+    for &id in &ids {
+        let object = match odb.read(id) {
+            Ok(object) => object,
+            Err(ref e) if e.code() == ErrorCode::HashsumMismatch => {
+                problems.push(FsckProblem::HashMismatch { id });
+                continue;
+            }
+            Err(e) => {
+                let message = e.message().to_string();
+                problems.push(FsckProblem::Corrupt { id, message });
+                continue;
+            }
+        };
+        let kind = object.kind();
+        kinds.insert(id, kind);
+        if opts.verify_hashes && Oid::hash_object(kind, object.data())? != id {
+            problems.push(FsckProblem::HashMismatch { id });
+            continue;
+        }
+        let malformed = match kind {
+            ObjectType::Tree => {
+                check_tree(id, object.data(), opts.strict, &mut problems, &mut edges).err()
+            }
+            ObjectType::Commit => match repo.find_commit(id) {
+                Ok(commit) => {
+                    let referrer = FsckReferrer::Object(id);
+                    edges.push(Edge {
+                        id: commit.tree_id(),
+                        kind: Some(ObjectType::Tree),
+                        referrer: referrer.clone(),
+                    });
+                    edges.extend(commit.parent_ids().map(|parent| Edge {
+                        id: parent,
+                        kind: Some(ObjectType::Commit),
+                        referrer: referrer.clone(),
+                    }));
+                    None
+                }
+                Err(e) => Some(e.message().to_string()),
+            },
+            ObjectType::Tag => match repo.find_tag(id) {
+                Ok(tag) => {
+                    edges.push(Edge {
+                        id: tag.target_id(),
+                        kind: tag.target_type(),
+                        referrer: FsckReferrer::Object(id),
+                    });
+                    None
+                }
+                Err(e) => Some(e.message().to_string()),
+            },
+            _ => None,
+        };
+        if let Some(message) = malformed {
+            problems.push(FsckProblem::Malformed { id, kind, message });
+        }
+    }
+
+    // Everything after this point is about connectivity.
+    if !opts.connectivity {
+        return Ok(FsckReport {
+            objects: ids.len(),
+            problems,
+        });
+    }
+
+    let mut roots = Vec::new();
+    add_roots(repo, opts, &mut roots, &mut problems)?;
+    for worktree in maintenance::worktree_roots(repo)? {
+        add_worktree_roots(worktree, opts, &mut roots, &mut problems);
+    }
+
+    let mut children = HashMap::new();
+    let mut referenced = HashSet::new();
+    for edge in edges.iter().chain(&roots) {
+        check_edge(edge, &kinds, &mut problems);
+        if let FsckReferrer::Object(parent) = edge.referrer {
+            children
+                .entry(parent)
+                .or_insert_with(Vec::new)
+                .push(edge.id);
+            referenced.insert(edge.id);
+        }
+    }
+
+    if opts.dangling {
+        let mut reachable = HashSet::new();
+        let mut stack = roots.iter().map(|root| root.id).collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if reachable.insert(id) {
+                stack.extend(children.get(&id).into_iter().flatten());
+            }
+        }
+        for &id in &ids {
+            if reachable.contains(&id) || referenced.contains(&id) {
+                continue;
+            }
+            if let Some(&kind) = kinds.get(&id) {
+                problems.push(FsckProblem::Dangling { id, kind });
+            }
+        }
+    }
+
+    Ok(FsckReport {
+        objects: ids.len(),
+        problems,
+    })
+}
+
+fn check_edge(edge: &Edge, kinds: &HashMap<Oid, ObjectType>, problems: &mut Vec<FsckProblem>) {
+    match (kinds.get(&edge.id), edge.kind) {
+        (None, _) => problems.push(FsckProblem::MissingObject {
+            id: edge.id,
+            referrer: edge.referrer.clone(),
+        }),
+        (Some(&actual), Some(expected)) if actual != expected => {
+            problems.push(FsckProblem::WrongType {
+                id: edge.id,
+                expected,
+                actual,
+                referrer: edge.referrer.clone(),
+            })
+        }
+        _ => {}
+    }
+}
+
+/// Add the objects referred to by the references, pseudo-refs, reflogs,
+/// `HEAD` and index of `repo`.
+fn add_roots(
+    repo: &Repository,
+    opts: &FsckOptions,
+    roots: &mut Vec<Edge>,
+    problems: &mut Vec<FsckProblem>,
+) -> Result<(), Error> {
+    let mut logs = vec!["HEAD".to_string()];
+    match repo.find_reference("HEAD") {
+        // A symbolic `HEAD` is covered by its branch, unless it is unborn.
+        Ok(head) => roots.extend(head.target().map(|id| Edge {
+            id,
+            kind: Some(ObjectType::Commit),
+            referrer: FsckReferrer::Reference(logs[0].clone()),
+        })),
+        Err(e) => problems.push(FsckProblem::BrokenReference {
+            name: logs[0].clone(),
+            message: e.message().to_string(),
+        }),
+    }
+
+    // Like `git gc`, keep what the pseudo-refs name.
+    for (name, id) in maintenance::pseudo_ref_targets(repo.path())? {
+        roots.push(Edge {
+            id,
+            kind: None,
+            referrer: FsckReferrer::Reference(name.to_string()),
+        });
+    }
+
+    for name in repo.references()?.names() {
+        let name = match name {
+            Ok(name) => name,
+            Err(e) => {
+                problems.push(FsckProblem::BrokenReference {
+                    name: String::new(),
+                    message: e.message().to_string(),
+                });
+                continue;
+            }
+        };
+        logs.push(name.to_string());
+        let reference = match repo.find_reference(name).and_then(|r| r.resolve()) {
+            Ok(reference) => reference,
+            Err(e) => {
+                problems.push(FsckProblem::BrokenReference {
+                    name: name.to_string(),
+                    message: e.message().to_string(),
+                });
+                continue;
+            }
+        };
+        // Branches must point to commits, other references to anything.
+        let kind = if name.starts_with("refs/heads/") {
+            Some(ObjectType::Commit)
+        } else {
+            None
+        };
+        roots.extend(reference.target().map(|id| Edge {
+            id,
+            kind,
+            referrer: FsckReferrer::Reference(name.to_string()),
+        }));
+    }
+
+    if opts.reflogs {
+        for name in logs {
+            let reflog = match repo.reflog(&name) {
+                Ok(reflog) => reflog,
+                Err(e) => {
+                    problems.push(FsckProblem::BrokenReflog {
+                        reference: name,
+                        message: e.message().to_string(),
+                    });
+                    continue;
+                }
+            };
+            for (index, entry) in reflog.iter().enumerate() {
+                for id in [entry.id_old(), entry.id_new()].iter() {
+                    if id.is_zero() {
+                        continue;
+                    }
+                    roots.push(Edge {
+                        id: *id,
+                        kind: None,
+                        referrer: FsckReferrer::Reflog {
+                            reference: name.clone(),
+                            index,
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    if !repo.is_bare() {
+        for entry in repo.index()?.iter() {
+            // Skip submodules.
+            if entry.mode != 0o160000 {
+                roots.push(Edge {
+                    id: entry.id,
+                    kind: Some(ObjectType::Blob),
+                    referrer: FsckReferrer::Index,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the objects referred to by the `HEAD`, its reflog, the pseudo-refs
+/// and the index of a worktree, whose names are prefixed with
+/// `worktrees/<id>/`.
+fn add_worktree_roots(
+    worktree: WorktreeRoots,
+    opts: &FsckOptions,
+    roots: &mut Vec<Edge>,
+    problems: &mut Vec<FsckProblem>,
+) {
+    let prefix = format!("worktrees/{}/", worktree.id);
+    let head = format!("{}HEAD", prefix);
+    match worktree.head {
+        Ok(id) => roots.extend(id.map(|id| Edge {
+            id,
+            kind: Some(ObjectType::Commit),
+            referrer: FsckReferrer::Reference(head.clone()),
+        })),
+        Err(message) => problems.push(FsckProblem::BrokenReference {
+            name: head.clone(),
+            message,
+        }),
+    }
+    if opts.reflogs {
+        for (index, (old, new)) in worktree.reflog.into_iter().enumerate() {
+            for id in [old, new].iter().filter(|id| !id.is_zero()) {
+                roots.push(Edge {
+                    id: *id,
+                    kind: None,
+                    referrer: FsckReferrer::Reflog {
+                        reference: head.clone(),
+                        index,
+                    },
+                });
+            }
+        }
+    }
+    for (name, id) in worktree.pseudo_refs {
+        roots.push(Edge {
+            id,
+            kind: None,
+            referrer: FsckReferrer::Reference(format!("{}{}", prefix, name)),
+        });
+    }
+    roots.extend(worktree.index.into_iter().map(|id| Edge {
+        id,
+        kind: Some(ObjectType::Blob),
+        referrer: FsckReferrer::Index,
+    }));
+}
+
+/// Parse the tree `id` and check its entries, returning why it is
+/// malformed if it cannot be parsed.
+fn check_tree(
+    id: Oid,
+    mut data: &[u8],
+    strict: bool,
+    problems: &mut Vec<FsckProblem>,
+    edges: &mut Vec<Edge>,
+) -> Result<(), String> {
+    let mut names = HashSet::new();
+    let mut previous: Option<Vec<u8>> = None;
+    let mut unsorted = false;
+    while !data.is_empty() {
+        let space = data
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or("missing space after mode")?;
+        let mode = str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .ok_or("invalid mode")?;
+        data = &data[space + 1..];
+        let nul = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("missing null after name")?;
+        let name = &data[..nul];
+        data = &data[nul + 1..];
+        if data.len() < 20 {
+            return Err("truncated object id".to_string());
+        }
+        let entry = Oid::from_bytes(&data[..20]).map_err(|e| e.message().to_string())?;
+        data = &data[20..];
+
+        let display = String::from_utf8_lossy(name).into_owned();
+        let known = matches!(mode, 0o100644 | 0o100755 | 0o120000 | 0o040000 | 0o160000);
+        if !known && (strict || mode != 0o100664) {
+            problems.push(FsckProblem::BadFileMode {
+                tree: id,
+                name: display.clone(),
+                mode,
+            });
+        }
+        if matches!(name, b"" | b"." | b".." | b".git") || name.contains(&b'/') {
+            problems.push(FsckProblem::BadEntryName {
+                tree: id,
+                name: display.clone(),
+            });
+        }
+        if !names.insert(name.to_vec()) {
+            problems.push(FsckProblem::DuplicateEntry {
+                tree: id,
+                name: display,
+            });
+        }
+
+        // Trees sort as if their name ended with a `/`.
+        let is_tree = mode & 0o170000 == 0o040000;
+        let mut key = name.to_vec();
+        if is_tree {
+            key.push(b'/');
+        }
+        if previous.as_ref().is_some_and(|previous| *previous > key) {
+            unsorted = true;
+        }
+        previous = Some(key);
+
+        let kind = match mode & 0o170000 {
+            0o040000 => ObjectType::Tree,
+            // Submodule commits are not part of this repository.
+            0o160000 => continue,
+            _ => ObjectType::Blob,
+        };
+        edges.push(Edge {
+            id: entry,
+            kind: Some(kind),
+            referrer: FsckReferrer::Object(id),
+        });
+    }
+    if unsorted {
+        problems.push(FsckProblem::UnsortedTree { tree: id });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{FsckOptions, FsckProblem, FsckReferrer, ObjectType, Oid, Repository};
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let report = repo.fsck(None).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems());
+        assert!(report.problems().is_empty());
+        assert_eq!(report.objects(), 2);
+
+        let dangling = repo.blob(b"dangling").unwrap();
+        let report = repo.fsck(None).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(
+            report.problems(),
+            &[FsckProblem::Dangling {
+                id: dangling,
+                kind: ObjectType::Blob
+            }]
+        );
+        assert_eq!(
+            report.problems()[0].to_string(),
+            format!("dangling blob {}", dangling)
+        );
+    }
+
+    #[test]
+    fn pseudo_refs() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let orphan = repo
+            .commit(None, &sig, &sig, "orphan", &tree, &[&head])
+            .unwrap();
+        fs::write(repo.path().join("ORIG_HEAD"), format!("{}\n", orphan)).unwrap();
+        let missing = Oid::from_str("1234567890123456789012345678901234567890").unwrap();
+        fs::write(repo.path().join("MERGE_HEAD"), format!("{}\n", missing)).unwrap();
+
+        let report = repo.fsck(None).unwrap();
+        assert_eq!(
+            report.problems(),
+            &[FsckProblem::MissingObject {
+                id: missing,
+                referrer: FsckReferrer::Reference("MERGE_HEAD".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_worktree() {
+        let (_td, repo) = crate::test::repo_init();
+        let td = tempfile::TempDir::new().unwrap();
+        let path = td.path().join("wt");
+        let worktree = repo.worktree("wt", &path, None).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = head.tree().unwrap();
+        let detached = repo
+            .commit(None, &sig, &sig, "detached", &tree, &[&head])
+            .unwrap();
+        let wt = Repository::open_from_worktree(&worktree).unwrap();
+        wt.set_head_detached(detached).unwrap();
+        drop(wt);
+        worktree.lock(None).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+
+        // The worktree still counts, its `HEAD` under its id.
+        let report = repo.fsck(None).unwrap();
+        assert!(report.problems().is_empty(), "{:?}", report.problems());
+        fs::write(repo.path().join("worktrees/wt/HEAD"), "nonsense\n").unwrap();
+        let mut opts = FsckOptions::new();
+        opts.reflogs(false);
+        let report = repo.fsck(Some(&opts)).unwrap();
+        assert!(report.problems().iter().any(|p| matches!(
+            p,
+            FsckProblem::BrokenReference { name, .. } if name == "worktrees/wt/HEAD"
+        )));
+        assert!(report.problems().contains(&FsckProblem::Dangling {
+            id: detached,
+            kind: ObjectType::Commit
+        }));
+    }
+
+    #[test]
+    fn malformed() {
+        let (_td, repo) = crate::test::repo_init();
+        let odb = repo.odb().unwrap();
+        let blob = repo.blob(b"blob").unwrap();
+        let missing = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+
+        let mut data = Vec::new();
+        for (mode, name, id) in &[
+            ("100644", "b", blob),
+            ("100644", "a", blob),
+            ("100664", "c", blob),
+            ("100644", "c", missing),
+            ("40000", ".git", blob),
+        ] {
+            data.extend(format!("{} {}\0", mode, name).bytes());
+            data.extend(id.as_bytes());
+        }
+        let tree = odb.write(ObjectType::Tree, &data).unwrap();
+        let report = repo.fsck(None).unwrap();
+        assert!(!report.is_ok());
+        let problems = report.problems();
+        let expected = [
+            FsckProblem::DuplicateEntry {
+                tree,
+                name: "c".to_string(),
+            },
+            FsckProblem::BadEntryName {
+                tree,
+                name: ".git".to_string(),
+            },
+            FsckProblem::UnsortedTree { tree },
+            FsckProblem::MissingObject {
+                id: missing,
+                referrer: FsckReferrer::Object(tree),
+            },
+            FsckProblem::WrongType {
+                id: blob,
+                expected: ObjectType::Tree,
+                actual: ObjectType::Blob,
+                referrer: FsckReferrer::Object(tree),
+            },
+            FsckProblem::Dangling {
+                id: tree,
+                kind: ObjectType::Tree,
+            },
+        ];
+        for problem in &expected {
+            assert!(problems.contains(problem), "{:?}", problems);
+        }
+        assert_eq!(problems.len(), expected.len(), "{:?}", problems);
+
+        // Group-writable files are only reported in strict mode.
+        let mut opts = FsckOptions::new();
+        opts.strict(true);
+        let report = repo.fsck(Some(&opts)).unwrap();
+        let bad_mode = FsckProblem::BadFileMode {
+            tree,
+            name: "c".to_string(),
+            mode: 0o100664,
+        };
+        assert!(report.problems().contains(&bad_mode));
+        assert_eq!(report.problems().len(), expected.len() + 1);
+
+        let commit = odb
+            .write(ObjectType::Commit, b"tree nonsense\n\nmessage\n")
+            .unwrap();
+        let report = repo.fsck(None).unwrap();
+        assert!(report
+            .problems()
+            .iter()
+            .any(|p| matches!(p, FsckProblem::Malformed { id, .. } if *id == commit)));
+
+        // Swap the contents of a loose object for those of another one.
+        let a = repo.blob(b"a").unwrap();
+        let b = repo.blob(b"b").unwrap();
+        let loose = |id: Oid| {
+            let hex = id.to_string();
+            repo.path().join("objects").join(&hex[..2]).join(&hex[2..])
+        };
+        fs::remove_file(loose(b)).unwrap();
+        fs::copy(loose(a), loose(b)).unwrap();
+        let report = repo.fsck(None).unwrap();
+        assert!(report
+            .problems()
+            .contains(&FsckProblem::HashMismatch { id: b }));
+    }
+
+    #[test]
+    fn references() {
+        let (_td, repo) = crate::test::repo_init();
+        let missing = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        fs::write(
+            repo.path().join("refs/heads/broken"),
+            format!("{}\n", missing),
+        )
+        .unwrap();
+        repo.reference_symbolic("refs/heads/link", "refs/heads/nowhere", false, "")
+            .unwrap();
+        let mut opts = FsckOptions::new();
+        opts.dangling(false);
+        let report = repo.fsck(Some(&opts)).unwrap();
+        let problems = report.problems();
+        assert!(problems.contains(&FsckProblem::MissingObject {
+            id: missing,
+            referrer: FsckReferrer::Reference("refs/heads/broken".to_string()),
+        }));
+        assert!(problems.iter().any(
+            |p| matches!(p, FsckProblem::BrokenReference { name, .. } if name == "refs/heads/link")
+        ));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+    }
+}
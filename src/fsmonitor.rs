@@ -0,0 +1,968 @@
+//! File system monitors, which tell `Repository::statuses` which files in the
+//! working directory may have changed since the last time it asked.
+//!
+//! Like git, the last answer of the monitor, its token, is kept in the index
+//! in the `FSMN` extension, along with a bitmap of the index entries which
+//! were found modified and so still need checking.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::index_file::{self, ewah_bits, ewah_bytes, IndexFile, Reader};
+use crate::util;
+use crate::{Config, Error, ErrorClass, ErrorCode, IndexEntry, Repository, Status, Statuses};
+
+const FSMN: &[u8; 4] = b"FSMN";
+
+/// A source of the paths in a working directory that changed over time.
+///
+/// Implementations are provided which run git's `core.fsmonitor` hook
+/// ([`HookFsMonitor`]) and, on Linux, which watch the working directory with
+/// inotify ([`InotifyFsMonitor`]). A monitor is used for a status through
+/// [`StatusOptions::fsmonitor`].
+///
+/// [`StatusOptions::fsmonitor`]: crate::StatusOptions::fsmonitor
+pub trait FsMonitor {
+    /// Report the paths in the working directory `workdir` that changed since
+    /// this monitor returned `token`, along with a new token to ask with next
+    /// time.
+    ///
+    /// There is no token the first time, in which case anything may have
+    /// changed.
+    fn changes(&mut self, workdir: &Path, token: Option<&str>) -> Result<FsMonitorChanges, Error>;
+}
+
+/// The answer of a [`FsMonitor`]: what changed since the token it was asked
+/// with, and the token to ask with next time.
+#[derive(Clone, Debug)]
+pub struct FsMonitorChanges {
+    token: String,
+    paths: Option<Vec<PathBuf>>,
+}
+
+/// A [`FsMonitor`] running a hook the way git runs its `core.fsmonitor` hook,
+/// such as the `fsmonitor-watchman` sample hook.
+#[derive(Clone, Debug)]
+pub struct HookFsMonitor {
+    command: String,
+    version: Option<u32>,
+}
+
+impl FsMonitorChanges {
+    /// Changes to the given paths, relative to the working directory, where a
+    /// directory stands for everything in it.
+    pub fn new(token: &str, paths: Vec<PathBuf>) -> FsMonitorChanges {
+        FsMonitorChanges {
+            token: token.to_string(),
+            paths: Some(paths),
+        }
+    }
+
+    /// Changes to anything in the working directory, as when the monitor does
+    /// not know the token or lost track of what changed.
+    pub fn everything(token: &str) -> FsMonitorChanges {
+        FsMonitorChanges {
+            token: token.to_string(),
+            paths: None,
+        }
+    }
+
+    /// The token to ask the monitor with next time.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// The paths that changed, or `None` if anything may have changed.
+    pub fn paths(&self) -> Option<&[PathBuf]> {
+        self.paths.as_deref()
+    }
+}
+
+impl HookFsMonitor {
+    /// Creates a monitor running `command` with the shell, in the working
+    /// directory.
+    ///
+    /// The command is first run with version 2 of the hook protocol, then
+    /// with version 1 if that fails.
+    pub fn new(command: &str) -> HookFsMonitor {
+        HookFsMonitor {
+            command: command.to_string(),
+            version: None,
+        }
+    }
+
+    /// Creates a monitor from the `core.fsmonitor` and
+    /// `core.fsmonitorHookVersion` configuration values.
+    ///
+    /// Returns `None` if no hook is configured, including when
+    /// `core.fsmonitor` is a boolean, which asks for git's builtin monitor.
+    pub fn from_config(config: &Config) -> Result<Option<HookFsMonitor>, Error> {
+        if config.get_bool("core.fsmonitor").is_ok() {
+            return Ok(None);
+        }
+        let command = match config.get_string("core.fsmonitor") {
+            Ok(command) if !command.is_empty() => command,
+            Ok(_) => return Ok(None),
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut monitor = HookFsMonitor::new(&command);
+        match config.get_i32("core.fsmonitorHookVersion") {
+            Ok(version @ (1 | 2)) => {
+                monitor.version(version as u32);
+            }
+            Ok(_) => {}
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Some(monitor))
+    }
+
+    /// Only use the given version of the hook protocol, 1 or 2.
+    pub fn version(&mut self, version: u32) -> &mut HookFsMonitor {
+        self.version = Some(version);
+        self
+    }
+
+    /// The output of the hook, or `None` if it failed, in which case git
+    /// checks every file.
+    fn run(&self, workdir: &Path, version: u32, token: &str) -> Option<Vec<u8>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg(&self.command)
+            .arg(version.to_string())
+            .arg(token)
+            .current_dir(workdir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .ok()?;
+        if output.status.success() {
+            Some(output.stdout)
+        } else {
+            None
+        }
+    }
+}
+
+impl FsMonitor for HookFsMonitor {
+    fn changes(&mut self, workdir: &Path, token: Option<&str>) -> Result<FsMonitorChanges, Error> {
+        // Like git, start from the current time, which is what version 1
+        // hooks take as a token.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos())
+            .to_string();
+        let token = match token {
+            Some(token) => token,
+            None => return Ok(FsMonitorChanges::everything(&now)),
+        };
+        if self.version != Some(1) {
+            if let Some(output) = self.run(workdir, 2, token) {
+                let mut fields = output.split(|&b| b == 0);
+                let token = String::from_utf8_lossy(fields.next().unwrap_or(&[]));
+                // A hook which printed nothing gave no token to ask again with.
+                if token.is_empty() {
+                    return Ok(FsMonitorChanges::everything(&now));
+                }
+                return Ok(hook_changes(&token, fields));
+            }
+        }
+        if self.version != Some(2) && token.parse::<u64>().is_ok() {
+            if let Some(output) = self.run(workdir, 1, token) {
+                return Ok(hook_changes(&now, output.split(|&b| b == 0)));
+            }
+        }
+        Ok(FsMonitorChanges::everything(&now))
+    }
+}
+
+/// The changes a hook reports as NUL-separated `paths`, where `/` stands for
+/// everything.
+fn hook_changes<'a>(token: &str, paths: impl Iterator<Item = &'a [u8]>) -> FsMonitorChanges {
+    let mut changed = Vec::new();
+    for path in paths.filter(|p| !p.is_empty()) {
+        if path == b"/" {
+            return FsMonitorChanges::everything(token);
+        }
+        changed.push(util::bytes2path(path).to_path_buf());
+    }
+    FsMonitorChanges::new(token, changed)
+}
+
+#[cfg(target_os = "linux")]
+pub use self::inotify::InotifyFsMonitor;
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::ptr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use libc::{c_int, c_void};
+
+    use super::{FsMonitor, FsMonitorChanges};
+    use crate::{Error, ErrorClass, ErrorCode};
+
+    const MASK: u32 = libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_ONLYDIR
+        | libc::IN_DONT_FOLLOW
+        | libc::IN_EXCL_UNLINK;
+
+    /// How many generations of changes are remembered; older tokens report
+    /// that anything may have changed.
+    pub(super) const GENERATIONS: u64 = 64;
+
+    /// How many changes are remembered, across generations.
+    const MAX_CHANGES: usize = 64 * 1024;
+
+    /// A [`FsMonitor`] watching every directory of a working directory, other
+    /// than `.git`, with inotify.
+    ///
+    /// The monitor only knows about the changes made while it exists, so its
+    /// tokens are only good for the same monitor; with any other token it
+    /// reports that anything may have changed.
+    pub struct InotifyFsMonitor {
+        fd: c_int,
+        root: PathBuf,
+        /// Identifies this monitor in its tokens.
+        id: String,
+        /// The directory, relative to `root`, of each watch.
+        watches: HashMap<c_int, PathBuf>,
+        /// The paths that changed in each generation after `lost`.
+        changed: BTreeMap<u64, HashSet<PathBuf>>,
+        /// The number of paths in `changed`.
+        len: usize,
+        /// The generation of the token handed out last.
+        generation: u64,
+        /// Whether anything was recorded since the token was handed out.
+        active: bool,
+        /// The last generation in which changes may have been missed.
+        lost: u64,
+    }
+
+    impl InotifyFsMonitor {
+        /// Starts watching the working directory at `workdir`.
+        pub fn new(workdir: &Path) -> Result<InotifyFsMonitor, Error> {
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(os_error(
+                    "failed to start inotify",
+                    io::Error::last_os_error(),
+                ));
+            }
+            let mut monitor = InotifyFsMonitor {
+                fd,
+                root: workdir.to_path_buf(),
+                id: format!(
+                    "inotify:{}:{}",
+                    process::id(),
+                    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+                ),
+                watches: HashMap::new(),
+                changed: BTreeMap::new(),
+                len: 0,
+                generation: 1,
+                active: false,
+                lost: 0,
+            };
+            monitor.watch(Path::new(""))?;
+            Ok(monitor)
+        }
+
+        /// Watch the directory `dir`, relative to the root, and the ones in
+        /// it.
+        fn watch(&mut self, dir: &Path) -> Result<(), Error> {
+            let path = self.root.join(dir);
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), MASK) };
+            if wd < 0 {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    // It went away or was replaced before we got to it.
+                    Some(libc::ENOENT) | Some(libc::ENOTDIR) => Ok(()),
+                    _ => Err(os_error(
+                        &format!("failed to watch '{}'", path.display()),
+                        e,
+                    )),
+                };
+            }
+            self.watches.insert(wd, dir.to_path_buf());
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(_) => return Ok(()),
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+                if is_dir && !(dir.as_os_str().is_empty() && entry.file_name() == ".git") {
+                    self.watch(&dir.join(entry.file_name()))?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Stop watching `dir` and the directories in it.
+        fn unwatch(&mut self, dir: &Path) {
+            let fd = self.fd;
+            self.watches.retain(|&wd, path| {
+                let inside = path.starts_with(dir);
+                if inside {
+                    unsafe { libc::inotify_rm_watch(fd, wd) };
+                }
+                !inside
+            });
+        }
+
+        /// Record the pending events.
+        fn read_events(&mut self) -> Result<(), Error> {
+            let header = std::mem::size_of::<libc::inotify_event>();
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let len =
+                    unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+                if len < 0 {
+                    let e = io::Error::last_os_error();
+                    match e.kind() {
+                        io::ErrorKind::WouldBlock => return Ok(()),
+                        io::ErrorKind::Interrupted => continue,
+                        _ => return Err(os_error("failed to read inotify events", e)),
+                    }
+                }
+                let mut pos = 0;
+                while pos + header <= len as usize {
+                    let event: libc::inotify_event =
+                        unsafe { ptr::read_unaligned(buf[pos..].as_ptr() as *const _) };
+                    let name = &buf[pos + header..pos + header + event.len as usize];
+                    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                    let name = PathBuf::from(std::ffi::OsStr::from_bytes(name));
+                    self.event(event.wd, event.mask, name);
+                    pos += header + event.len as usize;
+                }
+            }
+        }
+
+        fn event(&mut self, wd: c_int, mask: u32, name: PathBuf) {
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                self.lose(self.generation);
+                self.active = true;
+                return;
+            }
+            if mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&wd);
+                return;
+            }
+            let path = match self.watches.get(&wd) {
+                Some(dir) => dir.join(name),
+                None => return,
+            };
+            if mask & libc::IN_ISDIR != 0 {
+                if mask & libc::IN_MOVED_FROM != 0 {
+                    self.unwatch(&path);
+                } else if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+                    && self.watch(&path).is_err()
+                {
+                    self.lose(self.generation);
+                }
+            }
+            self.active = true;
+            if self
+                .changed
+                .entry(self.generation)
+                .or_default()
+                .insert(path)
+            {
+                self.len += 1;
+            }
+            // Forget the oldest generations rather than grow without bound.
+            while self.len > MAX_CHANGES {
+                let oldest = *self.changed.keys().next().unwrap();
+                self.lose(oldest);
+            }
+        }
+
+        /// Forget the changes up to `generation`, whose tokens can no longer
+        /// be answered.
+        fn lose(&mut self, generation: u64) {
+            self.lost = self.lost.max(generation);
+            while let Some(entry) = self.changed.first_entry() {
+                if *entry.key() > self.lost {
+                    break;
+                }
+                self.len -= entry.remove().len();
+            }
+        }
+    }
+
+    impl FsMonitor for InotifyFsMonitor {
+        fn changes(
+            &mut self,
+            workdir: &Path,
+            token: Option<&str>,
+        ) -> Result<FsMonitorChanges, Error> {
+            if workdir != self.root {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Os,
+                    format!("the monitor watches '{}'", self.root.display()),
+                ));
+            }
+            self.read_events()?;
+            let since = token
+                .and_then(|token| token.strip_prefix(&self.id[..]))
+                .and_then(|rest| rest.strip_prefix(':'))
+                .and_then(|generation| generation.parse::<u64>().ok())
+                .filter(|&generation| generation > self.lost && generation <= self.generation);
+            let changes = since.map(|since| {
+                let mut paths = HashSet::new();
+                for changed in self.changed.range(since..).map(|(_, paths)| paths) {
+                    paths.extend(changed.iter().cloned());
+                }
+                paths.into_iter().collect()
+            });
+            // Hand out the same token again while nothing happens, so that
+            // the index need not be updated.
+            if self.active {
+                self.generation += 1;
+                self.active = false;
+                if self.generation > GENERATIONS {
+                    self.lose(self.generation - GENERATIONS);
+                }
+            }
+            let token = format!("{}:{}", self.id, self.generation);
+            Ok(match changes {
+                Some(paths) => FsMonitorChanges::new(&token, paths),
+                None => FsMonitorChanges::everything(&token),
+            })
+        }
+    }
+
+    impl Drop for InotifyFsMonitor {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+
+    fn os_error(msg: &str, e: io::Error) -> Error {
+        Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Os,
+            format!("{}: {}", msg, e),
+        )
+    }
+}
+
+/// A status in progress with the help of a monitor.
+pub(crate) struct Query {
+    index_path: PathBuf,
+    checksum: [u8; 20],
+    entries: Vec<IndexEntry>,
+    /// The `FSMN` extension of the index, if it has one.
+    extension: Option<Vec<u8>>,
+    token: String,
+    /// The paths of the entries to check, or `None` for all of them.
+    paths: Option<Vec<CString>>,
+}
+
+impl Query {
+    /// Ask `monitor` what changed since the token in the index of `repo`.
+    ///
+    /// Returns `None` if the index has changes that were not written, as the
+    /// `FSMN` extension only describes the index on disk.
+    pub(crate) fn new(
+        repo: &Repository,
+        monitor: &mut dyn FsMonitor,
+    ) -> Result<Option<Query>, Error> {
+        let workdir = match repo.workdir() {
+            Some(workdir) => workdir,
+            None => return Ok(None),
+        };
+        let index_path = repo.path().join("index");
+        let file = match IndexFile::read(&index_path) {
+            Ok(file) => file,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut index = repo.index()?;
+        index.read(false)?;
+        if !file.matches(&index) {
+            return Ok(None);
+        }
+        let state = match file.extension(FSMN) {
+            Some(data) => State::parse(data, file.entries.len())?,
+            None => None,
+        };
+        let changes = monitor.changes(workdir, state.as_ref().map(|s| &s.token[..]))?;
+        let paths = match (state, changes.paths()) {
+            (Some(state), Some(changed)) => paths_to_check(&file.entries, &state.dirty, changed)?,
+            _ => None,
+        };
+        Ok(Some(Query {
+            index_path,
+            checksum: file.checksum,
+            extension: file.extension(FSMN).map(<[u8]>::to_vec),
+            entries: file.entries,
+            token: changes.token,
+            paths,
+        }))
+    }
+
+    /// The paths of the index entries to check, or `None` for all of them.
+    pub(crate) fn paths(&self) -> Option<&[CString]> {
+        self.paths.as_deref()
+    }
+
+    /// Record the new token in the index, along with which entries `statuses`
+    /// found modified, unless the index already records them.
+    ///
+    /// The index is left alone if it changed in the meantime or someone else
+    /// is writing it, leaving the next status to check every file.
+    pub(crate) fn finish(self, statuses: &Statuses<'_>) -> Result<(), Error> {
+        let workdir = Status::WT_NEW
+            | Status::WT_MODIFIED
+            | Status::WT_DELETED
+            | Status::WT_TYPECHANGE
+            | Status::WT_RENAMED
+            | Status::CONFLICTED;
+        let modified: HashSet<Vec<u8>> = statuses
+            .iter()
+            .filter(|s| s.status().intersects(workdir))
+            .map(|s| s.path_bytes().to_vec())
+            .collect();
+        let dirty: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| always_check(e) || modified.contains(&e.path))
+            .map(|(i, _)| i)
+            .collect();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(self.token.as_bytes());
+        data.push(0);
+        let bitmap = ewah_bytes(&dirty, self.entries.len());
+        data.extend_from_slice(&(bitmap.len() as u32).to_be_bytes());
+        data.extend_from_slice(&bitmap);
+        if self.extension.as_deref() == Some(&data[..]) {
+            return Ok(());
+        }
+        match index_file::set_extension(&self.index_path, &self.checksum, FSMN, &data) {
+            Ok(_) => Ok(()),
+            Err(e) if e.code() == ErrorCode::Locked => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The contents of the `FSMN` extension.
+struct State {
+    token: String,
+    /// The positions of the entries that need checking.
+    dirty: Vec<usize>,
+}
+
+impl State {
+    /// Parse the extension of an index with `entries` entries, or return
+    /// `None` for a version we do not know.
+    fn parse(data: &[u8], entries: usize) -> Result<Option<State>, Error> {
+        let mut reader = Reader::new(data);
+        let token = match reader.u32()? {
+            // Version 1 has the time of the last update in nanoseconds.
+            1 => reader.u64()?.to_string(),
+            2 => String::from_utf8(reader.cstr()?.to_vec())
+                .map_err(|_| corrupt("the token is not valid utf-8"))?,
+            _ => return Ok(None),
+        };
+        let _size = reader.u32()?;
+        let dirty = ewah_bits(&mut reader, entries)?;
+        Ok(Some(State { token, dirty }))
+    }
+}
+
+/// Whether the entry is checked whatever the monitor reports: a conflict, an
+/// intent-to-add entry, or a submodule, whose changes are in its own working
+/// directory.
+fn always_check(entry: &IndexEntry) -> bool {
+    entry.stage() != 0 || entry.is_intent_to_add() || entry.mode & 0o170000 == 0o160000
+}
+
+/// The paths of the `entries` to check: the `dirty` ones and the ones at or
+/// below the `changed` paths. Returns `None` if everything changed.
+fn paths_to_check(
+    entries: &[IndexEntry],
+    dirty: &[usize],
+    changed: &[PathBuf],
+) -> Result<Option<Vec<CString>>, Error> {
+    let mut check: HashSet<usize> = dirty
+        .iter()
+        .copied()
+        .filter(|&i| i < entries.len())
+        .collect();
+    check.extend((0..entries.len()).filter(|&i| always_check(&entries[i])));
+    for path in changed {
+        let path = util::path_to_repo_path(path)?;
+        let path = path.as_bytes();
+        let path = path.strip_suffix(b"/").unwrap_or(path);
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let start = entries.partition_point(|e| &e.path[..] < path);
+        for (i, entry) in entries.iter().enumerate().skip(start) {
+            if !entry.path.starts_with(path) {
+                break;
+            }
+            if entry.path.len() == path.len() || entry.path[path.len()] == b'/' {
+                check.insert(i);
+            }
+        }
+    }
+    let mut check: Vec<usize> = check.into_iter().collect();
+    check.sort_unstable();
+    let mut paths: Vec<CString> = Vec::with_capacity(check.len());
+    for i in check {
+        let path = &entries[i].path;
+        if paths.last().is_none_or(|last| last.as_bytes() != &path[..]) {
+            paths.push(CString::new(&path[..])?);
+        }
+    }
+    Ok(Some(paths))
+}
+
+fn corrupt(msg: &str) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Index,
+        format!("corrupt fsmonitor extension: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::rc::Rc;
+
+    use super::{FsMonitor, FsMonitorChanges, HookFsMonitor};
+    use crate::{Error, IndexAddOption, Repository, Status, StatusOptions};
+
+    /// A monitor answering what the test tells it to.
+    #[derive(Default)]
+    struct Script {
+        answer: Option<Vec<PathBuf>>,
+        tokens: Vec<Option<String>>,
+    }
+
+    struct Scripted(Rc<RefCell<Script>>);
+
+    impl FsMonitor for Scripted {
+        fn changes(&mut self, _: &Path, token: Option<&str>) -> Result<FsMonitorChanges, Error> {
+            let mut script = self.0.borrow_mut();
+            script.tokens.push(token.map(str::to_string));
+            let next = script.tokens.len().to_string();
+            Ok(match script.answer.take() {
+                Some(paths) => FsMonitorChanges::new(&next, paths),
+                None => FsMonitorChanges::everything(&next),
+            })
+        }
+    }
+
+    fn statuses(repo: &Repository, opts: &mut StatusOptions) -> Vec<(String, Status)> {
+        repo.statuses(Some(opts))
+            .unwrap()
+            .iter()
+            .map(|s| (s.path().unwrap().to_string(), s.status()))
+            .collect()
+    }
+
+    fn entry(path: &str, status: Status) -> (String, Status) {
+        (path.to_string(), status)
+    }
+
+    #[test]
+    fn statuses_with_monitor() {
+        let (td, repo) = crate::test::repo_init();
+        for name in ["a", "b", "dir/c"] {
+            let path = td.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, name).unwrap();
+        }
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "files", &tree, &[&head])
+            .unwrap();
+
+        let script = Rc::new(RefCell::new(Script::default()));
+        let mut opts = StatusOptions::new();
+        opts.fsmonitor(Scripted(script.clone()));
+        let answer = |paths: &[&str]| {
+            script.borrow_mut().answer = Some(paths.iter().map(PathBuf::from).collect());
+        };
+
+        // Without a token, every file is checked.
+        fs::write(td.path().join("a"), "changed").unwrap();
+        assert_eq!(
+            statuses(&repo, &mut opts),
+            [entry("a", Status::WT_MODIFIED)]
+        );
+        assert_eq!(script.borrow().tokens, [None]);
+
+        // Then only the files the monitor reports and the ones found
+        // modified before.
+        fs::write(td.path().join("b"), "changed").unwrap();
+        answer(&[]);
+        assert_eq!(
+            statuses(&repo, &mut opts),
+            [entry("a", Status::WT_MODIFIED)]
+        );
+        assert_eq!(script.borrow().tokens[1].as_deref(), Some("1"));
+        answer(&["b"]);
+        assert_eq!(
+            statuses(&repo, &mut opts),
+            [
+                entry("a", Status::WT_MODIFIED),
+                entry("b", Status::WT_MODIFIED)
+            ]
+        );
+
+        // A directory stands for everything in it.
+        fs::write(td.path().join("dir/c"), "changed").unwrap();
+        answer(&["dir/"]);
+        let all = statuses(&repo, &mut opts);
+        assert_eq!(
+            all,
+            [
+                entry("a", Status::WT_MODIFIED),
+                entry("b", Status::WT_MODIFIED),
+                entry("dir/c", Status::WT_MODIFIED),
+            ]
+        );
+        assert_eq!(statuses(&repo, &mut StatusOptions::new()), all);
+
+        // Reverted files are no longer checked once found unmodified.
+        fs::write(td.path().join("b"), "b").unwrap();
+        answer(&["b"]);
+        assert_eq!(statuses(&repo, &mut opts).len(), 2);
+        answer(&[]);
+        assert_eq!(statuses(&repo, &mut opts).len(), 2);
+
+        // Writing the index drops the extension, after which every file is
+        // checked again, and staged changes are reported along with the ones
+        // in the working directory.
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        index.write().unwrap();
+        fs::write(td.path().join("a"), "changed again").unwrap();
+        let both = [
+            entry("a", Status::INDEX_MODIFIED | Status::WT_MODIFIED),
+            entry("dir/c", Status::WT_MODIFIED),
+        ];
+        assert_eq!(statuses(&repo, &mut opts), both);
+        assert_eq!(script.borrow().tokens.last().unwrap(), &None);
+        answer(&[]);
+        assert_eq!(statuses(&repo, &mut opts), both);
+        opts.show(crate::StatusShow::Workdir);
+        answer(&[]);
+        assert_eq!(
+            statuses(&repo, &mut opts),
+            [
+                entry("a", Status::WT_MODIFIED),
+                entry("dir/c", Status::WT_MODIFIED),
+            ]
+        );
+
+        // libgit2 checks the index checksum, and git reads the extension.
+        crate::Index::open(&repo.path().join("index")).unwrap();
+        let git = Command::new("git")
+            .args(["ls-files"])
+            .current_dir(td.path())
+            .output();
+        if let Ok(output) = git {
+            assert!(output.status.success());
+            assert_eq!(output.stdout, b"a\nb\ndir/c\n");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unchanged_index_is_kept() {
+        use std::os::unix::fs::MetadataExt;
+
+        struct Same;
+
+        impl FsMonitor for Same {
+            fn changes(
+                &mut self,
+                _: &Path,
+                token: Option<&str>,
+            ) -> Result<FsMonitorChanges, Error> {
+                Ok(match token {
+                    Some(_) => FsMonitorChanges::new("same", Vec::new()),
+                    None => FsMonitorChanges::everything("same"),
+                })
+            }
+        }
+
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join("a"), "a").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        index.write().unwrap();
+        let mut opts = StatusOptions::new();
+        opts.fsmonitor(Same);
+        // Writing the index keeps its modification time, but not its ctime.
+        let changed = || {
+            let meta = fs::metadata(repo.path().join("index")).unwrap();
+            (meta.ino(), meta.ctime(), meta.ctime_nsec())
+        };
+
+        statuses(&repo, &mut opts);
+        let written = changed();
+        statuses(&repo, &mut opts);
+        statuses(&repo, &mut opts);
+        assert_eq!(changed(), written);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hook() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (td, repo) = crate::test::repo_init();
+        let hook = td.path().join(".git/query-fsmonitor");
+        fs::write(
+            &hook,
+            "#!/bin/sh\n\
+             echo \"$@\" >>.git/hook-calls\n\
+             case \"$1 $2\" in\n\
+             '2 all') printf 'next\\0/\\0' ;;\n\
+             '2 12') exit 1 ;;\n\
+             '2 empty') ;;\n\
+             2*) printf 'next\\0a\\0dir/\\0' ;;\n\
+             1*) printf 'a\\0' ;;\n\
+             esac\n",
+        )
+        .unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = repo.config().unwrap();
+        config.set_bool("core.fsmonitor", true).unwrap();
+        assert!(HookFsMonitor::from_config(&config).unwrap().is_none());
+        config
+            .set_str("core.fsmonitor", ".git/query-fsmonitor")
+            .unwrap();
+        let mut monitor = HookFsMonitor::from_config(&config).unwrap().unwrap();
+
+        let workdir = repo.workdir().unwrap();
+        let first = monitor.changes(workdir, None).unwrap();
+        assert!(first.paths().is_none());
+        assert!(first.token().parse::<u64>().is_ok());
+
+        let changes = monitor.changes(workdir, Some("token")).unwrap();
+        assert_eq!(changes.token(), "next");
+        let paths = [PathBuf::from("a"), PathBuf::from("dir/")];
+        assert_eq!(changes.paths().unwrap(), paths);
+        assert!(monitor
+            .changes(workdir, Some("all"))
+            .unwrap()
+            .paths()
+            .is_none());
+
+        // A hook giving no token stands for everything.
+        let empty = monitor.changes(workdir, Some("empty")).unwrap();
+        assert!(empty.paths().is_none());
+        assert!(empty.token().parse::<u64>().is_ok());
+
+        // If version 2 fails, a time is given to version 1.
+        let v1 = monitor.changes(workdir, Some("12")).unwrap();
+        assert_eq!(v1.paths().unwrap(), [PathBuf::from("a")]);
+        assert!(v1.token().parse::<u64>().is_ok());
+        let calls = fs::read_to_string(td.path().join(".git/hook-calls")).unwrap();
+        assert_eq!(calls, "2 token\n2 all\n2 empty\n2 12\n1 12\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inotify() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = td.path();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("dir")).unwrap();
+        let mut monitor = super::InotifyFsMonitor::new(root).unwrap();
+        let paths = |changes: &FsMonitorChanges| {
+            let mut paths = changes.paths().unwrap().to_vec();
+            paths.sort();
+            paths
+        };
+
+        let first = monitor.changes(root, None).unwrap();
+        assert!(first.paths().is_none());
+
+        fs::write(root.join("a"), "a").unwrap();
+        fs::write(root.join("dir/b"), "b").unwrap();
+        fs::write(root.join(".git/index"), "").unwrap();
+        fs::create_dir_all(root.join("new/inner")).unwrap();
+        let second = monitor.changes(root, Some(first.token())).unwrap();
+        assert_eq!(
+            paths(&second),
+            [
+                PathBuf::from("a"),
+                PathBuf::from("dir/b"),
+                PathBuf::from("new")
+            ]
+        );
+
+        // New directories are watched too.
+        fs::write(root.join("new/inner/c"), "c").unwrap();
+        let third = monitor.changes(root, Some(second.token())).unwrap();
+        assert_eq!(paths(&third), [PathBuf::from("new/inner/c")]);
+        let since_first = monitor.changes(root, Some(first.token())).unwrap();
+        assert_eq!(paths(&since_first).len(), 4);
+        // Nothing happened in the meantime, so the token stays the same.
+        assert_eq!(since_first.token(), third.token());
+
+        // Only so many generations are remembered.
+        for i in 0..super::inotify::GENERATIONS {
+            fs::write(root.join("a"), i.to_string()).unwrap();
+            let changes = monitor.changes(root, Some(third.token())).unwrap();
+            assert!(changes.paths().is_some());
+        }
+        fs::write(root.join("a"), "again").unwrap();
+        let lost = monitor.changes(root, Some(third.token())).unwrap();
+        assert!(lost.paths().is_none());
+
+        assert!(monitor
+            .changes(root, Some("other"))
+            .unwrap()
+            .paths()
+            .is_none());
+        assert!(monitor.changes(&root.join("dir"), None).is_err());
+    }
+}
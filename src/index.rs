@@ -1,5 +1,7 @@
 use std::ffi::{CStr, CString};
+use std::fs;
 use std::marker;
+use std::mem;
 use std::ops::Range;
 use std::path::Path;
 use std::ptr;
@@ -7,9 +9,14 @@ use std::slice;
 
 use libc::{c_char, c_int, c_uint, c_void, size_t};
 
+use crate::call;
+use crate::index_file;
 use crate::util::{self, path_to_repo_path, Binding};
 use crate::IntoCString;
-use crate::{panic, raw, Error, IndexAddOption, IndexTime, Oid, Repository, Tree};
+use crate::{
+    panic, raw, Error, ErrorClass, ErrorCode, IndexAddOption, IndexEntryExtendedFlag,
+    IndexEntryFlag, IndexTime, Oid, Repository, Tree,
+};
 
 /// A structure to represent a git [index][1]
 ///
@@ -85,6 +92,88 @@ pub struct IndexEntry {
     pub path: Vec<u8>,
 }
 
+impl IndexEntry {
+    /// The flags stored in `flags`, without the path length and stage.
+    pub fn entry_flags(&self) -> IndexEntryFlag {
+        IndexEntryFlag::from_bits_truncate(self.flags)
+    }
+
+    /// The flags stored in `flags_extended`.
+    pub fn extended_flags(&self) -> IndexEntryExtendedFlag {
+        IndexEntryExtendedFlag::from_bits_truncate(self.flags_extended)
+    }
+
+    /// The stage of this entry: 0 for a normal entry, 1 to 3 for the
+    /// ancestor, ours and theirs sides of a conflict.
+    pub fn stage(&self) -> i32 {
+        ((self.flags & raw::GIT_INDEX_ENTRY_STAGEMASK) >> raw::GIT_INDEX_ENTRY_STAGESHIFT) as i32
+    }
+
+    /// Whether the file is assumed to be unchanged in the working directory,
+    /// like `git update-index --assume-unchanged`.
+    pub fn is_assume_unchanged(&self) -> bool {
+        self.entry_flags().is_valid()
+    }
+
+    /// Set or clear the assume-unchanged bit.
+    pub fn set_assume_unchanged(&mut self, assume_unchanged: bool) {
+        set_bit(
+            &mut self.flags,
+            IndexEntryFlag::VALID.bits(),
+            assume_unchanged,
+        );
+    }
+
+    /// Whether the file is left out of the working directory, like
+    /// `git update-index --skip-worktree` or a sparse checkout.
+    pub fn is_skip_worktree(&self) -> bool {
+        self.extended_flags().is_skip_worktree()
+    }
+
+    /// Set or clear the skip-worktree bit.
+    pub fn set_skip_worktree(&mut self, skip_worktree: bool) {
+        set_bit(
+            &mut self.flags_extended,
+            IndexEntryExtendedFlag::SKIP_WORKTREE.bits(),
+            skip_worktree,
+        );
+    }
+
+    /// Whether this entry only records that the file will be added later,
+    /// like `git add --intent-to-add`.
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags().is_intent_to_add()
+    }
+
+    /// Set or clear the intent-to-add bit.
+    pub fn set_intent_to_add(&mut self, intent_to_add: bool) {
+        set_bit(
+            &mut self.flags_extended,
+            IndexEntryExtendedFlag::INTENT_TO_ADD.bits(),
+            intent_to_add,
+        );
+    }
+}
+
+fn set_bit(flags: &mut u16, bit: u16, on: bool) {
+    if on {
+        *flags |= bit;
+    } else {
+        *flags &= !bit;
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
 impl Index {
     /// Creates a new in-memory index.
     ///
@@ -107,13 +196,22 @@ impl Index {
     ///
     /// If you need an index attached to a repository, use the `index()` method
     /// on `Repository`.
+    ///
+    /// A split index, which libgit2 cannot read, is merged with its shared
+    /// index in memory. Writing the index keeps it split, linked to the same
+    /// shared index.
     pub fn open(index_path: &Path) -> Result<Index, Error> {
         crate::init();
         let mut raw = ptr::null_mut();
         // Normal file path OK (does not need Windows conversion).
-        let index_path = index_path.into_c_string()?;
+        let c_path = index_path.into_c_string()?;
         unsafe {
-            try_call!(raw::git_index_open(&mut raw, index_path));
+            if let Err(e) = call::c_try(raw::git_index_open(&mut raw, c_path.as_ptr())) {
+                if !index_file::is_split_index_error(&e) {
+                    return Err(e);
+                }
+                return index_file::open_split(index_path);
+            }
             Ok(Binding::from_raw(raw))
         }
     }
@@ -265,6 +363,143 @@ impl Index {
         }
     }
 
+    /// Record that a file will be added later, like
+    /// `git add --intent-to-add`.
+    ///
+    /// The entry has the id of the empty blob and is left out of the trees
+    /// written from this index, while status reports the file as new in the
+    /// working directory. Adding the file with `add_path` replaces it.
+    ///
+    /// Nothing is done if the path is already in the index. This method will
+    /// fail in bare index instances.
+    pub fn add_intent_to_add(&mut self, path: &Path) -> Result<(), Error> {
+        if self.get_path(path, 0).is_some() {
+            return Ok(());
+        }
+        let repo = self
+            .owner()
+            .ok_or_else(|| Error::from_str("cannot add to an index without a repository"))?;
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| Error::from_str("cannot add to an index without a working directory"))?;
+        let meta = fs::symlink_metadata(workdir.join(path)).map_err(|e| {
+            Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Index,
+                format!("could not stat '{}': {}", path.display(), e),
+            )
+        })?;
+        let mode = if meta.file_type().is_symlink() {
+            0o120000
+        } else if !meta.is_file() {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Index,
+                format!("'{}' is not a file", path.display()),
+            ));
+        } else if is_executable(&meta) {
+            0o100755
+        } else {
+            0o100644
+        };
+        let mut entry = IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: repo.blob(&[])?,
+            flags: 0,
+            flags_extended: 0,
+            path: path_to_repo_path(path)?.into_bytes(),
+        };
+        entry.set_intent_to_add(true);
+        self.add(&entry)
+    }
+
+    /// Set or clear the skip-worktree bit of the entry at `path`, like
+    /// `git update-index --[no-]skip-worktree`.
+    ///
+    /// The working directory file of such an entry is neither checked nor
+    /// reported as missing by status.
+    pub fn set_skip_worktree(&mut self, path: &Path, skip_worktree: bool) -> Result<(), Error> {
+        self.update_entry(path, |entry| entry.set_skip_worktree(skip_worktree))
+    }
+
+    /// Set or clear the assume-unchanged bit of the entry at `path`, like
+    /// `git update-index --[no-]assume-unchanged`.
+    ///
+    /// Changes to the working directory file of such an entry are not
+    /// reported by status and diff.
+    pub fn set_assume_unchanged(
+        &mut self,
+        path: &Path,
+        assume_unchanged: bool,
+    ) -> Result<(), Error> {
+        self.update_entry(path, |entry| entry.set_assume_unchanged(assume_unchanged))
+    }
+
+    fn update_entry<F>(&mut self, path: &Path, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut IndexEntry),
+    {
+        let mut entry = self.get_path(path, 0).ok_or_else(|| {
+            Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Index,
+                format!("'{}' is not in the index", path.display()),
+            )
+        })?;
+        f(&mut entry);
+        self.add(&entry)
+    }
+
+    /// The repository this index belongs to, if any.
+    fn owner(&self) -> Option<mem::ManuallyDrop<Repository>> {
+        unsafe {
+            let repo = raw::git_index_owner(self.raw);
+            if repo.is_null() {
+                None
+            } else {
+                Some(Repository::borrow_raw(repo))
+            }
+        }
+    }
+
+    /// Whether any entry has one of the `flags` or `flags_extended` bits
+    /// set. This looks at the flags of the entries without copying them.
+    pub(crate) fn has_flagged_entries(&self, flags: u16, flags_extended: u16) -> bool {
+        (0..self.len()).any(|n| unsafe {
+            let entry = raw::git_index_get_byindex(self.raw, n as size_t);
+            !entry.is_null()
+                && ((*entry).flags & flags != 0 || (*entry).flags_extended & flags_extended != 0)
+        })
+    }
+
+    /// Whether any entry is an intent-to-add entry, which trees leave out.
+    fn has_intent_to_add(&self) -> bool {
+        self.has_flagged_entries(0, IndexEntryExtendedFlag::INTENT_TO_ADD.bits())
+    }
+
+    /// A copy of this index with only the entries `keep` accepts, or `None`
+    /// if it accepts them all.
+    pub(crate) fn filtered<F>(&self, mut keep: F) -> Result<Option<Index>, Error>
+    where
+        F: FnMut(&IndexEntry) -> bool,
+    {
+        if self.iter().all(|entry| keep(&entry)) {
+            return Ok(None);
+        }
+        let mut index = Index::new()?;
+        for entry in self.iter().filter(|entry| keep(entry)) {
+            index.add(&entry)?;
+        }
+        Ok(Some(index))
+    }
+
     /// Add or update index entries matching files in the working directory.
     ///
     /// This method will fail in bare index instances.
@@ -431,8 +666,22 @@ impl Index {
     /// Purely in-memory index data will be untouched. Be aware: if there are
     /// changes on disk, unwritten in-memory changes are discarded.
     pub fn read(&mut self, force: bool) -> Result<(), Error> {
+        if let Some(path) = self.path().and_then(index_file::split_view_target) {
+            // A merged split index has nothing on disk for libgit2 to read.
+            if force {
+                let path = path.to_path_buf();
+                index_file::load(self, &path)?;
+            }
+            return Ok(());
+        }
         unsafe {
-            try_call!(raw::git_index_read(self.raw, force));
+            if let Err(e) = call::c_try(raw::git_index_read(self.raw, force as c_int)) {
+                let path = match self.path() {
+                    Some(path) if index_file::is_split_index_error(&e) => path.to_path_buf(),
+                    _ => return Err(e),
+                };
+                index_file::load(self, &path)?;
+            }
         }
         Ok(())
     }
@@ -542,6 +791,12 @@ impl Index {
         let callback = ptr
             .as_ref()
             .map(|_| index_matched_path_cb as extern "C" fn(_, _, _) -> _);
+        // libgit2 sees the missing files of skip-worktree entries as deleted,
+        // but their entries must be kept.
+        let skipped = self
+            .iter()
+            .filter(|entry| entry.is_skip_worktree())
+            .collect::<Vec<_>>();
         unsafe {
             try_call!(raw::git_index_update_all(
                 self.raw,
@@ -550,12 +805,25 @@ impl Index {
                 ptr.map(|p| p as *mut _).unwrap_or(ptr::null_mut()) as *mut c_void
             ));
         }
+        for entry in skipped {
+            let stage =
+                (entry.flags & raw::GIT_INDEX_ENTRY_STAGEMASK) >> raw::GIT_INDEX_ENTRY_STAGESHIFT;
+            if self
+                .get_path(util::bytes2path(&entry.path), stage as i32)
+                .is_none()
+            {
+                self.add(&entry)?;
+            }
+        }
         Ok(())
     }
 
     /// Write an existing index object from memory back to disk using an atomic
     /// file lock.
     pub fn write(&mut self) -> Result<(), Error> {
+        if let Some(path) = self.path().and_then(index_file::split_view_target) {
+            return index_file::write(self, path);
+        }
         unsafe {
             try_call!(raw::git_index_write(self.raw));
         }
@@ -574,6 +842,13 @@ impl Index {
     ///
     /// The index must not contain any file in conflict.
     pub fn write_tree(&mut self) -> Result<Oid, Error> {
+        if self.has_intent_to_add() {
+            if let Some(mut index) = self.filtered(|entry| !entry.is_intent_to_add())? {
+                if let Some(repo) = self.owner() {
+                    return index.write_tree_to(&repo);
+                }
+            }
+        }
         let mut raw = raw::git_oid {
             id: [0; raw::GIT_OID_RAWSZ],
         };
@@ -588,6 +863,11 @@ impl Index {
     /// This is the same as `write_tree` except that the destination repository
     /// can be chosen.
     pub fn write_tree_to(&mut self, repo: &Repository) -> Result<Oid, Error> {
+        if self.has_intent_to_add() {
+            if let Some(mut index) = self.filtered(|entry| !entry.is_intent_to_add())? {
+                return index.write_tree_to(repo);
+            }
+        }
         let mut raw = raw::git_oid {
             id: [0; raw::GIT_OID_RAWSZ],
         };
@@ -762,7 +1042,7 @@ mod tests {
     use std::path::Path;
     use tempfile::TempDir;
 
-    use crate::{ErrorCode, Index, IndexEntry, IndexTime, Oid, Repository, ResetType};
+    use crate::{ErrorCode, Index, IndexEntry, IndexTime, Oid, Repository, ResetType, Status};
 
     #[test]
     fn smoke() {
@@ -910,6 +1190,108 @@ mod tests {
         assert_eq!(b.content(), content);
     }
 
+    #[test]
+    fn entry_flags() {
+        let mut e = entry();
+        assert_eq!(e.stage(), 0);
+        e.set_skip_worktree(true);
+        e.set_assume_unchanged(true);
+        assert!(e.is_skip_worktree() && e.is_assume_unchanged() && !e.is_intent_to_add());
+
+        let mut index = Index::new().unwrap();
+        e.path = b"foo".to_vec();
+        index.add(&e).unwrap();
+        let mut e = index.get(0).unwrap();
+        assert!(e.is_skip_worktree() && e.is_assume_unchanged());
+        e.set_skip_worktree(false);
+        e.set_intent_to_add(true);
+        assert!(!e.is_skip_worktree() && e.is_intent_to_add());
+        assert!(e.entry_flags().is_valid());
+        assert!(e.extended_flags().is_intent_to_add());
+    }
+
+    #[test]
+    fn skip_worktree_and_assume_unchanged() {
+        let (td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        for name in ["skipped", "assumed"] {
+            fs::write(td.path().join(name), "foo\n").unwrap();
+            index.add_path(Path::new(name)).unwrap();
+        }
+        let id = index.write_tree().unwrap();
+        let tree = repo.find_tree(id).unwrap();
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "files", &tree, &[&parent])
+            .unwrap();
+
+        index.set_skip_worktree(Path::new("skipped"), true).unwrap();
+        index
+            .set_assume_unchanged(Path::new("assumed"), true)
+            .unwrap();
+        assert!(index.set_skip_worktree(Path::new("missing"), true).is_err());
+        index.write().unwrap();
+        let e = index.get_path(Path::new("skipped"), 0).unwrap();
+        assert!(e.is_skip_worktree() && !e.is_assume_unchanged());
+        let e = index.get_path(Path::new("assumed"), 0).unwrap();
+        assert!(e.is_assume_unchanged() && !e.is_skip_worktree());
+
+        fs::remove_file(td.path().join("skipped")).unwrap();
+        fs::remove_file(td.path().join("assumed")).unwrap();
+        assert_eq!(repo.statuses(None).unwrap().len(), 0);
+        let status = repo.status_file(Path::new("skipped")).unwrap();
+        assert_eq!(status, Status::CURRENT);
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        assert_eq!(diff.deltas().len(), 0);
+
+        index
+            .set_assume_unchanged(Path::new("assumed"), false)
+            .unwrap();
+        index.write().unwrap();
+        let status = repo.status_file(Path::new("assumed")).unwrap();
+        assert_eq!(status, Status::WT_DELETED);
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        assert_eq!(diff.deltas().len(), 1);
+    }
+
+    #[test]
+    fn intent_to_add() {
+        let (td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_tree().unwrap();
+        fs::write(td.path().join("new"), "foo\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_intent_to_add(Path::new("new")).unwrap();
+        assert!(index.add_intent_to_add(Path::new("missing")).is_err());
+        index.write().unwrap();
+        let e = index.get_path(Path::new("new"), 0).unwrap();
+        assert!(e.is_intent_to_add());
+        assert_eq!(e.mode, 0o100644);
+
+        let statuses = repo.statuses(None).unwrap();
+        assert_eq!(statuses.len(), 1);
+        let entry = statuses.get(0).unwrap();
+        assert_eq!(entry.path(), Some("new"));
+        assert_eq!(entry.status(), Status::WT_NEW);
+        assert!(entry.head_to_index().is_none());
+        assert!(entry.index_to_workdir().is_some());
+        let status = repo.status_file(Path::new("new")).unwrap();
+        assert_eq!(status, Status::WT_NEW);
+
+        assert_eq!(index.write_tree().unwrap(), head.id());
+        assert_eq!(index.write_tree_to(&repo).unwrap(), head.id());
+        let diff = repo.diff_tree_to_index(Some(&head), None, None).unwrap();
+        assert_eq!(diff.deltas().len(), 0);
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        assert_eq!(diff.deltas().len(), 1);
+
+        index.add_path(Path::new("new")).unwrap();
+        assert!(!index
+            .get_path(Path::new("new"), 0)
+            .unwrap()
+            .is_intent_to_add());
+        assert_ne!(index.write_tree().unwrap(), head.id());
+    }
+
     fn entry() -> IndexEntry {
         IndexEntry {
             ctime: IndexTime::new(0, 0),
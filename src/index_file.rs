@@ -0,0 +1,926 @@
+//! Reading and writing of git's on-disk index format, for the parts of it
+//! libgit2 does not handle itself: the split index, the untracked cache and
+//! the file system monitor state.
+//!
+//! libgit2 reads and writes index versions 2 to 4, but refuses an index
+//! carrying the mandatory `link` extension of a split index and drops the
+//! optional extensions it does not know, such as `UNTR` and `FSMN`, when it
+//! writes.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    Error, ErrorClass, ErrorCode, Index, IndexEntry, IndexEntryExtendedFlag, IndexTime, Oid,
+};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const HASH_SIZE: usize = 20;
+const LINK: &[u8; 4] = b"link";
+const EOIE: &[u8; 4] = b"EOIE";
+
+/// The parsed contents of an index file.
+pub(crate) struct IndexFile {
+    /// The on-disk version, 2 to 4.
+    pub(crate) version: u32,
+    /// The entries, in the order they are stored.
+    pub(crate) entries: Vec<IndexEntry>,
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// Where the extensions start in the file.
+    extensions_start: usize,
+    /// The trailing checksum of the file.
+    pub(crate) checksum: [u8; HASH_SIZE],
+}
+
+impl IndexFile {
+    /// Read and parse the index file at `path`.
+    pub(crate) fn read(path: &Path) -> Result<IndexFile, Error> {
+        let data = fs::read(path).map_err(|e| io_error(path, e))?;
+        IndexFile::parse(&data)
+    }
+
+    /// Parse the contents of an index file.
+    ///
+    /// The trailing checksum is not verified.
+    pub(crate) fn parse(data: &[u8]) -> Result<IndexFile, Error> {
+        if data.len() < 12 + HASH_SIZE {
+            return Err(corrupt("index is too short"));
+        }
+        let mut reader = Reader::new(&data[..data.len() - HASH_SIZE]);
+        if reader.bytes(4)? != SIGNATURE {
+            return Err(corrupt("bad index signature"));
+        }
+        let version = reader.u32()?;
+        if !(2..=4).contains(&version) {
+            return Err(corrupt(&format!("unsupported index version {}", version)));
+        }
+        let count = reader.u32()? as usize;
+        let mut entries = Vec::with_capacity(count);
+        let mut previous = Vec::new();
+        for _ in 0..count {
+            let entry = read_entry(&mut reader, version, &previous)?;
+            previous.clear();
+            previous.extend_from_slice(&entry.path);
+            entries.push(entry);
+        }
+        let extensions_start = reader.pos;
+        let mut extensions = Vec::new();
+        while !reader.is_empty() {
+            let mut signature = [0; 4];
+            signature.copy_from_slice(reader.bytes(4)?);
+            let size = reader.u32()? as usize;
+            extensions.push((signature, reader.bytes(size)?.to_vec()));
+        }
+        let mut checksum = [0; HASH_SIZE];
+        checksum.copy_from_slice(&data[data.len() - HASH_SIZE..]);
+        Ok(IndexFile {
+            version,
+            entries,
+            extensions,
+            extensions_start,
+            checksum,
+        })
+    }
+
+    /// Read the index file at `path`, merging in its shared index if it is
+    /// split, as git sees it.
+    pub(crate) fn read_merged(path: &Path) -> Result<IndexFile, Error> {
+        let mut file = IndexFile::read(path)?;
+        file.merge_shared_index(path)?;
+        Ok(file)
+    }
+
+    /// Whether `index` has the entries of this file, as it does if it was
+    /// last read from or written to it.
+    pub(crate) fn matches(&self, index: &Index) -> bool {
+        index.len() == self.entries.len()
+            && index
+                .iter()
+                .zip(&self.entries)
+                .all(|(a, b)| a.path == b.path && stage(&a) == stage(b))
+    }
+
+    /// The data of the extension with the given signature, if present.
+    pub(crate) fn extension(&self, signature: &[u8; 4]) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(s, _)| s == signature)
+            .map(|(_, data)| &data[..])
+    }
+
+    /// Replace the entries of a split index with the ones of the full index,
+    /// merging in the shared index stored next to `path`.
+    ///
+    /// The extensions stay those of the split index, which git writes for
+    /// the full index.
+    fn merge_shared_index(&mut self, path: &Path) -> Result<(), Error> {
+        let link = match self.extension(LINK) {
+            Some(link) => link.to_vec(),
+            None => return Ok(()),
+        };
+        let mut reader = Reader::new(&link);
+        let id = Oid::from_bytes(reader.bytes(HASH_SIZE)?)?;
+        if id.is_zero() {
+            return Ok(());
+        }
+        let shared = path.with_file_name(format!("sharedindex.{}", id));
+        let mut entries: Vec<Option<IndexEntry>> = IndexFile::read(&shared)?
+            .entries
+            .into_iter()
+            .map(Some)
+            .collect();
+        let (deleted, replaced) = if reader.is_empty() {
+            (Vec::new(), Vec::new())
+        } else {
+            (
+                ewah_bits(&mut reader, entries.len())?,
+                ewah_bits(&mut reader, entries.len())?,
+            )
+        };
+        let split = std::mem::take(&mut self.entries);
+
+        let mut split = split.into_iter();
+        for pos in replaced {
+            let base = entries
+                .get_mut(pos)
+                .and_then(Option::as_mut)
+                .ok_or_else(|| corrupt("split index replaces a missing entry"))?;
+            let mut entry = split
+                .next()
+                .ok_or_else(|| corrupt("split index lacks a replacement entry"))?;
+            if !entry.path.is_empty() {
+                return Err(corrupt("split index replacement entry has a name"));
+            }
+            entry.path = std::mem::take(&mut base.path);
+            *base = entry;
+        }
+        for pos in deleted {
+            match entries.get_mut(pos) {
+                Some(entry) => *entry = None,
+                None => return Err(corrupt("split index deletes a missing entry")),
+            }
+        }
+        // The remaining entries are added ones, which replace the entry with
+        // the same path and stage, and all conflict stages if merged.
+        let added: Vec<IndexEntry> = split.collect();
+        if added.iter().any(|e| e.path.is_empty()) {
+            return Err(corrupt("split index entry has no name"));
+        }
+        let merged: HashSet<Vec<u8>> = added
+            .iter()
+            .filter(|e| stage(e) == 0)
+            .map(|e| e.path.clone())
+            .collect();
+        let mut entries: Vec<IndexEntry> = entries
+            .into_iter()
+            .flatten()
+            .filter(|e| !merged.contains(&e.path))
+            .chain(added)
+            .collect();
+        entries.sort_by(|a, b| (&a.path, stage(a)).cmp(&(&b.path, stage(b))));
+        entries.dedup_by(|later, earlier| {
+            let same = later.path == earlier.path && stage(later) == stage(earlier);
+            if same {
+                std::mem::swap(later, earlier);
+            }
+            same
+        });
+        self.entries = entries;
+        self.extensions.retain(|(s, _)| s != LINK);
+        Ok(())
+    }
+}
+
+/// Whether `err` is libgit2 refusing to read a split index.
+pub(crate) fn is_split_index_error(err: &Error) -> bool {
+    err.class() == ErrorClass::Index && err.message().contains("'link'")
+}
+
+/// The name of the path an index merged from a split index is opened at,
+/// inside the index file, so that libgit2 finds no file there to reload the
+/// index from and cannot write one.
+const SPLIT_VIEW: &str = "split";
+
+/// Open the split index at `path` as an index merged with its shared index in
+/// memory, leaving the index file alone.
+///
+/// `Index::read` and `Index::write` go back to the index file at `path` for
+/// the index returned.
+pub(crate) fn open_split(path: &Path) -> Result<Index, Error> {
+    let mut index = Index::open(&path.join(SPLIT_VIEW))?;
+    load(&mut index, path)?;
+    Ok(index)
+}
+
+/// The index file an index opened by `open_split` was read from, given the
+/// path of the index.
+pub(crate) fn split_view_target(path: &Path) -> Option<&Path> {
+    if path.file_name()? != SPLIT_VIEW {
+        return None;
+    }
+    path.parent().filter(|parent| parent.is_file())
+}
+
+/// Replace the entries of `index` with the ones of the index file at `path`,
+/// merged with its shared index if it is split.
+pub(crate) fn load(index: &mut Index, path: &Path) -> Result<(), Error> {
+    let file = IndexFile::read_merged(path)?;
+    index.clear()?;
+    index.set_version(file.version)?;
+    for entry in &file.entries {
+        index.add(entry)?;
+    }
+    Ok(())
+}
+
+/// Write `index` to the index file at `path`.
+///
+/// If the index file is split, it stays split and linked to the same shared
+/// index, which is left alone: the entries of the shared index that `index`
+/// no longer has as they are are marked deleted, and the others of `index`
+/// are stored in the split index. Otherwise a full index is written.
+pub(crate) fn write(index: &Index, path: &Path) -> Result<(), Error> {
+    let lock = Lock::acquire(path)?;
+    let tmp = append(path, ".new");
+    let _ = fs::remove_file(&tmp);
+    let result = (|| {
+        let mut out = Index::open(&tmp)?;
+        out.set_version(index.version())?;
+        let link = match shared_index(path)? {
+            Some((id, shared)) => {
+                let mut entries: HashMap<(Vec<u8>, u16), IndexEntry> = index
+                    .iter()
+                    .map(|e| ((e.path.clone(), stage(&e)), e))
+                    .collect();
+                let mut deleted = Vec::new();
+                for (pos, entry) in shared.entries.iter().enumerate() {
+                    let key = (entry.path.clone(), stage(entry));
+                    match entries.get(&key) {
+                        Some(e) if same_entry(e, entry) => {
+                            entries.remove(&key);
+                        }
+                        _ => deleted.push(pos),
+                    }
+                }
+                for entry in entries.values() {
+                    out.add(entry)?;
+                }
+                let size = shared.entries.len();
+                let mut link = id.as_bytes().to_vec();
+                link.extend(ewah_bytes(&deleted, size));
+                link.extend(ewah_bytes(&[], size));
+                Some(link)
+            }
+            None => {
+                for entry in index.iter() {
+                    out.add(&entry)?;
+                }
+                None
+            }
+        };
+        out.write()?;
+        if let Some(link) = link {
+            let checksum = IndexFile::read(&tmp)?.checksum;
+            set_extension(&tmp, &checksum, LINK, &link)?;
+        }
+        fs::rename(&tmp, path).map_err(|e| io_error(path, e))
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    drop(lock);
+    result
+}
+
+/// The id and contents of the shared index the index file at `path` is
+/// split from, if it is split.
+fn shared_index(path: &Path) -> Result<Option<(Oid, IndexFile)>, Error> {
+    let file = IndexFile::read(path)?;
+    let id = match file.extension(LINK) {
+        Some(link) => Oid::from_bytes(Reader::new(link).bytes(HASH_SIZE)?)?,
+        None => return Ok(None),
+    };
+    if id.is_zero() {
+        return Ok(None);
+    }
+    let shared = IndexFile::read(&path.with_file_name(format!("sharedindex.{}", id)))?;
+    Ok(Some((id, shared)))
+}
+
+/// Whether two entries have the same path, stage and contents, as git would
+/// store them.
+fn same_entry(a: &IndexEntry, b: &IndexEntry) -> bool {
+    // The low bits of the flags hold the length of the path, and only some
+    // of the extended flags are stored.
+    let extended =
+        (IndexEntryExtendedFlag::INTENT_TO_ADD | IndexEntryExtendedFlag::SKIP_WORKTREE).bits();
+    a.path == b.path
+        && a.flags & !0x0fff == b.flags & !0x0fff
+        && a.flags_extended & extended == b.flags_extended & extended
+        && (a.ctime, a.mtime, a.dev, a.ino, a.mode) == (b.ctime, b.mtime, b.dev, b.ino, b.mode)
+        && (a.uid, a.gid, a.file_size, a.id) == (b.uid, b.gid, b.file_size, b.id)
+}
+
+/// Store `data` as the extension with the given signature in the index at
+/// `path`, replacing any previous one, provided the index still has the
+/// given `checksum`.
+///
+/// The entries are left alone, and so is the modification time of the file,
+/// which git and libgit2 compare file modification times to in order to tell
+/// whether an entry's stat data can be trusted. Returns whether the index was
+/// updated.
+pub(crate) fn set_extension(
+    path: &Path,
+    checksum: &[u8; HASH_SIZE],
+    signature: &[u8; 4],
+    data: &[u8],
+) -> Result<bool, Error> {
+    let lock = Lock::acquire(path)?;
+    let old = fs::read(path).map_err(|e| io_error(path, e))?;
+    let file = IndexFile::parse(&old)?;
+    if file.checksum != *checksum {
+        return Ok(false);
+    }
+    let mtime = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_err(|e| io_error(path, e))?;
+
+    // The end of index entries extension has a hash of the other extension
+    // headers, so it goes too.
+    let kept = file
+        .extensions
+        .iter()
+        .filter(|(s, _)| s != signature && s != EOIE)
+        .map(|(s, d)| (s, &d[..]));
+
+    let result = (|| {
+        let out = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&lock.0)?;
+        let mut out = io::BufWriter::new(out);
+        let mut hash = Sha1::new();
+        {
+            let mut put = |bytes: &[u8]| {
+                hash.update(bytes);
+                out.write_all(bytes)
+            };
+            put(&old[..file.extensions_start])?;
+            for (s, d) in kept.chain(Some((signature, data))) {
+                put(s)?;
+                put(&(d.len() as u32).to_be_bytes())?;
+                put(d)?;
+            }
+        }
+        out.write_all(&hash.finish())?;
+        let out = out.into_inner().map_err(|e| e.into_error())?;
+        out.set_modified(mtime)?;
+        fs::rename(&lock.0, path)
+    })();
+    match result {
+        Ok(()) => {
+            std::mem::forget(lock);
+            Ok(true)
+        }
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+/// The `index.lock` file git and libgit2 take while writing the index.
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: &Path) -> Result<Lock, Error> {
+        let lock = append(path, ".lock");
+        match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(_) => Ok(Lock(lock)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(Error::new(
+                ErrorCode::Locked,
+                ErrorClass::Index,
+                format!("the index is locked; '{}' exists", lock.display()),
+            )),
+            Err(e) => Err(io_error(&lock, e)),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn read_entry(reader: &mut Reader<'_>, version: u32, previous: &[u8]) -> Result<IndexEntry, Error> {
+    let start = reader.pos;
+    let ctime = IndexTime::new(reader.u32()? as i32, reader.u32()?);
+    let mtime = IndexTime::new(reader.u32()? as i32, reader.u32()?);
+    let dev = reader.u32()?;
+    let ino = reader.u32()?;
+    let mode = reader.u32()?;
+    let uid = reader.u32()?;
+    let gid = reader.u32()?;
+    let file_size = reader.u32()?;
+    let id = Oid::from_bytes(reader.bytes(HASH_SIZE)?)?;
+    let flags = reader.u16()?;
+    let flags_extended = if flags & 0x4000 != 0 {
+        if version < 3 {
+            return Err(corrupt("extended flags in a version 2 index"));
+        }
+        reader.u16()?
+    } else {
+        0
+    };
+    let path = if version == 4 {
+        let strip = reader.varint()? as usize;
+        if strip > previous.len() {
+            return Err(corrupt("bad path compression"));
+        }
+        let mut path = previous[..previous.len() - strip].to_vec();
+        path.extend_from_slice(reader.cstr()?);
+        path
+    } else {
+        let path = reader.cstr()?.to_vec();
+        // Entries are padded with NULs to a multiple of eight bytes, the
+        // first of which terminates the path.
+        let len = reader.pos - start;
+        reader.bytes((8 - len % 8) % 8)?;
+        path
+    };
+    Ok(IndexEntry {
+        ctime,
+        mtime,
+        dev,
+        ino,
+        mode,
+        uid,
+        gid,
+        file_size,
+        id,
+        flags,
+        flags_extended,
+        path,
+    })
+}
+
+fn stage(entry: &IndexEntry) -> u16 {
+    (entry.flags >> 12) & 0x3
+}
+
+/// A cursor over big-endian binary data.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| corrupt("unexpected end of data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Error> {
+        let hi = self.u32()? as u64;
+        let lo = self.u32()? as u64;
+        Ok(hi << 32 | lo)
+    }
+
+    /// A NUL-terminated string, without the NUL.
+    pub(crate) fn cstr(&mut self) -> Result<&'a [u8], Error> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| corrupt("unterminated string"))?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+
+    /// git's variable-length integer, in which each continuation byte also
+    /// adds one so that every value has a single encoding.
+    pub(crate) fn varint(&mut self) -> Result<u64, Error> {
+        let mut byte = self.bytes(1)?[0];
+        let mut value = (byte & 0x7f) as u64;
+        while byte & 0x80 != 0 {
+            byte = self.bytes(1)?[0];
+            value = value
+                .checked_add(1)
+                .and_then(|v| v.checked_mul(128))
+                .ok_or_else(|| corrupt("varint overflow"))?
+                | (byte & 0x7f) as u64;
+        }
+        Ok(value)
+    }
+}
+
+/// Write the bits at `positions`, in increasing order, of a bitmap of `size`
+/// bits as an EWAH compressed bitmap.
+///
+/// The words are all stored literally, behind a single marker word.
+pub(crate) fn ewah_bytes(positions: &[usize], size: usize) -> Vec<u8> {
+    let mut words = vec![0u64; size.div_ceil(64)];
+    for &pos in positions {
+        words[pos / 64] |= 1 << (pos % 64);
+    }
+    let marker = (words.len() as u64) << 33;
+    let mut out = Vec::with_capacity(12 + 8 * (words.len() + 1));
+    out.extend_from_slice(&(size as u32).to_be_bytes());
+    out.extend_from_slice(&(words.len() as u32 + 1).to_be_bytes());
+    for word in Some(marker).into_iter().chain(words) {
+        out.extend_from_slice(&word.to_be_bytes());
+    }
+    // The position of the last marker word.
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out
+}
+
+/// Read an EWAH compressed bitmap of at most `max_bits` bits and return the
+/// positions of its set bits.
+pub(crate) fn ewah_bits(reader: &mut Reader<'_>, max_bits: usize) -> Result<Vec<usize>, Error> {
+    let bit_size = reader.u32()? as usize;
+    if bit_size > max_bits {
+        return Err(corrupt("bitmap is too large"));
+    }
+    let words = reader.u32()? as usize;
+    if words > (reader.data.len() - reader.pos.min(reader.data.len())) / 8 {
+        return Err(corrupt("truncated bitmap"));
+    }
+    let mut buffer = Vec::with_capacity(words);
+    for _ in 0..words {
+        buffer.push(reader.u64()?);
+    }
+    let _rlw = reader.u32()?;
+
+    // Runs and literal words may pad the bitmap to a whole number of words.
+    let limit = bit_size.div_ceil(64) * 64;
+    let mut bits = Vec::new();
+    let mut pos = 0;
+    let mut i = 0;
+    while i < buffer.len() {
+        // A marker word: one bit to repeat, how many words of it, and how
+        // many literal words follow.
+        let marker = buffer[i];
+        let run_bit = marker & 1 != 0;
+        let run_len = ((marker >> 1) & 0xffff_ffff) as usize;
+        let literals = (marker >> 33) as usize;
+        let end = run_len
+            .checked_add(literals)
+            .and_then(|words| words.checked_mul(64))
+            .and_then(|len| len.checked_add(pos))
+            .filter(|&end| end <= limit)
+            .ok_or_else(|| corrupt("bitmap is longer than its size"))?;
+        if literals > buffer.len() - i - 1 {
+            return Err(corrupt("truncated bitmap"));
+        }
+        if run_bit {
+            bits.extend(pos..(pos + run_len * 64).min(bit_size));
+        }
+        pos += run_len * 64;
+        for &word in &buffer[i + 1..i + 1 + literals] {
+            bits.extend((0..64).filter(|b| word >> b & 1 != 0).map(|b| pos + b));
+            pos += 64;
+        }
+        debug_assert_eq!(pos, end);
+        i += 1 + literals;
+    }
+    if bits.last().is_some_and(|&last| last >= bit_size) {
+        return Err(corrupt("bitmap has bits past its size"));
+    }
+    Ok(bits)
+}
+
+/// The SHA-1 of `data`, which git checksums index files with.
+#[cfg(test)]
+fn sha1(data: &[u8]) -> [u8; HASH_SIZE] {
+    let mut hash = Sha1::new();
+    hash.update(data);
+    hash.finish()
+}
+
+/// An incremental SHA-1, so that files need not be copied to be hashed.
+struct Sha1 {
+    h: [u32; 5],
+    /// The bytes of the block being filled.
+    block: [u8; 64],
+    /// The number of bytes hashed so far.
+    len: u64,
+}
+
+impl Sha1 {
+    fn new() -> Sha1 {
+        Sha1 {
+            h: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            block: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let filled = (self.len % 64) as usize;
+            let n = data.len().min(64 - filled);
+            self.block[filled..filled + n].copy_from_slice(&data[..n]);
+            self.len += n as u64;
+            data = &data[n..];
+            if filled + n == 64 {
+                let block = self.block;
+                self.compress(&block);
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; HASH_SIZE] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.len % 64 != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut out = [0; HASH_SIZE];
+        for (chunk, v) in out.chunks_mut(4).zip(self.h) {
+            chunk.copy_from_slice(&v.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let h = &mut self.h;
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = *h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+}
+
+fn append(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn corrupt(msg: &str) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Index,
+        format!("corrupt index: {}", msg),
+    )
+}
+
+fn io_error(path: &Path, e: io::Error) -> Error {
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => ErrorCode::NotFound,
+        _ => ErrorCode::GenericError,
+    };
+    Error::new(
+        code,
+        ErrorClass::Os,
+        format!("failed to read '{}': {}", path.display(), e),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::{ewah_bits, ewah_bytes, sha1, IndexFile, Reader, LINK};
+    use crate::{Index, Repository, Status};
+
+    fn git(repo: &Repository, args: &[&str]) -> Option<String> {
+        Command::new("git")
+            .args(args)
+            .current_dir(repo.workdir().unwrap())
+            .env("GIT_AUTHOR_NAME", "foo")
+            .env("GIT_AUTHOR_EMAIL", "foo@example.com")
+            .env("GIT_COMMITTER_NAME", "foo")
+            .env("GIT_COMMITTER_EMAIL", "foo@example.com")
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8(o.stdout).unwrap())
+    }
+
+    /// The entries git sees, with their stat data but not the in-memory
+    /// flags, which tell the entries of a split index apart.
+    fn ls_files(repo: &Repository) -> Vec<String> {
+        git(repo, &["ls-files", "--stage", "--debug"])
+            .unwrap()
+            .lines()
+            .map(|line| line.split("\tflags:").next().unwrap().to_string())
+            .collect()
+    }
+
+    fn paths(index: &Index) -> Vec<String> {
+        index
+            .iter()
+            .map(|e| String::from_utf8(e.path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ewah() {
+        // Bits 1, 3 and 200: a literal word, two clean words, then another
+        // literal word.
+        let mut data = Vec::new();
+        data.extend_from_slice(&201u32.to_be_bytes());
+        data.extend_from_slice(&4u32.to_be_bytes());
+        for word in [1u64 << 33, 0b1010, 2 << 1 | 1 << 33, 1 << 8] {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        data.extend_from_slice(&2u32.to_be_bytes());
+        let bits = ewah_bits(&mut Reader::new(&data), 201).unwrap();
+        assert_eq!(bits, [1, 3, 200]);
+    }
+
+    #[test]
+    fn corrupt_ewah() {
+        let bitmap = |bit_size: u32, words: &[u64]| {
+            let mut data = Vec::new();
+            data.extend_from_slice(&bit_size.to_be_bytes());
+            data.extend_from_slice(&(words.len() as u32).to_be_bytes());
+            for word in words {
+                data.extend_from_slice(&word.to_be_bytes());
+            }
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data
+        };
+        let read = |data: &[u8]| ewah_bits(&mut Reader::new(data), 128);
+        assert!(read(&bitmap(128, &[2 << 1 | 1])).is_ok());
+        // A run of ones far longer than the bitmap.
+        assert!(read(&bitmap(128, &[0xffff_ffff << 1 | 1])).is_err());
+        // More literal words than there are.
+        assert!(read(&bitmap(128, &[3 << 33, 1])).is_err());
+        // Bits past the size, and a size past what the caller expects.
+        assert!(read(&bitmap(3, &[1 << 33, 1 << 5])).is_err());
+        assert!(read(&bitmap(129, &[])).is_err());
+        // More words than bytes.
+        let mut data = bitmap(128, &[]);
+        data[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read(&data).is_err());
+    }
+
+    #[test]
+    fn varint() {
+        let mut reader = Reader::new(&[0x05, 0x80, 0x00, 0xff, 0x7f]);
+        assert_eq!(reader.varint().unwrap(), 5);
+        assert_eq!(reader.varint().unwrap(), 128);
+        assert_eq!(reader.varint().unwrap(), 16511);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn ewah_round_trip() {
+        for bits in [&[][..], &[0], &[1, 3, 64, 200]] {
+            let data = ewah_bytes(bits, 201);
+            let mut reader = Reader::new(&data);
+            assert_eq!(ewah_bits(&mut reader, 201).unwrap(), bits);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn sha1_digests() {
+        let hex =
+            |data: &[u8]| -> String { sha1(data).iter().map(|b| format!("{:02x}", b)).collect() };
+        assert_eq!(hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(hex(long), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn git_written_indices() {
+        let (td, repo) = crate::test::repo_init();
+        if git(&repo, &["--version"]).is_none() {
+            return;
+        }
+        for name in ["dir/one", "dir/two", "dir/sub/three", "four"] {
+            let path = td.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, name).unwrap();
+        }
+        assert!(git(&repo, &["add", "."]).is_some());
+        assert!(git(&repo, &["update-index", "--index-version", "4"]).is_some());
+        let expected = ["dir/one", "dir/sub/three", "dir/two", "four"];
+
+        let file = IndexFile::read(&repo.path().join("index")).unwrap();
+        assert_eq!(file.version, 4);
+        let mut index = repo.index().unwrap();
+        index.read(true).unwrap();
+        assert_eq!(index.version(), 4);
+        assert_eq!(paths(&index), expected);
+
+        // A split index, with entries both replaced and added after the
+        // shared index was written.
+        assert!(git(&repo, &["update-index", "--split-index"]).is_some());
+        std::fs::write(td.path().join("four"), "changed").unwrap();
+        std::fs::write(td.path().join("five"), "five").unwrap();
+        assert!(git(&repo, &["add", "four", "five"]).is_some());
+        assert!(git(&repo, &["rm", "-q", "--cached", "dir/two"]).is_some());
+        let index_path = repo.path().join("index");
+        let split = std::fs::read(&index_path).unwrap();
+        assert!(IndexFile::parse(&split).unwrap().extension(LINK).is_some());
+        let staged = ls_files(&repo);
+
+        index.read(true).unwrap();
+        let expected = ["dir/one", "dir/sub/three", "five", "four"];
+        assert_eq!(paths(&index), expected);
+        let four = index.get_path(Path::new("four"), 0).unwrap();
+        assert_eq!(repo.find_blob(four.id).unwrap().content(), b"changed");
+        let file = IndexFile::read_merged(&index_path).unwrap();
+        assert_eq!(file.version, 4);
+        assert!(file.matches(&index));
+
+        // Opening the repository and reading its status leave the split
+        // index alone.
+        let repo = Repository::open(repo.path()).unwrap();
+        let mut index = repo.index().unwrap();
+        assert_eq!(paths(&index), expected);
+        let status = repo.status_file(Path::new("four")).unwrap();
+        assert_eq!(status, Status::INDEX_NEW);
+        let status = repo.status_file(Path::new("dir/two")).unwrap();
+        assert_eq!(status, Status::WT_NEW);
+        let statuses = repo.statuses(None).unwrap();
+        assert_eq!(statuses.len(), 5);
+        index.read(true).unwrap();
+        assert_eq!(paths(&index), expected);
+        assert_eq!(std::fs::read(&index_path).unwrap(), split);
+        assert_eq!(ls_files(&repo), staged);
+
+        // Writing the index keeps it split, linked to the same shared index,
+        // and git reads it the same.
+        let link = IndexFile::parse(&split).unwrap().extension(LINK).unwrap()[..20].to_vec();
+        index.write().unwrap();
+        let file = IndexFile::read(&index_path).unwrap();
+        assert_eq!(file.extension(LINK).unwrap()[..20], link[..]);
+        assert_eq!(file.version, 4);
+        assert_eq!(ls_files(&repo), staged);
+        assert!(git(&repo, &["diff", "--quiet", "--", "four", "five"]).is_some());
+
+        // And so do changes made to it.
+        std::fs::write(td.path().join("dir/one"), "changed").unwrap();
+        index.add_path(Path::new("dir/one")).unwrap();
+        index.remove_path(Path::new("five")).unwrap();
+        index.write().unwrap();
+        let file = IndexFile::read(&index_path).unwrap();
+        assert_eq!(file.extension(LINK).unwrap()[..20], link[..]);
+        let staged = git(&repo, &["ls-files"]).unwrap();
+        assert_eq!(staged, "dir/one\ndir/sub/three\nfour\n");
+        assert!(git(&repo, &["diff", "--quiet", "--", "dir/one"]).is_some());
+        let repo = Repository::open(repo.path()).unwrap();
+        let index = repo.index().unwrap();
+        assert_eq!(paths(&index), ["dir/one", "dir/sub/three", "four"]);
+    }
+}
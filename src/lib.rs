@@ -87,6 +87,7 @@ pub use crate::branch::{Branch, Branches};
 pub use crate::buf::Buf;
 pub use crate::cherrypick::CherrypickOptions;
 pub use crate::commit::{Commit, Parents};
+pub use crate::commit_graph::{CommitGraph, CommitGraphEntry, CommitGraphOptions};
 pub use crate::config::{Config, ConfigEntries, ConfigEntry};
 pub use crate::cred::{Cred, CredentialHelper};
 pub use crate::describe::{Describe, DescribeFormatOptions, DescribeOptions};
@@ -95,13 +96,20 @@ pub use crate::diff::{DiffBinary, DiffBinaryFile, DiffBinaryKind};
 pub use crate::diff::{DiffFindOptions, DiffHunk, DiffLine, DiffLineType, DiffStats};
 pub use crate::email::{Email, EmailCreateOptions};
 pub use crate::error::Error;
+pub use crate::fsck::{FsckOptions, FsckProblem, FsckReferrer, FsckReport};
+#[cfg(target_os = "linux")]
+pub use crate::fsmonitor::InotifyFsMonitor;
+pub use crate::fsmonitor::{FsMonitor, FsMonitorChanges, HookFsMonitor};
 pub use crate::index::{
     Index, IndexConflict, IndexConflicts, IndexEntries, IndexEntry, IndexMatchedPath,
 };
 pub use crate::indexer::{Indexer, IndexerProgress, Progress};
+pub use crate::mailbox::{Mailbox, MailboxIter, MailboxPatch};
 pub use crate::mailmap::Mailmap;
+pub use crate::maintenance::{GcOptions, GcReport, RepackOptions, UnreachableOptions};
 pub use crate::mempack::Mempack;
-pub use crate::merge::{AnnotatedCommit, MergeOptions};
+pub use crate::merge::{AnnotatedCommit, MergeOptions, MergeStrategy};
+pub use crate::merge::{MergeConflict, MergeConflictKind, MergeConflictSide, MergeReport};
 pub use crate::message::{
     message_prettify, message_trailers_bytes, message_trailers_strs, MessageTrailersBytes,
     MessageTrailersBytesIterator, MessageTrailersStrs, MessageTrailersStrsIterator,
@@ -117,7 +125,11 @@ pub use crate::pathspec::{Pathspec, PathspecFailedEntries, PathspecMatchList};
 pub use crate::pathspec::{PathspecDiffEntries, PathspecEntries};
 pub use crate::proxy_options::ProxyOptions;
 pub use crate::push_update::PushUpdate;
+pub use crate::range_diff::{
+    range_diff, RangeDiff, RangeDiffEntry, RangeDiffOptions, RangeDiffStatus,
+};
 pub use crate::rebase::{Rebase, RebaseOperation, RebaseOperationType, RebaseOptions};
+pub use crate::rebase_plan::{PlannedRebase, RebasePlan, RebasePlanStatus, RebaseStep};
 pub use crate::reference::{Reference, ReferenceNames, References};
 pub use crate::reflog::{Reflog, ReflogEntry, ReflogIter};
 pub use crate::refspec::Refspec;
@@ -128,17 +140,23 @@ pub use crate::remote_callbacks::{CertificateCheckStatus, Credentials, RemoteCal
 pub use crate::remote_callbacks::{TransportMessage, UpdateTips};
 pub use crate::repo::{Repository, RepositoryInitOptions};
 pub use crate::revert::RevertOptions;
-pub use crate::revspec::Revspec;
-pub use crate::revwalk::Revwalk;
+pub use crate::revspec::{RevSpecList, Revspec};
+pub use crate::revwalk::{Revwalk, RevwalkFilter};
+pub use crate::rewrite::{CommitMap, CommitRewrite, HistoryRewriter};
+pub use crate::sequencer::{Sequencer, SequencerOptions, SequencerStatus, SequencerStep};
 pub use crate::signature::Signature;
-pub use crate::stash::{StashApplyOptions, StashApplyProgressCb, StashCb};
+pub use crate::signing::{GpgSigner, GpgVerifier, SignatureStatus, SignatureVerification};
+pub use crate::signing::{Signer, SshSigner, SshVerifier, Verifier};
+pub use crate::sparse::SparseCheckout;
+pub use crate::stash::{StashApplyOptions, StashApplyProgressCb, StashCb, StashEntry};
 pub use crate::status::{StatusEntry, StatusIter, StatusOptions, StatusShow, Statuses};
-pub use crate::submodule::{Submodule, SubmoduleUpdateOptions};
+pub use crate::submodule::{Submodule, SubmoduleUpdateAllOptions, SubmoduleUpdateOptions};
 pub use crate::tag::Tag;
 pub use crate::time::{IndexTime, Time};
 pub use crate::tracing::{trace_set, TraceLevel};
 pub use crate::transaction::Transaction;
 pub use crate::tree::{Tree, TreeEntry, TreeIter, TreeWalkMode, TreeWalkResult};
+pub use crate::tree_editor::{TreeChange, TreeEditor};
 pub use crate::treebuilder::TreeBuilder;
 pub use crate::util::IntoCString;
 pub use crate::version::Version;
@@ -661,6 +679,7 @@ mod attr;
 mod call;
 mod util;
 
+pub mod binary_patch;
 pub mod build;
 pub mod cert;
 pub mod oid_array;
@@ -675,15 +694,21 @@ mod branch;
 mod buf;
 mod cherrypick;
 mod commit;
+mod commit_graph;
 mod config;
 mod cred;
 mod describe;
 mod diff;
 mod email;
 mod error;
+mod fsck;
+mod fsmonitor;
 mod index;
+mod index_file;
 mod indexer;
+mod mailbox;
 mod mailmap;
+mod maintenance;
 mod mempack;
 mod merge;
 mod message;
@@ -696,7 +721,9 @@ mod patch;
 mod pathspec;
 mod proxy_options;
 mod push_update;
+mod range_diff;
 mod rebase;
+mod rebase_plan;
 mod reference;
 mod reflog;
 mod refspec;
@@ -706,7 +733,11 @@ mod repo;
 mod revert;
 mod revspec;
 mod revwalk;
+mod rewrite;
+mod sequencer;
 mod signature;
+mod signing;
+mod sparse;
 mod stash;
 mod status;
 mod submodule;
@@ -716,7 +747,9 @@ mod time;
 mod tracing;
 mod transaction;
 mod tree;
+mod tree_editor;
 mod treebuilder;
+mod untracked_cache;
 mod version;
 mod worktree;
 
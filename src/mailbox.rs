@@ -0,0 +1,844 @@
+use std::slice;
+use std::str;
+
+use crate::{Diff, Error, ErrorClass, ErrorCode, Oid, Signature, Time};
+
+/// A series of patches parsed from a mailbox, such as the output of
+/// `git format-patch --stdout` or [`Email`](crate::Email).
+///
+/// Use [`Repository::apply_mailbox`](crate::Repository::apply_mailbox) to
+/// turn the patches back into commits, like `git am`.
+#[derive(Clone, Debug)]
+pub struct Mailbox {
+    patches: Vec<MailboxPatch>,
+}
+
+/// A single message of a [`Mailbox`], split into its commit metadata and
+/// its patch.
+#[derive(Clone, Debug)]
+pub struct MailboxPatch {
+    commit_id: Option<Oid>,
+    author_name: Option<String>,
+    author_email: Option<String>,
+    date: Option<Time>,
+    subject: String,
+    body: String,
+    number: Option<(usize, usize)>,
+    patch: Vec<u8>,
+}
+
+/// An iterator over the patches of a [`Mailbox`].
+pub struct MailboxIter<'a> {
+    inner: slice::Iter<'a, MailboxPatch>,
+}
+
+impl Mailbox {
+    /// Parse a mailbox.
+    ///
+    /// Messages are split at mbox `From ` separator lines. A buffer which
+    /// does not start with such a line is treated as a single message.
+    pub fn from_buffer(buf: &[u8]) -> Result<Mailbox, Error> {
+        let patches = split_messages(buf)
+            .into_iter()
+            .map(MailboxPatch::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Mailbox { patches })
+    }
+
+    /// Get the number of patches in this mailbox.
+    pub fn len(&self) -> usize {
+        self.patches.len()
+    }
+
+    /// Return `true` if this mailbox contains no patches.
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    /// Get the patch at the given position.
+    pub fn get(&self, i: usize) -> Option<&MailboxPatch> {
+        self.patches.get(i)
+    }
+
+    /// Iterate over the patches of this mailbox, in order.
+    pub fn iter(&self) -> MailboxIter<'_> {
+        MailboxIter {
+            inner: self.patches.iter(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Mailbox {
+    type Item = &'a MailboxPatch;
+    type IntoIter = MailboxIter<'a>;
+    fn into_iter(self) -> MailboxIter<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> Iterator for MailboxIter<'a> {
+    type Item = &'a MailboxPatch;
+    fn next(&mut self) -> Option<&'a MailboxPatch> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+impl<'a> DoubleEndedIterator for MailboxIter<'a> {
+    fn next_back(&mut self) -> Option<&'a MailboxPatch> {
+        self.inner.next_back()
+    }
+}
+impl<'a> ExactSizeIterator for MailboxIter<'a> {}
+
+impl MailboxPatch {
+    /// The id of the original commit, taken from the mbox `From <id> ...`
+    /// separator line, if present.
+    pub fn commit_id(&self) -> Option<Oid> {
+        self.commit_id
+    }
+
+    /// The name of the patch author.
+    ///
+    /// An in-body `From:` line takes precedence over the mail header.
+    pub fn author_name(&self) -> Option<&str> {
+        self.author_name.as_deref()
+    }
+
+    /// The email address of the patch author.
+    pub fn author_email(&self) -> Option<&str> {
+        self.author_email.as_deref()
+    }
+
+    /// The authoring date of the patch, if the message carries a valid
+    /// `Date:` header.
+    pub fn date(&self) -> Option<Time> {
+        self.date
+    }
+
+    /// Create the author signature of this patch.
+    ///
+    /// If the message has no date, the current time is used.
+    pub fn author(&self) -> Result<Signature<'static>, Error> {
+        let email = self
+            .author_email
+            .as_deref()
+            .ok_or_else(|| invalid("patch has no author"))?;
+        let name = self.author_name.as_deref().unwrap_or(email);
+        match self.date {
+            Some(ref date) => Signature::new(name, email, date),
+            None => Signature::now(name, email),
+        }
+    }
+
+    /// The subject of the patch, with `[PATCH n/m]`-style prefixes removed.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// The body of the commit message, without the subject line.
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// The full commit message: the subject, followed by the body.
+    pub fn message(&self) -> String {
+        if self.body.is_empty() {
+            format!("{}\n", self.subject)
+        } else {
+            format!("{}\n\n{}\n", self.subject, self.body)
+        }
+    }
+
+    /// The position of this patch in its series and the length of the
+    /// series, as given by a `[PATCH n/m]` subject prefix.
+    pub fn number(&self) -> Option<(usize, usize)> {
+        self.number
+    }
+
+    /// The patch text following the commit message, including any diffstat.
+    pub fn patch(&self) -> &[u8] {
+        &self.patch
+    }
+
+    /// Parse the patch text into a diff.
+    pub fn diff(&self) -> Result<Diff<'static>, Error> {
+        let start = lines(&self.patch)
+            .find(|(_, line)| line.starts_with(b"diff --git "))
+            .map(|(offset, _)| offset)
+            .ok_or_else(|| invalid("message does not contain a patch"))?;
+        Diff::from_buffer(&self.patch[start..])
+    }
+
+    fn parse(message: &[u8]) -> Result<MailboxPatch, Error> {
+        let mut commit_id = None;
+        let mut message = message;
+        if let Some(rest) = message.strip_prefix(b"From ") {
+            let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            commit_id = str::from_utf8(&rest[..end])
+                .ok()
+                .and_then(|line| line.split(' ').next())
+                .and_then(|id| Oid::from_str(id).ok());
+            message = rest.get(end + 1..).unwrap_or(&[]);
+        }
+
+        let (headers, body) = split_headers(message);
+        let mut patch = MailboxPatch {
+            commit_id,
+            author_name: None,
+            author_email: None,
+            date: None,
+            subject: String::new(),
+            body: String::new(),
+            number: None,
+            patch: Vec::new(),
+        };
+        let mut encoding = None;
+        for (name, value) in &headers {
+            patch.apply_header(name, value);
+            if name.eq_ignore_ascii_case("content-transfer-encoding") {
+                encoding = Some(value.trim().to_ascii_lowercase());
+            }
+        }
+
+        let body = match encoding.as_deref() {
+            Some("quoted-printable") => decode_quoted_printable(body, false),
+            Some("base64") => decode_base64(body).ok_or_else(|| invalid("invalid base64 body"))?,
+            _ => body.to_vec(),
+        };
+        let body = String::from_utf8_lossy(&body);
+
+        // git allows overriding the mail headers from the top of the body,
+        // e.g. when sending somebody else's patch.
+        let (in_body, body) = split_in_body_headers(&body);
+        for (name, value) in &in_body {
+            patch.apply_header(name, value);
+        }
+
+        let (mut message, diff) = split_patch(body);
+        // Everything above a scissors line is discussion which is not part of
+        // the commit message, and in-body headers may follow it.
+        if let Some(rest) = after_scissors(message) {
+            let (in_body, rest) = split_in_body_headers(rest);
+            for (name, value) in &in_body {
+                patch.apply_header(name, value);
+            }
+            message = rest;
+        }
+        patch.body = message.trim().to_string();
+        patch.patch = diff.as_bytes().to_vec();
+        Ok(patch)
+    }
+
+    fn apply_header(&mut self, name: &str, value: &str) {
+        let value = decode_header(value.trim());
+        if name.eq_ignore_ascii_case("from") {
+            let (name, email) = parse_address(&value);
+            self.author_name = name;
+            self.author_email = email;
+        } else if name.eq_ignore_ascii_case("date") {
+            self.date = parse_date(&value);
+        } else if name.eq_ignore_ascii_case("subject") {
+            let (subject, number) = clean_subject(&value);
+            self.subject = subject;
+            if number.is_some() {
+                self.number = number;
+            }
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorCode::Invalid, ErrorClass::Invalid, msg)
+}
+
+/// Iterate over the lines of `buf` along with their offsets, without the
+/// line terminators.
+fn lines(buf: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut offset = 0;
+    buf.split_inclusive(|&b| b == b'\n').map(move |line| {
+        let start = offset;
+        offset += line.len();
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        (start, line.strip_suffix(b"\r").unwrap_or(line))
+    })
+}
+
+/// Whether `line` is an mbox separator as written by `git format-patch`:
+/// `From <commit id> <asctime date>`, such as
+/// `From 0123...cdef Mon Sep 17 00:00:00 2001`.
+fn is_separator(line: &[u8]) -> bool {
+    let rest = match line.strip_prefix(b"From ") {
+        Some(rest) if rest.len() > 41 && rest[40] == b' ' => rest,
+        _ => return false,
+    };
+    if !rest[..40].iter().all(u8::is_ascii_hexdigit) {
+        return false;
+    }
+    let date = match std::str::from_utf8(&rest[41..]) {
+        Ok(date) => date,
+        Err(_) => return false,
+    };
+    let digits = |s: &str, min: usize, max: usize| {
+        (min..=max).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_digit())
+    };
+    let alpha = |s: &str| s.len() == 3 && s.bytes().all(|b| b.is_ascii_alphabetic());
+    let words = date.split_whitespace().collect::<Vec<_>>();
+    match words[..] {
+        [weekday, month, day, time, year] => {
+            let mut time = time.split(':');
+            alpha(weekday)
+                && alpha(month)
+                && digits(day, 1, 2)
+                && digits(year, 4, 4)
+                && (0..3).all(|_| time.next().is_some_and(|t| digits(t, 2, 2)))
+                && time.next().is_none()
+        }
+        _ => false,
+    }
+}
+
+fn split_messages(buf: &[u8]) -> Vec<&[u8]> {
+    let mut starts = lines(buf)
+        .filter(|(_, line)| is_separator(line))
+        .map(|(offset, _)| offset)
+        .collect::<Vec<_>>();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    let mut messages = Vec::new();
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(buf.len());
+        let message = &buf[start..end];
+        if message.iter().any(|b| !b.is_ascii_whitespace()) {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
+/// Split an RFC 2822 message into its (unfolded) headers and its body.
+fn split_headers(message: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for (offset, line) in lines(message) {
+        if line.is_empty() {
+            let skip = if message[offset..].starts_with(b"\r\n") {
+                2
+            } else {
+                1
+            };
+            return (headers, &message[offset + skip..]);
+        }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with([' ', '\t']) {
+            if let Some(last) = headers.last_mut() {
+                last.1.push(' ');
+                last.1.push_str(line.trim());
+                continue;
+            }
+        }
+        match parse_header_line(&line) {
+            Some(header) => headers.push(header),
+            // Not a header block at all, treat everything as the body.
+            None if offset == 0 => return (Vec::new(), message),
+            None => return (headers, &message[offset..]),
+        }
+    }
+    (headers, &[])
+}
+
+fn parse_header_line(line: &str) -> Option<(String, String)> {
+    let colon = line.find(':')?;
+    let name = &line[..colon];
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace()) {
+        return None;
+    }
+    Some((name.to_string(), line[colon + 1..].trim().to_string()))
+}
+
+/// Split `From:`, `Date:` and `Subject:` lines at the very start of the
+/// body from the rest of it.
+fn split_in_body_headers(body: &str) -> (Vec<(String, String)>, &str) {
+    let body = body.trim_start_matches(['\n', '\r']);
+    let mut headers = Vec::new();
+    let mut rest = body;
+    loop {
+        let (line, next) = match rest.find('\n') {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => (rest, ""),
+        };
+        let header = parse_header_line(line.trim_end()).filter(|(name, _)| {
+            ["from", "date", "subject"]
+                .iter()
+                .any(|h| name.eq_ignore_ascii_case(h))
+        });
+        match header {
+            Some(header) => {
+                headers.push(header);
+                rest = next;
+            }
+            None => break,
+        }
+    }
+    if headers.is_empty() {
+        return (headers, body);
+    }
+    (headers, rest)
+}
+
+/// Split the body at the start of the patch: a `---` line, or the first
+/// `diff -` or `Index: ` line.
+fn split_patch(body: &str) -> (&str, &str) {
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == "---" || line.starts_with("diff -") || line.starts_with("Index: ") {
+            return (&body[..offset], &body[offset..]);
+        }
+        offset += line.len();
+    }
+    (body, "")
+}
+
+/// The part of `message` after its last scissors line, if it has one.
+fn after_scissors(message: &str) -> Option<&str> {
+    let mut offset = 0;
+    let mut rest = None;
+    for line in message.split_inclusive('\n') {
+        offset += line.len();
+        if is_scissors(line) {
+            rest = Some(&message[offset..]);
+        }
+    }
+    rest
+}
+
+fn is_scissors(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 8
+        && (line.contains(">8") || line.contains("8<"))
+        && line.chars().all(|c| "-8<> ".contains(c))
+}
+
+/// Strip `Re:` and bracketed prefixes such as `[PATCH v2 3/7]` from a
+/// subject, returning the series position found in them.
+fn clean_subject(subject: &str) -> (String, Option<(usize, usize)>) {
+    let mut number = None;
+    let mut rest = subject.trim();
+    loop {
+        if rest
+            .get(..3)
+            .is_some_and(|re| re.eq_ignore_ascii_case("re:"))
+        {
+            rest = rest[3..].trim_start();
+        } else if rest.starts_with('[') {
+            let end = match rest.find(']') {
+                Some(end) => end,
+                None => break,
+            };
+            if number.is_none() {
+                number = rest[1..end]
+                    .split_whitespace()
+                    .find_map(parse_series_number);
+            }
+            rest = rest[end + 1..].trim_start();
+        } else {
+            break;
+        }
+    }
+    (
+        rest.split_whitespace().collect::<Vec<_>>().join(" "),
+        number,
+    )
+}
+
+fn parse_series_number(word: &str) -> Option<(usize, usize)> {
+    let slash = word.find('/')?;
+    let n = word[..slash].parse().ok()?;
+    let total = word[slash + 1..].parse().ok()?;
+    Some((n, total))
+}
+
+/// Split `Name <email>` into its parts.
+fn parse_address(value: &str) -> (Option<String>, Option<String>) {
+    match (value.find('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => {
+            let name = value[..start].trim().trim_matches('"').trim();
+            let email = value[start + 1..end].trim();
+            let name = if name.is_empty() {
+                None
+            } else {
+                Some(name.to_string())
+            };
+            (name, Some(email.to_string()))
+        }
+        _ if value.contains('@') => {
+            // `email (Name)` form
+            match (value.find('('), value.rfind(')')) {
+                (Some(start), Some(end)) if start < end => (
+                    Some(value[start + 1..end].trim().to_string()),
+                    Some(value[..start].trim().to_string()),
+                ),
+                _ => (None, Some(value.trim().to_string())),
+            }
+        }
+        _ => (None, None),
+    }
+}
+
+/// Parse an RFC 2822 date such as `Mon, 17 Sep 2001 12:34:56 +0200`.
+fn parse_date(value: &str) -> Option<Time> {
+    let value = match value.find(',') {
+        Some(idx) => &value[idx + 1..],
+        None => value,
+    };
+    let mut parts = value.split_whitespace();
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.to_ascii_lowercase();
+    let month = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ]
+    .iter()
+    .position(|m| month.starts_with(m))? as i64
+        + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut clock = parts.next()?.split(':');
+    let hour: i64 = clock.next()?.parse().ok()?;
+    let minute: i64 = clock.next()?.parse().ok()?;
+    let second: i64 = clock.next().unwrap_or("0").parse().ok()?;
+    let offset = parts.next().and_then(parse_zone).unwrap_or(0);
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second - i64::from(offset) * 60;
+    Some(Time::new(seconds, offset))
+}
+
+/// Parse a numeric time zone such as `+0200` into minutes east of UTC.
+fn parse_zone(zone: &str) -> Option<i32> {
+    let (sign, digits) = match zone.as_bytes() {
+        [b'+', digits @ ..] => (1, digits),
+        [b'-', digits @ ..] => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let value = |d: &[u8]| i32::from(d[0] - b'0') * 10 + i32::from(d[1] - b'0');
+    Some(sign * (value(&digits[..2]) * 60 + value(&digits[2..])))
+}
+
+/// Number of days between 1970-01-01 and the given date of the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Decode RFC 2047 encoded words (`=?UTF-8?q?...?=`) in a header value.
+fn decode_header(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, word) = rest.split_at(start);
+        match decode_encoded_word(word) {
+            Some((text, len)) => {
+                // Whitespace between two encoded words is not significant.
+                if !(after_word && before.trim().is_empty()) {
+                    out.push_str(before);
+                }
+                out.push_str(&text);
+                rest = &word[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &word[2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_encoded_word(word: &str) -> Option<(String, usize)> {
+    let mut parts = word[2..].splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let rest = parts.next()?;
+    let end = rest.find("?=")?;
+    let text = &rest[..end];
+    let bytes = match encoding {
+        "Q" | "q" => decode_quoted_printable(text.as_bytes(), true),
+        "B" | "b" => decode_base64(text.as_bytes())?,
+        _ => return None,
+    };
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+    let text =
+        if charset.eq_ignore_ascii_case("iso-8859-1") || charset.eq_ignore_ascii_case("latin1") {
+            bytes.iter().map(|&b| b as char).collect()
+        } else {
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+    Some((text, len))
+}
+
+fn decode_quoted_printable(data: &[u8], header: bool) -> Vec<u8> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'_' if header => out.push(b' '),
+            b'=' if data[i + 1..].starts_with(b"\r\n") => i += 2,
+            b'=' if data[i + 1..].starts_with(b"\n") => i += 1,
+            b'=' if i + 2 < data.len() => match (hex(data[i + 1]), hex(data[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 2;
+                }
+                _ => out.push(b'='),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn decode_base64(data: &[u8]) -> Option<Vec<u8>> {
+    let value = |b: u8| match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a' + 26),
+        b'0'..=b'9' => Some(b - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &b in data {
+        if b.is_ascii_whitespace() {
+            continue;
+        }
+        if b == b'=' {
+            break;
+        }
+        acc = acc << 6 | u32::from(value(b)?);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clean_subject, decode_header, parse_date, Mailbox};
+    use crate::{Email, EmailCreateOptions, Oid, Signature, Time};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn smoke() {
+        let mbox = b"From 9c9d82beb1f4af0f0ff1b5a9bee1e6ddf4e2b26a Mon Sep 17 00:00:00 2001
+From: =?UTF-8?q?J=C3=B6rg=20Doe?= <jd@example.com>
+Date: Tue, 2 May 2023 13:14:15 -0700
+Subject: [PATCH v2 2/3] Fix the
+ frobnicator
+
+It was broken.
+---
+ foo | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/foo b/foo
+index 257cc56..5716ca5 100644
+--- a/foo
++++ b/foo
+@@ -1 +1 @@
+-foo
++bar
+--
+2.40.0
+
+From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: Sender <sender@example.com>
+Subject: Re: [PATCH] second
+
+From: Real Author <real@example.com>
+
+Body.
+";
+        let mailbox = t!(Mailbox::from_buffer(mbox));
+        assert_eq!(mailbox.len(), 2);
+
+        let first = mailbox.get(0).unwrap();
+        assert_eq!(
+            first.commit_id(),
+            Some(t!(Oid::from_str(
+                "9c9d82beb1f4af0f0ff1b5a9bee1e6ddf4e2b26a"
+            )))
+        );
+        assert_eq!(first.author_name(), Some("Jörg Doe"));
+        assert_eq!(first.author_email(), Some("jd@example.com"));
+        assert_eq!(first.date(), Some(Time::new(1683058455, -420)));
+        assert_eq!(first.subject(), "Fix the frobnicator");
+        assert_eq!(first.number(), Some((2, 3)));
+        assert_eq!(first.message(), "Fix the frobnicator\n\nIt was broken.\n");
+        let diff = t!(first.diff());
+        assert_eq!(diff.deltas().len(), 1);
+
+        let second = mailbox.get(1).unwrap();
+        assert_eq!(second.author_name(), Some("Real Author"));
+        assert_eq!(second.author_email(), Some("real@example.com"));
+        assert_eq!(second.subject(), "second");
+        assert_eq!(second.number(), None);
+        assert_eq!(second.body(), "Body.");
+        assert!(second.diff().is_err());
+    }
+
+    #[test]
+    fn from_lines_in_bodies() {
+        let mbox = b"From 9c9d82beb1f4af0f0ff1b5a9bee1e6ddf4e2b26a Mon Sep 17 00:00:00 2001
+From: Sender <sender@example.com>
+Subject: [PATCH] Move the meeting
+
+From now on we meet at 10:30:00 on Mondays.
+From 0123 Mon Sep 17 00:00:00 2001
+";
+        let mailbox = t!(Mailbox::from_buffer(mbox));
+        assert_eq!(mailbox.len(), 1);
+        let patch = mailbox.get(0).unwrap();
+        assert_eq!(
+            patch.body(),
+            "From now on we meet at 10:30:00 on Mondays.\n\
+             From 0123 Mon Sep 17 00:00:00 2001"
+        );
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(
+            clean_subject("Re: [RFC] [PATCH 10/12]  a  b"),
+            ("a b".to_string(), Some((10, 12)))
+        );
+        assert_eq!(
+            decode_header("=?utf-8?b?SGVsbG8=?= =?utf-8?q?_W=C3=B6rld?= !"),
+            "Hello Wörld !"
+        );
+        assert_eq!(parse_date("1 Jan 1970 01:00 +0100"), Some(Time::new(0, 60)));
+        assert_eq!(parse_date("not a date"), None);
+    }
+
+    #[test]
+    fn non_ascii_subjects() {
+        assert_eq!(clean_subject("Исправить"), ("Исправить".to_string(), None));
+        assert_eq!(clean_subject("Ré: x"), ("Ré: x".to_string(), None));
+        assert_eq!(
+            clean_subject("RE: [PATCH 1/2] 修正"),
+            ("修正".to_string(), Some((1, 2)))
+        );
+        assert_eq!(clean_subject("é"), ("é".to_string(), None));
+        assert_eq!(clean_subject("[PATCH] ü"), ("ü".to_string(), None));
+    }
+
+    #[test]
+    fn truncated_zones() {
+        let utc = Some(Time::new(3600, 0));
+        assert_eq!(parse_date("1 Jan 1970 01:00 +"), utc);
+        assert_eq!(parse_date("1 Jan 1970 01:00 -01"), utc);
+        assert_eq!(parse_date("1 Jan 1970 01:00 +01000"), utc);
+        assert_eq!(parse_date("1 Jan 1970 01:00 +1é0"), utc);
+        assert_eq!(parse_date("1 Jan 1970 01:00 é"), utc);
+        assert_eq!(parse_date("1 Jan 1970 01:00 GMT"), utc);
+        assert_eq!(
+            parse_date("1 Jan 1970 01:00 -0130"),
+            Some(Time::new(3600 + 90 * 60, -90))
+        );
+    }
+
+    #[test]
+    fn scissors() {
+        let mbox = b"From: Sender <sender@example.com>
+Subject: [PATCH] Discussion subject
+
+Thanks for the review, here is the new version.
+
+-- >8 --
+Subject: Real subject
+
+Real body.
+---
+diff --git a/foo b/foo
+";
+        let mailbox = t!(Mailbox::from_buffer(mbox));
+        let patch = mailbox.get(0).unwrap();
+        assert_eq!(patch.subject(), "Real subject");
+        assert_eq!(patch.body(), "Real body.");
+        assert_eq!(patch.message(), "Real subject\n\nReal body.\n");
+    }
+
+    #[test]
+    fn apply_mailbox() {
+        let (td, repo) = crate::test::repo_init();
+        let base = t!(repo.head()).target().unwrap();
+        let author = t!(Signature::new(
+            "Author",
+            "author@example.com",
+            &Time::new(1234, 60)
+        ));
+
+        let mut mbox = Vec::new();
+        let mut last = Vec::new();
+        for (i, contents) in ["one\n", "two\n"].iter().enumerate() {
+            fs::write(td.path().join("file"), contents).unwrap();
+            let mut index = t!(repo.index());
+            t!(index.add_path(Path::new("file")));
+            let tree = t!(repo.find_tree(t!(index.write_tree())));
+            let parent = t!(t!(repo.head()).peel_to_commit());
+            let message = format!("change {}\n\ndetails {}\n", i, i);
+            let id = t!(repo.commit(Some("HEAD"), &author, &author, &message, &tree, &[&parent]));
+            let mut opts = EmailCreateOptions::new();
+            opts.always_number(true).start_number(i + 1);
+            let email = t!(Email::from_commit(&t!(repo.find_commit(id)), &mut opts));
+            mbox.extend_from_slice(email.as_slice());
+            last = email.as_slice().to_vec();
+        }
+        let expected = t!(t!(repo.head()).peel_to_tree()).id();
+
+        let base = t!(repo.find_commit(base));
+        t!(repo.reset(base.as_object(), crate::ResetType::Hard, None));
+
+        let mailbox = t!(Mailbox::from_buffer(&mbox));
+        assert_eq!(mailbox.len(), 2);
+        let ids = t!(repo.apply_mailbox(&mailbox, Some("HEAD"), None));
+        assert_eq!(ids.len(), 2);
+
+        let head = t!(t!(repo.head()).peel_to_commit());
+        assert_eq!(head.id(), ids[1]);
+        assert_eq!(head.tree_id(), expected);
+        assert_eq!(head.message(), Some("change 1\n\ndetails 1\n"));
+        assert!(head.author() == author);
+        assert_eq!(t!(head.parent(0)).parent_id(0).unwrap(), base.id());
+
+        // A patch which does not apply, here the last one applied twice,
+        // leaves the reference where it was.
+        t!(repo.reset(base.as_object(), crate::ResetType::Hard, None));
+        let mut broken = mbox.clone();
+        broken.extend_from_slice(&last);
+        let mailbox = t!(Mailbox::from_buffer(&broken));
+        assert_eq!(mailbox.len(), 3);
+        assert!(repo.apply_mailbox(&mailbox, Some("HEAD"), None).is_err());
+        assert_eq!(t!(repo.head()).target(), Some(base.id()));
+    }
+}
@@ -0,0 +1,1234 @@
+//! Repository maintenance: packing, pruning and garbage collection, the
+//! tasks of `git gc` and `git maintenance`.
+//!
+//! This module is private. Its entry points are methods of [`Repository`]:
+//! [`Repository::write_multi_pack_index`], [`Repository::repack`],
+//! [`Repository::prune_packed`], [`Repository::expire_unreachable`],
+//! [`Repository::pack_refs`], [`Repository::reachable_objects`],
+//! [`Repository::unreachable_objects`] and [`Repository::gc`]. Their
+//! options and results, [`RepackOptions`], [`UnreachableOptions`],
+//! [`GcOptions`] and [`GcReport`], are exported from the crate root.
+//!
+//! [`Repository::fsck`] counts the same pseudo-refs as roots.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, SystemTime};
+
+use crate::call;
+use crate::util::Binding;
+use crate::Repository;
+use crate::{raw, Error, ErrorClass, ErrorCode, Index, IntoCString, ObjectType, Odb, Oid};
+
+/// Options for [`Repository::repack`].
+pub struct RepackOptions {
+    reachable_only: bool,
+    delete_old: bool,
+    threads: u32,
+}
+
+/// Options for [`Repository::unreachable_objects`].
+pub struct UnreachableOptions {
+    expire: Option<SystemTime>,
+    packed: bool,
+}
+
+/// Options for [`Repository::gc`].
+pub struct GcOptions {
+    dry_run: bool,
+    expire: SystemTime,
+    repack: bool,
+    pack_refs: bool,
+    threads: u32,
+}
+
+/// What [`Repository::gc`] deleted, or would delete in a dry run.
+#[derive(Debug)]
+pub struct GcReport {
+    expired: Vec<Oid>,
+    removed_packs: Vec<PathBuf>,
+    pack: Option<String>,
+    deleted_bytes: u64,
+}
+
+/// The files making up a pack, besides the `.pack` file itself.
+const PACK_EXTENSIONS: &[&str] = &["idx", "rev", "bitmap", "mtimes", "promisor"];
+
+const MIDX: &str = "multi-pack-index";
+
+/// The references outside of `refs/` which keep objects alive.
+const PSEUDO_REFS: &[&str] = &[
+    "ORIG_HEAD",
+    "FETCH_HEAD",
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "BISECT_HEAD",
+    "AUTO_MERGE",
+];
+
+impl RepackOptions {
+    /// Creates a default set of repack options, which pack the reachable
+    /// objects of the repository and delete the packs that were replaced.
+    pub fn new() -> RepackOptions {
+        RepackOptions {
+            reachable_only: false,
+            delete_old: true,
+            threads: 0,
+        }
+    }
+
+    /// Drop the unreachable objects of the packs that are deleted, like
+    /// `git repack -a -d`, instead of writing them loose.
+    ///
+    /// Objects are reachable from the references, reflogs, `HEAD` and
+    /// index of the repository and its worktrees. Defaults to `false`.
+    pub fn reachable_only(&mut self, reachable_only: bool) -> &mut RepackOptions {
+        self.reachable_only = reachable_only;
+        self
+    }
+
+    /// Delete the existing packs and the loose objects once the new pack
+    /// has been written, like `git repack -d`.
+    ///
+    /// Packs with a `.keep` file are left in place. Defaults to `true`.
+    pub fn delete_old(&mut self, delete_old: bool) -> &mut RepackOptions {
+        self.delete_old = delete_old;
+        self
+    }
+
+    /// Set the number of threads used to build the pack.
+    ///
+    /// Defaults to 0, which autodetects the number of CPUs.
+    pub fn threads(&mut self, threads: u32) -> &mut RepackOptions {
+        self.threads = threads;
+        self
+    }
+}
+
+impl Default for RepackOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UnreachableOptions {
+    /// Creates a default set of options, which list every unreachable
+    /// object.
+    pub fn new() -> UnreachableOptions {
+        UnreachableOptions {
+            expire: None,
+            packed: true,
+        }
+    }
+
+    /// Only list the objects last modified before `cutoff`.
+    ///
+    /// The modification time of a packed object is that of its pack.
+    pub fn expire(&mut self, cutoff: SystemTime) -> &mut UnreachableOptions {
+        self.expire = Some(cutoff);
+        self
+    }
+
+    /// List the objects only stored in packs.
+    ///
+    /// Defaults to `true`. Otherwise only loose objects are listed.
+    pub fn packed(&mut self, packed: bool) -> &mut UnreachableOptions {
+        self.packed = packed;
+        self
+    }
+}
+
+impl Default for UnreachableOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GcOptions {
+    /// Creates a default set of options, which repack the repository and
+    /// expire the unreachable objects older than two weeks, like `git gc`.
+    pub fn new() -> GcOptions {
+        GcOptions {
+            dry_run: false,
+            expire: SystemTime::now() - Duration::from_secs(14 * 24 * 60 * 60),
+            repack: true,
+            pack_refs: true,
+            threads: 0,
+        }
+    }
+
+    /// Only report what would be deleted, without changing the repository.
+    ///
+    /// Defaults to `false`.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut GcOptions {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Expire the unreachable objects last modified before `cutoff`, like
+    /// `gc.pruneExpire`.
+    ///
+    /// More recent unreachable objects are kept, as they may be in the
+    /// process of being referenced. Defaults to two weeks ago.
+    pub fn expire(&mut self, cutoff: SystemTime) -> &mut GcOptions {
+        self.expire = cutoff;
+        self
+    }
+
+    /// Pack the objects which are kept into a single pack, deleting the
+    /// existing packs and loose objects.
+    ///
+    /// Defaults to `true`. Otherwise only expired loose objects are deleted.
+    pub fn repack(&mut self, repack: bool) -> &mut GcOptions {
+        self.repack = repack;
+        self
+    }
+
+    /// Move the loose references into the `packed-refs` file.
+    ///
+    /// Defaults to `true`.
+    pub fn pack_refs(&mut self, pack_refs: bool) -> &mut GcOptions {
+        self.pack_refs = pack_refs;
+        self
+    }
+
+    /// Set the number of threads used to repack.
+    ///
+    /// Defaults to 0, which autodetects the number of CPUs.
+    pub fn threads(&mut self, threads: u32) -> &mut GcOptions {
+        self.threads = threads;
+        self
+    }
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GcReport {
+    /// The unreachable objects which were deleted, sorted by id.
+    pub fn expired(&self) -> &[Oid] {
+        &self.expired
+    }
+
+    /// The packs which were replaced by the new pack and deleted.
+    pub fn removed_packs(&self) -> &[PathBuf] {
+        &self.removed_packs
+    }
+
+    /// The name of the new pack, if one was written.
+    pub fn pack(&self) -> Option<&str> {
+        self.pack.as_deref()
+    }
+
+    /// The total size in bytes of the deleted files.
+    ///
+    /// The size of the new pack is not subtracted.
+    pub fn deleted_bytes(&self) -> u64 {
+        self.deleted_bytes
+    }
+}
+
+/// Write a multi-pack-index covering every pack of `repo`.
+pub(crate) fn write_midx(repo: &Repository) -> Result<(), Error> {
+    let pack_dir = repo.objects_dir().join("pack");
+    let indexes = pack_files(&pack_dir, "idx")?;
+    if indexes.is_empty() {
+        remove_file(&pack_dir.join(MIDX))?;
+        return repo.odb()?.refresh();
+    }
+
+    struct Writer(*mut raw::git_midx_writer);
+    impl Drop for Writer {
+        fn drop(&mut self) {
+            unsafe { raw::git_midx_writer_free(self.0) }
+        }
+    }
+
+    let dir = pack_dir.as_path().into_c_string()?;
+    unsafe {
+        let mut writer = Writer(ptr::null_mut());
+        try_call!(raw::git_midx_writer_new(&mut writer.0, dir));
+        for index in indexes {
+            let index = index.as_path().into_c_string()?;
+            try_call!(raw::git_midx_writer_add(writer.0, index));
+        }
+        try_call!(raw::git_midx_writer_commit(writer.0));
+    }
+    repo.odb()?.refresh()
+}
+
+/// Pack the objects of `repo` into a single new pack, returning its name.
+pub(crate) fn repack(repo: &Repository, opts: &RepackOptions) -> Result<Option<String>, Error> {
+    let objects_dir = repo.objects_dir();
+    let old_packs = pack_files(&objects_dir.join("pack"), "pack")?;
+    let objects = local_objects(&objects_dir)?;
+    let reachable = reachable(repo)?;
+    // Unreachable objects are not packed, which would make them as recent
+    // as the new pack.
+    let packed = objects
+        .keys()
+        .filter(|id| reachable.contains(id))
+        .copied()
+        .collect::<HashSet<_>>();
+    if packed.is_empty() {
+        return Ok(None);
+    }
+
+    let name = write_pack(repo, packed, opts.threads)?;
+    if opts.delete_old {
+        if !opts.reachable_only {
+            let unpacked = objects
+                .iter()
+                .filter(|(id, object)| {
+                    !reachable.contains(id)
+                        && object.packed
+                        && !object.kept
+                        && object.loose.is_none()
+                })
+                .map(|(id, object)| (*id, object.modified))
+                .collect::<Vec<_>>();
+            unpack(repo, &unpacked)?;
+        }
+        remove_packs(repo, &old_packs, &name)?;
+        prune_packed(repo)?;
+    }
+    repo.odb()?.refresh()?;
+    Ok(Some(name))
+}
+
+/// Write the packed `objects` of `repo` as loose objects, last modified
+/// when their pack was, so that they expire as they would have in it, like
+/// `git repack -A`.
+fn unpack(repo: &Repository, objects: &[(Oid, SystemTime)]) -> Result<(), Error> {
+    if objects.is_empty() {
+        return Ok(());
+    }
+    let odb = repo.odb()?;
+    let objects_dir = repo.objects_dir();
+    let loose = loose_odb(&objects_dir)?;
+    for &(id, modified) in objects {
+        let object = odb.read(id)?;
+        loose.write(object.kind(), object.data())?;
+        let hex = id.to_string();
+        set_modified(&objects_dir.join(&hex[..2]).join(&hex[2..]), modified).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Set the modification time of the file at `path`, which is read-only
+/// like the loose objects are.
+fn set_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    #[cfg(not(windows))]
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_WRITE_ATTRIBUTES, which read-only files allow.
+        options.access_mode(0x100);
+    }
+    options.open(path)?.set_modified(time)
+}
+
+/// Remove the loose objects of `repo` which are also stored in a pack.
+pub(crate) fn prune_packed(repo: &Repository) -> Result<usize, Error> {
+    let objects_dir = repo.objects_dir();
+    let mut pruned = 0;
+    for object in local_objects(&objects_dir)?.values() {
+        if let (true, Some(path)) = (object.packed, &object.loose) {
+            remove_file(path)?;
+            pruned += 1;
+        }
+    }
+    remove_empty_fanout(&objects_dir)?;
+    Ok(pruned)
+}
+
+/// Remove the unreachable loose objects of `repo` last modified before
+/// `cutoff`.
+pub(crate) fn expire_unreachable(repo: &Repository, cutoff: SystemTime) -> Result<usize, Error> {
+    let objects_dir = repo.objects_dir();
+    let objects = local_objects(&objects_dir)?;
+    let retained = retained(repo, &objects, cutoff)?;
+    let mut expired = 0;
+    for (id, object) in objects {
+        if retained.contains(&id) {
+            continue;
+        }
+        if let Some(path) = object.loose {
+            remove_file(&path)?;
+            expired += 1;
+        }
+    }
+    remove_empty_fanout(&objects_dir)?;
+    Ok(expired)
+}
+
+/// List the objects of `repo` which are not reachable.
+pub(crate) fn unreachable(repo: &Repository, opts: &UnreachableOptions) -> Result<Vec<Oid>, Error> {
+    let objects = local_objects(&repo.objects_dir())?;
+    let kept = match opts.expire {
+        Some(cutoff) => retained(repo, &objects, cutoff)?,
+        None => reachable(repo)?,
+    };
+    let mut unreachable = objects
+        .into_iter()
+        .filter(|(id, object)| !kept.contains(id) && (opts.packed || object.loose.is_some()))
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    unreachable.sort();
+    Ok(unreachable)
+}
+
+/// Collect garbage in `repo`, like `git gc`.
+pub(crate) fn gc(repo: &Repository, opts: &GcOptions) -> Result<GcReport, Error> {
+    if opts.pack_refs && !opts.dry_run {
+        pack_refs(repo)?;
+    }
+
+    let objects_dir = repo.objects_dir();
+    let objects = local_objects(&objects_dir)?;
+    let retained = retained(repo, &objects, opts.expire)?;
+    let mut report = GcReport {
+        expired: Vec::new(),
+        removed_packs: Vec::new(),
+        pack: None,
+        deleted_bytes: 0,
+    };
+    let mut keep = HashSet::new();
+    for (id, object) in &objects {
+        if retained.contains(id) {
+            keep.insert(*id);
+        } else if opts.repack || !object.packed {
+            report.expired.push(*id);
+        }
+    }
+    report.expired.sort();
+
+    let mut loose = Vec::new();
+    for (id, object) in &objects {
+        if let Some(path) = &object.loose {
+            if opts.repack || (!object.packed && report.expired.binary_search(id).is_ok()) {
+                loose.push(path.clone());
+            }
+        }
+    }
+    if opts.repack {
+        for pack in pack_files(&objects_dir.join("pack"), "pack")? {
+            if !pack.with_extension("keep").exists() {
+                report.removed_packs.push(pack);
+            }
+        }
+    }
+    for path in loose.iter().chain(&report.removed_packs) {
+        report.deleted_bytes += fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    }
+    if opts.dry_run {
+        return Ok(report);
+    }
+
+    if opts.repack && !keep.is_empty() {
+        let name = write_pack(repo, keep, opts.threads)?;
+        let new_pack = objects_dir.join("pack").join(format!("pack-{}.pack", name));
+        report.removed_packs.retain(|pack| *pack != new_pack);
+        report.pack = Some(name);
+    }
+    if opts.repack {
+        let name = report.pack.as_deref().unwrap_or("");
+        remove_packs(repo, &report.removed_packs, name)?;
+    }
+    for path in loose {
+        remove_file(&path)?;
+    }
+    remove_empty_fanout(&objects_dir)?;
+    repo.odb()?.refresh()?;
+    Ok(report)
+}
+
+/// Move the loose references of `repo` into the `packed-refs` file.
+pub(crate) fn pack_refs(repo: &Repository) -> Result<(), Error> {
+    struct Refdb(*mut raw::git_refdb);
+    impl Drop for Refdb {
+        fn drop(&mut self) {
+            unsafe { raw::git_refdb_free(self.0) }
+        }
+    }
+
+    unsafe {
+        let mut refdb = Refdb(ptr::null_mut());
+        try_call!(raw::git_refdb_open(&mut refdb.0, repo.raw()));
+        try_call!(raw::git_refdb_compress(refdb.0));
+    }
+    Ok(())
+}
+
+/// Find the objects reachable from the references, reflogs, pseudo
+/// references such as `ORIG_HEAD`, `HEAD` and index of `repo` and its
+/// worktrees.
+///
+/// Missing objects are not reported, finding them is left to `fsck`.
+pub(crate) fn reachable(repo: &Repository) -> Result<HashSet<Oid>, Error> {
+    let mut roots = Vec::new();
+    let mut blobs = Vec::new();
+    add_roots(repo, &mut roots, &mut blobs)?;
+    for worktree in worktree_roots(repo)? {
+        roots.extend(worktree.head.ok().flatten());
+        for (old, new) in worktree.reflog {
+            roots.extend([old, new].iter().filter(|id| !id.is_zero()));
+        }
+        roots.extend(worktree.pseudo_refs.into_iter().map(|(_, id)| id));
+        blobs.extend(worktree.index);
+    }
+
+    let odb = repo.odb()?;
+    let mut seen = HashSet::new();
+    seen.extend(blobs.into_iter().filter(|id| odb.exists(*id)));
+    mark(repo, roots, &mut seen)?;
+    Ok(seen)
+}
+
+/// Find the objects of `repo` to keep when expiring the ones last modified
+/// before `cutoff`: the reachable objects, and like git's
+/// `mark_recent_objects`, the recent unreachable objects and the ones in kept
+/// packs along with everything they reference.
+///
+/// Recent unreachable objects may be about to be referenced by a concurrent
+/// process, which may have reused older objects for them.
+fn retained(
+    repo: &Repository,
+    objects: &HashMap<Oid, LocalObject>,
+    cutoff: SystemTime,
+) -> Result<HashSet<Oid>, Error> {
+    let mut retained = reachable(repo)?;
+    let recent = objects
+        .iter()
+        .filter(|(id, object)| !retained.contains(id) && (object.modified >= cutoff || object.kept))
+        .map(|(id, _)| *id)
+        .collect();
+    mark(repo, recent, &mut retained)?;
+    Ok(retained)
+}
+
+/// Add the objects reachable from `roots` to `seen`, not walking further
+/// from the ones already there.
+fn mark(repo: &Repository, mut roots: Vec<Oid>, seen: &mut HashSet<Oid>) -> Result<(), Error> {
+    let odb = repo.odb()?;
+    while let Some(id) = roots.pop() {
+        if seen.contains(&id) {
+            continue;
+        }
+        let object = match repo.find_object(id, None) {
+            Ok(object) => object,
+            Err(ref e) if e.code() == ErrorCode::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        seen.insert(id);
+        match object.kind() {
+            Some(ObjectType::Commit) => {
+                let commit = object.as_commit().unwrap();
+                roots.push(commit.tree_id());
+                roots.extend(commit.parent_ids());
+            }
+            Some(ObjectType::Tag) => roots.push(object.as_tag().unwrap().target_id()),
+            Some(ObjectType::Tree) => {
+                for entry in object.as_tree().unwrap().iter() {
+                    match entry.kind() {
+                        Some(ObjectType::Tree) => roots.push(entry.id()),
+                        Some(ObjectType::Blob) if odb.exists(entry.id()) => {
+                            seen.insert(entry.id());
+                        }
+                        // Submodule commits are not part of this repository.
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn add_roots(repo: &Repository, roots: &mut Vec<Oid>, blobs: &mut Vec<Oid>) -> Result<(), Error> {
+    let mut logs = vec!["HEAD".to_string()];
+    if let Ok(head) = repo.refname_to_id("HEAD") {
+        roots.push(head);
+    }
+    // The stash is covered by `refs/stash` and its reflog.
+    for reference in repo.references()? {
+        let reference = reference?;
+        if let Some(id) = reference.target() {
+            roots.push(id);
+        }
+        if let Some(name) = reference.name() {
+            logs.push(name.to_string());
+        }
+    }
+    for name in logs {
+        for entry in repo.reflog(&name)?.iter() {
+            roots.extend(
+                [entry.id_old(), entry.id_new()]
+                    .iter()
+                    .filter(|id| !id.is_zero()),
+            );
+        }
+    }
+    roots.extend(
+        pseudo_ref_targets(repo.path())?
+            .into_iter()
+            .map(|(_, id)| id),
+    );
+    if !repo.is_bare() {
+        for entry in repo.index()?.iter() {
+            // Skip submodules.
+            if entry.mode != 0o160000 {
+                blobs.push(entry.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The objects named by the pseudo-refs in `git_dir`, such as `ORIG_HEAD`,
+/// along with the name of the pseudo-ref.
+pub(crate) fn pseudo_ref_targets(git_dir: &Path) -> Result<Vec<(&'static str, Oid)>, Error> {
+    let mut targets = Vec::new();
+    for name in PSEUDO_REFS {
+        let contents = match fs::read_to_string(git_dir.join(name)) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(io_error(e)),
+        };
+        // `FETCH_HEAD` and `MERGE_HEAD` hold one object per line.
+        for line in contents.lines() {
+            if let Some(id) = line.get(..40).and_then(|hex| Oid::from_str(hex).ok()) {
+                targets.push((*name, id));
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// The roots of a worktree other than the main one.
+///
+/// They are read from the administrative directory of the worktree,
+/// `$GIT_DIR/worktrees/<id>`, so that they are found even when its working
+/// directory is missing, e.g. on an unmounted drive.
+pub(crate) struct WorktreeRoots {
+    /// The id of the worktree.
+    pub(crate) id: String,
+    /// The commit `HEAD` points to when it is detached, or why it could not
+    /// be read. A symbolic `HEAD` is covered by its branch.
+    pub(crate) head: Result<Option<Oid>, String>,
+    /// The old and new ids of the entries of the reflog of `HEAD`, from the
+    /// most recent.
+    pub(crate) reflog: Vec<(Oid, Oid)>,
+    /// The objects named by the pseudo-refs of the worktree.
+    pub(crate) pseudo_refs: Vec<(&'static str, Oid)>,
+    /// The blobs of the index, submodules left out.
+    pub(crate) index: Vec<Oid>,
+}
+
+/// Read the roots of the worktrees of `repo`. Worktrees whose
+/// administrative directory is gone are skipped.
+pub(crate) fn worktree_roots(repo: &Repository) -> Result<Vec<WorktreeRoots>, Error> {
+    let dir = repo.common_dir().join("worktrees");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(io_error(e)),
+    };
+    let mut worktrees = Vec::new();
+    for entry in entries {
+        let admin = entry.map_err(io_error)?.path();
+        if !admin.is_dir() {
+            continue;
+        }
+        let head = match fs::read_to_string(admin.join("HEAD")) {
+            Ok(head) => head,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(io_error(e)),
+        };
+        let head = head.trim_end();
+        let head = if head.starts_with("ref: ") {
+            Ok(None)
+        } else {
+            Oid::from_str(head)
+                .map(Some)
+                .map_err(|_| format!("invalid HEAD '{}'", head))
+        };
+
+        let mut reflog = match fs::read_to_string(admin.join("logs/HEAD")) {
+            Ok(log) => log
+                .lines()
+                .filter_map(|line| {
+                    let old = Oid::from_str(line.get(..40)?).ok()?;
+                    let new = Oid::from_str(line.get(41..81)?).ok()?;
+                    Some((old, new))
+                })
+                .collect(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(io_error(e)),
+        };
+        reflog.reverse();
+
+        let mut index = Vec::new();
+        let index_path = admin.join("index");
+        if index_path.exists() {
+            for entry in Index::open(&index_path)?.iter() {
+                if entry.mode != 0o160000 {
+                    index.push(entry.id);
+                }
+            }
+        }
+
+        worktrees.push(WorktreeRoots {
+            id: admin
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            head,
+            reflog,
+            pseudo_refs: pseudo_ref_targets(&admin)?,
+            index,
+        });
+    }
+    worktrees.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(worktrees)
+}
+
+/// An object stored in the objects directory of a repository.
+struct LocalObject {
+    /// The path of the loose object, if there is one.
+    loose: Option<PathBuf>,
+    packed: bool,
+    /// Whether the object is stored in a pack with a `.keep` file.
+    kept: bool,
+    /// When the object was last written, loose or as part of a pack.
+    modified: SystemTime,
+}
+
+/// List the objects stored in `objects_dir`, loose or packed, ignoring
+/// those of alternate object databases.
+fn local_objects(objects_dir: &Path) -> Result<HashMap<Oid, LocalObject>, Error> {
+    fn add(
+        objects: &mut HashMap<Oid, LocalObject>,
+        id: Oid,
+        modified: SystemTime,
+    ) -> &mut LocalObject {
+        let object = objects.entry(id).or_insert(LocalObject {
+            loose: None,
+            packed: false,
+            kept: false,
+            modified,
+        });
+        object.modified = object.modified.max(modified);
+        object
+    }
+
+    let mut objects = HashMap::new();
+    for index in pack_files(&objects_dir.join("pack"), "idx")? {
+        let pack = index.with_extension("pack");
+        let modified = fs::metadata(&pack)
+            .and_then(|m| m.modified())
+            .map_err(io_error)?;
+        let kept = index.with_extension("keep").exists();
+        pack_odb(&index)?.foreach(|id| {
+            let object = add(&mut objects, *id, modified);
+            object.packed = true;
+            object.kept |= kept;
+            true
+        })?;
+    }
+    for (id, path) in loose_objects(objects_dir)? {
+        let modified = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .map_err(io_error)?;
+        add(&mut objects, id, modified).loose = Some(path);
+    }
+    Ok(objects)
+}
+
+/// Write `objects` to a new pack of `repo`, returning its name.
+fn write_pack(repo: &Repository, objects: HashSet<Oid>, threads: u32) -> Result<String, Error> {
+    let pack_dir = repo.objects_dir().join("pack");
+    let mut builder = repo.packbuilder()?;
+    builder.set_threads(threads);
+    for id in objects {
+        builder.insert_object(id, None)?;
+    }
+    fs::create_dir_all(&pack_dir).map_err(io_error)?;
+    builder.write(&pack_dir, 0)?;
+    match builder.name() {
+        Some(name) => Ok(name.to_string()),
+        None => Err(Error::from_str("failed to get the name of the new pack")),
+    }
+}
+
+/// Delete `packs`, except for the pack `name` and those with a `.keep`
+/// file.
+fn remove_packs(repo: &Repository, packs: &[PathBuf], name: &str) -> Result<(), Error> {
+    let pack_dir = repo.objects_dir().join("pack");
+    let new_pack = pack_dir.join(format!("pack-{}.pack", name));
+    for pack in packs {
+        if *pack == new_pack || pack.with_extension("keep").exists() {
+            continue;
+        }
+        for ext in PACK_EXTENSIONS {
+            remove_file(&pack.with_extension(ext))?;
+        }
+        remove_file(pack)?;
+    }
+    // A multi-pack-index would refer to the deleted packs.
+    if pack_dir.join(MIDX).exists() {
+        write_midx(repo)?;
+    }
+    Ok(())
+}
+
+/// An object database reading only the pack of the index file `index`.
+fn pack_odb(index: &Path) -> Result<Odb<'static>, Error> {
+    let index = index.into_c_string()?;
+    unsafe {
+        let mut backend = ptr::null_mut();
+        try_call!(raw::git_odb_backend_one_pack(&mut backend, index));
+        backend_odb(backend)
+    }
+}
+
+/// An object database writing only loose objects to `objects_dir`.
+fn loose_odb(objects_dir: &Path) -> Result<Odb<'static>, Error> {
+    let objects_dir = objects_dir.into_c_string()?;
+    unsafe {
+        let mut backend = ptr::null_mut();
+        try_call!(raw::git_odb_backend_loose(
+            &mut backend,
+            objects_dir,
+            -1,
+            0,
+            0,
+            0
+        ));
+        backend_odb(backend)
+    }
+}
+
+/// An object database with `backend` as its only backend, which it takes
+/// ownership of.
+unsafe fn backend_odb(backend: *mut raw::git_odb_backend) -> Result<Odb<'static>, Error> {
+    let free = || {
+        if let Some(free) = (*backend).free {
+            free(backend);
+        }
+    };
+    let odb = match Odb::new() {
+        Ok(odb) => odb,
+        Err(e) => {
+            free();
+            return Err(e);
+        }
+    };
+    // The backend is only owned by the database once added.
+    if let Err(e) = call::c_try(raw::git_odb_add_backend(odb.raw(), backend, 1)) {
+        free();
+        return Err(e);
+    }
+    Ok(odb)
+}
+
+/// List the files of `pack_dir` with the extension `ext`.
+fn pack_files(pack_dir: &Path, ext: &str) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(io_error(e)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(io_error)?.path();
+        if path.extension().is_some_and(|e| e == ext) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// List the loose objects of `objects_dir` with their paths.
+fn loose_objects(objects_dir: &Path) -> Result<Vec<(Oid, PathBuf)>, Error> {
+    let mut objects = Vec::new();
+    for fanout in fanout_dirs(objects_dir)? {
+        let prefix = fanout.file_name().unwrap().to_string_lossy().into_owned();
+        for entry in fs::read_dir(&fanout).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(name) if name.len() == 38 => name,
+                _ => continue,
+            };
+            if let Ok(id) = Oid::from_str(&format!("{}{}", prefix, name)) {
+                objects.push((id, entry.path()));
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// List the `objects/xx` directories holding loose objects.
+fn fanout_dirs(objects_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(objects_dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(io_error(e)),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(io_error)?;
+        let is_fanout = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()));
+        if is_fanout && entry.path().is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+fn remove_empty_fanout(objects_dir: &Path) -> Result<(), Error> {
+    for dir in fanout_dirs(objects_dir)? {
+        // Fails if the directory still holds objects.
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Os,
+        format!("failed to maintain the object database: {}", e),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use crate::test::commit_files;
+    use crate::{GcOptions, Oid, RepackOptions, Repository, UnreachableOptions};
+
+    fn packs(repo: &Repository) -> usize {
+        let dir = repo.path().join("objects/pack");
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter(|e| {
+                        let path = e.as_ref().unwrap().path();
+                        path.extension().is_some_and(|e| e == "pack")
+                    })
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    fn loose(repo: &Repository) -> usize {
+        super::loose_objects(&repo.path().join("objects"))
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        commit_files(&repo, &[("a", Some("a"))], "a");
+        commit_files(&repo, &[("b", Some("b"))], "b");
+        let dangling = repo.blob(b"dangling").unwrap();
+        assert_eq!(packs(&repo), 0);
+        let objects = loose(&repo);
+
+        // Unreachable objects younger than the cutoff are kept.
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(repo.expire_unreachable(old).unwrap(), 0);
+
+        // Unreachable objects are not packed.
+        let mut opts = RepackOptions::new();
+        opts.delete_old(false);
+        repo.repack(Some(&opts)).unwrap().unwrap();
+        assert_eq!(packs(&repo), 1);
+        assert_eq!(loose(&repo), objects);
+        assert_eq!(repo.prune_packed().unwrap(), objects - 1);
+        assert_eq!(loose(&repo), 1);
+        assert!(repo.find_blob(dangling).is_ok());
+
+        // A second pack, covered by a multi-pack-index.
+        commit_files(&repo, &[("c", Some("c"))], "c");
+        let mut builder = repo.packbuilder().unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        builder.insert_commit(head).unwrap();
+        builder.write(&repo.path().join("objects/pack"), 0).unwrap();
+        assert_eq!(packs(&repo), 2);
+        repo.write_multi_pack_index().unwrap();
+        assert!(repo.path().join("objects/pack/multi-pack-index").exists());
+
+        // Only reachable objects are kept by the new pack, which leaves
+        // loose objects alone.
+        let mut opts = RepackOptions::new();
+        opts.reachable_only(true);
+        repo.repack(Some(&opts)).unwrap().unwrap();
+        assert_eq!(packs(&repo), 1);
+        assert_eq!(loose(&repo), 1);
+        let repo = Repository::open(repo.path()).unwrap();
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        assert_eq!(walk.count(), 4);
+
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        assert_eq!(repo.expire_unreachable(future).unwrap(), 1);
+        assert_eq!(loose(&repo), 0);
+        assert!(repo.find_blob(dangling).is_err());
+    }
+
+    #[test]
+    fn unreachable_packed_objects() {
+        let (_td, repo) = crate::test::repo_init();
+        commit_files(&repo, &[("a", Some("a"))], "a");
+        let dangling = repo.blob(b"dangling").unwrap();
+        let mut builder = repo.packbuilder().unwrap();
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        builder.insert_walk(&mut walk).unwrap();
+        builder.insert_object(dangling, None).unwrap();
+        let pack_dir = repo.path().join("objects/pack");
+        builder.write(&pack_dir, 0).unwrap();
+        let pack = pack_dir.join(format!("pack-{}.pack", builder.name().unwrap()));
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        super::set_modified(&pack, old).unwrap();
+        repo.prune_packed().unwrap();
+        assert_eq!(loose(&repo), 0);
+
+        // Repacking writes the unreachable object loose, as old as its pack
+        // was, and drops it with `reachable_only`.
+        repo.repack(None).unwrap().unwrap();
+        assert_eq!(
+            super::loose_objects(&repo.path().join("objects")).unwrap()[0].0,
+            dangling
+        );
+        let cutoff = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(repo.unreachable_objects(None).unwrap(), vec![dangling]);
+        let mut opts = UnreachableOptions::new();
+        opts.expire(cutoff);
+        assert_eq!(
+            repo.unreachable_objects(Some(&opts)).unwrap(),
+            vec![dangling]
+        );
+        assert_eq!(repo.expire_unreachable(cutoff).unwrap(), 1);
+
+        let dangling = repo.blob(b"dangling").unwrap();
+        repo.repack(None).unwrap().unwrap();
+        let mut builder = repo.packbuilder().unwrap();
+        builder.insert_object(dangling, None).unwrap();
+        builder.write(&pack_dir, 0).unwrap();
+        repo.prune_packed().unwrap();
+        let mut opts = RepackOptions::new();
+        opts.reachable_only(true);
+        repo.repack(Some(&opts)).unwrap().unwrap();
+        assert_eq!(loose(&repo), 0);
+        let repo = Repository::open(repo.path()).unwrap();
+        assert!(repo.find_blob(dangling).is_err());
+    }
+
+    #[test]
+    fn gc() {
+        let (_td, repo) = crate::test::repo_init();
+        commit_files(&repo, &[("a", Some("a"))], "a");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let dangling = repo.blob(b"dangling").unwrap();
+
+        // A commit only referenced by `ORIG_HEAD`.
+        let sig = repo.signature().unwrap();
+        let tree = head.tree().unwrap();
+        let orig = repo
+            .commit(None, &sig, &sig, "orig", &tree, &[&head])
+            .unwrap();
+        fs::write(repo.path().join("ORIG_HEAD"), format!("{}\n", orig)).unwrap();
+        // And a tree only referenced by the `AUTO_MERGE` of a conflicted
+        // merge.
+        let mut builder = repo.treebuilder(Some(&tree)).unwrap();
+        builder.insert("merged", dangling, 0o100644).unwrap();
+        let auto_merge = builder.write().unwrap();
+        fs::write(repo.path().join("AUTO_MERGE"), format!("{}\n", auto_merge)).unwrap();
+        let dangling = repo.blob(b"really dangling").unwrap();
+
+        let reachable = repo.reachable_objects().unwrap();
+        assert!(reachable.contains(&auto_merge));
+        assert!(reachable.contains(&head.id()));
+        assert!(reachable.contains(&head.tree_id()));
+        assert!(reachable.contains(&orig));
+        assert!(!reachable.contains(&dangling));
+        assert_eq!(repo.unreachable_objects(None).unwrap(), vec![dangling]);
+        let mut opts = UnreachableOptions::new();
+        opts.expire(SystemTime::now() - Duration::from_secs(3600));
+        assert!(repo.unreachable_objects(Some(&opts)).unwrap().is_empty());
+
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        let mut opts = GcOptions::new();
+        opts.dry_run(true).expire(future);
+        let loose_before = loose(&repo);
+        let report = repo.gc(Some(&opts)).unwrap();
+        assert_eq!(report.expired(), &[dangling]);
+        assert!(report.deleted_bytes() > 0);
+        assert!(report.pack().is_none());
+        assert_eq!(loose(&repo), loose_before);
+        assert!(repo.path().join("refs/heads/main").exists());
+
+        // Recent unreachable objects are kept.
+        let report = repo.gc(None).unwrap();
+        assert!(report.expired().is_empty());
+        assert!(report.pack().is_some());
+        assert_eq!(packs(&repo), 1);
+        assert_eq!(loose(&repo), 0);
+        assert!(!repo.path().join("refs/heads/main").exists());
+        assert_eq!(repo.unreachable_objects(None).unwrap(), vec![dangling]);
+        let mut opts = UnreachableOptions::new();
+        opts.packed(false);
+        assert!(repo.unreachable_objects(Some(&opts)).unwrap().is_empty());
+
+        let mut opts = GcOptions::new();
+        opts.expire(future);
+        let report = repo.gc(Some(&opts)).unwrap();
+        assert_eq!(report.expired(), &[dangling]);
+        assert_eq!(report.removed_packs().len(), 1);
+        assert_eq!(packs(&repo), 1);
+        let repo = Repository::open(repo.path()).unwrap();
+        assert!(repo.find_blob(dangling).is_err());
+        assert!(repo.find_commit(orig).is_ok());
+        assert!(repo.unreachable_objects(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn locked_worktree() {
+        let (_td, repo) = crate::test::repo_init();
+        let td = tempfile::TempDir::new().unwrap();
+        let path = td.path().join("wt");
+        let worktree = repo.worktree("wt", &path, None).unwrap();
+
+        // A detached `HEAD` and a staged blob only known to the worktree.
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = head.tree().unwrap();
+        let detached = repo
+            .commit(None, &sig, &sig, "detached", &tree, &[&head])
+            .unwrap();
+        let wt = Repository::open_from_worktree(&worktree).unwrap();
+        wt.set_head_detached(detached).unwrap();
+        fs::write(path.join("staged"), "staged").unwrap();
+        let mut index = wt.index().unwrap();
+        index.add_path(Path::new("staged")).unwrap();
+        index.write().unwrap();
+        let staged = index.get_path(Path::new("staged"), 0).unwrap().id;
+        drop(wt);
+
+        // The drive holding the worktree is not mounted.
+        worktree.lock(Some("on an unmounted drive")).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+        assert!(worktree.validate().is_err());
+
+        let mut opts = GcOptions::new();
+        opts.expire(SystemTime::now() + Duration::from_secs(3600));
+        let report = repo.gc(Some(&opts)).unwrap();
+        assert!(report.expired().is_empty());
+        let repo = Repository::open(repo.path()).unwrap();
+        assert!(repo.find_commit(detached).is_ok());
+        assert!(repo.find_blob(staged).is_ok());
+    }
+
+    /// Make the loose object `id` look as if it was written at `time`.
+    fn set_mtime(repo: &Repository, id: Oid, time: SystemTime) {
+        let hex = id.to_string();
+        let path = repo.path().join("objects").join(&hex[..2]).join(&hex[2..]);
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(&path, perms).unwrap();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn recent_objects_keep_what_they_reference() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let old = SystemTime::now() - Duration::from_secs(30 * 24 * 3600);
+        let cutoff = SystemTime::now() - Duration::from_secs(3600);
+
+        // An old blob and tree, and a recent commit using them which nothing
+        // references yet.
+        let blob = repo.blob(b"old").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("old", blob, 0o100644).unwrap();
+        let tree = builder.write().unwrap();
+        let dangling = repo.blob(b"dangling").unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let recent = repo
+            .commit(None, &sig, &sig, "recent", &tree, &[&head])
+            .unwrap();
+        // Writing the commit freshened its tree.
+        for id in [blob, tree.id(), dangling] {
+            set_mtime(&repo, id, old);
+        }
+
+        let mut opts = UnreachableOptions::new();
+        opts.expire(cutoff);
+        let expiring = repo.unreachable_objects(Some(&opts)).unwrap();
+        assert_eq!(expiring, vec![dangling]);
+
+        let mut opts = GcOptions::new();
+        opts.expire(cutoff).repack(false);
+        let report = repo.gc(Some(&opts)).unwrap();
+        assert_eq!(report.expired(), &[dangling]);
+        let repo = Repository::open(repo.path()).unwrap();
+        assert!(repo.find_blob(dangling).is_err());
+        let commit = repo.find_commit(recent).unwrap();
+        let tree = commit.tree().unwrap();
+        assert_eq!(tree.get_name("old").unwrap().id(), blob);
+        assert_eq!(repo.find_blob(blob).unwrap().content(), b"old");
+
+        // Once the commit is old too, it goes along with them.
+        set_mtime(&repo, recent, old);
+        let report = repo.gc(Some(&opts)).unwrap();
+        let mut expected = vec![blob, tree.id(), recent];
+        expected.sort();
+        assert_eq!(report.expired(), &expected[..]);
+    }
+
+    #[test]
+    fn pack_refs() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("topic", &head, false).unwrap();
+        assert!(repo.path().join("refs/heads/topic").exists());
+        repo.pack_refs().unwrap();
+        assert!(!repo.path().join("refs/heads/topic").exists());
+        let packed = fs::read_to_string(repo.path().join("packed-refs")).unwrap();
+        assert!(packed.contains("refs/heads/topic"));
+        assert!(repo.find_branch("topic", crate::BranchType::Local).is_ok());
+    }
+}
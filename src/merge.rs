@@ -1,11 +1,20 @@
 use libc::c_uint;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
 use std::marker;
 use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
 use std::str;
 
+use crate::build::CheckoutBuilder;
 use crate::call::Convert;
-use crate::util::Binding;
-use crate::{raw, Commit, FileFavor, Oid};
+use crate::util::{self, Binding};
+use crate::{raw, Commit, Error, FileFavor, Index, IndexEntry, IntoCString, ObjectType, Oid};
+use crate::{ErrorClass, ErrorCode, Repository, Tree};
 
 /// A structure to represent an annotated commit, the input to merge and rebase.
 ///
@@ -20,6 +29,79 @@ pub struct AnnotatedCommit<'repo> {
 /// Options to specify when merging.
 pub struct MergeOptions {
     raw: raw::git_merge_options,
+    strategy: MergeStrategy,
+}
+
+/// The strategy used to combine the merged heads, like the `-s` option of
+/// `git merge`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Three-way merge of two heads, using libgit2's recursive merge. When
+    /// more than two heads are merged, the octopus strategy is used instead.
+    /// This is the default.
+    #[default]
+    Recursive,
+    /// Merge any number of heads by merging them one at a time into the
+    /// result so far. The merge is aborted if a conflict arises before the
+    /// last head.
+    Octopus,
+    /// Record a merge of the heads but keep our tree unchanged, discarding
+    /// all of their changes.
+    Ours,
+    /// Three-way merge where their tree is first shifted to line up with a
+    /// subdirectory of ours.
+    ///
+    /// The subdirectory may be given explicitly, like `-Xsubtree=<path>`. If
+    /// it is `None`, the subdirectory of our tree which best matches the
+    /// root of theirs is used.
+    Subtree(Option<PathBuf>),
+}
+
+/// A summary of the outcome of a tree merge: which paths conflict and why,
+/// and which paths were changed on both sides but merged cleanly.
+///
+/// A report is produced alongside the merged index by
+/// [`Repository::merge_trees_with_report`] and
+/// [`Repository::merge_commits_with_report`].
+pub struct MergeReport {
+    conflicts: Vec<MergeConflict>,
+    auto_merged: Vec<PathBuf>,
+    file_favor: raw::git_merge_file_favor_t,
+    file_flags: u32,
+}
+
+/// A single conflict of a [`MergeReport`].
+#[derive(Clone, Debug)]
+pub struct MergeConflict {
+    kind: MergeConflictKind,
+    ancestor: Option<MergeConflictSide>,
+    ours: Option<MergeConflictSide>,
+    theirs: Option<MergeConflictSide>,
+}
+
+/// One version of a conflicting file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeConflictSide {
+    path: PathBuf,
+    id: Oid,
+    mode: u32,
+}
+
+/// The reason a path could not be merged.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MergeConflictKind {
+    /// Both sides changed the same file in overlapping ways.
+    Content,
+    /// Both sides added a file at the same path with different contents.
+    AddAdd,
+    /// One side modified the file while the other deleted it.
+    ModifyDelete,
+    /// Both sides renamed the file, to different paths.
+    RenameRename,
+    /// One side renamed the file while the other deleted it.
+    RenameDelete,
+    /// One side has a file where the other side has a directory.
+    DirectoryFile,
 }
 
 impl<'repo> AnnotatedCommit<'repo> {
@@ -39,6 +121,22 @@ impl<'repo> AnnotatedCommit<'repo> {
     pub fn refname_bytes(&self) -> &[u8] {
         unsafe { crate::opt_bytes(self, raw::git_annotated_commit_ref(&*self.raw)).unwrap() }
     }
+
+    /// The refname this commit was looked up from, if it was looked up from
+    /// a reference and the name is valid utf-8.
+    pub(crate) fn ref_name(&self) -> Option<&str> {
+        unsafe { crate::opt_bytes(self, raw::git_annotated_commit_ref(&*self.raw)) }
+            .and_then(|name| str::from_utf8(name).ok())
+    }
+}
+
+impl Clone for MergeOptions {
+    fn clone(&self) -> Self {
+        MergeOptions {
+            raw: unsafe { ptr::read(&self.raw) },
+            strategy: self.strategy.clone(),
+        }
+    }
 }
 
 impl Default for MergeOptions {
@@ -52,6 +150,7 @@ impl MergeOptions {
     pub fn new() -> MergeOptions {
         let mut opts = MergeOptions {
             raw: unsafe { mem::zeroed() },
+            strategy: MergeStrategy::Recursive,
         };
         assert_eq!(unsafe { raw::git_merge_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -168,12 +267,790 @@ impl MergeOptions {
         self.file_flag(raw::GIT_MERGE_FILE_DIFF_MINIMAL as u32, minimal)
     }
 
+    /// Select the strategy used to combine the merged heads.
+    ///
+    /// The default is [`MergeStrategy::Recursive`].
+    pub fn strategy(&mut self, strategy: MergeStrategy) -> &mut MergeOptions {
+        self.strategy = strategy;
+        self
+    }
+
     /// Acquire a pointer to the underlying raw options.
     pub unsafe fn raw(&self) -> *const raw::git_merge_options {
         &self.raw as *const _
     }
 }
 
+/// The strategy selected by `opts`, if any.
+pub(crate) fn strategy_of(opts: Option<&MergeOptions>) -> MergeStrategy {
+    opts.map(|o| o.strategy.clone()).unwrap_or_default()
+}
+
+/// Merge `heads` into `HEAD` with a strategy libgit2 does not implement
+/// itself, leaving the repository in the same state `git_merge` would:
+/// the merge state files are written and the result is checked out.
+pub(crate) fn merge_heads(
+    repo: &Repository,
+    heads: &[&AnnotatedCommit<'_>],
+    opts: Option<&MergeOptions>,
+    checkout: Option<&mut CheckoutBuilder<'_>>,
+) -> Result<(), Error> {
+    if heads.is_empty() {
+        return Err(Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Merge,
+            "no heads given to merge",
+        ));
+    }
+    if repo.is_bare() {
+        return Err(Error::new(
+            ErrorCode::BareRepo,
+            ErrorClass::Repository,
+            "cannot merge in a bare repository",
+        ));
+    }
+    let ours = repo.head()?.peel_to_commit()?;
+    let theirs = heads
+        .iter()
+        .map(|head| repo.find_commit(head.id()))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut index = match strategy_of(opts) {
+        MergeStrategy::Ours => tree_index(&ours.tree()?)?,
+        MergeStrategy::Subtree(_) if theirs.len() == 1 => {
+            repo.merge_commits(&ours, &theirs[0], opts)?
+        }
+        MergeStrategy::Subtree(_) => {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Merge,
+                "the subtree strategy can only merge a single branch",
+            ))
+        }
+        MergeStrategy::Recursive | MergeStrategy::Octopus => octopus(repo, &ours, &theirs, opts)?,
+    };
+
+    write_merge_state(repo, ours.id(), heads, &index)?;
+    let mut default_checkout = CheckoutBuilder::new();
+    let checkout = checkout.unwrap_or(&mut default_checkout);
+    if index.has_conflicts() {
+        checkout.allow_conflicts(true);
+    }
+    repo.checkout_index(Some(&mut index), Some(checkout))
+}
+
+/// Merge `theirs` into `ours` one head at a time, like `git merge-octopus`.
+fn octopus(
+    repo: &Repository,
+    ours: &Commit<'_>,
+    theirs: &[Commit<'_>],
+    opts: Option<&MergeOptions>,
+) -> Result<Index, Error> {
+    let mut merged = vec![ours.id()];
+    let mut tree = ours.tree()?;
+    let mut index = tree_index(&tree)?;
+    let mut fast_forward = true;
+    for (i, head) in theirs.iter().enumerate() {
+        if index.has_conflicts() {
+            return Err(Error::new(
+                ErrorCode::MergeConflict,
+                ErrorClass::Merge,
+                format!(
+                    "automated merge of {} did not work; should not be doing an octopus",
+                    theirs[i - 1].id()
+                ),
+            ));
+        }
+        let mut ids = vec![head.id()];
+        ids.extend(&merged);
+        let base = repo.merge_base_many(&ids)?;
+        if base == head.id() {
+            // Already up to date with this head.
+            continue;
+        }
+        if fast_forward && merged.len() == 1 && base == merged[0] {
+            merged[0] = head.id();
+            tree = head.tree()?;
+            index = tree_index(&tree)?;
+            continue;
+        }
+        fast_forward = false;
+        let base_tree = repo.find_commit(base)?.tree()?;
+        index = repo.merge_trees(&base_tree, &tree, &head.tree()?, opts)?;
+        if !index.has_conflicts() {
+            tree = repo.find_tree(index.write_tree_to(repo)?)?;
+        }
+        merged.push(head.id());
+    }
+    Ok(index)
+}
+
+/// An in-memory index holding the contents of `tree`.
+fn tree_index(tree: &Tree<'_>) -> Result<Index, Error> {
+    let mut index = Index::new()?;
+    index.read_tree(tree)?;
+    Ok(index)
+}
+
+/// The subdirectory of `ours` which `theirs` lines up with: `prefix`, or
+/// without one, the subdirectory of `ours` whose entries best match those of
+/// `theirs`. Returns `None` if that is the root.
+pub(crate) fn subtree_prefix(
+    repo: &Repository,
+    ours: &Tree<'_>,
+    theirs: &Tree<'_>,
+    prefix: Option<&Path>,
+) -> Result<Option<String>, Error> {
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .to_str()
+            .ok_or_else(|| Error::from_str("subtree prefix is not valid utf-8"))?
+            .trim_matches('/')
+            .to_string(),
+        None => best_subtree(repo, ours, theirs)?.unwrap_or_default(),
+    };
+    Ok(Some(prefix).filter(|p| !p.is_empty()))
+}
+
+/// Graft `tree` into a copy of `ours`, so that it takes the place of the
+/// subdirectory at `prefix`.
+pub(crate) fn shift_tree<'repo>(
+    repo: &'repo Repository,
+    ours: &Tree<'_>,
+    tree: &Tree<'_>,
+    prefix: &str,
+) -> Result<Tree<'repo>, Error> {
+    let components = prefix.split('/').collect::<Vec<_>>();
+    let id = graft(repo, Some(ours), &components, tree.id())?;
+    repo.find_tree(id)
+}
+
+fn graft(
+    repo: &Repository,
+    tree: Option<&Tree<'_>>,
+    components: &[&str],
+    id: Oid,
+) -> Result<Oid, Error> {
+    let mut builder = repo.treebuilder(tree)?;
+    let id = match components {
+        [_] => id,
+        [name, rest @ ..] => {
+            let subtree = match tree.and_then(|t| t.get_name(name)) {
+                Some(entry) if entry.kind() == Some(ObjectType::Tree) => {
+                    Some(repo.find_tree(entry.id())?)
+                }
+                _ => None,
+            };
+            graft(repo, subtree.as_ref(), rest, id)?
+        }
+        [] => unreachable!(),
+    };
+    builder.insert(components[0], id, 0o040000)?;
+    builder.write()
+}
+
+/// How many levels of subdirectories below the first one are searched for
+/// the best match of a subtree merge, like the default of git's
+/// `shift_tree`.
+const SUBTREE_DEPTH: usize = 2;
+
+/// The path of the subdirectory of `ours` which shares the most entries
+/// with the root of `theirs`.
+///
+/// Like git, only subdirectories at most `SUBTREE_DEPTH` levels below the
+/// first one are considered, and each distinct tree is scored once.
+fn best_subtree(
+    repo: &Repository,
+    ours: &Tree<'_>,
+    theirs: &Tree<'_>,
+) -> Result<Option<String>, Error> {
+    let mut search = SubtreeSearch {
+        repo,
+        theirs,
+        seen: HashSet::new(),
+        best: (score_subtree(ours, theirs), None),
+    };
+    search.search(ours, "", SUBTREE_DEPTH)?;
+    Ok(search.best.1)
+}
+
+/// How many entries of `theirs` `tree` has, counting twice the ones which
+/// are the same.
+fn score_subtree(tree: &Tree<'_>, theirs: &Tree<'_>) -> usize {
+    theirs
+        .iter()
+        .map(|entry| match tree.get_name_bytes(entry.name_bytes()) {
+            Some(other) if other.id() == entry.id() => 2,
+            Some(_) => 1,
+            None => 0,
+        })
+        .sum()
+}
+
+struct SubtreeSearch<'a, 'repo> {
+    repo: &'repo Repository,
+    theirs: &'a Tree<'repo>,
+    /// The trees scored already.
+    seen: HashSet<Oid>,
+    best: (usize, Option<String>),
+}
+
+impl<'a, 'repo> SubtreeSearch<'a, 'repo> {
+    fn search(&mut self, tree: &Tree<'_>, prefix: &str, depth: usize) -> Result<(), Error> {
+        for entry in tree.iter() {
+            let name = match entry.name() {
+                Some(name) if entry.kind() == Some(ObjectType::Tree) => name,
+                _ => continue,
+            };
+            if !self.seen.insert(entry.id()) {
+                continue;
+            }
+            let subtree = self.repo.find_tree(entry.id())?;
+            let path = format!("{}{}", prefix, name);
+            let score = score_subtree(&subtree, self.theirs);
+            if score > self.best.0 {
+                self.best = (score, Some(path.clone()));
+            }
+            if depth > 0 {
+                self.search(&subtree, &format!("{}/", path), depth - 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write `ORIG_HEAD`, `MERGE_HEAD`, `MERGE_MODE` and `MERGE_MSG` for a
+/// merge of `heads` into `ours` which produced `index`.
+fn write_merge_state(
+    repo: &Repository,
+    ours: Oid,
+    heads: &[&AnnotatedCommit<'_>],
+    index: &Index,
+) -> Result<(), Error> {
+    let path = repo.path();
+    let mut merge_head = String::new();
+    for head in heads {
+        merge_head.push_str(&format!("{}\n", head.id()));
+    }
+    let mut msg = merge_message(heads);
+    msg.push_str(&conflicts_comment(index)?);
+    let write = |name: &str, contents: &str| {
+        fs::write(path.join(name), contents).map_err(|e| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to write {}: {}", name, e),
+            )
+        })
+    };
+    // Like git, only record that the merge must not fast-forward when
+    // `merge.ff` says so.
+    let no_ff = repo.config()?.get_bool("merge.ff").ok() == Some(false);
+    write("ORIG_HEAD", &format!("{}\n", ours))?;
+    write("MERGE_HEAD", &merge_head)?;
+    write("MERGE_MODE", if no_ff { "no-ff" } else { "" })?;
+    write("MERGE_MSG", &msg)
+}
+
+/// The `#Conflicts:` section listing the conflicted paths of `index` which
+/// git appends to `MERGE_MSG`, or nothing if there are no conflicts.
+pub(crate) fn conflicts_comment(index: &Index) -> Result<String, Error> {
+    let mut msg = String::new();
+    if !index.has_conflicts() {
+        return Ok(msg);
+    }
+    let mut paths = Vec::new();
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+        if let Some(entry) = entry {
+            paths.push(String::from_utf8_lossy(&entry.path).into_owned());
+        }
+    }
+    paths.dedup();
+    msg.push_str("\n#Conflicts:\n");
+    for path in paths {
+        msg.push_str(&format!("#\t{}\n", path));
+    }
+    Ok(msg)
+}
+
+/// The default message of a merge of `heads`, in the format of `git merge`,
+/// e.g. `Merge branches 'a' and 'b'`.
+fn merge_message(heads: &[&AnnotatedCommit<'_>]) -> String {
+    let names = heads
+        .iter()
+        .map(|h| h.ref_name().map(|name| name.to_string()))
+        .collect::<Vec<_>>();
+    let mut written = vec![false; heads.len()];
+    let mut msg = String::from("Merge ");
+
+    // Like git, leading heads given by id come first...
+    let mut sep = "";
+    for (i, name) in names.iter().enumerate() {
+        if name.is_some() {
+            break;
+        }
+        msg.push_str(&format!("{}commit '{}'", sep, heads[i].id()));
+        written[i] = true;
+        sep = "; ";
+    }
+
+    // ...then named heads grouped by kind...
+    let groups: [(&str, &str, &str); 3] = [
+        ("refs/heads/", "branch", "branches"),
+        (
+            "refs/remotes/",
+            "remote-tracking branch",
+            "remote-tracking branches",
+        ),
+        ("refs/tags/", "tag", "tags"),
+    ];
+    for (prefix, singular, plural) in groups.iter() {
+        let matching = names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| Some((i, name.as_ref()?.strip_prefix(prefix)?)))
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            continue;
+        }
+        msg.push_str(sep);
+        msg.push_str(if matching.len() == 1 {
+            singular
+        } else {
+            plural
+        });
+        msg.push(' ');
+        for (n, (i, name)) in matching.iter().enumerate() {
+            if n > 0 {
+                msg.push_str(if n == matching.len() - 1 {
+                    " and "
+                } else {
+                    ", "
+                });
+            }
+            msg.push_str(&format!("'{}'", name));
+            written[*i] = true;
+        }
+        sep = ", ";
+    }
+
+    // ...and anything else last.
+    for (i, head) in heads.iter().enumerate() {
+        if !written[i] {
+            msg.push_str(&format!("; commit '{}'", head.id()));
+        }
+    }
+    msg.push('\n');
+    msg
+}
+
+impl MergeReport {
+    /// Build the report of a merge of `ours` and `theirs` based on
+    /// `ancestor`, whose result is `index`.
+    pub(crate) fn new(
+        repo: &Repository,
+        ancestor: &Tree<'_>,
+        ours: &Tree<'_>,
+        theirs: &Tree<'_>,
+        index: &Index,
+        opts: Option<&MergeOptions>,
+    ) -> Result<MergeReport, Error> {
+        // libgit2 records a conflict on each path of a rename, and the
+        // paths which belong together in the index's rename conflict
+        // entries; join them back into one conflict per rename.
+        let renames = rename_conflicts(index);
+        let mut joined = vec![None; renames.len()];
+        let mut sides = Vec::new();
+        for conflict in index.conflicts()? {
+            let conflict = conflict?;
+            let side =
+                |entry: &Option<IndexEntry>| entry.as_ref().map(MergeConflictSide::from_entry);
+            let conflict = [
+                side(&conflict.ancestor),
+                side(&conflict.our),
+                side(&conflict.their),
+            ];
+            let rename = renames.iter().position(|paths| {
+                conflict.iter().zip(paths).any(|(side, path)| {
+                    side.as_ref()
+                        .is_some_and(|s| Some(&s.path) == path.as_ref())
+                })
+            });
+            match rename {
+                Some(i) => {
+                    let n = *joined[i].get_or_insert_with(|| {
+                        sides.push([None, None, None]);
+                        sides.len() - 1
+                    });
+                    for (slot, side) in sides[n].iter_mut().zip(conflict) {
+                        if slot.is_none() {
+                            *slot = side;
+                        }
+                    }
+                }
+                None => sides.push(conflict),
+            }
+        }
+        let conflicts = sides
+            .into_iter()
+            .map(|[ancestor, our_side, their_side]| MergeConflict {
+                kind: classify(&ancestor, &our_side, &their_side, ours, theirs),
+                ancestor,
+                ours: our_side,
+                theirs: their_side,
+            })
+            .collect::<Vec<_>>();
+
+        let conflicted = conflicts
+            .iter()
+            .flat_map(|c| c.sides())
+            .map(|side| side.path.clone())
+            .collect::<HashSet<_>>();
+        let ours_changed = changed_paths(repo, ancestor, ours)?;
+        let theirs_changed = changed_paths(repo, ancestor, theirs)?;
+        let auto_merged = ours_changed
+            .iter()
+            .filter(|(path, id)| {
+                theirs_changed.get(*path).is_some_and(|other| other != *id)
+                    && !conflicted.contains(*path)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        let (file_favor, file_flags) = match opts {
+            Some(opts) => (opts.raw.file_favor, opts.raw.file_flags),
+            None => (raw::GIT_MERGE_FILE_FAVOR_NORMAL, 0),
+        };
+        Ok(MergeReport {
+            conflicts,
+            auto_merged,
+            file_favor,
+            file_flags,
+        })
+    }
+
+    /// The conflicts of the merge, in index order.
+    pub fn conflicts(&self) -> &[MergeConflict] {
+        &self.conflicts
+    }
+
+    /// Returns `true` if the merge has conflicts.
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+
+    /// Find the conflict involving `path` on any side.
+    pub fn conflict(&self, path: &Path) -> Option<&MergeConflict> {
+        self.conflicts
+            .iter()
+            .find(|c| c.sides().any(|side| side.path == path))
+    }
+
+    /// The paths which were changed differently on both sides and merged
+    /// without conflicts, sorted.
+    pub fn auto_merged(&self) -> &[PathBuf] {
+        &self.auto_merged
+    }
+
+    /// Render the contents of a conflicting file as they would be written to
+    /// the working directory, with conflict markers around the regions which
+    /// could not be merged.
+    ///
+    /// The merge style and file favor of the [`MergeOptions`] used for the
+    /// merge are honored. When only one side still has the file, as in a
+    /// modify/delete conflict, that side's contents are returned unchanged.
+    pub fn render(&self, repo: &Repository, conflict: &MergeConflict) -> Result<Vec<u8>, Error> {
+        let (ours, theirs) = match (&conflict.ours, &conflict.theirs) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            (Some(side), None) | (None, Some(side)) => {
+                return Ok(repo.find_blob(side.id)?.content().to_vec())
+            }
+            (None, None) => return Ok(Vec::new()),
+        };
+        let ancestor = match conflict.ancestor {
+            Some(ref side) => Some((side, repo.find_blob(side.id)?)),
+            None => None,
+        };
+        let our_blob = repo.find_blob(ours.id)?;
+        let their_blob = repo.find_blob(theirs.id)?;
+
+        let ancestor_label = CString::new("base")?;
+        let our_label = CString::new("ours")?;
+        let their_label = CString::new("theirs")?;
+        let ancestor_path = match ancestor {
+            Some((side, _)) => Some(side.path.as_path().into_c_string()?),
+            None => None,
+        };
+        let our_path = ours.path.as_path().into_c_string()?;
+        let their_path = theirs.path.as_path().into_c_string()?;
+        unsafe {
+            let mut opts = mem::zeroed();
+            try_call!(raw::git_merge_file_options_init(
+                &mut opts,
+                raw::GIT_MERGE_FILE_OPTIONS_VERSION
+            ));
+            opts.ancestor_label = ancestor_label.as_ptr();
+            opts.our_label = our_label.as_ptr();
+            opts.their_label = their_label.as_ptr();
+            opts.favor = self.file_favor;
+            opts.flags = self.file_flags;
+
+            let ancestor_input = match ancestor {
+                Some((side, ref blob)) => Some(merge_file_input(
+                    blob.content(),
+                    ancestor_path.as_ref().unwrap(),
+                    side.mode,
+                )?),
+                None => None,
+            };
+            let our_input = merge_file_input(our_blob.content(), &our_path, ours.mode)?;
+            let their_input = merge_file_input(their_blob.content(), &their_path, theirs.mode)?;
+
+            let mut result = mem::zeroed();
+            try_call!(raw::git_merge_file(
+                &mut result,
+                ancestor_input
+                    .as_ref()
+                    .map(|i| i as *const _)
+                    .unwrap_or(ptr::null()),
+                &our_input,
+                &their_input,
+                &opts
+            ));
+            let contents = if result.ptr.is_null() {
+                Vec::new()
+            } else {
+                slice::from_raw_parts(result.ptr as *const u8, result.len).to_vec()
+            };
+            raw::git_merge_file_result_free(&mut result);
+            Ok(contents)
+        }
+    }
+}
+
+impl fmt::Debug for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MergeReport")
+            .field("conflicts", &self.conflicts)
+            .field("auto_merged", &self.auto_merged)
+            .finish()
+    }
+}
+
+impl MergeConflict {
+    /// The reason of this conflict.
+    pub fn kind(&self) -> MergeConflictKind {
+        self.kind
+    }
+
+    /// The path of the conflicting file in the merge result: our path if we
+    /// still have the file, their path otherwise.
+    pub fn path(&self) -> &Path {
+        self.sides().last().map(|side| side.path.as_path()).unwrap()
+    }
+
+    /// The common ancestor's version of the file, if it had one.
+    pub fn ancestor(&self) -> Option<&MergeConflictSide> {
+        self.ancestor.as_ref()
+    }
+
+    /// Our version of the file, unless we deleted it.
+    pub fn ours(&self) -> Option<&MergeConflictSide> {
+        self.ours.as_ref()
+    }
+
+    /// Their version of the file, unless they deleted it.
+    pub fn theirs(&self) -> Option<&MergeConflictSide> {
+        self.theirs.as_ref()
+    }
+
+    /// The versions present in the conflict, ordered by ancestor, theirs,
+    /// then ours.
+    fn sides(&self) -> impl Iterator<Item = &MergeConflictSide> {
+        self.ancestor
+            .iter()
+            .chain(self.theirs.iter())
+            .chain(self.ours.iter())
+    }
+}
+
+/// Formats the conflict the way git reports it, e.g.
+/// `CONFLICT (content): Merge conflict in src/lib.rs`.
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path().display();
+        let (present, missing) = if self.ours.is_some() {
+            ("ours", "theirs")
+        } else {
+            ("theirs", "ours")
+        };
+        match self.kind {
+            MergeConflictKind::Content => {
+                write!(f, "CONFLICT (content): Merge conflict in {}", path)
+            }
+            MergeConflictKind::AddAdd => {
+                write!(f, "CONFLICT (add/add): Merge conflict in {}", path)
+            }
+            MergeConflictKind::ModifyDelete => write!(
+                f,
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}",
+                path, missing, present
+            ),
+            MergeConflictKind::RenameRename => {
+                let old = self.ancestor.as_ref().map(|a| a.path.display());
+                let ours = self.ours.as_ref().map(|a| a.path.display());
+                let theirs = self.theirs.as_ref().map(|a| a.path.display());
+                match (old, ours, theirs) {
+                    (Some(old), Some(ours), Some(theirs)) => write!(
+                        f,
+                        "CONFLICT (rename/rename): {} renamed to {} in ours and to {} in theirs",
+                        old, ours, theirs
+                    ),
+                    _ => write!(f, "CONFLICT (rename/rename): {}", path),
+                }
+            }
+            MergeConflictKind::RenameDelete => {
+                let old = self.ancestor.as_ref().map(|a| a.path.display());
+                match old {
+                    Some(old) => write!(
+                        f,
+                        "CONFLICT (rename/delete): {} renamed to {} in {}, but deleted in {}",
+                        old, path, present, missing
+                    ),
+                    None => write!(f, "CONFLICT (rename/delete): {}", path),
+                }
+            }
+            MergeConflictKind::DirectoryFile => write!(
+                f,
+                "CONFLICT (directory/file): {} is a file in {} but a directory in {}",
+                path, present, missing
+            ),
+        }
+    }
+}
+
+impl MergeConflictSide {
+    fn from_entry(entry: &IndexEntry) -> MergeConflictSide {
+        MergeConflictSide {
+            path: util::bytes2path(&entry.path).to_path_buf(),
+            id: entry.id,
+            mode: entry.mode,
+        }
+    }
+
+    /// The path of this version of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The blob id of this version of the file.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// The file mode of this version of the file.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+}
+
+fn classify(
+    ancestor: &Option<MergeConflictSide>,
+    ours: &Option<MergeConflictSide>,
+    theirs: &Option<MergeConflictSide>,
+    our_tree: &Tree<'_>,
+    their_tree: &Tree<'_>,
+) -> MergeConflictKind {
+    match (ancestor, ours, theirs) {
+        (Some(a), Some(o), Some(t)) => {
+            if o.path != a.path && t.path != a.path && o.path != t.path {
+                MergeConflictKind::RenameRename
+            } else {
+                MergeConflictKind::Content
+            }
+        }
+        (_, Some(side), None) if in_directory_conflict(their_tree, &side.path) => {
+            MergeConflictKind::DirectoryFile
+        }
+        (_, None, Some(side)) if in_directory_conflict(our_tree, &side.path) => {
+            MergeConflictKind::DirectoryFile
+        }
+        (Some(a), Some(side), None) | (Some(a), None, Some(side)) => {
+            if a.path != side.path {
+                MergeConflictKind::RenameDelete
+            } else {
+                MergeConflictKind::ModifyDelete
+            }
+        }
+        _ => MergeConflictKind::AddAdd,
+    }
+}
+
+/// The ancestor, our and their paths of each rename conflict in `index`.
+fn rename_conflicts(index: &Index) -> Vec<[Option<PathBuf>; 3]> {
+    let path = |ptr: *const libc::c_char| unsafe {
+        crate::opt_bytes(index, ptr).map(|bytes| util::bytes2path(bytes).to_path_buf())
+    };
+    unsafe {
+        let count = raw::git_index_name_entrycount(index.raw());
+        (0..count)
+            .filter_map(|n| raw::git_index_name_get_byindex(index.raw(), n).as_ref())
+            .map(|entry| [path(entry.ancestor), path(entry.ours), path(entry.theirs)])
+            .collect()
+    }
+}
+
+/// Whether `tree` has a directory at `path`, or a file at one of its
+/// parent directories.
+fn in_directory_conflict(tree: &Tree<'_>, path: &Path) -> bool {
+    if let Ok(entry) = tree.get_path(path) {
+        return entry.kind() == Some(ObjectType::Tree);
+    }
+    path.ancestors()
+        .skip(1)
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .any(|parent| {
+            tree.get_path(parent)
+                .map(|entry| entry.kind() == Some(ObjectType::Blob))
+                .unwrap_or(false)
+        })
+}
+
+/// The paths changed between two trees, with their new blob ids.
+fn changed_paths(
+    repo: &Repository,
+    from: &Tree<'_>,
+    to: &Tree<'_>,
+) -> Result<BTreeMap<PathBuf, Oid>, Error> {
+    let diff = repo.diff_tree_to_tree(Some(from), Some(to), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| {
+            let file = delta.new_file();
+            file.path().map(|path| (path.to_path_buf(), file.id()))
+        })
+        .collect())
+}
+
+unsafe fn merge_file_input(
+    content: &[u8],
+    path: &CString,
+    mode: u32,
+) -> Result<raw::git_merge_file_input, Error> {
+    let mut input = mem::zeroed();
+    try_call!(raw::git_merge_file_input_init(
+        &mut input,
+        raw::GIT_MERGE_FILE_INPUT_VERSION
+    ));
+    input.ptr = content.as_ptr() as *const _;
+    input.size = content.len();
+    input.path = path.as_ptr();
+    input.mode = mode;
+    Ok(input)
+}
+
 impl<'repo> Binding for AnnotatedCommit<'repo> {
     type Raw = *mut raw::git_annotated_commit;
     unsafe fn from_raw(raw: *mut raw::git_annotated_commit) -> AnnotatedCommit<'repo> {
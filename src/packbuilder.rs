@@ -1,11 +1,12 @@
 use libc::{c_int, c_uint, c_void, size_t};
 use std::marker;
+use std::path::Path;
 use std::ptr;
 use std::slice;
 use std::str;
 
 use crate::util::Binding;
-use crate::{panic, raw, Buf, Error, Oid, Repository, Revwalk};
+use crate::{panic, raw, Buf, Error, IntoCString, Oid, Repository, Revwalk};
 
 #[derive(PartialEq, Eq, Clone, Debug, Copy)]
 /// Stages that are reported by the `PackBuilder` progress callback.
@@ -84,6 +85,24 @@ impl<'repo> PackBuilder<'repo> {
         Ok(())
     }
 
+    /// Write the new pack and its index to the directory `path`.
+    ///
+    /// `mode` is the permissions of the written files, or 0 for the
+    /// default. The name of the pack is available from `name()` afterwards.
+    pub fn write(&mut self, path: &Path, mode: u32) -> Result<(), Error> {
+        let path = path.into_c_string()?;
+        unsafe {
+            try_call!(raw::git_packbuilder_write(
+                self.raw,
+                path,
+                mode,
+                None,
+                ptr::null_mut()
+            ));
+        }
+        Ok(())
+    }
+
     /// Create the new pack and pass each object to the callback.
     pub fn foreach<F>(&mut self, mut cb: F) -> Result<(), Error>
     where
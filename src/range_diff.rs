@@ -0,0 +1,524 @@
+use std::path::Path;
+
+use crate::{Commit, DiffFormat, DiffOptions, Error, Oid, Patch, Repository, Sort};
+
+/// Options for [`range_diff`].
+#[derive(Clone, Debug)]
+pub struct RangeDiffOptions {
+    creation_factor: u32,
+    context_lines: u32,
+}
+
+/// The result of comparing two versions of a patch series.
+#[derive(Clone, Debug)]
+pub struct RangeDiff {
+    entries: Vec<RangeDiffEntry>,
+}
+
+/// How a commit of one series relates to the other series.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RangeDiffStatus {
+    /// The commit is unchanged between the two series (`=`).
+    Equal,
+    /// The commit is present in both series but its message or patch
+    /// changed (`!`).
+    Modified,
+    /// The commit only exists in the old series (`<`).
+    Removed,
+    /// The commit only exists in the new series (`>`).
+    Added,
+}
+
+/// A single line of a [`RangeDiff`]: a commit of the old series, a commit
+/// of the new series, or a pair of matching commits.
+#[derive(Clone, Debug)]
+pub struct RangeDiffEntry {
+    status: RangeDiffStatus,
+    old: Option<(usize, Oid)>,
+    new: Option<(usize, Oid)>,
+    diff: Vec<u8>,
+}
+
+impl Default for RangeDiffOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RangeDiffOptions {
+    /// Creates a new set of options with the same defaults as
+    /// `git range-diff`.
+    pub fn new() -> RangeDiffOptions {
+        RangeDiffOptions {
+            creation_factor: 60,
+            context_lines: 3,
+        }
+    }
+
+    /// Set the creation factor, in percent.
+    ///
+    /// This is the cost, relative to the size of a patch, of treating a
+    /// commit as removed from the old series and a different one as added to
+    /// the new series rather than pairing them up. Higher values pair up less
+    /// similar commits. The default is 60.
+    pub fn creation_factor(&mut self, percent: u32) -> &mut RangeDiffOptions {
+        self.creation_factor = percent;
+        self
+    }
+
+    /// Set the number of context lines of the generated diffs of diffs.
+    ///
+    /// The default is 3.
+    pub fn context_lines(&mut self, lines: u32) -> &mut RangeDiffOptions {
+        self.context_lines = lines;
+        self
+    }
+}
+
+impl RangeDiff {
+    /// The entries of the comparison, in the order `git range-diff` shows
+    /// them: following the new series, with commits removed from the old
+    /// series shown near their old position.
+    pub fn entries(&self) -> &[RangeDiffEntry] {
+        &self.entries
+    }
+
+    /// Returns `true` if both series contain the same patches.
+    pub fn is_unchanged(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|e| e.status == RangeDiffStatus::Equal)
+    }
+}
+
+impl RangeDiffEntry {
+    /// The relation between the two commits of this entry.
+    pub fn status(&self) -> RangeDiffStatus {
+        self.status
+    }
+
+    /// The commit of the old series, if any.
+    pub fn old_id(&self) -> Option<Oid> {
+        self.old.map(|(_, id)| id)
+    }
+
+    /// The position of the old commit in its series, starting at 0.
+    pub fn old_index(&self) -> Option<usize> {
+        self.old.map(|(i, _)| i)
+    }
+
+    /// The commit of the new series, if any.
+    pub fn new_id(&self) -> Option<Oid> {
+        self.new.map(|(_, id)| id)
+    }
+
+    /// The position of the new commit in its series, starting at 0.
+    pub fn new_index(&self) -> Option<usize> {
+        self.new.map(|(i, _)| i)
+    }
+
+    /// The diff between the old and the new version of the patch, including
+    /// its metadata and commit message.
+    ///
+    /// This is empty unless the status is [`RangeDiffStatus::Modified`].
+    pub fn diff(&self) -> &[u8] {
+        &self.diff
+    }
+}
+
+struct SeriesCommit {
+    id: Oid,
+    patch_id: Oid,
+    text: Vec<u8>,
+    lines: i64,
+    matching: Option<usize>,
+}
+
+/// Compare two versions of a patch series, like `git range-diff`.
+///
+/// Both ranges are of the form `<base>..<tip>`; merge commits are skipped.
+/// Commits are first paired up when their patches and messages are
+/// identical, then when their patch-ids match, and the remaining ones by
+/// finding the pairing with the smallest diffs of diffs.
+pub fn range_diff(
+    repo: &Repository,
+    old_range: &str,
+    new_range: &str,
+    opts: Option<&RangeDiffOptions>,
+) -> Result<RangeDiff, Error> {
+    let default = RangeDiffOptions::new();
+    let opts = opts.unwrap_or(&default);
+    let mut old = read_series(repo, old_range)?;
+    let mut new = read_series(repo, new_range)?;
+
+    // Identical patches, then patches with the same patch-id.
+    for exact in [true, false].iter() {
+        for (i, commit) in old.iter_mut().enumerate() {
+            if commit.matching.is_some() {
+                continue;
+            }
+            let found = new.iter().position(|n| {
+                n.matching.is_none()
+                    && if *exact {
+                        n.text == commit.text
+                    } else {
+                        n.patch_id == commit.patch_id
+                    }
+            });
+            if let Some(j) = found {
+                commit.matching = Some(j);
+                new[j].matching = Some(i);
+            }
+        }
+    }
+
+    pair_by_similarity(&mut old, &mut new, opts)?;
+
+    let mut entries = Vec::new();
+    let mut shown = vec![false; old.len()];
+    let mut i = 0;
+    let mut j = 0;
+    while i < old.len() || j < new.len() {
+        if i < old.len() && shown[i] {
+            i += 1;
+            continue;
+        }
+        if i < old.len() && old[i].matching.is_none() {
+            entries.push(RangeDiffEntry {
+                status: RangeDiffStatus::Removed,
+                old: Some((i, old[i].id)),
+                new: None,
+                diff: Vec::new(),
+            });
+            i += 1;
+            continue;
+        }
+        if j >= new.len() {
+            break;
+        }
+        let entry = match new[j].matching {
+            None => RangeDiffEntry {
+                status: RangeDiffStatus::Added,
+                old: None,
+                new: Some((j, new[j].id)),
+                diff: Vec::new(),
+            },
+            Some(m) => {
+                shown[m] = true;
+                let diff = if old[m].text == new[j].text {
+                    Vec::new()
+                } else {
+                    diff_of_diffs(&old[m].text, &new[j].text, opts)?.0
+                };
+                let status = if diff.is_empty() {
+                    RangeDiffStatus::Equal
+                } else {
+                    RangeDiffStatus::Modified
+                };
+                RangeDiffEntry {
+                    status,
+                    old: Some((m, old[m].id)),
+                    new: Some((j, new[j].id)),
+                    diff,
+                }
+            }
+        };
+        entries.push(entry);
+        j += 1;
+    }
+    Ok(RangeDiff { entries })
+}
+
+fn read_series(repo: &Repository, range: &str) -> Result<Vec<SeriesCommit>, Error> {
+    if !range.contains("..") || range.contains("...") {
+        return Err(Error::from_str("range must be of the form <base>..<tip>"));
+    }
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push_range(range)?;
+    let mut series = Vec::new();
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let (text, patch_id) = commit_text(repo, &commit)?;
+        series.push(SeriesCommit {
+            id: commit.id(),
+            patch_id,
+            lines: text.iter().filter(|&&b| b == b'\n').count() as i64,
+            text,
+            matching: None,
+        });
+    }
+    Ok(series)
+}
+
+/// Render a commit the way it is compared: its metadata, its indented
+/// message and its patch, without blob ids or line numbers so that
+/// rebasing alone does not show up as a change.
+fn commit_text(repo: &Repository, commit: &Commit<'_>) -> Result<(Vec<u8>, Oid), Error> {
+    let parent_tree = match commit.parent_count() {
+        0 => None,
+        _ => Some(commit.parent(0)?.tree()?),
+    };
+    let tree = commit.tree()?;
+    let mut diff_opts = DiffOptions::new();
+    diff_opts.show_binary(true);
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut diff_opts))?;
+    let patch_id = diff.patchid(None)?;
+
+    let mut text = Vec::new();
+    text.extend_from_slice(b" ## Metadata ##\nAuthor: ");
+    let author = commit.author();
+    text.extend_from_slice(author.name_bytes());
+    text.extend_from_slice(b" <");
+    text.extend_from_slice(author.email_bytes());
+    text.extend_from_slice(b">\n\n ## Commit message ##\n");
+    for line in commit.message_bytes().split(|&b| b == b'\n') {
+        if !line.is_empty() {
+            text.extend_from_slice(b"    ");
+            text.extend_from_slice(line);
+        }
+        text.push(b'\n');
+    }
+    diff.print(DiffFormat::Patch, |delta, _hunk, line| {
+        match line.origin() {
+            'F' => {
+                let path = delta.new_file().path().or_else(|| delta.old_file().path());
+                text.extend_from_slice(b"\n ## ");
+                text.extend_from_slice(
+                    path.unwrap_or_else(|| Path::new(""))
+                        .to_string_lossy()
+                        .as_bytes(),
+                );
+                text.extend_from_slice(b" ##\n");
+            }
+            'H' => {
+                // Keep only the section heading following the line numbers.
+                let content = line.content();
+                let heading = content
+                    .windows(2)
+                    .skip(2)
+                    .position(|w| w == b"@@")
+                    .map(|p| &content[p + 4..])
+                    .unwrap_or(b"\n");
+                text.extend_from_slice(b"@@");
+                text.extend_from_slice(heading);
+            }
+            origin @ ' ' | origin @ '+' | origin @ '-' => {
+                text.push(origin as u8);
+                text.extend_from_slice(line.content());
+            }
+            _ => text.extend_from_slice(line.content()),
+        }
+        true
+    })?;
+    Ok((text, patch_id))
+}
+
+/// Compute the diff between two rendered commits, along with the number of
+/// changed lines.
+fn diff_of_diffs(old: &[u8], new: &[u8], opts: &RangeDiffOptions) -> Result<(Vec<u8>, i64), Error> {
+    let mut diff_opts = DiffOptions::new();
+    diff_opts.context_lines(opts.context_lines).force_text(true);
+    let mut patch = Patch::from_buffers(old, None, new, None, Some(&mut diff_opts))?;
+    let (_, additions, deletions) = patch.line_stats()?;
+    let mut out = Vec::new();
+    patch.print(&mut |_delta, _hunk, line| {
+        match line.origin() {
+            'F' => {}
+            origin @ ' ' | origin @ '+' | origin @ '-' => {
+                out.push(origin as u8);
+                out.extend_from_slice(line.content());
+            }
+            _ => out.extend_from_slice(line.content()),
+        }
+        true
+    })?;
+    Ok((out, (additions + deletions) as i64))
+}
+
+/// Pair up the remaining commits of both series by solving the linear
+/// assignment problem over the sizes of their diffs of diffs, where leaving
+/// a commit unpaired costs `creation_factor` percent of its size.
+fn pair_by_similarity(
+    old: &mut [SeriesCommit],
+    new: &mut [SeriesCommit],
+    opts: &RangeDiffOptions,
+) -> Result<(), Error> {
+    let old_left = (0..old.len())
+        .filter(|&i| old[i].matching.is_none())
+        .collect::<Vec<_>>();
+    let new_left = (0..new.len())
+        .filter(|&j| new[j].matching.is_none())
+        .collect::<Vec<_>>();
+    if old_left.is_empty() || new_left.is_empty() {
+        return Ok(());
+    }
+
+    let factor = i64::from(opts.creation_factor);
+    let n = old_left.len() + new_left.len();
+    let mut cost = vec![vec![0i64; n]; n];
+    for (row, &i) in old_left.iter().enumerate() {
+        let creation = old[i].lines * factor / 100;
+        for (col, &j) in new_left.iter().enumerate() {
+            cost[row][col] = diff_of_diffs(&old[i].text, &new[j].text, opts)?.1;
+        }
+        for slot in cost[row][new_left.len()..].iter_mut() {
+            *slot = creation;
+        }
+    }
+    for (col, &j) in new_left.iter().enumerate() {
+        let creation = new[j].lines * factor / 100;
+        for row in cost[old_left.len()..].iter_mut() {
+            row[col] = creation;
+        }
+    }
+
+    for (row, col) in assign(&cost).into_iter().enumerate() {
+        if row < old_left.len() && col < new_left.len() {
+            let (i, j) = (old_left[row], new_left[col]);
+            old[i].matching = Some(j);
+            new[j].matching = Some(i);
+        }
+    }
+    Ok(())
+}
+
+/// Solve the square assignment problem with the Hungarian algorithm,
+/// returning the column assigned to each row.
+fn assign(cost: &[Vec<i64>]) -> Vec<usize> {
+    let n = cost.len();
+    let inf = i64::MAX / 2;
+    // Potentials and matching use 1-based indices, with 0 as a sentinel.
+    let mut u = vec![0i64; n + 1];
+    let mut v = vec![0i64; n + 1];
+    let mut row_of = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+    for row in 1..=n {
+        row_of[0] = row;
+        let mut col0 = 0;
+        let mut min = vec![inf; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col0] = true;
+            let row0 = row_of[col0];
+            let mut delta = inf;
+            let mut col1 = 0;
+            for col in 1..=n {
+                if used[col] {
+                    continue;
+                }
+                let cur = cost[row0 - 1][col - 1] - u[row0] - v[col];
+                if cur < min[col] {
+                    min[col] = cur;
+                    way[col] = col0;
+                }
+                if min[col] < delta {
+                    delta = min[col];
+                    col1 = col;
+                }
+            }
+            for col in 0..=n {
+                if used[col] {
+                    u[row_of[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min[col] -= delta;
+                }
+            }
+            col0 = col1;
+            if row_of[col0] == 0 {
+                break;
+            }
+        }
+        while col0 != 0 {
+            let col1 = way[col0];
+            row_of[col0] = row_of[col1];
+            col0 = col1;
+        }
+    }
+
+    let mut result = vec![0; n];
+    for (col, &row) in row_of.iter().enumerate().skip(1) {
+        if row != 0 {
+            result[row - 1] = col - 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assign, range_diff, RangeDiffOptions, RangeDiffStatus};
+    use crate::{Oid, Repository, Signature};
+    use std::fs;
+    use std::path::Path;
+
+    fn commit_file(repo: &Repository, parent: Oid, path: &str, data: &str, msg: &str) -> Oid {
+        let root = repo.workdir().unwrap();
+        fs::write(root.join(path), data).unwrap();
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new(path)));
+        let tree = t!(repo.find_tree(t!(index.write_tree())));
+        let sig = t!(Signature::now("name", "email"));
+        let parent = t!(repo.find_commit(parent));
+        t!(repo.commit(None, &sig, &sig, msg, &tree, &[&parent]))
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = t!(repo.head()).target().unwrap();
+
+        let a1 = commit_file(&repo, base, "a", "a\n", "add a");
+        let b1 = commit_file(&repo, a1, "b", "b1\nb2\nb3\n", "add b");
+        let c1 = commit_file(&repo, b1, "c", "c\n", "add c");
+
+        t!(repo.reset(
+            &t!(repo.find_object(base, None)),
+            crate::ResetType::Hard,
+            None
+        ));
+        let a2 = commit_file(&repo, base, "a", "a\n", "add a");
+        let b2 = commit_file(&repo, a2, "b", "b1\nB2\nb3\n", "add b");
+        let d2 = commit_file(&repo, b2, "d", "d\n", "add d");
+
+        let old = format!("{}..{}", base, c1);
+        let new = format!("{}..{}", base, d2);
+        // Small commits are cheap to pair up, so make that less likely.
+        let mut opts = RangeDiffOptions::new();
+        opts.creation_factor(20);
+        let result = t!(range_diff(&repo, &old, &new, Some(&opts)));
+        let statuses = result
+            .entries()
+            .iter()
+            .map(|e| (e.status(), e.old_id(), e.new_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                (RangeDiffStatus::Equal, Some(a1), Some(a2)),
+                (RangeDiffStatus::Modified, Some(b1), Some(b2)),
+                (RangeDiffStatus::Removed, Some(c1), None),
+                (RangeDiffStatus::Added, None, Some(d2)),
+            ]
+        );
+        let diff = String::from_utf8(result.entries()[1].diff().to_vec()).unwrap();
+        assert!(diff.contains("-+b2\n"), "{}", diff);
+        assert!(diff.contains("++B2\n"), "{}", diff);
+        assert!(!result.is_unchanged());
+
+        assert!(t!(range_diff(&repo, &old, &old, None)).is_unchanged());
+        let paired = t!(range_diff(&repo, &old, &new, None));
+        assert_eq!(paired.entries()[2].status(), RangeDiffStatus::Modified);
+        assert!(range_diff(&repo, "HEAD", &old, None).is_err());
+    }
+
+    #[test]
+    fn assignment() {
+        let cost = vec![vec![4, 1, 3], vec![2, 0, 5], vec![3, 2, 2]];
+        assert_eq!(assign(&cost), vec![1, 0, 2]);
+    }
+}
@@ -1,9 +1,16 @@
+use libc::c_void;
+use std::cell::Cell;
 use std::ffi::CString;
 use std::{marker, mem, ptr, str};
 
 use crate::build::CheckoutBuilder;
+use crate::signing::{self, Signer};
 use crate::util::Binding;
-use crate::{raw, Error, Index, MergeOptions, Oid, Signature};
+use crate::{raw, Error, ErrorClass, ErrorCode, Index, MergeOptions, Oid, Signature};
+
+/// Where `Rebase::commit_with_signer` places the signer for the duration of
+/// a commit: a pointer to a `&dyn Signer`, or null.
+pub(crate) type SignerSlot = Cell<*const c_void>;
 
 /// Rebase options
 ///
@@ -13,6 +20,7 @@ pub struct RebaseOptions<'cb> {
     rewrite_notes_ref: Option<CString>,
     merge_options: Option<MergeOptions>,
     checkout_options: Option<CheckoutBuilder<'cb>>,
+    sign_commits: bool,
 }
 
 impl<'cb> Default for RebaseOptions<'cb> {
@@ -29,6 +37,7 @@ impl<'cb> RebaseOptions<'cb> {
             rewrite_notes_ref: None,
             merge_options: None,
             checkout_options: None,
+            sign_commits: false,
         };
         assert_eq!(unsafe { raw::git_rebase_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -81,6 +90,14 @@ impl<'cb> RebaseOptions<'cb> {
         self
     }
 
+    /// Allow the commits of the rebase to be signed with
+    /// `Rebase::commit_with_signer`. This takes over libgit2's commit
+    /// creation callback, so it is off by default.
+    pub fn sign_commits(&mut self, sign: bool) -> &mut RebaseOptions<'cb> {
+        self.sign_commits = sign;
+        self
+    }
+
     /// Acquire a pointer to the underlying raw options.
     pub fn raw(&mut self) -> *const raw::git_rebase_options {
         unsafe {
@@ -98,11 +115,43 @@ impl<'cb> RebaseOptions<'cb> {
         }
         &self.raw
     }
+
+    /// Whether an in-memory rebase was requested.
+    pub(crate) fn is_inmemory(&self) -> bool {
+        self.raw.inmemory != 0
+    }
+
+    /// The options used to merge trees, if any were given.
+    pub(crate) fn merge_opts(&self) -> Option<&MergeOptions> {
+        self.merge_options.as_ref()
+    }
+
+    /// A copy of the raw options which, if commit signing was requested,
+    /// routes commit creation of the rebase through the signer placed in
+    /// `slot`, which must outlive the rebase.
+    pub(crate) fn raw_with_signer(&mut self, slot: Option<&SignerSlot>) -> raw::git_rebase_options {
+        let mut raw = unsafe { ptr::read(self.raw()) };
+        if let Some(slot) = slot {
+            raw.commit_create_cb = Some(signing::rebase_commit_create_cb);
+            raw.payload = slot as *const _ as *mut c_void;
+        }
+        raw
+    }
+
+    /// A signer slot for the rebase, if commit signing was requested.
+    pub(crate) fn signer_slot(&self) -> Option<Box<SignerSlot>> {
+        if self.sign_commits {
+            Some(Box::new(Cell::new(ptr::null())))
+        } else {
+            None
+        }
+    }
 }
 
 /// Representation of a rebase
 pub struct Rebase<'repo> {
     raw: *mut raw::git_rebase,
+    signer: Option<Box<SignerSlot>>,
     _marker: marker::PhantomData<&'repo raw::git_rebase>,
 }
 
@@ -188,6 +237,36 @@ impl<'repo> Rebase<'repo> {
         }
     }
 
+    /// Commits the current patch like `commit()`, signing the new commit
+    /// with `signer`.
+    ///
+    /// The rebase must have been started or opened with
+    /// `RebaseOptions::sign_commits` enabled.
+    pub fn commit_with_signer(
+        &mut self,
+        author: Option<&Signature<'_>>,
+        committer: &Signature<'_>,
+        message: Option<&str>,
+        signer: &dyn Signer,
+    ) -> Result<Oid, Error> {
+        let slot = match &self.signer {
+            Some(slot) => slot,
+            None => {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Rebase,
+                    "commit signing was not enabled in the rebase options",
+                ))
+            }
+        };
+        slot.set(&signer as *const &dyn Signer as *const c_void);
+        let result = self.commit(author, committer, message);
+        if let Some(slot) = &self.signer {
+            slot.set(ptr::null());
+        }
+        result
+    }
+
     /// Aborts a rebase that is currently in progress, resetting the repository
     /// and working directory to their state before rebase began.
     pub fn abort(&mut self) -> Result<(), Error> {
@@ -229,14 +308,26 @@ impl<'rebase> Iterator for Rebase<'rebase> {
 impl<'repo> Binding for Rebase<'repo> {
     type Raw = *mut raw::git_rebase;
     unsafe fn from_raw(raw: *mut raw::git_rebase) -> Rebase<'repo> {
+        Rebase::from_raw_parts(raw, None)
+    }
+    fn raw(&self) -> *mut raw::git_rebase {
+        self.raw
+    }
+}
+
+impl<'repo> Rebase<'repo> {
+    /// Wrap a rebase whose options were set up with `signer` as the signer
+    /// slot, if any.
+    pub(crate) unsafe fn from_raw_parts(
+        raw: *mut raw::git_rebase,
+        signer: Option<Box<SignerSlot>>,
+    ) -> Rebase<'repo> {
         Rebase {
             raw,
+            signer,
             _marker: marker::PhantomData,
         }
     }
-    fn raw(&self) -> *mut raw::git_rebase {
-        self.raw
-    }
 }
 
 impl<'repo> Drop for Rebase<'repo> {
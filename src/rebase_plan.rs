@@ -0,0 +1,1654 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build::CheckoutBuilder;
+use crate::{AnnotatedCommit, Commit, Error, ErrorClass, ErrorCode, Index, MergeOptions, Oid};
+use crate::{RebaseOptions, Repository, RepositoryState, Signature, Sort};
+
+/// A list of steps to replay onto a new base, like the todo list of
+/// `git rebase --interactive`.
+///
+/// A plan starts out picking every commit between the upstream and the
+/// branch, and can then be edited before being driven by
+/// [`Repository::rebase_with_plan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebasePlan {
+    steps: Vec<RebaseStep>,
+    onto: Oid,
+    orig_head: Oid,
+    head_name: Option<String>,
+}
+
+/// A single instruction of a [`RebasePlan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RebaseStep {
+    /// Replay the commit.
+    Pick(Oid),
+    /// Replay the commit with the given message.
+    Reword(Oid, String),
+    /// Replay the commit, then stop to allow it to be amended.
+    Edit(Oid),
+    /// Meld the commit into the previous one, appending its message.
+    Squash(Oid),
+    /// Meld the commit into the previous one, discarding its message.
+    Fixup(Oid),
+    /// Run a shell command, stopping if it fails.
+    Exec(String),
+    /// Stop unconditionally.
+    Break,
+    /// Give a name to the current head, to refer to it from later
+    /// [`RebaseStep::Reset`] and [`RebaseStep::Merge`] steps.
+    Label(String),
+    /// Move the head to a labelled commit, or to a commit given by its id.
+    Reset(String),
+    /// Merge labelled commits, or commits given by their id, into the head.
+    ///
+    /// The message and author are those of the original merge commit, if
+    /// one is given.
+    Merge(Option<Oid>, Vec<String>),
+}
+
+/// A rebase driven by a [`RebasePlan`], created by
+/// [`Repository::rebase_with_plan`].
+///
+/// Steps are applied by [`PlannedRebase::run`] until the plan is complete
+/// or a step needs attention, after which `run` can be called again to
+/// continue.
+pub struct PlannedRebase<'repo> {
+    repo: &'repo Repository,
+    steps: Vec<RebaseStep>,
+    next: usize,
+    onto: Oid,
+    orig_head: Oid,
+    head_name: Option<String>,
+    head: Oid,
+    inmemory: bool,
+    merge_options: Option<MergeOptions>,
+    committer: Option<Signature<'static>>,
+    index: Option<Index>,
+    pending: Option<PendingCommit>,
+    rewritten: Vec<(Oid, Oid)>,
+    labels: HashMap<String, Oid>,
+}
+
+/// Why [`PlannedRebase::run`] returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RebasePlanStatus {
+    /// Every step has been applied; call [`PlannedRebase::finish`].
+    Complete,
+    /// The step could not be applied without conflicts. Resolve them in the
+    /// index (the repository's, or [`PlannedRebase::inmemory_index`] for an
+    /// in-memory rebase) and run again to commit the result.
+    Conflict(RebaseStep),
+    /// The commit was replayed as requested by an [`RebaseStep::Edit`].
+    Edit(Oid),
+    /// The command of an [`RebaseStep::Exec`] failed, exiting with the
+    /// given status if it was not killed by a signal.
+    ExecFailed(String, Option<i32>),
+    /// A [`RebaseStep::Break`] was reached.
+    Break,
+}
+
+/// A commit to create once the conflicts of its step are resolved.
+struct PendingCommit {
+    step: RebaseStep,
+    author: Signature<'static>,
+    message: String,
+    parents: Vec<Oid>,
+}
+
+impl RebasePlan {
+    /// Plan picking every commit reachable from `branch` but not from
+    /// `upstream`, oldest first, onto `onto`.
+    ///
+    /// As with [`Repository::rebase`], `branch` defaults to `HEAD` and
+    /// `onto` defaults to `upstream`. Merge commits are left out of the plan;
+    /// use [`RebasePlan::with_merges`] to recreate them.
+    pub fn new(
+        repo: &Repository,
+        branch: Option<&AnnotatedCommit<'_>>,
+        upstream: Option<&AnnotatedCommit<'_>>,
+        onto: Option<&AnnotatedCommit<'_>>,
+    ) -> Result<RebasePlan, Error> {
+        let (mut plan, commits) = RebasePlan::walk(repo, branch, upstream, onto)?;
+        for commit in commits {
+            if commit.parent_count() <= 1 {
+                plan.steps.push(RebaseStep::Pick(commit.id()));
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Plan recreating the commits reachable from `branch` but not from
+    /// `upstream` onto `onto`, merges included, like
+    /// `git rebase --rebase-merges`.
+    ///
+    /// Branches merged in the rebased history are replayed with
+    /// [`RebaseStep::Label`] and [`RebaseStep::Reset`] steps, and merge
+    /// commits with [`RebaseStep::Merge`] steps. Commits whose first parent
+    /// is not rebased are replayed onto `onto`, while merged parents which
+    /// are not rebased are merged as they are.
+    pub fn with_merges(
+        repo: &Repository,
+        branch: Option<&AnnotatedCommit<'_>>,
+        upstream: Option<&AnnotatedCommit<'_>>,
+        onto: Option<&AnnotatedCommit<'_>>,
+    ) -> Result<RebasePlan, Error> {
+        let (mut plan, commits) = RebasePlan::walk(repo, branch, upstream, onto)?;
+        let mut rebased = HashMap::new();
+        for commit in commits {
+            rebased.insert(commit.id(), commit);
+        }
+
+        // Order the commits parents first, visiting merged branches before
+        // the first parent so each branch is replayed in one go, as git does.
+        let mut commits = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(plan.orig_head, false)];
+        while let Some((id, expanded)) = stack.pop() {
+            let commit = match rebased.get(&id) {
+                Some(commit) => commit,
+                None => continue,
+            };
+            if expanded {
+                commits.push(commit);
+                continue;
+            }
+            if !visited.insert(id) {
+                continue;
+            }
+            stack.push((id, true));
+            let parents = commit.parent_ids().collect::<Vec<_>>();
+            if let Some((first, merged)) = parents.split_first() {
+                stack.push((*first, false));
+                stack.extend(merged.iter().rev().map(|p| (*p, false)));
+            }
+        }
+
+        // Replay the commits parents first, resetting whenever the next
+        // commit is not based on the previous one. The commits reset to or
+        // merged need a label right after they are replayed.
+        let mut names = HashMap::new();
+        let mut taken = HashSet::new();
+        taken.insert("onto".to_string());
+        let mut label = |id: Oid, hint: &str| -> String {
+            names
+                .entry(id)
+                .or_insert_with(|| {
+                    let mut name = hint.to_string();
+                    let mut n = 1;
+                    while !taken.insert(name.clone()) {
+                        n += 1;
+                        name = format!("{}-{}", hint, n);
+                    }
+                    name
+                })
+                .clone()
+        };
+        let mut script = Vec::new();
+        let mut head = None;
+        for commit in commits.iter() {
+            let base = commit.parent_id(0).ok().filter(|p| rebased.contains_key(p));
+            if head != Some(base) {
+                let name = match base {
+                    Some(base) => label(base, "branch-point"),
+                    None => "onto".to_string(),
+                };
+                script.push((None, RebaseStep::Reset(name)));
+            }
+            let step = if commit.parent_count() > 1 {
+                let hint = merged_branch(commit.summary().unwrap_or(""));
+                let parents = commit
+                    .parent_ids()
+                    .skip(1)
+                    .map(|p| {
+                        if rebased.contains_key(&p) {
+                            label(p, hint.unwrap_or("branch"))
+                        } else {
+                            p.to_string()
+                        }
+                    })
+                    .collect();
+                RebaseStep::Merge(Some(commit.id()), parents)
+            } else {
+                RebaseStep::Pick(commit.id())
+            };
+            script.push((Some(commit.id()), step));
+            head = Some(Some(commit.id()));
+        }
+
+        plan.steps.push(RebaseStep::Label("onto".to_string()));
+        for (id, step) in script {
+            plan.steps.push(step);
+            if let Some(name) = id.and_then(|id| names.get(&id)) {
+                plan.steps.push(RebaseStep::Label(name.clone()));
+            }
+        }
+        Ok(plan)
+    }
+
+    /// The plan for rebasing `branch` without any steps, and the commits to
+    /// rebase, parents first.
+    fn walk<'repo>(
+        repo: &'repo Repository,
+        branch: Option<&AnnotatedCommit<'_>>,
+        upstream: Option<&AnnotatedCommit<'_>>,
+        onto: Option<&AnnotatedCommit<'_>>,
+    ) -> Result<(RebasePlan, Vec<Commit<'repo>>), Error> {
+        let (orig_head, head_name) = match branch {
+            Some(branch) => (branch.id(), branch.ref_name().map(|s| s.to_string())),
+            None => {
+                let head = repo.head()?;
+                let name = if head.is_branch() {
+                    head.name().map(|s| s.to_string())
+                } else {
+                    None
+                };
+                (head.peel_to_commit()?.id(), name)
+            }
+        };
+        let upstream = match upstream.or(onto) {
+            Some(upstream) => upstream.id(),
+            None => {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Rebase,
+                    "an upstream or onto commit is required",
+                ))
+            }
+        };
+        let onto = onto.map(|c| c.id()).unwrap_or(upstream);
+
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(orig_head)?;
+        walk.hide(upstream)?;
+        let commits = walk
+            .map(|id| repo.find_commit(id?))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let plan = RebasePlan {
+            steps: Vec::new(),
+            onto,
+            orig_head,
+            head_name,
+        };
+        Ok((plan, commits))
+    }
+
+    /// The steps of the plan, in order.
+    pub fn steps(&self) -> &[RebaseStep] {
+        &self.steps
+    }
+
+    /// Mutable access to the steps of the plan, to edit it freely.
+    pub fn steps_mut(&mut self) -> &mut Vec<RebaseStep> {
+        &mut self.steps
+    }
+
+    /// The commit the steps are replayed onto.
+    pub fn onto(&self) -> Oid {
+        self.onto
+    }
+
+    /// The commit being rebased.
+    pub fn orig_head(&self) -> Oid {
+        self.orig_head
+    }
+
+    /// The branch updated when the rebase finishes, if any.
+    pub fn head_name(&self) -> Option<&str> {
+        self.head_name.as_deref()
+    }
+
+    /// Reorder the commits of the plan. `order` lists commits of the plan;
+    /// they are moved in that order before the commits not listed, along
+    /// with the squashes, fixups and commands following them.
+    pub fn reorder(&mut self, order: &[Oid]) -> Result<&mut RebasePlan, Error> {
+        let mut groups: Vec<Vec<RebaseStep>> = Vec::new();
+        for step in self.steps.drain(..) {
+            match groups.last_mut() {
+                Some(group) if step.is_attached() => group.push(step),
+                _ => groups.push(vec![step]),
+            }
+        }
+        let mut reordered = Vec::new();
+        for id in order {
+            match groups.iter().position(|g| g[0].id() == Some(*id)) {
+                Some(i) => reordered.push(groups.remove(i)),
+                None => {
+                    self.steps = reordered.into_iter().chain(groups).flatten().collect();
+                    return Err(not_in_plan(*id));
+                }
+            }
+        }
+        self.steps = reordered.into_iter().chain(groups).flatten().collect();
+        Ok(self)
+    }
+
+    /// Remove a commit from the plan.
+    pub fn drop_commit(&mut self, id: Oid) -> Result<&mut RebasePlan, Error> {
+        let i = self.position(id)?;
+        self.steps.remove(i);
+        Ok(self)
+    }
+
+    /// Meld a commit into the one before it, appending its message.
+    pub fn squash(&mut self, id: Oid) -> Result<&mut RebasePlan, Error> {
+        self.replace(id, RebaseStep::Squash(id))
+    }
+
+    /// Meld a commit into the one before it, discarding its message.
+    pub fn fixup(&mut self, id: Oid) -> Result<&mut RebasePlan, Error> {
+        self.replace(id, RebaseStep::Fixup(id))
+    }
+
+    /// Replay a commit with a new message.
+    pub fn reword(&mut self, id: Oid, message: &str) -> Result<&mut RebasePlan, Error> {
+        self.replace(id, RebaseStep::Reword(id, message.to_string()))
+    }
+
+    /// Stop after replaying a commit, to allow it to be amended.
+    pub fn edit(&mut self, id: Oid) -> Result<&mut RebasePlan, Error> {
+        self.replace(id, RebaseStep::Edit(id))
+    }
+
+    /// Run a shell command after replaying a commit.
+    pub fn exec_after(&mut self, id: Oid, command: &str) -> Result<&mut RebasePlan, Error> {
+        let i = self.position(id)?;
+        self.steps
+            .insert(i + 1, RebaseStep::Exec(command.to_string()));
+        Ok(self)
+    }
+
+    /// Move `fixup!` and `squash!` commits right after the commit they
+    /// refer to and turn them into fixups and squashes, like
+    /// `git rebase --autosquash`.
+    ///
+    /// The commit referred to is found by its subject, by a prefix of its
+    /// id, or by a prefix of its subject, in that order.
+    pub fn autosquash(&mut self, repo: &Repository) -> Result<&mut RebasePlan, Error> {
+        let mut subjects = Vec::new();
+        for step in self.steps.iter() {
+            subjects.push(match step.id() {
+                Some(id) => repo.find_commit(id)?.summary().unwrap_or("").to_string(),
+                None => String::new(),
+            });
+        }
+
+        let n = self.steps.len();
+        let mut children = vec![Vec::new(); n];
+        let mut parent = vec![None; n];
+        for j in 0..n {
+            let id = match self.steps[j] {
+                RebaseStep::Pick(id) => id,
+                _ => continue,
+            };
+            let (squash, target) = match autosquash_target(&subjects[j]) {
+                Some(target) => target,
+                None => continue,
+            };
+            let matches = |k: usize, f: &dyn Fn(&str, Oid) -> bool| {
+                self.steps[k].id().is_some_and(|kid| f(&subjects[k], kid))
+            };
+            let found = (0..j)
+                .find(|&k| matches(k, &|s, _| s == target))
+                .or_else(|| {
+                    (0..j).find(|&k| {
+                        matches(k, &|_, kid| {
+                            target.len() >= 4 && kid.to_string().starts_with(target)
+                        })
+                    })
+                })
+                .or_else(|| (0..j).find(|&k| matches(k, &|s, _| s.starts_with(target))));
+            if let Some(k) = found {
+                let root = parent[k].unwrap_or(k);
+                children[root].push(j);
+                parent[j] = Some(root);
+                self.steps[j] = if squash {
+                    RebaseStep::Squash(id)
+                } else {
+                    RebaseStep::Fixup(id)
+                };
+            }
+        }
+
+        let mut steps = Vec::with_capacity(n);
+        for i in 0..n {
+            if parent[i].is_none() {
+                steps.push(self.steps[i].clone());
+                steps.extend(children[i].iter().map(|&j| self.steps[j].clone()));
+            }
+        }
+        self.steps = steps;
+        Ok(self)
+    }
+
+    fn position(&self, id: Oid) -> Result<usize, Error> {
+        self.steps
+            .iter()
+            .position(|step| step.id() == Some(id))
+            .ok_or_else(|| not_in_plan(id))
+    }
+
+    fn replace(&mut self, id: Oid, step: RebaseStep) -> Result<&mut RebasePlan, Error> {
+        let i = self.position(id)?;
+        self.steps[i] = step;
+        Ok(self)
+    }
+}
+
+/// Formats the plan as a `git-rebase-todo` file.
+impl fmt::Display for RebasePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in self.steps.iter() {
+            writeln!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// If `subject` is that of a `fixup!` or `squash!` commit, whether it is a
+/// squash and the subject it refers to.
+fn autosquash_target(subject: &str) -> Option<(bool, &str)> {
+    let squash = if subject.starts_with("squash! ") {
+        true
+    } else if subject.starts_with("fixup! ") {
+        false
+    } else {
+        return None;
+    };
+    let mut target = subject;
+    while let Some(rest) = target
+        .strip_prefix("squash! ")
+        .or_else(|| target.strip_prefix("fixup! "))
+    {
+        target = rest;
+    }
+    Some((squash, target.trim()))
+}
+
+/// The name of the branch merged by a commit with the default message of
+/// `git merge`, e.g. `topic` for `Merge branch 'topic' into main`.
+fn merged_branch(subject: &str) -> Option<&str> {
+    let rest = subject
+        .strip_prefix("Merge branch '")
+        .or_else(|| subject.strip_prefix("Merge remote-tracking branch '"))?;
+    let name = &rest[..rest.find('\'')?];
+    // Labels are written to `refs/rewritten/`, so they must be valid there.
+    let valid = !name.is_empty() && crate::Reference::is_valid_name(&format!("refs/{}", name));
+    if valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn not_in_plan(id: Oid) -> Error {
+    Error::new(
+        ErrorCode::NotFound,
+        ErrorClass::Rebase,
+        format!("commit {} is not part of the rebase plan", id),
+    )
+}
+
+impl RebaseStep {
+    /// Whether this step belongs with the commit before it when reordering.
+    fn is_attached(&self) -> bool {
+        matches!(
+            *self,
+            RebaseStep::Squash(_) | RebaseStep::Fixup(_) | RebaseStep::Exec(_)
+        )
+    }
+
+    /// The commit replayed by this step, if any.
+    pub fn id(&self) -> Option<Oid> {
+        match *self {
+            RebaseStep::Pick(id)
+            | RebaseStep::Reword(id, _)
+            | RebaseStep::Edit(id)
+            | RebaseStep::Squash(id)
+            | RebaseStep::Fixup(id) => Some(id),
+            RebaseStep::Merge(id, _) => id,
+            RebaseStep::Exec(_)
+            | RebaseStep::Break
+            | RebaseStep::Label(_)
+            | RebaseStep::Reset(_) => None,
+        }
+    }
+}
+
+/// Formats the step as a line of a `git-rebase-todo` file, e.g.
+/// `pick 1234abcd`.
+impl fmt::Display for RebaseStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RebaseStep::Pick(id) => write!(f, "pick {}", id),
+            RebaseStep::Reword(id, _) => write!(f, "reword {}", id),
+            RebaseStep::Edit(id) => write!(f, "edit {}", id),
+            RebaseStep::Squash(id) => write!(f, "squash {}", id),
+            RebaseStep::Fixup(id) => write!(f, "fixup {}", id),
+            RebaseStep::Exec(ref command) => write!(f, "exec {}", command),
+            RebaseStep::Break => write!(f, "break"),
+            RebaseStep::Label(ref name) => write!(f, "label {}", name),
+            RebaseStep::Reset(ref name) => write!(f, "reset {}", name),
+            RebaseStep::Merge(id, ref parents) => {
+                write!(f, "merge")?;
+                if let Some(id) = id {
+                    write!(f, " -C {}", id)?;
+                }
+                for parent in parents {
+                    write!(f, " {}", parent)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'repo> PlannedRebase<'repo> {
+    /// Start a rebase following `plan`. See [`Repository::rebase_with_plan`].
+    pub(crate) fn new(
+        repo: &'repo Repository,
+        plan: &RebasePlan,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<PlannedRebase<'repo>, Error> {
+        let inmemory = opts.as_ref().is_some_and(|o| o.is_inmemory());
+        let merge_options = opts.as_ref().and_then(|o| o.merge_opts()).cloned();
+        // Like git, refuse to meld the first commit into the one rebased onto.
+        let command = match plan.steps.first() {
+            Some(RebaseStep::Squash(_)) => Some("squash"),
+            Some(RebaseStep::Fixup(_)) => Some("fixup"),
+            _ => None,
+        };
+        if let Some(command) = command {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Rebase,
+                format!("cannot '{}' without a previous commit", command),
+            ));
+        }
+        let rebase = PlannedRebase {
+            repo,
+            steps: plan.steps.clone(),
+            next: 0,
+            onto: plan.onto,
+            orig_head: plan.orig_head,
+            head_name: plan.head_name.clone(),
+            head: plan.onto,
+            inmemory,
+            merge_options,
+            committer: None,
+            index: None,
+            pending: None,
+            rewritten: Vec::new(),
+            labels: HashMap::new(),
+        };
+        if !inmemory {
+            if repo.state() != RepositoryState::Clean {
+                return Err(Error::new(
+                    ErrorCode::Locked,
+                    ErrorClass::Rebase,
+                    "there is an operation already in progress",
+                ));
+            }
+            let onto = repo.find_object(plan.onto, None)?;
+            repo.checkout_tree(&onto, None)?;
+            repo.set_head_detached(plan.onto)?;
+            rebase.write_state()?;
+        }
+        Ok(rebase)
+    }
+
+    /// Open the rebase on disk stopped by [`PlannedRebase::run`]. See
+    /// [`Repository::open_rebase_plan`].
+    pub(crate) fn open(
+        repo: &'repo Repository,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<PlannedRebase<'repo>, Error> {
+        if opts.as_ref().is_some_and(|o| o.is_inmemory()) {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Rebase,
+                "an in-memory rebase cannot be opened",
+            ));
+        }
+        let dir = repo.path().join("rebase-merge");
+        if !dir.join("interactive").exists() {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Rebase,
+                "there is no interactive rebase in progress",
+            ));
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).map_err(state_error);
+        let head_name = match read("head-name")?.trim() {
+            "detached HEAD" => None,
+            name => Some(name.to_string()),
+        };
+        let onto = Oid::from_str(read("onto")?.trim())?;
+        let orig_head = Oid::from_str(read("orig-head")?.trim())?;
+        let mut steps = parse_todo(repo, &dir, &read("done")?)?;
+        let next = steps.len();
+        steps.extend(parse_todo(repo, &dir, &read("git-rebase-todo")?)?);
+
+        let mut labels = HashMap::new();
+        for step in &steps[..next] {
+            if let RebaseStep::Label(ref name) = *step {
+                labels.insert(name.clone(), repo.refname_to_id(&label_ref(name))?);
+            }
+        }
+        let mut rewritten = Vec::new();
+        if dir.join("rewritten-list").exists() {
+            for line in read("rewritten-list")?.lines() {
+                let mut ids = line.split_whitespace().map(Oid::from_str);
+                if let (Some(orig), Some(new)) = (ids.next(), ids.next()) {
+                    rewritten.push((orig?, new?));
+                }
+            }
+        }
+
+        let mut rebase = PlannedRebase {
+            repo,
+            steps,
+            next,
+            onto,
+            orig_head,
+            head_name,
+            head: repo.head()?.peel_to_commit()?.id(),
+            inmemory: false,
+            merge_options: opts.as_ref().and_then(|o| o.merge_opts()).cloned(),
+            committer: None,
+            index: None,
+            pending: None,
+            rewritten,
+            labels,
+        };
+        // The message is only recorded while the last step is unresolved.
+        if next > 0 && dir.join("message").exists() {
+            let step = rebase.steps[next - 1].clone();
+            rebase.pending = Some(rebase.pending_commit(&step)?);
+        }
+        Ok(rebase)
+    }
+
+    /// Set the committer of the rebased commits, instead of the default
+    /// signature of the repository.
+    pub fn committer(&mut self, committer: &Signature<'_>) -> &mut PlannedRebase<'repo> {
+        self.committer = Some(committer.to_owned());
+        self
+    }
+
+    /// The commit at the tip of the rebased history so far.
+    pub fn head(&self) -> Oid {
+        self.head
+    }
+
+    /// The steps which have not been applied yet.
+    pub fn remaining(&self) -> &[RebaseStep] {
+        &self.steps[self.next..]
+    }
+
+    /// The original and rebased ids of every commit replayed so far. Commits
+    /// melded by squashes and fixups map to the combined commit.
+    pub fn rewritten(&self) -> &[(Oid, Oid)] {
+        &self.rewritten
+    }
+
+    /// The index holding the conflicts of the last step of an in-memory
+    /// rebase, in which they are to be resolved.
+    pub fn inmemory_index(&mut self) -> Option<&mut Index> {
+        self.index.as_mut()
+    }
+
+    /// Apply steps until the plan is complete or a step needs attention.
+    ///
+    /// If the rebase stopped because of conflicts, the resolved index is
+    /// committed first; it is an error if conflicts remain.
+    pub fn run(&mut self) -> Result<RebasePlanStatus, Error> {
+        self.resume()?;
+        while self.next < self.steps.len() {
+            let step = self.steps[self.next].clone();
+            self.next += 1;
+            let status = self.apply(&step)?;
+            if !self.inmemory {
+                self.write_state()?;
+            }
+            if let Some(status) = status {
+                return Ok(status);
+            }
+        }
+        Ok(RebasePlanStatus::Complete)
+    }
+
+    /// Skip the step which stopped with conflicts, discarding its changes.
+    pub fn skip(&mut self) -> Result<(), Error> {
+        if self.pending.take().is_none() {
+            return Ok(());
+        }
+        self.index = None;
+        if !self.inmemory {
+            let head = self.repo.find_object(self.head, None)?;
+            self.repo
+                .checkout_tree(&head, Some(CheckoutBuilder::new().force()))?;
+            self.repo.set_head_detached(self.head)?;
+        }
+        Ok(())
+    }
+
+    /// Abandon the rebase, restoring the original branch.
+    pub fn abort(&mut self) -> Result<(), Error> {
+        self.pending = None;
+        self.index = None;
+        if self.inmemory {
+            return Ok(());
+        }
+        let orig = self.repo.find_object(self.orig_head, None)?;
+        self.repo
+            .checkout_tree(&orig, Some(CheckoutBuilder::new().force()))?;
+        match self.head_name {
+            Some(ref name) => self.repo.set_head(name)?,
+            None => self.repo.set_head_detached(self.orig_head)?,
+        }
+        self.remove_state()
+    }
+
+    /// Finish a complete rebase and return the rebased tip.
+    ///
+    /// For a rebase on disk, the original branch is updated to the rebased
+    /// tip and checked out again. An in-memory rebase does not touch any
+    /// reference.
+    pub fn finish(&mut self) -> Result<Oid, Error> {
+        if self.pending.is_some() || self.next < self.steps.len() {
+            return Err(Error::new(
+                ErrorCode::Unmerged,
+                ErrorClass::Rebase,
+                "the rebase has not been completed",
+            ));
+        }
+        if self.inmemory {
+            return Ok(self.head);
+        }
+        match self.head_name {
+            Some(ref name) => {
+                let msg = format!("rebase (finish): {} onto {}", name, self.onto);
+                self.repo.reference(name, self.head, true, &msg)?;
+                self.repo.set_head(name)?;
+            }
+            None => self.repo.set_head_detached(self.head)?,
+        }
+        self.remove_state()?;
+        Ok(self.head)
+    }
+
+    /// Commit the resolution of a conflicted step, if there is one.
+    fn resume(&mut self) -> Result<(), Error> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                if !self.inmemory {
+                    // The commit may have been amended while stopped.
+                    self.head = self.repo.head()?.peel_to_commit()?.id();
+                }
+                return Ok(());
+            }
+        };
+        let tree = {
+            let mut disk_index;
+            let index = match self.index {
+                Some(ref mut index) => index,
+                None => {
+                    disk_index = self.repo.index()?;
+                    &mut disk_index
+                }
+            };
+            if index.has_conflicts() {
+                self.pending = Some(pending);
+                return Err(Error::new(
+                    ErrorCode::Unmerged,
+                    ErrorClass::Rebase,
+                    "conflicts must be resolved before continuing",
+                ));
+            }
+            index.write_tree_to(self.repo)?
+        };
+        self.index = None;
+        let tree = self.repo.find_tree(tree)?;
+        // The working directory already holds the resolution.
+        self.commit(&pending, &tree, false)?;
+        if let RebaseStep::Edit(id) = pending.step {
+            self.next -= 1;
+            self.steps[self.next] = RebaseStep::Pick(id);
+            self.next += 1;
+        }
+        Ok(())
+    }
+
+    /// Apply a single step, returning why the rebase must stop, if it must.
+    fn apply(&mut self, step: &RebaseStep) -> Result<Option<RebasePlanStatus>, Error> {
+        let id = match *step {
+            RebaseStep::Exec(ref command) => return self.exec(command),
+            RebaseStep::Break => return Ok(Some(RebasePlanStatus::Break)),
+            RebaseStep::Label(ref name) => return self.label(name).map(|()| None),
+            RebaseStep::Reset(ref name) => return self.reset(name).map(|()| None),
+            RebaseStep::Merge(..) => return self.merge(step),
+            _ => step.id().unwrap(),
+        };
+        let commit = self.repo.find_commit(id)?;
+        let head = self.repo.find_commit(self.head)?;
+        let pending = self.pending_commit(step)?;
+
+        let mut index = self.pick_index(&commit, &head)?;
+        if index.has_conflicts() {
+            if self.inmemory {
+                self.index = Some(index);
+            } else {
+                let mut checkout = CheckoutBuilder::new();
+                checkout.allow_conflicts(true);
+                self.repo
+                    .checkout_index(Some(&mut index), Some(&mut checkout))?;
+            }
+            self.pending = Some(pending);
+            return Ok(Some(RebasePlanStatus::Conflict(step.clone())));
+        }
+        let tree = self.repo.find_tree(index.write_tree_to(self.repo)?)?;
+        self.commit(&pending, &tree, true)?;
+        match *step {
+            RebaseStep::Edit(_) => Ok(Some(RebasePlanStatus::Edit(self.head))),
+            _ => Ok(None),
+        }
+    }
+
+    /// The commit to create for a step which replays a commit or merges.
+    fn pending_commit(&self, step: &RebaseStep) -> Result<PendingCommit, Error> {
+        if let RebaseStep::Merge(id, ref labels) = *step {
+            let mut parents = vec![self.head];
+            for name in labels {
+                parents.push(self.resolve_label(name)?);
+            }
+            let (author, message) = match id {
+                Some(id) => {
+                    let commit = self.repo.find_commit(id)?;
+                    let author = commit.author().to_owned();
+                    (author, commit.message_raw().unwrap_or("").to_string())
+                }
+                None => {
+                    let author = match self.committer {
+                        Some(ref committer) => committer.clone(),
+                        None => self.repo.signature()?,
+                    };
+                    (author, merge_message(labels))
+                }
+            };
+            return Ok(PendingCommit {
+                step: step.clone(),
+                author,
+                message,
+                parents,
+            });
+        }
+
+        let id = step.id().unwrap();
+        let commit = self.repo.find_commit(id)?;
+        let head = self.repo.find_commit(self.head)?;
+        let message = commit.message_raw().unwrap_or("").to_string();
+        Ok(match *step {
+            RebaseStep::Squash(_) | RebaseStep::Fixup(_) => {
+                let mut combined = head.message_raw().unwrap_or("").to_string();
+                if let RebaseStep::Squash(_) = step {
+                    combined = squash_message(&combined, &message);
+                }
+                PendingCommit {
+                    step: step.clone(),
+                    author: head.author().to_owned(),
+                    message: combined,
+                    parents: head.parent_ids().collect(),
+                }
+            }
+            RebaseStep::Reword(_, ref message) => PendingCommit {
+                step: step.clone(),
+                author: commit.author().to_owned(),
+                message: message.clone(),
+                parents: vec![self.head],
+            },
+            _ => PendingCommit {
+                step: step.clone(),
+                author: commit.author().to_owned(),
+                message,
+                parents: vec![self.head],
+            },
+        })
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), Error> {
+        if !self.inmemory {
+            let msg = format!("rebase (label) '{}'", name);
+            self.repo
+                .reference(&label_ref(name), self.head, true, &msg)?;
+        }
+        self.labels.insert(name.to_string(), self.head);
+        Ok(())
+    }
+
+    fn reset(&mut self, name: &str) -> Result<(), Error> {
+        let id = self.resolve_label(name)?;
+        if !self.inmemory {
+            let object = self.repo.find_object(id, None)?;
+            self.repo.checkout_tree(&object, None)?;
+            self.repo.set_head_detached(id)?;
+        }
+        self.head = id;
+        Ok(())
+    }
+
+    /// The commit a label refers to: a labelled commit, `onto`, or a commit
+    /// given by its id.
+    fn resolve_label(&self, name: &str) -> Result<Oid, Error> {
+        if let Some(id) = self.labels.get(name) {
+            return Ok(*id);
+        }
+        if name == "onto" {
+            return Ok(self.onto);
+        }
+        match self.repo.revparse_single(name) {
+            Ok(object) => Ok(object.peel_to_commit()?.id()),
+            Err(_) => Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Rebase,
+                format!("could not resolve label '{}'", name),
+            )),
+        }
+    }
+
+    /// Merge the commits labelled `labels` into the head, one after the
+    /// other for octopus merges.
+    fn merge(&mut self, step: &RebaseStep) -> Result<Option<RebasePlanStatus>, Error> {
+        let pending = self.pending_commit(step)?;
+        let mut tree = self.repo.find_commit(self.head)?.tree()?;
+        let mut index = None;
+        for (i, &parent) in pending.parents.iter().enumerate().skip(1) {
+            // Each further head is merged with everything merged so far.
+            let mut ids = vec![parent];
+            ids.extend(&pending.parents[..i]);
+            let base = match self.repo.merge_base_many(&ids) {
+                Ok(base) => self.repo.find_commit(base)?.tree()?,
+                Err(ref e) if e.code() == ErrorCode::NotFound => {
+                    self.repo.find_tree(self.repo.treebuilder(None)?.write()?)?
+                }
+                Err(e) => return Err(e),
+            };
+            let theirs = self.repo.find_commit(parent)?.tree()?;
+            let mut merged =
+                self.repo
+                    .merge_trees(&base, &tree, &theirs, self.merge_options.as_ref())?;
+            if merged.has_conflicts() {
+                index = Some(merged);
+                break;
+            }
+            tree = self.repo.find_tree(merged.write_tree_to(self.repo)?)?;
+        }
+        if let Some(mut index) = index {
+            if self.inmemory {
+                self.index = Some(index);
+            } else {
+                let mut checkout = CheckoutBuilder::new();
+                checkout.allow_conflicts(true);
+                self.repo
+                    .checkout_index(Some(&mut index), Some(&mut checkout))?;
+            }
+            self.pending = Some(pending);
+            return Ok(Some(RebasePlanStatus::Conflict(step.clone())));
+        }
+        self.commit(&pending, &tree, true)?;
+        Ok(None)
+    }
+
+    /// The result of applying the changes of `commit` onto `head`.
+    fn pick_index(&self, commit: &Commit<'_>, head: &Commit<'_>) -> Result<Index, Error> {
+        let base = match commit.parent_count() {
+            0 => self.repo.find_tree(self.repo.treebuilder(None)?.write()?)?,
+            _ => commit.parent(0)?.tree()?,
+        };
+        self.repo.merge_trees(
+            &base,
+            &head.tree()?,
+            &commit.tree()?,
+            self.merge_options.as_ref(),
+        )
+    }
+
+    /// Create the commit of a step with `tree` and make it the new head,
+    /// checking it out if asked to on disk.
+    fn commit(
+        &mut self,
+        pending: &PendingCommit,
+        tree: &crate::Tree<'_>,
+        checkout: bool,
+    ) -> Result<(), Error> {
+        let committer = match self.committer {
+            Some(ref committer) => committer.clone(),
+            None => self.repo.signature()?,
+        };
+        let parents = pending
+            .parents
+            .iter()
+            .map(|id| self.repo.find_commit(*id))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let parents = parents.iter().collect::<Vec<_>>();
+        let id = self.repo.commit(
+            None,
+            &pending.author,
+            &committer,
+            &pending.message,
+            tree,
+            &parents,
+        )?;
+        let squashed = match pending.step {
+            RebaseStep::Squash(_) | RebaseStep::Fixup(_) => Some(self.head),
+            _ => None,
+        };
+        for entry in self.rewritten.iter_mut() {
+            if Some(entry.1) == squashed {
+                entry.1 = id;
+            }
+        }
+        if let Some(orig) = pending.step.id() {
+            self.rewritten.push((orig, id));
+        }
+        if !self.inmemory {
+            if checkout {
+                let object = self.repo.find_object(id, None)?;
+                self.repo.checkout_tree(&object, None)?;
+            }
+            self.repo.set_head_detached(id)?;
+        }
+        self.head = id;
+        Ok(())
+    }
+
+    fn exec(&mut self, command: &str) -> Result<Option<RebasePlanStatus>, Error> {
+        let dir = self
+            .repo
+            .workdir()
+            .unwrap_or_else(|| self.repo.path())
+            .to_path_buf();
+        let mut cmd = if cfg!(windows) {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C");
+            cmd
+        } else {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c");
+            cmd
+        };
+        let status = cmd.arg(command).current_dir(dir).status().map_err(|e| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to run '{}': {}", command, e),
+            )
+        })?;
+        if status.success() {
+            Ok(None)
+        } else {
+            Ok(Some(RebasePlanStatus::ExecFailed(
+                command.to_string(),
+                status.code(),
+            )))
+        }
+    }
+
+    fn state_dir(&self) -> PathBuf {
+        self.repo.path().join("rebase-merge")
+    }
+
+    /// Record the progress of a rebase on disk in the layout used by
+    /// `git rebase --interactive`.
+    fn write_state(&self) -> Result<(), Error> {
+        let dir = self.state_dir();
+        let todo = |steps: &[RebaseStep]| {
+            steps
+                .iter()
+                .map(|step| format!("{}\n", step))
+                .collect::<String>()
+        };
+        let mut files = vec![
+            (
+                "head-name",
+                format!("{}\n", self.head_name.as_deref().unwrap_or("detached HEAD")),
+            ),
+            ("onto", format!("{}\n", self.onto)),
+            ("orig-head", format!("{}\n", self.orig_head)),
+            ("interactive", String::new()),
+            ("done", todo(&self.steps[..self.next])),
+            ("git-rebase-todo", todo(&self.steps[self.next..])),
+            ("msgnum", format!("{}\n", self.next)),
+            ("end", format!("{}\n", self.steps.len())),
+        ];
+        let rewritten = self
+            .rewritten
+            .iter()
+            .map(|(orig, new)| format!("{} {}\n", orig, new))
+            .collect::<String>();
+        files.push(("rewritten-list", rewritten));
+        fs::create_dir_all(dir.join("reword")).map_err(state_error)?;
+        for step in &self.steps {
+            if let RebaseStep::Reword(id, ref message) = *step {
+                let path = dir.join("reword").join(id.to_string());
+                fs::write(path, message).map_err(state_error)?;
+            }
+        }
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).map_err(state_error)?;
+        }
+        // A resolved step leaves nothing pending to be committed on resume.
+        let stopped = self.pending.as_ref().map(|p| (&p.message, p.step.id()));
+        for (name, contents) in [
+            ("message", stopped.map(|(message, _)| message.clone())),
+            (
+                "stopped-sha",
+                stopped.and_then(|(_, id)| id).map(|id| format!("{}\n", id)),
+            ),
+        ] {
+            let path = dir.join(name);
+            match contents {
+                Some(contents) => fs::write(path, contents).map_err(state_error)?,
+                None => match fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(state_error(e))
+                    }
+                    _ => {}
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_state(&self) -> Result<(), Error> {
+        for name in self.labels.keys() {
+            if let Ok(mut reference) = self.repo.find_reference(&label_ref(name)) {
+                reference.delete()?;
+            }
+        }
+        match fs::remove_dir_all(self.state_dir()) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to remove rebase state: {}", e),
+            )),
+        }
+    }
+}
+
+fn state_error(e: std::io::Error) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Os,
+        format!("failed to access rebase state: {}", e),
+    )
+}
+
+/// Parse the steps of a todo list written by [`PlannedRebase`] or git,
+/// taking reworded messages from the `reword` directory of the state.
+fn parse_todo(repo: &Repository, dir: &Path, todo: &str) -> Result<Vec<RebaseStep>, Error> {
+    let invalid = |line: &str| {
+        Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Rebase,
+            format!("invalid rebase todo line '{}'", line),
+        )
+    };
+    let commit = |id: Option<&str>, line: &str| match id {
+        Some(id) => Ok(repo.revparse_single(id)?.peel_to_commit()?.id()),
+        None => Err(invalid(line)),
+    };
+    let mut steps = Vec::new();
+    for line in todo.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+        let mut args = rest.split_whitespace();
+        let step = match command {
+            "pick" | "p" => RebaseStep::Pick(commit(args.next(), line)?),
+            "reword" | "r" => {
+                let id = commit(args.next(), line)?;
+                let message = match fs::read(dir.join("reword").join(id.to_string())) {
+                    Ok(message) => String::from_utf8_lossy(&message).into_owned(),
+                    Err(_) => {
+                        let commit = repo.find_commit(id)?;
+                        commit.message_raw().unwrap_or("").to_string()
+                    }
+                };
+                RebaseStep::Reword(id, message)
+            }
+            "edit" | "e" => RebaseStep::Edit(commit(args.next(), line)?),
+            "squash" | "s" => RebaseStep::Squash(commit(args.next(), line)?),
+            "fixup" | "f" => RebaseStep::Fixup(commit(args.next(), line)?),
+            "exec" | "x" if !rest.is_empty() => RebaseStep::Exec(rest.to_string()),
+            "break" | "b" => RebaseStep::Break,
+            "label" | "l" => RebaseStep::Label(args.next().ok_or_else(|| invalid(line))?.into()),
+            "reset" | "t" => RebaseStep::Reset(args.next().ok_or_else(|| invalid(line))?.into()),
+            "merge" | "m" => {
+                let mut args = args.peekable();
+                let id = match args.peek() {
+                    Some(&"-C") | Some(&"-c") => {
+                        args.next();
+                        Some(commit(args.next(), line)?)
+                    }
+                    _ => None,
+                };
+                // Everything after a `#` is the one-line message of the merge.
+                let labels = args
+                    .take_while(|arg| *arg != "#")
+                    .map(String::from)
+                    .collect::<Vec<_>>();
+                if labels.is_empty() {
+                    return Err(invalid(line));
+                }
+                RebaseStep::Merge(id, labels)
+            }
+            _ => return Err(invalid(line)),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+/// The reference recording a label of a rebase on disk, as git does.
+fn label_ref(name: &str) -> String {
+    format!("refs/rewritten/{}", name)
+}
+
+/// The default message of a merge of `names`, like `git merge` would write.
+fn merge_message(names: &[String]) -> String {
+    let quoted = names.iter().map(|n| format!("'{}'", n)).collect::<Vec<_>>();
+    match quoted.split_last() {
+        Some((last, [])) => format!("Merge branch {}\n", last),
+        Some((last, rest)) => format!("Merge branches {} and {}\n", rest.join(", "), last),
+        None => "Merge\n".to_string(),
+    }
+}
+
+/// The message of a commit squashed into one with message `into`, leaving
+/// out the subject of `squash!` commits like git does.
+fn squash_message(into: &str, message: &str) -> String {
+    let message = match message.lines().next() {
+        Some(subject) if subject.starts_with("squash! ") => {
+            message[subject.len()..].trim_start_matches('\n')
+        }
+        _ => message,
+    };
+    let mut combined = into.trim_end().to_string();
+    if !message.trim().is_empty() {
+        combined.push_str("\n\n");
+        combined.push_str(message);
+    } else {
+        combined.push('\n');
+    }
+    combined
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::commit_files;
+    use crate::{Oid, RebaseOptions, RebasePlan, RebasePlanStatus, RebaseStep, Repository};
+    use std::fs;
+    use std::path::Path;
+
+    fn subjects(repo: &Repository, tip: Oid, count: usize) -> Vec<String> {
+        let mut subjects = Vec::new();
+        let mut commit = repo.find_commit(tip).unwrap();
+        for _ in 0..count {
+            subjects.push(commit.summary().unwrap().to_string());
+            commit = commit.parent(0).unwrap();
+        }
+        subjects.reverse();
+        subjects
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_files(&repo, &[("a", Some("a\n"))], "add a");
+        let b = commit_files(&repo, &[("b", Some("b\n"))], "add b");
+        let fix = commit_files(&repo, &[("a", Some("a fixed\n"))], "fixup! add a");
+        let c = commit_files(&repo, &[("c", Some("c\n"))], "add c");
+        let squash = commit_files(
+            &repo,
+            &[("c", Some("c\nmore\n"))],
+            "squash! add c\n\nmore c",
+        );
+
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut plan = RebasePlan::new(&repo, None, Some(&upstream), None).unwrap();
+        assert_eq!(plan.head_name(), Some("refs/heads/main"));
+        assert_eq!(plan.steps().len(), 5);
+        plan.autosquash(&repo).unwrap();
+        assert_eq!(
+            plan.steps(),
+            &[
+                RebaseStep::Pick(a),
+                RebaseStep::Fixup(fix),
+                RebaseStep::Pick(b),
+                RebaseStep::Pick(c),
+                RebaseStep::Squash(squash),
+            ]
+        );
+        plan.reorder(&[c]).unwrap();
+        plan.reword(b, "add b, reworded\n").unwrap();
+        assert_eq!(plan.steps()[0], RebaseStep::Pick(c));
+        assert_eq!(plan.steps()[1], RebaseStep::Squash(squash));
+
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo.rebase_with_plan(&plan, Some(&mut opts)).unwrap();
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        let tip = rebase.finish().unwrap();
+        assert_eq!(
+            subjects(&repo, tip, 3),
+            vec!["add c", "add a", "add b, reworded"]
+        );
+        let c2 = repo.find_commit(tip).unwrap().parent(0).unwrap();
+        let c2 = c2.parent(0).unwrap();
+        assert_eq!(c2.message(), Some("add c\n\nmore c"));
+        let tree = repo.find_commit(tip).unwrap().tree().unwrap();
+        let blob = tree.get_path(Path::new("a")).unwrap().id();
+        assert_eq!(repo.find_blob(blob).unwrap().content(), b"a fixed\n");
+        assert_eq!(rebase.rewritten().len(), 5);
+
+        // An in-memory rebase leaves the branch alone.
+        assert_eq!(repo.head().unwrap().target(), Some(squash));
+    }
+
+    #[test]
+    fn on_disk_with_conflict() {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_files(&repo, &[("file", Some("a\n"))], "first");
+        let b = commit_files(&repo, &[("file", Some("b\n"))], "second");
+        let c = commit_files(&repo, &[("other", Some("c\n"))], "third");
+
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut plan = RebasePlan::new(&repo, None, Some(&upstream), None).unwrap();
+        plan.drop_commit(a).unwrap();
+        plan.exec_after(c, "test -f other").unwrap();
+        assert_eq!(plan.steps().len(), 3);
+
+        let mut rebase = repo.rebase_with_plan(&plan, None).unwrap();
+        assert_eq!(repo.state(), crate::RepositoryState::RebaseInteractive);
+        // Without the first commit, the second one conflicts (add/add).
+        assert_eq!(
+            rebase.run().unwrap(),
+            RebasePlanStatus::Conflict(RebaseStep::Pick(b))
+        );
+        assert!(repo.index().unwrap().has_conflicts());
+        assert!(rebase.run().is_err());
+
+        fs::write(td.path().join("file"), "resolved\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file")).unwrap();
+        index.write().unwrap();
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        let tip = rebase.finish().unwrap();
+
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+        let head = repo.head().unwrap();
+        assert_eq!(head.name(), Some("refs/heads/main"));
+        assert_eq!(head.target(), Some(tip));
+        assert_eq!(subjects(&repo, tip, 2), vec!["second", "third"]);
+        assert_eq!(
+            fs::read_to_string(td.path().join("file")).unwrap(),
+            "resolved\n"
+        );
+    }
+
+    #[test]
+    fn reopen_after_conflict() {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_files(&repo, &[("file", Some("a\n"))], "first");
+        let b = commit_files(&repo, &[("file", Some("b\n"))], "second");
+        let c = commit_files(&repo, &[("other", Some("c\n"))], "third");
+
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut plan = RebasePlan::new(&repo, None, Some(&upstream), None).unwrap();
+        plan.drop_commit(a).unwrap();
+        plan.reword(c, "reworded\n").unwrap();
+        {
+            let mut rebase = repo.rebase_with_plan(&plan, None).unwrap();
+            assert_eq!(
+                rebase.run().unwrap(),
+                RebasePlanStatus::Conflict(RebaseStep::Pick(b))
+            );
+        }
+
+        let mut rebase = repo.open_rebase_plan(None).unwrap();
+        assert_eq!(
+            rebase.remaining(),
+            &[RebaseStep::Reword(c, "reworded\n".to_string())]
+        );
+        fs::write(td.path().join("file"), "resolved\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file")).unwrap();
+        index.write().unwrap();
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        let tip = rebase.finish().unwrap();
+
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+        assert_eq!(repo.head().unwrap().target(), Some(tip));
+        assert_eq!(subjects(&repo, tip, 2), vec!["second", "reworded"]);
+        assert!(repo.open_rebase_plan(None).is_err());
+    }
+
+    #[test]
+    fn leading_squash() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_files(&repo, &[("file", Some("a\n"))], "first");
+        commit_files(&repo, &[("file", Some("b\n"))], "second");
+
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut plan = RebasePlan::new(&repo, None, Some(&upstream), None).unwrap();
+        plan.squash(a).unwrap();
+        let err = repo.rebase_with_plan(&plan, None).err().unwrap();
+        assert_eq!(err.message(), "cannot 'squash' without a previous commit");
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+    }
+
+    #[test]
+    fn abort() {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_files(&repo, &[("file", Some("a\n"))], "first");
+        let b = commit_files(&repo, &[("file", Some("b\n"))], "second");
+
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut plan = RebasePlan::new(&repo, None, Some(&upstream), None).unwrap();
+        plan.edit(a).unwrap();
+        let mut rebase = repo.rebase_with_plan(&plan, None).unwrap();
+        let status = rebase.run().unwrap();
+        assert!(matches!(status, RebasePlanStatus::Edit(_)));
+        rebase.abort().unwrap();
+
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+        assert_eq!(repo.head().unwrap().target(), Some(b));
+        assert_eq!(fs::read_to_string(td.path().join("file")).unwrap(), "b\n");
+    }
+
+    /// Create a commit with `parents`, changing `file` in the tree of the
+    /// first one, or merging them if `file` is `None`.
+    fn commit_on(repo: &Repository, parents: &[Oid], file: Option<(&str, &str)>, msg: &str) -> Oid {
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let tree = match file {
+            Some((name, contents)) => {
+                let base = parents[0].tree().unwrap();
+                let mut builder = repo.treebuilder(Some(&base)).unwrap();
+                let blob = repo.blob(contents.as_bytes()).unwrap();
+                builder.insert(name, blob, 0o100644).unwrap();
+                builder.write().unwrap()
+            }
+            None => {
+                let mut index = repo.merge_commits(&parents[0], &parents[1], None).unwrap();
+                take_theirs(&mut index);
+                index.write_tree_to(repo).unwrap()
+            }
+        };
+        let tree = repo.find_tree(tree).unwrap();
+        let sig = repo.signature().unwrap();
+        let parents = parents.iter().collect::<Vec<_>>();
+        repo.commit(None, &sig, &sig, msg, &tree, &parents).unwrap()
+    }
+
+    /// Resolve every conflict of `index` with their side.
+    fn take_theirs(index: &mut crate::Index) {
+        let conflicts = index
+            .conflicts()
+            .unwrap()
+            .map(|c| c.unwrap().their.unwrap())
+            .collect::<Vec<_>>();
+        for mut entry in conflicts {
+            let path = String::from_utf8(entry.path.clone()).unwrap();
+            for stage in 1..=3 {
+                let _ = index.remove(Path::new(&path), stage);
+            }
+            entry.flags &= !0x3000;
+            index.add(&entry).unwrap();
+        }
+    }
+
+    /// Build `A - B - M` on main where `M` merges `C`, branched from `A`,
+    /// and `U` on top of the initial commit. Returns `(upstream, plan)`.
+    fn merges(repo: &Repository, side: &str) -> (Oid, RebasePlan) {
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_on(repo, &[base], Some(("a", "a\n")), "add a");
+        let b = commit_on(repo, &[a], Some(("b", "b\n")), "add b");
+        let c = commit_on(repo, &[a], Some(side.split_once(':').unwrap()), "add c");
+        let m = commit_on(repo, &[b, c], None, "Merge branch 'side'\n");
+        let u = commit_on(repo, &[base], Some(("u", "u\n")), "add u");
+        repo.reference("refs/heads/main", m, true, "").unwrap();
+        repo.checkout_head(Some(crate::build::CheckoutBuilder::new().force()))
+            .unwrap();
+
+        let upstream = repo.find_annotated_commit(u).unwrap();
+        let plan = RebasePlan::with_merges(repo, None, Some(&upstream), None).unwrap();
+        let todo = format!(
+            "label onto\nreset onto\npick {a}\nlabel branch-point\n\
+             pick {c}\nlabel side\nreset branch-point\npick {b}\nmerge -C {m} side\n",
+            a = a,
+            b = b,
+            c = c,
+            m = m
+        );
+        assert_eq!(plan.to_string(), todo);
+        (u, plan)
+    }
+
+    /// Check that `tip` is a merge of `add b` and `add c`, both replayed
+    /// onto `add a` on top of `upstream`.
+    fn assert_merged(repo: &Repository, tip: Oid, upstream: Oid) {
+        let tip = repo.find_commit(tip).unwrap();
+        assert_eq!(tip.summary(), Some("Merge branch 'side'"));
+        let b = tip.parent(0).unwrap();
+        let c = tip.parent(1).unwrap();
+        assert_eq!(b.summary(), Some("add b"));
+        assert_eq!(c.summary(), Some("add c"));
+        assert_eq!(b.parent_id(0).unwrap(), c.parent_id(0).unwrap());
+        let a = b.parent(0).unwrap();
+        assert_eq!(a.summary(), Some("add a"));
+        assert_eq!(a.parent_id(0).unwrap(), upstream);
+        for file in ["a", "b", "c", "u"] {
+            assert!(tip.tree().unwrap().get_name(file).is_some(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn rebase_merges() {
+        let (_td, repo) = crate::test::repo_init();
+        let (upstream, plan) = merges(&repo, "c:c\n");
+
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo.rebase_with_plan(&plan, Some(&mut opts)).unwrap();
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        let tip = rebase.finish().unwrap();
+        assert_merged(&repo, tip, upstream);
+        assert_eq!(rebase.rewritten().len(), 4);
+
+        let mut rebase = repo.rebase_with_plan(&plan, None).unwrap();
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        assert!(repo.find_reference("refs/rewritten/side").is_ok());
+        let tip = rebase.finish().unwrap();
+        assert_merged(&repo, tip, upstream);
+        assert_eq!(repo.head().unwrap().target(), Some(tip));
+        assert!(repo.find_reference("refs/rewritten/side").is_err());
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+    }
+
+    #[test]
+    fn rebase_merges_conflict() {
+        let (_td, repo) = crate::test::repo_init();
+        // The side branch changes the file added by `add b`, so the merge
+        // conflicts when replayed.
+        let (upstream, plan) = merges(&repo, "b:c\n");
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo.rebase_with_plan(&plan, Some(&mut opts)).unwrap();
+        let status = rebase.run().unwrap();
+        let step = plan.steps().last().unwrap().clone();
+        assert_eq!(status, RebasePlanStatus::Conflict(step));
+
+        let index = rebase.inmemory_index().unwrap();
+        take_theirs(index);
+        assert!(!index.has_conflicts());
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        let tip = rebase.finish().unwrap();
+        let tip = repo.find_commit(tip).unwrap();
+        assert_eq!(tip.parent_count(), 2);
+        assert_eq!(
+            tip.parent(0)
+                .unwrap()
+                .parent(0)
+                .unwrap()
+                .parent_id(0)
+                .unwrap(),
+            upstream
+        );
+    }
+
+    #[test]
+    fn octopus_merge() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        // The second head builds on the first, changing the same line.
+        let first = commit_on(&repo, &[base], Some(("f", "first\n")), "first");
+        let second = commit_on(&repo, &[first], Some(("f", "second\n")), "second");
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut plan = RebasePlan::new(&repo, None, Some(&upstream), None).unwrap();
+        plan.steps_mut().push(RebaseStep::Merge(
+            None,
+            vec![first.to_string(), second.to_string()],
+        ));
+
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo.rebase_with_plan(&plan, Some(&mut opts)).unwrap();
+        assert_eq!(rebase.run().unwrap(), RebasePlanStatus::Complete);
+        let tip = repo.find_commit(rebase.finish().unwrap()).unwrap();
+        assert_eq!(tip.parent_ids().collect::<Vec<_>>(), [base, first, second]);
+        let entry = tip.tree().unwrap().get_name("f").unwrap().id();
+        assert_eq!(repo.find_blob(entry).unwrap().content(), b"second\n");
+    }
+}
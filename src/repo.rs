@@ -1,38 +1,58 @@
 use libc::{c_char, c_int, c_uint, c_void, size_t};
+use std::collections::HashSet;
 use std::env;
 use std::ffi::{CStr, CString, OsStr};
+use std::fs;
 use std::iter::IntoIterator;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::ptr;
 use std::str;
+use std::time::SystemTime;
 
 use crate::build::{CheckoutBuilder, RepoBuilder};
+use crate::call;
+use crate::commit_graph;
 use crate::diff::{
     binary_cb_c, file_cb_c, hunk_cb_c, line_cb_c, BinaryCb, DiffCallbacks, FileCb, HunkCb, LineCb,
 };
+use crate::fsck;
+use crate::fsmonitor;
+use crate::index_file;
+use crate::maintenance;
+use crate::merge;
 use crate::oid_array::OidArray;
-use crate::stash::{stash_cb, StashApplyOptions, StashCbData, StashSaveOptions};
+use crate::signing::{self, Signer, Verifier};
+use crate::sparse;
+use crate::stash::{stash_cb, StashApplyOptions, StashCbData, StashEntry, StashSaveOptions};
+use crate::status::{self, adjust_status};
 use crate::string_array::StringArray;
+use crate::submodule;
 use crate::tagforeach::{tag_foreach_cb, TagForeachCB, TagForeachData};
+use crate::untracked_cache;
 use crate::util::{self, path_to_repo_path, Binding};
 use crate::worktree::{Worktree, WorktreeAddOptions};
 use crate::CherrypickOptions;
+use crate::Mailbox;
 use crate::RevertOptions;
+use crate::SparseCheckout;
 use crate::{mailmap::Mailmap, panic};
 use crate::{
-    raw, AttrCheckFlags, Buf, Error, Object, Remote, RepositoryOpenFlags, RepositoryState, Revspec,
-    StashFlags,
+    raw, AttrCheckFlags, Buf, Error, ErrorClass, Object, Remote, RepositoryOpenFlags,
+    RepositoryState, Revspec, StashFlags,
 };
 use crate::{
-    AnnotatedCommit, MergeAnalysis, MergeOptions, MergePreference, SubmoduleIgnore,
-    SubmoduleStatus, SubmoduleUpdate,
+    AnnotatedCommit, ErrorCode, MergeAnalysis, MergeOptions, MergePreference, MergeReport,
+    MergeStrategy, SubmoduleIgnore, SubmoduleStatus, SubmoduleUpdate, SubmoduleUpdateAllOptions,
 };
-use crate::{ApplyLocation, ApplyOptions, Rebase, RebaseOptions};
+use crate::{ApplyLocation, ApplyOptions, PlannedRebase, Rebase, RebaseOptions, RebasePlan};
 use crate::{Blame, BlameOptions, Reference, References, ResetType, Signature, Submodule};
-use crate::{Blob, BlobWriter, Branch, BranchType, Branches, Commit, Config, Index, Oid, Tree};
-use crate::{Describe, IntoCString, Reflog, RepositoryInitMode, RevparseMode};
-use crate::{DescribeOptions, Diff, DiffOptions, Odb, PackBuilder, TreeBuilder};
+use crate::{Blob, BlobWriter, Branch, BranchType, Branches, Commit, Config, Index, IndexEntry};
+use crate::{CommitGraph, CommitGraphOptions, Sequencer, SequencerOptions, SequencerStep};
+use crate::{Describe, IntoCString, Oid, Reflog, RepositoryInitMode, RevparseMode, Tree};
+use crate::{DescribeOptions, Diff, DiffOptions, Odb, PackBuilder, RepackOptions, TreeBuilder};
+use crate::{FsMonitor, FsckOptions, FsckReport, GcOptions, GcReport, UnreachableOptions};
+use crate::{IndexEntryExtendedFlag, IndexEntryFlag};
 use crate::{Note, Notes, ObjectType, Revwalk, Status, StatusOptions, Statuses, Tag, Transaction};
 
 type MergeheadForeachCb<'a> = dyn FnMut(&Oid) -> bool + 'a;
@@ -457,6 +477,22 @@ impl Repository {
         }
     }
 
+    /// The directory holding what the worktrees of this repository share:
+    /// the objects, the references and the administrative directories of
+    /// the worktrees.
+    pub(crate) fn common_dir(&self) -> &Path {
+        unsafe {
+            let ptr = raw::git_repository_commondir(self.raw);
+            util::bytes2path(crate::opt_bytes(self, ptr).unwrap())
+        }
+    }
+
+    /// The directory holding the objects of this repository, shared by all
+    /// of its worktrees.
+    pub(crate) fn objects_dir(&self) -> PathBuf {
+        self.common_dir().join("objects")
+    }
+
     /// Returns the current state of this repository
     pub fn state(&self) -> RepositoryState {
         let state = unsafe { raw::git_repository_state(self.raw) };
@@ -755,7 +791,8 @@ impl Repository {
     /// object can either be a commit or a tag, but tags must be dereferenceable
     /// to a commit.
     ///
-    /// The `checkout` options will only be used for a hard reset.
+    /// The `checkout` options will only be used for a hard reset. A hard
+    /// reset does not respect sparse checkout, see [`SparseCheckout`].
     pub fn reset(
         &self,
         target: &Object<'_>,
@@ -963,16 +1000,97 @@ impl Repository {
     /// status, then the results from rename detection (if you enable it) may
     /// not be accurate. To do rename detection properly, this must be called
     /// with no pathspec so that all files can be considered.
-    pub fn statuses(&self, options: Option<&mut StatusOptions>) -> Result<Statuses<'_>, Error> {
-        let mut ret = ptr::null_mut();
-        unsafe {
-            try_call!(raw::git_status_list_new(
-                &mut ret,
-                self.raw,
-                options.map(|s| s.raw()).unwrap_or(ptr::null())
-            ));
-            Ok(Binding::from_raw(ret))
+    pub fn statuses(&self, mut options: Option<&mut StatusOptions>) -> Result<Statuses<'_>, Error> {
+        let mut monitor = options
+            .as_deref_mut()
+            .and_then(|opts| opts.take_fsmonitor());
+        let result = self.statuses_with(options.as_deref_mut(), monitor.as_mut());
+        if let Some(opts) = options {
+            opts.restore_fsmonitor(monitor);
         }
+        result
+    }
+
+    fn statuses_with(
+        &self,
+        options: Option<&mut StatusOptions>,
+        monitor: Option<&mut Box<dyn FsMonitor>>,
+    ) -> Result<Statuses<'_>, Error> {
+        // When git's untracked cache has the untracked files, libgit2 need
+        // not look for them.
+        let untracked = match options.as_deref() {
+            // An unusable cache only means libgit2 looks for them.
+            Some(opts) if opts.wants_untracked_cache() => {
+                untracked_cache::untracked_files(self).unwrap_or(None)
+            }
+            _ => None,
+        };
+        // When the monitor knows what changed, only some files need checking.
+        let query = match (monitor, options.as_deref()) {
+            (Some(monitor), Some(opts)) if opts.can_use_fsmonitor(untracked.is_some()) => {
+                fsmonitor::Query::new(self, &mut **monitor)?
+            }
+            _ => None,
+        };
+        let mut statuses = match (query.as_ref().and_then(|q| q.paths()), options.as_deref()) {
+            (Some(paths), Some(opts)) => self.status_list_of(opts, paths)?,
+            (None, Some(opts)) if untracked.is_some() => {
+                self.raw_status_list(&opts.without_untracked())?
+            }
+            _ => self.status_list(options)?,
+        };
+        let index = self.index()?;
+        if status::has_adjusted_entries(&index) {
+            statuses.adjust_to_index(|path| Ok(index.get_path(util::bytes2path(path), 0)))?;
+        }
+        if let Some(untracked) = untracked {
+            statuses.add_untracked(untracked)?;
+        }
+        if let Some(query) = query {
+            query.finish(&statuses)?;
+        }
+        Ok(statuses)
+    }
+
+    fn status_list(&self, options: Option<&mut StatusOptions>) -> Result<Statuses<'_>, Error> {
+        let options = options.map(|s| unsafe { s.raw() }).unwrap_or(ptr::null());
+        self.reading_index(|| {
+            let mut ret = ptr::null_mut();
+            unsafe {
+                try_call!(raw::git_status_list_new(&mut ret, self.raw, options));
+                Ok(Binding::from_raw(ret))
+            }
+        })
+    }
+
+    /// The status of the tracked files at `paths` in the working directory,
+    /// along with the differences between HEAD and the index if `options`
+    /// ask for them.
+    fn status_list_of(
+        &self,
+        options: &StatusOptions,
+        paths: &[CString],
+    ) -> Result<Statuses<'_>, Error> {
+        let mut statuses = self.raw_status_list(&options.index_only())?;
+        if !options.shows_index() {
+            statuses.clear();
+        }
+        if !paths.is_empty() {
+            let ptrs: Vec<*const c_char> = paths.iter().map(|p| p.as_ptr()).collect();
+            let workdir = self.raw_status_list(&options.workdir_only(&ptrs))?;
+            statuses.add_workdir(workdir)?;
+        }
+        Ok(statuses)
+    }
+
+    fn raw_status_list(&self, options: &raw::git_status_options) -> Result<Statuses<'_>, Error> {
+        self.reading_index(|| {
+            let mut ret = ptr::null_mut();
+            unsafe {
+                try_call!(raw::git_status_list_new(&mut ret, self.raw, options));
+                Ok(Binding::from_raw(ret))
+            }
+        })
     }
 
     /// Test if the ignore rules apply to a given file.
@@ -1009,12 +1127,20 @@ impl Repository {
     /// detection, there is no choice but to do a full `statuses` and scan
     /// through looking for the path that you are interested in.
     pub fn status_file(&self, path: &Path) -> Result<Status, Error> {
-        let mut ret = 0 as c_uint;
         let path = path_to_repo_path(path)?;
-        unsafe {
-            try_call!(raw::git_status_file(&mut ret, self.raw, path));
+        let ret = self.reading_index(|| {
+            let mut ret = 0 as c_uint;
+            unsafe {
+                try_call!(raw::git_status_file(&mut ret, self.raw, path.as_ptr()));
+            }
+            Ok(ret)
+        })?;
+        let status = Status::from_bits_truncate(ret);
+        let index = self.index()?;
+        match index.get_path(util::bytes2path(path.as_bytes()), 0) {
+            Some(entry) => Ok(adjust_status(status, &entry).unwrap_or(Status::CURRENT)),
+            None => Ok(status),
         }
-        Ok(Status::from_bits_truncate(ret as u32))
     }
 
     /// Create an iterator which loops over the requested branches.
@@ -1038,11 +1164,36 @@ impl Repository {
     pub fn index(&self) -> Result<Index, Error> {
         let mut raw = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_repository_index(&mut raw, self.raw()));
+            if let Err(e) = call::c_try(raw::git_repository_index(&mut raw, self.raw())) {
+                if !index_file::is_split_index_error(&e) {
+                    return Err(e);
+                }
+                return self.use_split_index();
+            }
             Ok(Binding::from_raw(raw))
         }
     }
 
+    /// Make a merged copy of the split index git left, which libgit2 cannot
+    /// read, the index of this repository.
+    fn use_split_index(&self) -> Result<Index, Error> {
+        let mut index = index_file::open_split(&self.path().join("index"))?;
+        self.set_index(&mut index)?;
+        Ok(index)
+    }
+
+    /// Call `f`, and again with a merged copy of the index if libgit2 could
+    /// not read it because it is split.
+    fn reading_index<T>(&self, mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        match f() {
+            Err(e) if index_file::is_split_index_error(&e) => {
+                self.use_split_index()?;
+                f()
+            }
+            result => result,
+        }
+    }
+
     /// Set the Index file for this repository.
     pub fn set_index(&self, index: &mut Index) -> Result<(), Error> {
         unsafe {
@@ -1316,6 +1467,115 @@ impl Repository {
         }
     }
 
+    /// Create new commit in the repository like [`Repository::commit`],
+    /// signing it with `signer`.
+    ///
+    /// Use [`Repository::signer`] to sign the way `git commit -S` would.
+    #[allow(clippy::too_many_arguments)]
+    pub fn commit_with_signer(
+        &self,
+        update_ref: Option<&str>,
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        message: &str,
+        tree: &Tree<'_>,
+        parents: &[&Commit<'_>],
+        signer: &dyn Signer,
+    ) -> Result<Oid, Error> {
+        let mut parent_ptrs = parents
+            .iter()
+            .map(|p| p.raw() as *const raw::git_commit)
+            .collect::<Vec<_>>();
+        let c_message = CString::new(message)?;
+        let id = unsafe {
+            signing::create_commit(
+                self.raw(),
+                author.raw(),
+                committer.raw(),
+                ptr::null(),
+                c_message.as_ptr(),
+                tree.raw(),
+                &mut parent_ptrs,
+                signer,
+            )?
+        };
+        if let Some(update_ref) = update_ref {
+            let kind = if parents.is_empty() {
+                "commit (initial)"
+            } else {
+                "commit"
+            };
+            let log_message = format!("{}: {}", kind, message.lines().next().unwrap_or(""));
+            self.update_ref_to_commit(
+                update_ref,
+                id,
+                parents.first().map(|p| p.id()),
+                &log_message,
+            )?;
+        }
+        Ok(id)
+    }
+
+    /// Point `name`, or the reference it symbolically refers to, at the new
+    /// commit `id`, provided it currently points at `expected` (or does not
+    /// exist yet), like the reference update of `git_commit_create`.
+    ///
+    /// The reflog of the reference, and of `HEAD` if it points at it, gets
+    /// `log_message`.
+    pub(crate) fn update_ref_to_commit(
+        &self,
+        name: &str,
+        id: Oid,
+        expected: Option<Oid>,
+        log_message: &str,
+    ) -> Result<(), Error> {
+        let mut name = name.to_string();
+        let current = loop {
+            match self.find_reference(&name) {
+                Ok(reference) => match reference.symbolic_target() {
+                    Some(target) => name = target.to_string(),
+                    None => break reference.target(),
+                },
+                Err(ref e) if e.code() == ErrorCode::NotFound => break None,
+                Err(e) => return Err(e),
+            }
+        };
+        match current {
+            // The reference is only moved if it has not been moved since.
+            Some(current) if Some(current) == expected => {
+                self.reference_matching(&name, id, true, current, log_message)?;
+            }
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorCode::Modified,
+                    ErrorClass::Object,
+                    "failed to create commit: current tip is not the first parent",
+                ))
+            }
+            None => {
+                self.reference(&name, id, false, log_message)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the [`Signer`] configured for this repository, the way
+    /// `git commit -S` picks one: `gpg.format` selects between GnuPG,
+    /// X.509 and SSH signatures, `user.signingkey` names the key and
+    /// `gpg.program`, `gpg.<format>.program` override the program to run.
+    pub fn signer(&self) -> Result<Box<dyn Signer>, Error> {
+        signing::signer_from_config(&self.config()?)
+    }
+
+    /// Get the [`Verifier`] configured for this repository. It checks each
+    /// signature with the program for its kind: `gpg.program` (or
+    /// `gpg.openpgp.program`) for OpenPGP, `gpg.x509.program` for X.509, and
+    /// `gpg.ssh.program` for SSH signatures, which are matched against
+    /// `gpg.ssh.allowedSignersFile`.
+    pub fn verifier(&self) -> Result<Box<dyn Verifier>, Error> {
+        signing::verifier_from_config(&self.config()?)
+    }
+
     /// Create a commit object and return that as a Buf.
     ///
     /// That can be converted to a string like this `str::from_utf8(&buf).unwrap().to_string()`.
@@ -1744,6 +2004,35 @@ impl Repository {
         }
     }
 
+    /// Update all submodules, like `git submodule update`.
+    ///
+    /// Each initialized submodule is cloned if needed and its working tree
+    /// checked out at the commit recorded in the index, or at the tip of its
+    /// tracked branch when `remote` is set in `opts`.
+    pub fn submodule_update_all(
+        &self,
+        opts: Option<&SubmoduleUpdateAllOptions<'_>>,
+    ) -> Result<(), Error> {
+        match opts {
+            Some(opts) => submodule::update_all(self, opts),
+            None => submodule::update_all(self, &SubmoduleUpdateAllOptions::new()),
+        }
+    }
+
+    /// Call `callback` for each submodule that is checked out, like
+    /// `git submodule foreach`.
+    ///
+    /// The callback receives the path of the submodule relative to the
+    /// working directory of this repository, the submodule and its opened
+    /// repository. When `recursive` is `true`, nested submodules are visited
+    /// right after their parent. Iteration stops at the first error.
+    pub fn submodule_foreach<C>(&self, recursive: bool, mut callback: C) -> Result<(), Error>
+    where
+        C: FnMut(&Path, &Submodule<'_>, &Repository) -> Result<(), Error>,
+    {
+        submodule::foreach(self, recursive, Path::new(""), &mut callback)
+    }
+
     /// Get the status for a submodule.
     ///
     /// This looks at a submodule and tries to determine the status.  It
@@ -1881,6 +2170,31 @@ impl Repository {
         }
     }
 
+    /// Create a new signed tag in the repository like [`Repository::tag`],
+    /// with a signature made by `signer` appended to the message.
+    pub fn tag_with_signer(
+        &self,
+        name: &str,
+        target: &Object<'_>,
+        tagger: &Signature<'_>,
+        message: &str,
+        force: bool,
+        signer: &dyn Signer,
+    ) -> Result<Oid, Error> {
+        let kind = target.kind().map(|k| k.str()).unwrap_or("commit");
+        let buffer = signing::tag_buffer(name, target.id(), kind, tagger, message, signer)?;
+        let buffer = CString::new(buffer)?;
+        let mut raw = raw::git_oid {
+            id: [0; raw::GIT_OID_RAWSZ],
+        };
+        unsafe {
+            try_call!(raw::git_tag_create_frombuffer(
+                &mut raw, self.raw, buffer, force
+            ));
+            Ok(Binding::from_raw(&raw as *const _))
+        }
+    }
+
     /// Create a new tag in the repository from an object without creating a reference.
     ///
     /// The message will not be cleaned up.
@@ -2017,6 +2331,11 @@ impl Repository {
             }
 
             try_call!(raw::git_checkout_head(self.raw, &raw_opts));
+            if sparse::affects(&raw_opts) {
+                if let Some(cone) = sparse::cone(self)? {
+                    sparse::apply(self, Some(&cone))?;
+                }
+            }
         }
         Ok(())
     }
@@ -2024,6 +2343,8 @@ impl Repository {
     /// Updates files in the working tree to match the content of the index.
     ///
     /// If the index is `None`, the repository's index will be used.
+    ///
+    /// Sparse checkout is not respected, see [`SparseCheckout`].
     pub fn checkout_index(
         &self,
         index: Option<&mut Index>,
@@ -2065,11 +2386,39 @@ impl Repository {
                 c.configure(&mut raw_opts);
             }
 
+            // With sparse checkout, files outside of the cone are missing
+            // from the working directory, which libgit2 would otherwise see
+            // as local deletions.
+            let cone = if sparse::affects(&raw_opts) {
+                sparse::cone(self)?
+            } else {
+                None
+            };
+            let sparse = match cone {
+                Some(cone) => {
+                    let tree = treeish.peel_to_tree()?;
+                    let baseline = sparse::checkout_baseline(self, &cone, &tree)?;
+                    Some((cone, tree, baseline))
+                }
+                None => None,
+            };
+            if let Some((_, _, baseline)) = &sparse {
+                raw_opts.baseline_index = baseline.raw();
+            }
+
             try_call!(raw::git_checkout_tree(self.raw, &*treeish.raw(), &raw_opts));
+            if let Some((cone, tree, _)) = &sparse {
+                sparse::checkout_finish(self, cone, tree)?;
+            }
         }
         Ok(())
     }
 
+    /// Get the sparse checkout state of this repository.
+    pub fn sparse_checkout(&self) -> SparseCheckout<'_> {
+        SparseCheckout::new(self)
+    }
+
     /// Merges the given commit(s) into HEAD, writing the results into the
     /// working directory. Any changes are staged for commit and any conflicts
     /// are written to the index. Callers should inspect the repository's index
@@ -2078,12 +2427,23 @@ impl Repository {
     /// For compatibility with git, the repository is put into a merging state.
     /// Once the commit is done (or if the user wishes to abort), you should
     /// clear this state by calling cleanup_state().
+    ///
+    /// Sparse checkout is not respected, see [`SparseCheckout`].
     pub fn merge(
         &self,
         annotated_commits: &[&AnnotatedCommit<'_>],
         merge_opts: Option<&mut MergeOptions>,
         checkout_opts: Option<&mut CheckoutBuilder<'_>>,
     ) -> Result<(), Error> {
+        let strategy = merge::strategy_of(merge_opts.as_deref());
+        if strategy != MergeStrategy::Recursive || annotated_commits.len() != 1 {
+            return merge::merge_heads(
+                self,
+                annotated_commits,
+                merge_opts.as_deref(),
+                checkout_opts,
+            );
+        }
         unsafe {
             let mut raw_checkout_opts = mem::zeroed();
             try_call!(raw::git_checkout_init_options(
@@ -2120,6 +2480,18 @@ impl Repository {
         their_commit: &Commit<'_>,
         opts: Option<&MergeOptions>,
     ) -> Result<Index, Error> {
+        match merge::strategy_of(opts) {
+            MergeStrategy::Ours | MergeStrategy::Subtree(_) => {
+                let ancestor_tree = self.merge_base_tree(our_commit, their_commit)?;
+                return self.merge_trees(
+                    &ancestor_tree,
+                    &our_commit.tree()?,
+                    &their_commit.tree()?,
+                    opts,
+                );
+            }
+            MergeStrategy::Recursive | MergeStrategy::Octopus => {}
+        }
         let mut raw = ptr::null_mut();
         unsafe {
             try_call!(raw::git_merge_commits(
@@ -2144,6 +2516,29 @@ impl Repository {
         their_tree: &Tree<'_>,
         opts: Option<&MergeOptions>,
     ) -> Result<Index, Error> {
+        let shifted;
+        let (ancestor_tree, their_tree) = match merge::strategy_of(opts) {
+            MergeStrategy::Ours => {
+                let mut index = Index::new()?;
+                index.read_tree(our_tree)?;
+                return Ok(index);
+            }
+            MergeStrategy::Subtree(prefix) => {
+                // The ancestor is shifted along with theirs, to wherever
+                // their tree lines up with ours.
+                match merge::subtree_prefix(self, our_tree, their_tree, prefix.as_deref())? {
+                    Some(prefix) => {
+                        shifted = (
+                            merge::shift_tree(self, our_tree, ancestor_tree, &prefix)?,
+                            merge::shift_tree(self, our_tree, their_tree, &prefix)?,
+                        );
+                        (&shifted.0, &shifted.1)
+                    }
+                    None => (ancestor_tree, their_tree),
+                }
+            }
+            MergeStrategy::Recursive | MergeStrategy::Octopus => (ancestor_tree, their_tree),
+        };
         let mut raw = ptr::null_mut();
         unsafe {
             try_call!(raw::git_merge_trees(
@@ -2158,6 +2553,58 @@ impl Repository {
         }
     }
 
+    /// Merge two trees like [`Repository::merge_trees`], additionally
+    /// producing a [`MergeReport`] which classifies each conflict and lists
+    /// the files which were merged automatically.
+    pub fn merge_trees_with_report(
+        &self,
+        ancestor_tree: &Tree<'_>,
+        our_tree: &Tree<'_>,
+        their_tree: &Tree<'_>,
+        opts: Option<&MergeOptions>,
+    ) -> Result<(Index, MergeReport), Error> {
+        let index = self.merge_trees(ancestor_tree, our_tree, their_tree, opts)?;
+        let report = MergeReport::new(self, ancestor_tree, our_tree, their_tree, &index, opts)?;
+        Ok((index, report))
+    }
+
+    /// Merge two commits like [`Repository::merge_commits`], additionally
+    /// producing a [`MergeReport`] which classifies each conflict and lists
+    /// the files which were merged automatically.
+    ///
+    /// Files merged automatically are determined against the first merge
+    /// base of the two commits.
+    pub fn merge_commits_with_report(
+        &self,
+        our_commit: &Commit<'_>,
+        their_commit: &Commit<'_>,
+        opts: Option<&MergeOptions>,
+    ) -> Result<(Index, MergeReport), Error> {
+        let index = self.merge_commits(our_commit, their_commit, opts)?;
+        let ancestor_tree = self.merge_base_tree(our_commit, their_commit)?;
+        let report = MergeReport::new(
+            self,
+            &ancestor_tree,
+            &our_commit.tree()?,
+            &their_commit.tree()?,
+            &index,
+            opts,
+        )?;
+        Ok((index, report))
+    }
+
+    /// The tree of the first merge base of two commits, or the empty tree if
+    /// they have no common history.
+    fn merge_base_tree(&self, one: &Commit<'_>, two: &Commit<'_>) -> Result<Tree<'_>, Error> {
+        match self.merge_base(one.id(), two.id()) {
+            Ok(base) => self.find_commit(base)?.tree(),
+            Err(ref e) if e.code() == ErrorCode::NotFound => {
+                self.find_tree(self.treebuilder(None)?.write()?)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Remove all the metadata associated with an ongoing command like merge,
     /// revert, cherry-pick, etc. For example: MERGE_HEAD, MERGE_MSG, etc.
     pub fn cleanup_state(&self) -> Result<(), Error> {
@@ -2234,6 +2681,10 @@ impl Repository {
         opts: Option<&mut RebaseOptions<'_>>,
     ) -> Result<Rebase<'_>, Error> {
         let mut rebase: *mut raw::git_rebase = ptr::null_mut();
+        let mut default_opts = RebaseOptions::new();
+        let opts = opts.unwrap_or(&mut default_opts);
+        let signer = opts.signer_slot();
+        let raw_opts = opts.raw_with_signer(signer.as_deref());
         unsafe {
             try_call!(raw::git_rebase_init(
                 &mut rebase,
@@ -2241,24 +2692,207 @@ impl Repository {
                 branch.map(|c| c.raw()),
                 upstream.map(|c| c.raw()),
                 onto.map(|c| c.raw()),
-                opts.map(|o| o.raw()).unwrap_or(ptr::null())
+                &raw_opts
             ));
 
-            Ok(Rebase::from_raw(rebase))
+            Ok(Rebase::from_raw_parts(rebase, signer))
         }
     }
 
+    /// Starts cherry-picking `commits` one after the other onto `HEAD`,
+    /// like `git cherry-pick A B C`. Call [`Sequencer::run`] to apply them.
+    ///
+    /// The commits of a range like `A..B` can be listed with a [`Revwalk`]
+    /// using [`Revwalk::push_range`] and [`Sort::REVERSE`](crate::Sort::REVERSE).
+    pub fn cherrypick_commits(
+        &self,
+        commits: &[Oid],
+        opts: Option<&SequencerOptions>,
+    ) -> Result<Sequencer<'_>, Error> {
+        let todo = commits.iter().map(|id| SequencerStep::Pick(*id)).collect();
+        Sequencer::start(self, todo, opts)
+    }
+
+    /// Starts reverting `commits` one after the other on top of `HEAD`,
+    /// like `git revert A B C`. Call [`Sequencer::run`] to apply them.
+    pub fn revert_commits(
+        &self,
+        commits: &[Oid],
+        opts: Option<&SequencerOptions>,
+    ) -> Result<Sequencer<'_>, Error> {
+        let todo = commits
+            .iter()
+            .map(|id| SequencerStep::Revert(*id))
+            .collect();
+        Sequencer::start(self, todo, opts)
+    }
+
+    /// Opens the cherry-pick or revert sequence in progress, started by
+    /// either [`Repository::cherrypick_commits`],
+    /// [`Repository::revert_commits`] or git.
+    pub fn open_sequencer(&self) -> Result<Sequencer<'_>, Error> {
+        Sequencer::open(self)
+    }
+
     /// Opens an existing rebase that was previously started by either an
     /// invocation of `rebase()` or by another client.
     pub fn open_rebase(&self, opts: Option<&mut RebaseOptions<'_>>) -> Result<Rebase<'_>, Error> {
         let mut rebase: *mut raw::git_rebase = ptr::null_mut();
+        let mut default_opts = RebaseOptions::new();
+        let opts = opts.unwrap_or(&mut default_opts);
+        let signer = opts.signer_slot();
+        let raw_opts = opts.raw_with_signer(signer.as_deref());
         unsafe {
-            try_call!(raw::git_rebase_open(
-                &mut rebase,
-                self.raw(),
-                opts.map(|o| o.raw()).unwrap_or(ptr::null())
-            ));
-            Ok(Rebase::from_raw(rebase))
+            try_call!(raw::git_rebase_open(&mut rebase, self.raw(), &raw_opts));
+            Ok(Rebase::from_raw_parts(rebase, signer))
+        }
+    }
+
+    /// Starts a rebase following `plan`, like `git rebase --interactive`.
+    ///
+    /// Unless `opts` requests an in-memory rebase, the state of the rebase is
+    /// recorded in `.git/rebase-merge` and `HEAD` is detached at the commit
+    /// being rebased onto. Only the in-memory and merge options of `opts`
+    /// are used. Call [`PlannedRebase::run`] to apply the steps.
+    pub fn rebase_with_plan(
+        &self,
+        plan: &RebasePlan,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<PlannedRebase<'_>, Error> {
+        PlannedRebase::new(self, plan, opts)
+    }
+
+    /// Opens a rebase on disk that stopped before completing, as started by
+    /// [`Repository::rebase_with_plan`] or `git rebase --interactive`.
+    ///
+    /// If the last applied step stopped with conflicts, resolve them in the
+    /// index before calling [`PlannedRebase::run`] to commit the resolution
+    /// and apply the remaining steps.
+    pub fn open_rebase_plan(
+        &self,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<PlannedRebase<'_>, Error> {
+        PlannedRebase::open(self, opts)
+    }
+
+    /// Write a commit-graph file for the repository, like
+    /// `git commit-graph write`.
+    ///
+    /// The commit-graph is used by revision walks, merge base and
+    /// ahead/behind computations to avoid parsing commits, which speeds
+    /// them up considerably for large histories.
+    pub fn write_commit_graph(&self, opts: Option<&CommitGraphOptions>) -> Result<(), Error> {
+        match opts {
+            Some(opts) => commit_graph::write(self, opts),
+            None => commit_graph::write(self, &CommitGraphOptions::new()),
+        }
+    }
+
+    /// Read the commit-graph of the repository.
+    ///
+    /// Fails with [`ErrorCode::NotFound`] if there is none.
+    pub fn commit_graph(&self) -> Result<CommitGraph, Error> {
+        CommitGraph::open(&self.objects_dir())
+    }
+
+    /// Write a multi-pack-index covering every pack of the repository, like
+    /// `git multi-pack-index write`.
+    ///
+    /// The multi-pack-index allows objects to be looked up with a single
+    /// index instead of searching the index of every pack.
+    pub fn write_multi_pack_index(&self) -> Result<(), Error> {
+        maintenance::write_midx(self)
+    }
+
+    /// Pack the reachable objects of the repository into a single pack, like
+    /// `git repack -A -d`.
+    ///
+    /// Unreachable objects are not packed, so that they keep expiring like
+    /// they did: loose ones are left alone, and packed ones are written
+    /// loose with the modification time of their pack before it is deleted.
+    /// See [`RepackOptions::reachable_only`] to drop those instead.
+    ///
+    /// Objects of alternate object databases are not packed. Returns the
+    /// name of the new pack, or `None` if there were no objects to pack.
+    pub fn repack(&self, opts: Option<&RepackOptions>) -> Result<Option<String>, Error> {
+        match opts {
+            Some(opts) => maintenance::repack(self, opts),
+            None => maintenance::repack(self, &RepackOptions::new()),
+        }
+    }
+
+    /// Remove the loose objects which are also stored in a pack, like
+    /// `git prune-packed`.
+    ///
+    /// Returns the number of objects removed.
+    pub fn prune_packed(&self) -> Result<usize, Error> {
+        maintenance::prune_packed(self)
+    }
+
+    /// Remove the unreachable loose objects last modified before `cutoff`,
+    /// like `git prune --expire`.
+    ///
+    /// Objects are reachable from the references, reflogs, `HEAD` and index
+    /// of the repository and its worktrees. Returns the number of objects
+    /// removed.
+    pub fn expire_unreachable(&self, cutoff: SystemTime) -> Result<usize, Error> {
+        maintenance::expire_unreachable(self, cutoff)
+    }
+
+    /// Move the loose references into the `packed-refs` file, like
+    /// `git pack-refs --all`.
+    pub fn pack_refs(&self) -> Result<(), Error> {
+        maintenance::pack_refs(self)
+    }
+
+    /// Find the objects reachable from the repository.
+    ///
+    /// The references, their reflogs, the stash, pseudo references such as
+    /// `ORIG_HEAD` and `FETCH_HEAD`, and the `HEAD` and index of the
+    /// repository and of each of its worktrees are taken into account.
+    /// Missing objects are left out.
+    pub fn reachable_objects(&self) -> Result<HashSet<Oid>, Error> {
+        maintenance::reachable(self)
+    }
+
+    /// List the objects stored in the repository which are not reachable,
+    /// sorted by id.
+    ///
+    /// See [`Repository::reachable_objects`] for what is reachable. Objects
+    /// of alternate object databases are not listed.
+    pub fn unreachable_objects(
+        &self,
+        opts: Option<&UnreachableOptions>,
+    ) -> Result<Vec<Oid>, Error> {
+        match opts {
+            Some(opts) => maintenance::unreachable(self, opts),
+            None => maintenance::unreachable(self, &UnreachableOptions::new()),
+        }
+    }
+
+    /// Collect garbage, like `git gc`.
+    ///
+    /// Packs the references, then repacks the reachable and recently
+    /// modified objects into a single pack, deleting the other packs and
+    /// loose objects. Unreachable objects older than the expiry are dropped.
+    /// Use [`GcOptions::dry_run`] to find out what would be deleted.
+    pub fn gc(&self, opts: Option<&GcOptions>) -> Result<GcReport, Error> {
+        match opts {
+            Some(opts) => maintenance::gc(self, opts),
+            None => maintenance::gc(self, &GcOptions::new()),
+        }
+    }
+
+    /// Check the integrity of the repository, like `git fsck`.
+    ///
+    /// Every object is read, hashed and parsed, and the objects referred to
+    /// by objects, references, reflogs and the index are checked to exist
+    /// with the right type. Problems are reported rather than returned as
+    /// errors, which are left for failures to access the repository.
+    pub fn fsck(&self, opts: Option<&FsckOptions>) -> Result<FsckReport, Error> {
+        match opts {
+            Some(opts) => fsck::fsck(self, opts),
+            None => fsck::fsck(self, &FsckOptions::new()),
         }
     }
 
@@ -2676,6 +3310,11 @@ impl Repository {
         index: Option<&Index>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        // Intent-to-add entries are not staged yet.
+        let intent_to_add = IndexEntryExtendedFlag::INTENT_TO_ADD.bits();
+        let filtered =
+            self.filter_index(index, 0, intent_to_add, |entry| !entry.is_intent_to_add())?;
+        let index = filtered.as_ref().or(index);
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_tree_to_index(
@@ -2730,6 +3369,21 @@ impl Repository {
         index: Option<&Index>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        // The missing files of skip-worktree and assume-unchanged entries are
+        // not deleted.
+        let filtered = match self.workdir() {
+            Some(workdir) => self.filter_index(
+                index,
+                IndexEntryFlag::VALID.bits(),
+                IndexEntryExtendedFlag::SKIP_WORKTREE.bits(),
+                |entry| {
+                    !(entry.is_skip_worktree() || entry.is_assume_unchanged())
+                        || fs::symlink_metadata(workdir.join(util::bytes2path(&entry.path))).is_ok()
+                },
+            )?,
+            None => None,
+        };
+        let index = filtered.as_ref().or(index);
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_index_to_workdir(
@@ -2742,6 +3396,35 @@ impl Repository {
         }
     }
 
+    /// A copy of `index`, or of the repository index if `None`, with only the
+    /// entries `keep` accepts, or `None` if it accepts them all.
+    ///
+    /// The index is only looked at further if one of its entries has one of
+    /// the `flags` or `flags_extended` bits, so `keep` must accept all the
+    /// other entries.
+    fn filter_index<F>(
+        &self,
+        index: Option<&Index>,
+        flags: u16,
+        flags_extended: u16,
+        keep: F,
+    ) -> Result<Option<Index>, Error>
+    where
+        F: FnMut(&IndexEntry) -> bool,
+    {
+        let filter = |index: &Index| {
+            if index.has_flagged_entries(flags, flags_extended) {
+                index.filtered(keep)
+            } else {
+                Ok(None)
+            }
+        };
+        match index {
+            Some(index) => filter(index),
+            None => filter(&self.index()?),
+        }
+    }
+
     /// Create a diff between a tree and the working directory.
     ///
     /// The tree you provide will be used for the "old_file" side of the delta,
@@ -2918,6 +3601,87 @@ impl Repository {
         }
     }
 
+    /// List the stashed states, the most recent one first.
+    pub fn stash_list(&self) -> Result<Vec<StashEntry>, Error> {
+        let reflog = self.reflog("refs/stash")?;
+        reflog
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let commit = self.find_commit(entry.id_new())?;
+                Ok(StashEntry::new(
+                    index,
+                    entry.message().unwrap_or(""),
+                    &commit,
+                ))
+            })
+            .collect()
+    }
+
+    /// Create a diff of the changes recorded in a stashed state against the
+    /// commit it was created on.
+    ///
+    /// This is equivalent to `git stash show -p stash@{<index>}`. When
+    /// `include_untracked` is `true`, untracked files saved with the stash
+    /// are included as additions, like `git stash show --include-untracked`.
+    pub fn stash_diff(
+        &self,
+        index: usize,
+        include_untracked: bool,
+        mut opts: Option<&mut DiffOptions>,
+    ) -> Result<Diff<'_>, Error> {
+        let stash = self.stash_commit(index)?;
+        let base = stash.parent(0)?.tree()?;
+        let mut diff =
+            self.diff_tree_to_tree(Some(&base), Some(&stash.tree()?), opts.as_deref_mut())?;
+        if include_untracked && stash.parent_count() > 2 {
+            // The untracked files are all the commit has.
+            let untracked = stash.parent(2)?.tree()?;
+            diff.merge(&self.diff_tree_to_tree(None, Some(&untracked), opts)?)?;
+        }
+        Ok(diff)
+    }
+
+    /// Create a new branch at the commit a stashed state was created on, check
+    /// it out, apply the stash with its index and drop it if successful.
+    ///
+    /// This is equivalent to `git stash branch <branch_name> stash@{<index>}`.
+    pub fn stash_branch(
+        &mut self,
+        branch_name: &str,
+        index: usize,
+        opts: Option<&mut StashApplyOptions<'_>>,
+    ) -> Result<(), Error> {
+        {
+            let base = self.stash_commit(index)?.parent(0)?;
+            let mut branch = self.branch(branch_name, &base, false)?;
+            if let Err(e) = self.checkout_tree(base.as_object(), None) {
+                // Leave no branch behind if the working directory is in the
+                // way.
+                let _ = branch.delete();
+                return Err(e);
+            }
+            self.set_head_bytes(branch.get().name_bytes())?;
+        }
+
+        let mut default = StashApplyOptions::new();
+        let opts = opts.unwrap_or(&mut default);
+        opts.reinstantiate_index();
+        self.stash_pop(index, Some(opts))
+    }
+
+    fn stash_commit(&self, index: usize) -> Result<Commit<'_>, Error> {
+        let reflog = self.reflog("refs/stash")?;
+        let entry = reflog.get(index).ok_or_else(|| {
+            Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Stash,
+                format!("no stashed state at position {}", index),
+            )
+        })?;
+        self.find_commit(entry.id_new())
+    }
+
     /// Add ignore rules for a repository.
     ///
     /// The format of the rules is the same one of the .gitignore file.
@@ -3066,6 +3830,48 @@ impl Repository {
         }
     }
 
+    /// Apply the patches of a mailbox, creating one commit per patch like
+    /// `git am`.
+    ///
+    /// Each patch is applied with [`Repository::apply_to_tree`] on top of the
+    /// previous commit, starting from the commit `update_ref` points to (or
+    /// `HEAD` when `None`). The commits keep the author, date and message of
+    /// the original patches and are committed by the repository's default
+    /// signature. If `update_ref` is given it is moved to the last commit
+    /// once every patch applied, so that it is left alone if one does not;
+    /// the index and working directory are left untouched.
+    ///
+    /// Returns the ids of the created commits, in order.
+    pub fn apply_mailbox(
+        &self,
+        mailbox: &Mailbox,
+        update_ref: Option<&str>,
+        mut options: Option<&mut ApplyOptions<'_>>,
+    ) -> Result<Vec<Oid>, Error> {
+        let base = self
+            .find_reference(update_ref.unwrap_or("HEAD"))?
+            .peel_to_commit()?;
+        let committer = self.signature()?;
+        let mut parent = base.clone();
+        let mut ids = Vec::with_capacity(mailbox.len());
+        for patch in mailbox {
+            let diff = patch.diff()?;
+            let tree = parent.tree()?;
+            let mut index = self.apply_to_tree(&tree, &diff, options.as_deref_mut())?;
+            let tree = self.find_tree(index.write_tree_to(self)?)?;
+            let author = patch.author()?;
+            let message = patch.message();
+            let id = self.commit(None, &author, &committer, &message, &tree, &[&parent])?;
+            parent = self.find_commit(id)?;
+            ids.push(id);
+        }
+        if let (Some(update_ref), Some(&last)) = (update_ref, ids.last()) {
+            let log_message = format!("am: {}", parent.summary().unwrap_or(""));
+            self.update_ref_to_commit(update_ref, last, Some(base.id()), &log_message)?;
+        }
+        Ok(ids)
+    }
+
     /// Reverts the given commit, producing changes in the index and working directory.
     pub fn revert(
         &self,
@@ -3218,6 +4024,16 @@ impl Repository {
     }
 }
 
+impl Repository {
+    /// Borrow the repository owning an object, such as the result of
+    /// `git_commit_owner`, without freeing it when the handle is dropped.
+    pub(crate) unsafe fn borrow_raw(
+        raw: *mut raw::git_repository,
+    ) -> mem::ManuallyDrop<Repository> {
+        mem::ManuallyDrop::new(Repository { raw })
+    }
+}
+
 impl Binding for Repository {
     type Raw = *mut raw::git_repository;
     unsafe fn from_raw(ptr: *mut raw::git_repository) -> Repository {
@@ -4085,6 +4901,261 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn smoke_merge_trees_with_report() -> Result<(), crate::Error> {
+        use crate::MergeConflictKind;
+
+        let (_td, repo) = crate::test::repo_init();
+        let tree = |files: &[(&str, &str)]| -> Result<crate::Tree<'_>, crate::Error> {
+            let mut builder = repo.treebuilder(None)?;
+            for (name, content) in files {
+                builder.insert(name, repo.blob(content.as_bytes())?, 0o100644)?;
+            }
+            repo.find_tree(builder.write()?)
+        };
+        let ancestor = tree(&[
+            ("content", "a\nb\nc\n"),
+            ("clean", "1\n2\n3\n4\n5\n6\n7\n"),
+            ("deleted", "x\n"),
+        ])?;
+        let ours = tree(&[
+            ("content", "a\nours\nc\n"),
+            ("clean", "one\n2\n3\n4\n5\n6\n7\n"),
+            ("deleted", "modified\n"),
+            ("added", "ours\n"),
+        ])?;
+        let theirs = tree(&[
+            ("content", "a\ntheirs\nc\n"),
+            ("clean", "1\n2\n3\n4\n5\n6\nseven\n"),
+            ("added", "theirs\n"),
+        ])?;
+
+        let (index, report) = repo.merge_trees_with_report(&ancestor, &ours, &theirs, None)?;
+        assert!(index.has_conflicts());
+        assert!(report.has_conflicts());
+        assert_eq!(report.conflicts().len(), 3);
+        assert_eq!(report.auto_merged(), &[Path::new("clean").to_path_buf()]);
+
+        let content = report.conflict(Path::new("content")).unwrap();
+        assert_eq!(content.kind(), MergeConflictKind::Content);
+        assert_eq!(
+            content.to_string(),
+            "CONFLICT (content): Merge conflict in content"
+        );
+        let rendered = report.render(&repo, content)?;
+        assert_eq!(
+            String::from_utf8(rendered).unwrap(),
+            "a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nc\n"
+        );
+
+        let deleted = report.conflict(Path::new("deleted")).unwrap();
+        assert_eq!(deleted.kind(), MergeConflictKind::ModifyDelete);
+        assert!(deleted.theirs().is_none());
+        assert_eq!(report.render(&repo, deleted)?, b"modified\n");
+
+        let added = report.conflict(Path::new("added")).unwrap();
+        assert_eq!(added.kind(), MergeConflictKind::AddAdd);
+        assert!(added.ancestor().is_none());
+
+        // Renames are one conflict, whichever paths the sides ended up at.
+        let long = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let ancestor = tree(&[("file", long)])?;
+        let ours = tree(&[("ours", long)])?;
+        let theirs = tree(&[("theirs", long)])?;
+        let (_, report) = repo.merge_trees_with_report(&ancestor, &ours, &theirs, None)?;
+        assert_eq!(report.conflicts().len(), 1);
+        let renamed = &report.conflicts()[0];
+        assert_eq!(renamed.kind(), MergeConflictKind::RenameRename);
+        assert_eq!(renamed.ancestor().unwrap().path(), Path::new("file"));
+        assert_eq!(renamed.ours().unwrap().path(), Path::new("ours"));
+        assert_eq!(renamed.theirs().unwrap().path(), Path::new("theirs"));
+        assert_eq!(
+            renamed.to_string(),
+            "CONFLICT (rename/rename): file renamed to ours in ours and to theirs in theirs"
+        );
+
+        let theirs = tree(&[])?;
+        let (_, report) = repo.merge_trees_with_report(&ancestor, &ours, &theirs, None)?;
+        assert_eq!(report.conflicts().len(), 1);
+        let renamed = report.conflict(Path::new("ours")).unwrap();
+        assert_eq!(renamed.kind(), MergeConflictKind::RenameDelete);
+        assert_eq!(renamed.ancestor().unwrap().path(), Path::new("file"));
+        assert!(renamed.theirs().is_none());
+        assert_eq!(
+            renamed.to_string(),
+            "CONFLICT (rename/delete): file renamed to ours in ours, but deleted in theirs"
+        );
+
+        let ours = tree(&[("file", long), ("dir", "file\n")])?;
+        let mut builder = repo.treebuilder(Some(&ancestor))?;
+        builder.insert("dir", tree(&[("nested", "directory\n")])?.id(), 0o040000)?;
+        let theirs = repo.find_tree(builder.write()?)?;
+        let (_, report) = repo.merge_trees_with_report(&ancestor, &ours, &theirs, None)?;
+        assert_eq!(report.conflicts().len(), 1);
+        let file = report.conflict(Path::new("dir")).unwrap();
+        assert_eq!(file.kind(), MergeConflictKind::DirectoryFile);
+        assert_eq!(
+            file.to_string(),
+            "CONFLICT (directory/file): dir is a file in ours but a directory in theirs"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn smoke_merge_octopus() -> Result<(), crate::Error> {
+        let (td, repo) = crate::test::repo_init();
+        let head = repo.head()?.peel_to_commit()?;
+        let sig = repo.signature()?;
+        let branch = |name: &str| -> Result<Oid, crate::Error> {
+            let mut builder = repo.treebuilder(Some(&head.tree()?))?;
+            builder.insert(name, repo.blob(name.as_bytes())?, 0o100644)?;
+            let tree = repo.find_tree(builder.write()?)?;
+            let id = repo.commit(None, &sig, &sig, name, &tree, &[&head])?;
+            repo.branch(name, &repo.find_commit(id)?, false)?;
+            Ok(id)
+        };
+        let a = branch("a")?;
+        let b = branch("b")?;
+        let c = branch("c")?;
+
+        let heads = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let reference = repo.find_reference(&format!("refs/heads/{}", name))?;
+                repo.reference_to_annotated_commit(&reference)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        repo.merge(&heads.iter().collect::<Vec<_>>(), None, None)?;
+
+        for name in ["a", "b", "c"].iter() {
+            assert_eq!(fs::read(td.path().join(name)).unwrap(), name.as_bytes());
+        }
+        let merge_head = fs::read_to_string(repo.path().join("MERGE_HEAD")).unwrap();
+        let merge_heads = merge_head
+            .lines()
+            .map(|line| Oid::from_str(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(merge_heads, vec![a, b, c]);
+        assert_eq!(repo.message()?, "Merge branches 'a', 'b' and 'c'\n");
+        let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+        assert_eq!(tree.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn smoke_merge_state() -> Result<(), crate::Error> {
+        let (td, repo) = crate::test::repo_init();
+        let head = repo.head()?.peel_to_commit()?;
+        let sig = repo.signature()?;
+        let commit = |name: &str, refname: &str| -> Result<(), crate::Error> {
+            let mut builder = repo.treebuilder(Some(&head.tree()?))?;
+            builder.insert(name, repo.blob(name.as_bytes())?, 0o100644)?;
+            let tree = repo.find_tree(builder.write()?)?;
+            let id = repo.commit(None, &sig, &sig, name, &tree, &[&head])?;
+            repo.reference(refname, id, false, "test")?;
+            Ok(())
+        };
+        commit("a", "refs/heads/a")?;
+        commit("x", "refs/remotes/origin/x")?;
+        let heads = ["refs/heads/a", "refs/remotes/origin/x"]
+            .iter()
+            .map(|name| repo.reference_to_annotated_commit(&repo.find_reference(name)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let heads = heads.iter().collect::<Vec<_>>();
+
+        repo.merge(&heads, None, None)?;
+        assert_eq!(
+            repo.message()?,
+            "Merge branch 'a', remote-tracking branch 'origin/x'\n"
+        );
+        let mode = || fs::read_to_string(repo.path().join("MERGE_MODE")).unwrap();
+        assert_eq!(mode(), "");
+
+        repo.cleanup_state()?;
+        repo.reset(head.as_object(), crate::ResetType::Hard, None)?;
+        assert!(!td.path().join("a").exists());
+        repo.config()?.set_bool("merge.ff", false)?;
+        repo.merge(&heads, None, None)?;
+        assert_eq!(mode(), "no-ff");
+        Ok(())
+    }
+
+    #[test]
+    fn smoke_merge_strategies() -> Result<(), crate::Error> {
+        use crate::{MergeOptions, MergeStrategy};
+
+        let (_td, repo) = crate::test::repo_init();
+        let tree = |files: &[(&str, &str)]| -> Result<crate::Tree<'_>, crate::Error> {
+            let mut builder = repo.treebuilder(None)?;
+            for (name, content) in files {
+                builder.insert(name, repo.blob(content.as_bytes())?, 0o100644)?;
+            }
+            repo.find_tree(builder.write()?)
+        };
+        let ancestor = tree(&[("x", "1\n"), ("y", "1\n")])?;
+        let theirs = tree(&[("x", "2\n"), ("y", "1\n")])?;
+        let lib = tree(&[("x", "1\n"), ("y", "1\n")])?;
+        let ours = {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("lib", lib.id(), 0o040000)?;
+            builder.insert("top", repo.blob(b"top\n")?, 0o100644)?;
+            repo.find_tree(builder.write()?)?
+        };
+
+        let mut opts = MergeOptions::new();
+        opts.strategy(MergeStrategy::Ours);
+        let mut index = repo.merge_trees(&ancestor, &ours, &theirs, Some(&opts))?;
+        assert_eq!(index.write_tree_to(&repo)?, ours.id());
+
+        for prefix in [Some(Path::new("lib").to_path_buf()), None].iter() {
+            opts.strategy(MergeStrategy::Subtree(prefix.clone()));
+            let mut index = repo.merge_trees(&ancestor, &ours, &theirs, Some(&opts))?;
+            assert!(!index.has_conflicts());
+            let merged = repo.find_tree(index.write_tree_to(&repo)?)?;
+            let x = merged.get_path(Path::new("lib/x"))?;
+            assert_eq!(x.id(), repo.blob(b"2\n")?);
+            assert!(merged.get_name("top").is_some());
+            assert!(merged.get_name("x").is_none());
+        }
+
+        // The ancestor is shifted to where theirs lines up, even if another
+        // directory matches it as well.
+        let ancestor = tree(&[("x", "1\n"), ("y", "1\n")])?;
+        let theirs = tree(&[("v", "1\n"), ("x", "2\n"), ("y", "1\n")])?;
+        let lib = tree(&[("v", "1\n"), ("x", "1\n"), ("y", "1\n"), ("z", "1\n")])?;
+        let docs = tree(&[("w", "1\n"), ("x", "1\n"), ("y", "1\n")])?;
+        let ours = {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("docs", docs.id(), 0o040000)?;
+            builder.insert("lib", lib.id(), 0o040000)?;
+            repo.find_tree(builder.write()?)?
+        };
+        opts.strategy(MergeStrategy::Subtree(None));
+        let mut index = repo.merge_trees(&ancestor, &ours, &theirs, Some(&opts))?;
+        assert!(!index.has_conflicts());
+        let merged = repo.find_tree(index.write_tree_to(&repo)?)?;
+        let x = merged.get_path(Path::new("lib/x"))?;
+        assert_eq!(x.id(), repo.blob(b"2\n")?);
+        assert!(merged.get_path(Path::new("lib/z")).is_ok());
+        assert_eq!(merged.get_name("docs").unwrap().id(), docs.id());
+
+        // Like git, subdirectories more than three levels deep are not
+        // searched.
+        let nest = |tree: &crate::Tree<'_>, name: &str| -> Result<crate::Tree<'_>, crate::Error> {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert(name, tree.id(), 0o040000)?;
+            repo.find_tree(builder.write()?)
+        };
+        let ours = nest(&nest(&nest(&lib, "lib")?, "b")?, "a")?;
+        let prefix = crate::merge::subtree_prefix(&repo, &ours, &theirs, None)?;
+        assert_eq!(prefix.as_deref(), Some("a/b/lib"));
+        let ours = nest(&ours, "deeper")?;
+        let prefix = crate::merge::subtree_prefix(&repo, &ours, &theirs, None)?;
+        assert_eq!(prefix, None);
+        Ok(())
+    }
+
     #[test]
     fn smoke_submodule_set() -> Result<(), crate::Error> {
         let (td1, _repo) = crate::test::repo_init();
@@ -1,4 +1,4 @@
-use crate::{Object, RevparseMode};
+use crate::{Error, ErrorClass, ErrorCode, Object, Oid, Repository, RevparseMode, Revwalk};
 
 /// A revspec represents a range of revisions within a repository.
 pub struct Revspec<'repo> {
@@ -32,3 +32,293 @@ impl<'repo> Revspec<'repo> {
         self.mode
     }
 }
+
+/// A list of revisions parsed from `git rev-list` style arguments.
+///
+/// Each argument either names a commit to start walking from or a commit to
+/// exclude, together with everything reachable from it. The following forms
+/// are understood:
+///
+/// * `A` includes `A`, `^A` excludes it.
+/// * `A..B` excludes `A` and includes `B`, either side defaults to `HEAD`.
+/// * `A...B` includes `A` and `B` and excludes their merge bases.
+/// * `A^@` includes all parents of `A` but not `A` itself.
+/// * `A^!` includes `A` and excludes all of its parents.
+/// * `A^-n` includes `A` and excludes its `n`th parent, `n` defaults to 1.
+/// * `--all` includes `HEAD` and every reference.
+/// * `--branches[=glob]`, `--tags[=glob]`, `--remotes[=glob]` and
+///   `--glob=glob` include the matching references.
+/// * `--not` flips the meaning of all following arguments, up to the next
+///   `--not`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RevSpecList {
+    pushed: Vec<Oid>,
+    hidden: Vec<Oid>,
+}
+
+impl RevSpecList {
+    /// Parse a list of arguments, resolving every revision against `repo`.
+    pub fn parse<I, T>(repo: &Repository, args: I) -> Result<RevSpecList, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut list = RevSpecList::default();
+        let mut not = false;
+        for arg in args {
+            let arg = arg.as_ref();
+            if arg == "--not" {
+                not = !not;
+            } else if let Some(option) = arg.strip_prefix("--") {
+                list.parse_option(repo, option, not)?;
+            } else if arg.starts_with('-') || arg.is_empty() {
+                return Err(invalid(format!("unrecognized argument: {}", arg)));
+            } else {
+                list.parse_revision(repo, arg, not)?;
+            }
+        }
+        Ok(list)
+    }
+
+    /// The commits the walk starts from.
+    pub fn pushed(&self) -> &[Oid] {
+        &self.pushed
+    }
+
+    /// The commits excluded from the walk, along with their ancestors.
+    pub fn hidden(&self) -> &[Oid] {
+        &self.hidden
+    }
+
+    /// Returns `true` if no commit is included or excluded.
+    pub fn is_empty(&self) -> bool {
+        self.pushed.is_empty() && self.hidden.is_empty()
+    }
+
+    /// Push and hide the parsed commits on `revwalk`.
+    pub fn apply(&self, revwalk: &mut Revwalk<'_>) -> Result<(), Error> {
+        for id in &self.pushed {
+            revwalk.push(*id)?;
+        }
+        for id in &self.hidden {
+            revwalk.hide(*id)?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, id: Oid, hide: bool) {
+        let ids = if hide {
+            &mut self.hidden
+        } else {
+            &mut self.pushed
+        };
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    fn parse_option(&mut self, repo: &Repository, option: &str, not: bool) -> Result<(), Error> {
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (option, None),
+        };
+        let glob = match (name, value) {
+            ("all", None) => {
+                if let Ok(head) = repo.head() {
+                    self.add(head.peel_to_commit()?.id(), not);
+                }
+                "refs/*".to_string()
+            }
+            ("branches", _) => ref_glob("refs/heads/", value),
+            ("tags", _) => ref_glob("refs/tags/", value),
+            ("remotes", _) => ref_glob("refs/remotes/", value),
+            ("glob", Some(value)) if value.starts_with("refs/") => ref_glob("", Some(value)),
+            ("glob", Some(value)) => ref_glob("refs/", Some(value)),
+            _ => return Err(invalid(format!("unrecognized argument: --{}", option))),
+        };
+        for reference in repo.references_glob(&glob)? {
+            // References to trees and blobs have no history to walk.
+            if let Ok(commit) = reference?.peel_to_commit() {
+                self.add(commit.id(), not);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_revision(&mut self, repo: &Repository, arg: &str, not: bool) -> Result<(), Error> {
+        if let Some((from, to)) = arg.split_once("...") {
+            let from = resolve(repo, from)?;
+            let to = resolve(repo, to)?;
+            // Unrelated histories have no merge bases to exclude.
+            match repo.merge_bases(from, to) {
+                Ok(bases) => {
+                    for base in bases.iter() {
+                        self.add(*base, !not);
+                    }
+                }
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            self.add(from, not);
+            self.add(to, not);
+            return Ok(());
+        }
+        if let Some((from, to)) = arg.split_once("..") {
+            self.add(resolve(repo, from)?, !not);
+            self.add(resolve(repo, to)?, not);
+            return Ok(());
+        }
+
+        let (arg, hide) = match arg.strip_prefix('^') {
+            Some(arg) => (arg, !not),
+            None => (arg, not),
+        };
+        if let Some(rev) = arg.strip_suffix("^@") {
+            for parent in repo.find_commit(resolve(repo, rev)?)?.parent_ids() {
+                self.add(parent, hide);
+            }
+        } else if let Some(rev) = arg.strip_suffix("^!") {
+            let commit = repo.find_commit(resolve(repo, rev)?)?;
+            for parent in commit.parent_ids() {
+                self.add(parent, !hide);
+            }
+            self.add(commit.id(), hide);
+        } else if let Some((rev, n)) = arg.rsplit_once("^-").filter(|(_, n)| is_number(n)) {
+            let n = if n.is_empty() {
+                1
+            } else {
+                n.parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| invalid(format!("invalid parent number in {}", arg)))?
+            };
+            let commit = repo.find_commit(resolve(repo, rev)?)?;
+            let parent = commit
+                .parent_id(n - 1)
+                .map_err(|_| invalid(format!("{} has no parent {}", rev, n)))?;
+            self.add(parent, !hide);
+            self.add(commit.id(), hide);
+        } else {
+            self.add(resolve(repo, arg)?, hide);
+        }
+        Ok(())
+    }
+}
+
+/// Resolve `spec` to a commit, an empty side of a range meaning `HEAD`.
+fn resolve(repo: &Repository, spec: &str) -> Result<Oid, Error> {
+    let spec = if spec.is_empty() { "HEAD" } else { spec };
+    Ok(repo.revparse_single(spec)?.peel_to_commit()?.id())
+}
+
+/// Build a reference glob the way git does: a pattern without any wildcard
+/// matches everything below it.
+fn ref_glob(prefix: &str, pattern: Option<&str>) -> String {
+    match pattern {
+        None => format!("{}*", prefix),
+        Some(pattern) if pattern.contains(['*', '?', '[']) => format!("{}{}", prefix, pattern),
+        Some(pattern) => format!("{}{}/*", prefix, pattern.trim_end_matches('/')),
+    }
+}
+
+fn is_number(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorCode::InvalidSpec, ErrorClass::Invalid, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Oid, Repository, RevSpecList};
+
+    fn commit(repo: &Repository, message: &str, parents: &[Oid]) -> Oid {
+        let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap());
+        let tree = tree.unwrap();
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        let sig = repo.signature().unwrap();
+        repo.commit(None, &sig, &sig, message, &tree, &parents)
+            .unwrap()
+    }
+
+    fn walk(repo: &Repository, args: &[&str]) -> Vec<Oid> {
+        let list = RevSpecList::parse(repo, args).unwrap();
+        let mut walk = repo.revwalk().unwrap();
+        list.apply(&mut walk).unwrap();
+        let mut ids = walk.collect::<Result<Vec<_>, _>>().unwrap();
+        ids.sort();
+        ids
+    }
+
+    fn sorted(ids: &[Oid]) -> Vec<Oid> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let root = repo.head().unwrap().target().unwrap();
+        let a = commit(&repo, "a", &[root]);
+        let b = commit(&repo, "b", &[root]);
+        let m = commit(&repo, "m", &[a, b]);
+        repo.reference("refs/heads/left", a, false, "").unwrap();
+        repo.reference("refs/heads/topic/right", b, false, "")
+            .unwrap();
+        repo.reference("refs/tags/m", m, false, "").unwrap();
+
+        let list = RevSpecList::parse(&repo, ["left", "^topic/right"]).unwrap();
+        assert_eq!(list.pushed(), &[a]);
+        assert_eq!(list.hidden(), &[b]);
+
+        assert_eq!(walk(&repo, &["left", "^topic/right"]), vec![a]);
+        assert_eq!(walk(&repo, &["topic/right..m"]), sorted(&[a, m]));
+        assert_eq!(walk(&repo, &["left...topic/right"]), sorted(&[a, b]));
+        assert_eq!(walk(&repo, &["m^@"]), sorted(&[a, b, root]));
+        assert_eq!(walk(&repo, &["m^!"]), vec![m]);
+        assert_eq!(walk(&repo, &["m^-"]), sorted(&[m, b]));
+        assert_eq!(walk(&repo, &["m^-2"]), sorted(&[m, a]));
+        assert_eq!(
+            walk(&repo, &["m", "--not", "left", "--not", "^topic/right"]),
+            vec![m]
+        );
+        assert_eq!(
+            walk(&repo, &["m", "--not", "^topic/right", "main"]),
+            sorted(&[a, b, m])
+        );
+        assert_eq!(walk(&repo, &["--tags", "--not", "--branches"]), vec![m]);
+        assert_eq!(walk(&repo, &["--branches=topic", "^main"]), vec![b]);
+        assert_eq!(walk(&repo, &["--glob=heads/l*", "^main"]), vec![a]);
+        assert_eq!(walk(&repo, &["--all"]), sorted(&[root, a, b, m]));
+
+        // Unrelated histories have no merge base to exclude.
+        let unrelated = commit(&repo, "unrelated", &[]);
+        repo.reference("refs/heads/unrelated", unrelated, false, "")
+            .unwrap();
+        let list = RevSpecList::parse(&repo, ["left...unrelated"]).unwrap();
+        assert_eq!(list.pushed(), &[a, unrelated]);
+        assert!(list.hidden().is_empty());
+        assert_eq!(
+            walk(&repo, &["left...unrelated"]),
+            sorted(&[root, a, unrelated])
+        );
+        assert_eq!(
+            walk(&repo, &["left...unrelated", "^main"]),
+            sorted(&[a, unrelated])
+        );
+        assert_eq!(
+            walk(&repo, &["m...unrelated", "^left", "^topic/right"]),
+            sorted(&[m, unrelated])
+        );
+
+        assert!(RevSpecList::parse(&repo, ["--bogus"]).is_err());
+        assert!(RevSpecList::parse(&repo, ["left^-2"]).is_err());
+        assert!(RevSpecList::parse(&repo, ["missing"]).is_err());
+    }
+}
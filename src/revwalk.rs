@@ -1,17 +1,53 @@
 use libc::{c_int, c_uint, c_void};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ffi::CString;
 use std::marker;
+use std::path::{Path, PathBuf};
 
-use crate::util::Binding;
-use crate::{panic, raw, Error, Oid, Repository, Sort};
+use crate::util::{self, Binding};
+use crate::{
+    panic, raw, Commit, Delta, DiffFindOptions, DiffOptions, Error, Oid, Repository, Sort,
+};
+use crate::{IntoCString, Mailmap, Signature, Tree};
 
 /// A revwalk allows traversal of the commit graph defined by including one or
 /// more leaves and excluding one or more roots.
 pub struct Revwalk<'repo> {
     raw: *mut raw::git_revwalk,
+    paths: Vec<CString>,
+    follow: bool,
+    full_history: bool,
+    /// How far a path-limited walk has simplified the history.
+    simplified: Simplified,
+    /// The commits left to return from a path-limited walk in reverse.
+    pending: Option<VecDeque<Oid>>,
+    sorting: Sort,
+    filter: Option<RevwalkFilter>,
+    /// The number of commits skipped and returned so far by the filter.
+    skipped: usize,
+    returned: usize,
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
+/// Predicates on the commits returned by a [`Revwalk`], like the commit
+/// limiting options of `git log`.
+///
+/// See [`Revwalk::set_filter`].
+pub struct RevwalkFilter {
+    authors: Vec<String>,
+    committers: Vec<String>,
+    greps: Vec<String>,
+    all_match: bool,
+    ignore_case: bool,
+    since: Option<i64>,
+    until: Option<i64>,
+    min_parents: usize,
+    max_parents: Option<usize>,
+    max_count: Option<usize>,
+    skip: usize,
+    mailmap: Option<Mailmap>,
+}
+
 /// A `Revwalk` with an associated "hide callback", see `with_hide_callback`
 pub struct RevwalkWithHideCb<'repo, 'cb, C>
 where
@@ -55,18 +91,31 @@ impl<'repo> Revwalk<'repo> {
         unsafe {
             try_call!(raw::git_revwalk_reset(self.raw()));
         }
+        self.simplified = Simplified::default();
+        self.pending = None;
+        self.skipped = 0;
+        self.returned = 0;
         Ok(())
     }
 
     /// Set the order in which commits are visited.
     pub fn set_sorting(&mut self, sort_mode: Sort) -> Result<(), Error> {
-        unsafe {
-            try_call!(raw::git_revwalk_sorting(
-                self.raw(),
-                sort_mode.bits() as c_uint
-            ));
-        }
-        Ok(())
+        self.sorting = sort_mode;
+        self.apply_sorting()
+    }
+
+    /// Only return the commits matching `filter`, or every commit if
+    /// `None`.
+    ///
+    /// When sorting by time only, the walk stops at the first commit older
+    /// than the `since` date of the filter instead of going through the rest
+    /// of the history. A walk stopped early, there or by the `max_count` of
+    /// the filter, is reset as if its commits had all been returned: the
+    /// commits pushed and hidden are forgotten.
+    pub fn set_filter(&mut self, filter: Option<RevwalkFilter>) {
+        self.filter = filter;
+        self.skipped = 0;
+        self.returned = 0;
     }
 
     /// Simplify the history by first-parent
@@ -79,6 +128,61 @@ impl<'repo> Revwalk<'repo> {
         Ok(())
     }
 
+    /// Only return the commits modifying the given paths, like
+    /// `git log -- <paths>`.
+    ///
+    /// The paths are pathspecs relative to the root of the repository, as
+    /// accepted by [`DiffOptions::pathspec`]. An empty list of paths removes
+    /// the limit.
+    ///
+    /// The history is simplified like git does by default: a commit which is
+    /// TREESAME to one of its parents, that is whose tree is the same for
+    /// these paths, is left out and only that parent is followed. Merges
+    /// bringing in unrelated history are thereby skipped along with that
+    /// history. See [`Revwalk::full_history`] to follow every parent.
+    ///
+    /// The history is simplified as it is walked, in topological order so
+    /// that children come before their parents, with the time or the order
+    /// of insertion between them as set by [`Revwalk::set_sorting`]. A walk
+    /// sorted in reverse is simplified whole when the first commit is
+    /// requested.
+    pub fn limit_to_paths<I, T>(&mut self, paths: I) -> Result<(), Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        self.paths = paths
+            .into_iter()
+            .map(|path| path.into_c_string())
+            .collect::<Result<_, _>>()?;
+        self.follow = false;
+        self.apply_sorting()
+    }
+
+    /// Only return the commits modifying the file `path`, following it
+    /// across renames, like `git log --follow -- <path>`.
+    ///
+    /// `path` is relative to the root of the repository. When a commit adds
+    /// the file and a parent has a similar file under another name, the
+    /// walk continues with that name for the parent. The history is
+    /// simplified as for [`Revwalk::limit_to_paths`].
+    pub fn follow(&mut self, path: &Path) -> Result<(), Error> {
+        self.paths = vec![util::path_to_repo_path(path)?];
+        self.follow = true;
+        self.apply_sorting()
+    }
+
+    /// Follow every parent of merges when limiting the walk to paths, like
+    /// `git log --full-history`.
+    ///
+    /// Commits are then returned when they are not TREESAME to at least one
+    /// of their parents, and no history is skipped.
+    pub fn full_history(&mut self, full_history: bool) {
+        self.full_history = full_history;
+        self.simplified = Simplified::default();
+        self.pending = None;
+    }
+
     /// Mark a commit to start traversal from.
     ///
     /// The given OID must belong to a commitish on the walked repository.
@@ -221,6 +325,15 @@ impl<'repo> Binding for Revwalk<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_revwalk) -> Revwalk<'repo> {
         Revwalk {
             raw,
+            paths: Vec::new(),
+            follow: false,
+            full_history: false,
+            simplified: Simplified::default(),
+            pending: None,
+            sorting: Sort::NONE,
+            filter: None,
+            skipped: 0,
+            returned: 0,
             _marker: marker::PhantomData,
         }
     }
@@ -238,6 +351,333 @@ impl<'repo> Drop for Revwalk<'repo> {
 impl<'repo> Iterator for Revwalk<'repo> {
     type Item = Result<Oid, Error>;
     fn next(&mut self) -> Option<Result<Oid, Error>> {
+        loop {
+            let id = match self.next_unfiltered() {
+                Some(Ok(id)) => id,
+                other => {
+                    self.skipped = 0;
+                    self.returned = 0;
+                    return other;
+                }
+            };
+            let filter = match self.filter {
+                Some(ref filter) => filter,
+                None => return Some(Ok(id)),
+            };
+            let matched = match filter.max_count {
+                Some(max_count) if self.returned >= max_count => Ok(FilterMatch::Stop),
+                _ => {
+                    let repo =
+                        unsafe { Repository::borrow_raw(raw::git_revwalk_repository(self.raw)) };
+                    // A topological order may return a commit before newer
+                    // ones, so it cannot stop at the first old commit.
+                    let sorting = self.raw_sorting();
+                    let sorted = sorting.contains(Sort::TIME)
+                        && !sorting.intersects(Sort::TOPOLOGICAL | Sort::REVERSE);
+                    repo.find_commit(id)
+                        .and_then(|commit| filter.matches(&commit, sorted))
+                }
+            };
+            match matched {
+                Ok(FilterMatch::Yes) if self.skipped < filter.skip => self.skipped += 1,
+                Ok(FilterMatch::Yes) => {
+                    self.returned += 1;
+                    return Some(Ok(id));
+                }
+                Ok(FilterMatch::No) => {}
+                Ok(FilterMatch::Stop) => {
+                    if let Err(e) = self.reset() {
+                        return Some(Err(e));
+                    }
+                    return None;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Whether a commit is returned by a filtered walk.
+enum FilterMatch {
+    Yes,
+    No,
+    /// No more commits can match.
+    Stop,
+}
+
+impl RevwalkFilter {
+    /// Creates a filter matching every commit.
+    pub fn new() -> RevwalkFilter {
+        RevwalkFilter {
+            authors: Vec::new(),
+            committers: Vec::new(),
+            greps: Vec::new(),
+            all_match: false,
+            ignore_case: false,
+            since: None,
+            until: None,
+            min_parents: 0,
+            max_parents: None,
+            max_count: None,
+            skip: 0,
+            mailmap: None,
+        }
+    }
+
+    /// Only match commits whose author contains `pattern` in its
+    /// `Name <email>` form, like `git log --author --fixed-strings`.
+    ///
+    /// The pattern is a literal string, not a regular expression.
+    ///
+    /// When called several times, commits matching any of the patterns
+    /// match.
+    pub fn author(&mut self, pattern: &str) -> &mut RevwalkFilter {
+        self.authors.push(pattern.to_string());
+        self
+    }
+
+    /// Only match commits whose committer contains `pattern` in its
+    /// `Name <email>` form, like `git log --committer --fixed-strings`.
+    ///
+    /// The pattern is a literal string, not a regular expression.
+    ///
+    /// When called several times, commits matching any of the patterns
+    /// match.
+    pub fn committer(&mut self, pattern: &str) -> &mut RevwalkFilter {
+        self.committers.push(pattern.to_string());
+        self
+    }
+
+    /// Only match commits whose message contains `pattern`, like
+    /// `git log --grep --fixed-strings`.
+    ///
+    /// The pattern is a literal string, not a regular expression.
+    ///
+    /// When called several times, commits matching any of the patterns
+    /// match, unless `all_match` is set.
+    pub fn grep(&mut self, pattern: &str) -> &mut RevwalkFilter {
+        self.greps.push(pattern.to_string());
+        self
+    }
+
+    /// Require the message to match every `grep` pattern, like
+    /// `git log --all-match`.
+    pub fn all_match(&mut self, all: bool) -> &mut RevwalkFilter {
+        self.all_match = all;
+        self
+    }
+
+    /// Match the author, committer and message patterns regardless of
+    /// case, like `git log -i`.
+    pub fn ignore_case(&mut self, ignore: bool) -> &mut RevwalkFilter {
+        self.ignore_case = ignore;
+        self
+    }
+
+    /// Only match commits committed at or after `seconds` since the Unix
+    /// epoch, like `git log --since`.
+    pub fn since(&mut self, seconds: i64) -> &mut RevwalkFilter {
+        self.since = Some(seconds);
+        self
+    }
+
+    /// Only match commits committed at or before `seconds` since the Unix
+    /// epoch, like `git log --until`.
+    pub fn until(&mut self, seconds: i64) -> &mut RevwalkFilter {
+        self.until = Some(seconds);
+        self
+    }
+
+    /// Only match commits with at least `n` parents, like
+    /// `git log --min-parents`.
+    pub fn min_parents(&mut self, n: usize) -> &mut RevwalkFilter {
+        self.min_parents = n;
+        self
+    }
+
+    /// Only match commits with at most `n` parents, like
+    /// `git log --max-parents`.
+    pub fn max_parents(&mut self, n: usize) -> &mut RevwalkFilter {
+        self.max_parents = Some(n);
+        self
+    }
+
+    /// Only match merge commits, like `git log --merges`.
+    pub fn merges(&mut self) -> &mut RevwalkFilter {
+        self.min_parents(2)
+    }
+
+    /// Only match commits which are not merges, like `git log --no-merges`.
+    pub fn no_merges(&mut self) -> &mut RevwalkFilter {
+        self.max_parents(1)
+    }
+
+    /// Stop the walk after returning `n` commits, like
+    /// `git log --max-count`.
+    ///
+    /// The walk is then reset, see [`Revwalk::set_filter`].
+    pub fn max_count(&mut self, n: usize) -> &mut RevwalkFilter {
+        self.max_count = Some(n);
+        self
+    }
+
+    /// Skip the first `n` matching commits, like `git log --skip`.
+    pub fn skip(&mut self, n: usize) -> &mut RevwalkFilter {
+        self.skip = n;
+        self
+    }
+
+    /// Resolve the author and committer with `mailmap` before matching
+    /// them, like `git log --use-mailmap`.
+    pub fn mailmap(&mut self, mailmap: Mailmap) -> &mut RevwalkFilter {
+        self.mailmap = Some(mailmap);
+        self
+    }
+
+    /// Whether `commit` matches, `sorted` being whether the walk is sorted
+    /// by decreasing time.
+    fn matches(&self, commit: &Commit<'_>, sorted: bool) -> Result<FilterMatch, Error> {
+        let time = commit.time().seconds();
+        if self.since.is_some_and(|since| time < since) {
+            return Ok(if sorted {
+                FilterMatch::Stop
+            } else {
+                FilterMatch::No
+            });
+        }
+        if self.until.is_some_and(|until| time > until) {
+            return Ok(FilterMatch::No);
+        }
+        let parents = commit.parent_count();
+        if parents < self.min_parents || self.max_parents.is_some_and(|max| parents > max) {
+            return Ok(FilterMatch::No);
+        }
+        if !self.authors.is_empty() && !self.signature_matches(&commit.author(), &self.authors)? {
+            return Ok(FilterMatch::No);
+        }
+        if !self.committers.is_empty()
+            && !self.signature_matches(&commit.committer(), &self.committers)?
+        {
+            return Ok(FilterMatch::No);
+        }
+        if !self.greps.is_empty() {
+            let message = self.fold(String::from_utf8_lossy(commit.message_bytes()).into_owned());
+            let mut matches = self
+                .greps
+                .iter()
+                .map(|p| message.contains(&self.fold(p.clone())));
+            let matched = if self.all_match {
+                matches.all(|m| m)
+            } else {
+                matches.any(|m| m)
+            };
+            if !matched {
+                return Ok(FilterMatch::No);
+            }
+        }
+        Ok(FilterMatch::Yes)
+    }
+
+    fn signature_matches(&self, sig: &Signature<'_>, patterns: &[String]) -> Result<bool, Error> {
+        let sig = match self.mailmap {
+            Some(ref mailmap) => mailmap.resolve_signature(sig)?,
+            None => sig.to_owned(),
+        };
+        let ident = self.fold(format!(
+            "{} <{}>",
+            String::from_utf8_lossy(sig.name_bytes()),
+            String::from_utf8_lossy(sig.email_bytes())
+        ));
+        Ok(patterns
+            .iter()
+            .any(|p| ident.contains(&self.fold(p.clone()))))
+    }
+
+    fn fold(&self, s: String) -> String {
+        if self.ignore_case {
+            s.to_lowercase()
+        } else {
+            s
+        }
+    }
+}
+
+impl Default for RevwalkFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The parents followed by a path-limited walk, with the path of the
+/// followed file in each of them.
+type Followed = Vec<(Oid, Option<PathBuf>)>;
+
+/// The part of the history a path-limited walk has yet to reach.
+#[derive(Default)]
+struct Simplified {
+    /// The commits the simplified history goes through, with the path of
+    /// the followed file in each of them.
+    followed: HashMap<Oid, Option<PathBuf>>,
+    /// The parents of the commits walked so far.
+    reached: HashSet<Oid>,
+}
+
+/// How a commit compares to one of its parents for the limiting paths.
+enum Treesame {
+    Same,
+    /// The paths differ, the file followed being known as the given path
+    /// in the parent.
+    Different(Option<PathBuf>),
+}
+
+impl<'repo> Revwalk<'repo> {
+    /// The sorting of the underlying walk, which is topological when the
+    /// walk is limited to paths, and reversed by `next_unfiltered` then.
+    fn raw_sorting(&self) -> Sort {
+        if self.paths.is_empty() {
+            self.sorting
+        } else {
+            (self.sorting - Sort::REVERSE) | Sort::TOPOLOGICAL
+        }
+    }
+
+    fn apply_sorting(&mut self) -> Result<(), Error> {
+        unsafe {
+            try_call!(raw::git_revwalk_sorting(
+                self.raw(),
+                self.raw_sorting().bits() as c_uint
+            ));
+        }
+        self.simplified = Simplified::default();
+        self.pending = None;
+        Ok(())
+    }
+
+    fn next_unfiltered(&mut self) -> Option<Result<Oid, Error>> {
+        if self.paths.is_empty() {
+            return self.next_raw();
+        }
+        if !self.sorting.contains(Sort::REVERSE) {
+            return self.next_simplified();
+        }
+        if self.pending.is_none() {
+            let mut pending = VecDeque::new();
+            while let Some(id) = self.next_simplified() {
+                match id {
+                    Ok(id) => pending.push_front(id),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            self.pending = Some(pending);
+        }
+        let next = self.pending.as_mut().unwrap().pop_front();
+        if next.is_none() {
+            self.pending = None;
+        }
+        next.map(Ok)
+    }
+
+    fn next_raw(&mut self) -> Option<Result<Oid, Error>> {
         let mut out: raw::git_oid = raw::git_oid {
             id: [0; raw::GIT_OID_RAWSZ],
         };
@@ -246,6 +686,149 @@ impl<'repo> Iterator for Revwalk<'repo> {
             Some(Ok(Binding::from_raw(&out as *const _)))
         }
     }
+
+    /// The next commit of the history simplified for the limiting paths.
+    fn next_simplified(&mut self) -> Option<Result<Oid, Error>> {
+        loop {
+            let id = match self.next_raw() {
+                Some(Ok(id)) => id,
+                other => {
+                    self.simplified = Simplified::default();
+                    return other;
+                }
+            };
+            match self.simplify(id) {
+                Ok(true) => return Some(Ok(id)),
+                Ok(false) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Whether the commit `id` is shown in the simplified history.
+    ///
+    /// The walk being topological, every child of the commit in the walk
+    /// has been simplified already: the commit is part of the simplified
+    /// history if one of them followed it, or if none of them leads to it.
+    fn simplify(&mut self, id: Oid) -> Result<bool, Error> {
+        let repo = unsafe { Repository::borrow_raw(raw::git_revwalk_repository(self.raw)) };
+        let commit = repo.find_commit(id)?;
+        let reached = self.simplified.reached.remove(&id);
+        let path = match self.simplified.followed.remove(&id) {
+            Some(path) => Some(path),
+            None if !reached => Some(if self.follow {
+                Some(util::bytes2path(self.paths[0].as_bytes()).to_path_buf())
+            } else {
+                None
+            }),
+            None => None,
+        };
+        self.simplified.reached.extend(commit.parent_ids());
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let (show, followed) = self.simplify_commit(&repo, &commit, &path)?;
+        for (parent, path) in followed {
+            self.simplified.followed.entry(parent).or_insert(path);
+        }
+        Ok(show)
+    }
+
+    /// Decide whether `commit` is shown and which of its parents are
+    /// followed, with the path of the followed file in each of them.
+    fn simplify_commit(
+        &self,
+        repo: &Repository,
+        commit: &Commit<'_>,
+        path: &Option<PathBuf>,
+    ) -> Result<(bool, Followed), Error> {
+        let tree = commit.tree()?;
+        if commit.parent_count() == 0 {
+            let show = match path {
+                Some(path) => tree.get_path(path).is_ok(),
+                None => {
+                    let mut opts = self.diff_options();
+                    let diff = repo.diff_tree_to_tree(None, Some(&tree), Some(&mut opts))?;
+                    diff.deltas().len() > 0
+                }
+            };
+            return Ok((show, Vec::new()));
+        }
+
+        let mut followed = Vec::new();
+        let mut show = false;
+        for parent in commit.parents() {
+            let parent_tree = parent.tree()?;
+            match self.treesame(repo, &parent_tree, &tree, path)? {
+                // Only follow the first parent the commit is TREESAME to.
+                Treesame::Same if !self.full_history => {
+                    return Ok((false, vec![(parent.id(), path.clone())]));
+                }
+                Treesame::Same => followed.push((parent.id(), path.clone())),
+                Treesame::Different(parent_path) => {
+                    show = true;
+                    followed.push((parent.id(), parent_path));
+                }
+            }
+        }
+        Ok((show, followed))
+    }
+
+    fn treesame(
+        &self,
+        repo: &Repository,
+        parent: &Tree<'_>,
+        tree: &Tree<'_>,
+        path: &Option<PathBuf>,
+    ) -> Result<Treesame, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let mut opts = self.diff_options();
+                let diff = repo.diff_tree_to_tree(Some(parent), Some(tree), Some(&mut opts))?;
+                return Ok(if diff.deltas().len() == 0 {
+                    Treesame::Same
+                } else {
+                    Treesame::Different(None)
+                });
+            }
+        };
+
+        let same = match (tree.get_path(path), parent.get_path(path)) {
+            (Ok(entry), Ok(parent_entry)) => {
+                entry.id() == parent_entry.id() && entry.filemode() == parent_entry.filemode()
+            }
+            (Err(_), Err(_)) => true,
+            (Err(_), Ok(_)) => false,
+            // The file was added, look for where it was renamed from.
+            (Ok(_), Err(_)) => {
+                let mut diff = repo.diff_tree_to_tree(Some(parent), Some(tree), None)?;
+                diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+                let renamed = diff.deltas().find(|delta| {
+                    delta.status() == Delta::Renamed && delta.new_file().path() == Some(path)
+                });
+                let parent_path = match renamed.and_then(|delta| delta.old_file().path()) {
+                    Some(old) => old.to_path_buf(),
+                    None => path.clone(),
+                };
+                return Ok(Treesame::Different(Some(parent_path)));
+            }
+        };
+        Ok(if same {
+            Treesame::Same
+        } else {
+            Treesame::Different(Some(path.clone()))
+        })
+    }
+
+    fn diff_options(&self) -> DiffOptions {
+        let mut opts = DiffOptions::new();
+        for path in &self.paths {
+            opts.pathspec(path.as_bytes());
+        }
+        opts
+    }
 }
 
 impl<'repo, 'cb, C: FnMut(Oid) -> bool> Iterator for RevwalkWithHideCb<'repo, 'cb, C> {
@@ -259,6 +842,243 @@ impl<'repo, 'cb, C: FnMut(Oid) -> bool> Iterator for RevwalkWithHideCb<'repo, 'c
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
+    use crate::{Mailmap, Oid, Repository, RevwalkFilter, Signature, Sort, Time};
+
+    fn commit(repo: &Repository, parents: &[Oid], files: &[(&str, &str)]) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, contents) in files {
+            let blob = repo.blob(contents.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        let sig = repo.signature().unwrap();
+        repo.commit(None, &sig, &sig, "commit", &tree, &parents)
+            .unwrap()
+    }
+
+    fn walk(repo: &Repository, tip: Oid, f: impl FnOnce(&mut crate::Revwalk<'_>)) -> Vec<Oid> {
+        let mut walk = repo.revwalk().unwrap();
+        walk.push(tip).unwrap();
+        f(&mut walk);
+        let mut ids = walk.collect::<Result<Vec<_>, _>>().unwrap();
+        ids.sort();
+        ids
+    }
+
+    fn sorted(mut ids: Vec<Oid>) -> Vec<Oid> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn filter() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        let commit = |name: &str, time: i64, message: &str, parents: &[Oid]| {
+            let email = format!("{}@example.com", name.to_lowercase());
+            let sig = Signature::new(name, &email, &Time::new(time, 0)).unwrap();
+            let parents = parents
+                .iter()
+                .map(|id| repo.find_commit(*id).unwrap())
+                .collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(None, &sig, &sig, message, &tree, &parents)
+                .unwrap()
+        };
+        let c1 = commit("Alice", 100, "initial", &[]);
+        let c2 = commit("Bob", 200, "fix bug", &[c1]);
+        let c3 = commit("Alice", 300, "add feature", &[c2]);
+        let s1 = commit("Carol", 250, "Fix typo", &[c2]);
+        let merge = commit("Alice", 400, "merge", &[c3, s1]);
+
+        let walk = |sorting: Sort, f: &dyn Fn(&mut RevwalkFilter)| {
+            let mut filter = RevwalkFilter::new();
+            f(&mut filter);
+            let mut walk = repo.revwalk().unwrap();
+            walk.set_sorting(sorting).unwrap();
+            walk.set_filter(Some(filter));
+            walk.push(merge).unwrap();
+            walk.collect::<Result<Vec<_>, _>>().unwrap()
+        };
+
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.author("ALICE");
+            }),
+            vec![]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.author("ALICE").ignore_case(true);
+            }),
+            vec![merge, c3, c1]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.author("Alice <alice@").author("Bob");
+            }),
+            vec![merge, c3, c2, c1]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                let mailmap =
+                    Mailmap::from_buffer("Alice <alice@example.com> <carol@example.com>").unwrap();
+                f.author("Alice <alice@").mailmap(mailmap);
+            }),
+            vec![merge, c3, s1, c1]
+        );
+
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.grep("fix");
+            }),
+            vec![c2]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.grep("fix").ignore_case(true);
+            }),
+            vec![s1, c2]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.grep("fix").grep("typo").all_match(true).ignore_case(true);
+            }),
+            vec![s1]
+        );
+
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.since(250);
+            }),
+            vec![merge, c3, s1]
+        );
+        assert_eq!(
+            walk(Sort::TIME | Sort::REVERSE, &|f| {
+                f.since(250);
+            }),
+            vec![s1, c3, merge]
+        );
+        // Dates out of order do not stop a topological walk.
+        let old = commit("Bob", 100, "old", &[merge]);
+        let tip = commit("Bob", 500, "tip", &[old]);
+        let mut filter = RevwalkFilter::new();
+        filter.since(250);
+        let mut topo = repo.revwalk().unwrap();
+        topo.set_sorting(Sort::TIME | Sort::TOPOLOGICAL).unwrap();
+        topo.set_filter(Some(filter));
+        topo.push(tip).unwrap();
+        assert_eq!(
+            topo.collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![tip, merge, c3, s1]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.until(250);
+            }),
+            vec![s1, c2, c1]
+        );
+
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.merges();
+            }),
+            vec![merge]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.no_merges();
+            }),
+            vec![c3, s1, c2, c1]
+        );
+        assert_eq!(
+            walk(Sort::TIME, &|f| {
+                f.skip(1).max_count(2);
+            }),
+            vec![c3, s1]
+        );
+
+        // The walk is reset when stopped early.
+        let mut walk = repo.revwalk().unwrap();
+        let mut filter = RevwalkFilter::new();
+        filter.max_count(1);
+        walk.set_filter(Some(filter));
+        walk.push(merge).unwrap();
+        assert_eq!(walk.by_ref().count(), 1);
+        walk.push(c2).unwrap();
+        assert_eq!(
+            walk.by_ref().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![c2]
+        );
+    }
+
+    #[test]
+    fn limit_to_paths() {
+        let (_td, repo) = crate::test::repo_init();
+        let text = "a\nfile\nwith\nsome\nlines\n";
+        let c1 = commit(&repo, &[], &[("a", text), ("other", "1")]);
+        let c2 = commit(&repo, &[c1], &[("a", text), ("other", "2")]);
+        let c3 = commit(&repo, &[c2], &[("a", "changed\n"), ("other", "2")]);
+        let s1 = commit(&repo, &[c2], &[("a", text), ("other", "3")]);
+        let merge = commit(&repo, &[c3, s1], &[("a", "changed\n"), ("other", "3")]);
+        let renamed = commit(&repo, &[merge], &[("b", "changed\n"), ("other", "3")]);
+        let tip = commit(
+            &repo,
+            &[renamed],
+            &[("b", "changed again\n"), ("other", "3")],
+        );
+
+        // The merge is TREESAME to its first parent, the side branch is
+        // not walked.
+        let ids = walk(&repo, tip, |w| w.limit_to_paths(["a"]).unwrap());
+        assert_eq!(ids, sorted(vec![renamed, c3, c1]));
+
+        let ids = walk(&repo, tip, |w| {
+            w.limit_to_paths(["a"]).unwrap();
+            w.full_history(true);
+        });
+        assert_eq!(ids, sorted(vec![renamed, merge, c3, c1]));
+
+        // The merge is TREESAME to its second parent instead.
+        let ids = walk(&repo, tip, |w| w.limit_to_paths(["other"]).unwrap());
+        assert_eq!(ids, sorted(vec![s1, c2, c1]));
+
+        let ids = walk(&repo, tip, |w| w.limit_to_paths(["b"]).unwrap());
+        assert_eq!(ids, sorted(vec![tip, renamed]));
+
+        let ids = walk(&repo, tip, |w| w.follow(Path::new("b")).unwrap());
+        assert_eq!(ids, sorted(vec![tip, renamed, c3, c1]));
+
+        // Children come before their parents, or after them in reverse.
+        let mut walk = repo.revwalk().unwrap();
+        walk.limit_to_paths(["a"]).unwrap();
+        walk.push(tip).unwrap();
+        let ids = walk.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(ids, [renamed, c3, c1]);
+        walk.set_sorting(Sort::TIME | Sort::REVERSE).unwrap();
+        walk.push(tip).unwrap();
+        let ids = walk.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(ids, [c1, c3, renamed]);
+
+        // The walk is reset once done, keeping the paths.
+        let mut walk = repo.revwalk().unwrap();
+        walk.limit_to_paths(["b"]).unwrap();
+        walk.push(tip).unwrap();
+        assert_eq!(walk.by_ref().count(), 2);
+        walk.push(tip).unwrap();
+        assert_eq!(walk.by_ref().count(), 2);
+        walk.limit_to_paths(Vec::<&str>::new()).unwrap();
+        walk.push(tip).unwrap();
+        assert_eq!(walk.count(), 7);
+    }
+
     #[test]
     fn smoke() {
         let (_td, repo) = crate::test::repo_init();
@@ -0,0 +1,850 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::str;
+
+use libc::size_t;
+
+use crate::build::TreeUpdateBuilder;
+use crate::util::{self, Binding};
+use crate::{raw, Blob, Commit, Error, ErrorClass, ErrorCode, FileMode, ObjectType, Oid};
+use crate::{Repository, Signature, Sort, Tree};
+
+type CommitCb<'a> = dyn FnMut(&mut CommitRewrite<'_>) -> Result<(), Error> + 'a;
+type PathCb<'a> = dyn FnMut(&Path) -> Option<PathBuf> + 'a;
+type BlobCb<'a> = dyn FnMut(&Path, &Blob<'_>) -> Result<Option<Vec<u8>>, Error> + 'a;
+
+/// Rewrites the history of references, like `git filter-branch` and
+/// `git filter-repo`.
+///
+/// Commits reachable from the pushed references, and not from hidden
+/// commits, are rewritten parents first. Callbacks can change the metadata
+/// of each commit, move, remove or replace the files of its tree, or drop it
+/// altogether. Once every commit is rewritten, the references are updated in
+/// a single transaction, so either all or none of them move.
+///
+/// The index and working directory are left alone, even if the branch
+/// checked out is rewritten.
+pub struct HistoryRewriter<'repo, 'cb> {
+    repo: &'repo Repository,
+    refs: Vec<String>,
+    hidden: Vec<Oid>,
+    commit_cb: Option<Box<CommitCb<'cb>>>,
+    path_cb: Option<Box<PathCb<'cb>>>,
+    blob_cb: Option<Box<BlobCb<'cb>>>,
+    prune_empty: bool,
+    backup: Option<String>,
+    message: String,
+}
+
+/// A commit being rewritten, handed to the callback set with
+/// [`HistoryRewriter::commit_callback`].
+pub struct CommitRewrite<'a> {
+    commit: &'a Commit<'a>,
+    author: Signature<'static>,
+    committer: Signature<'static>,
+    message: Vec<u8>,
+    encoding: Option<String>,
+    modified: bool,
+    dropped: bool,
+}
+
+/// The commits rewritten by a [`HistoryRewriter`], mapping the id of each
+/// original commit to the id of its rewritten commit, or to nothing if it
+/// was dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommitMap {
+    entries: Vec<(Oid, Option<Oid>)>,
+    positions: HashMap<Oid, usize>,
+}
+
+/// How the parents of commits are rewritten.
+enum Rewritten {
+    /// The commit was rewritten, or kept, as the given commit.
+    Commit(Oid),
+    /// The commit was dropped; its children get the given parents instead.
+    Dropped(Vec<Oid>),
+}
+
+impl<'repo, 'cb> HistoryRewriter<'repo, 'cb> {
+    /// Creates a rewriter for `repo` which rewrites nothing until references
+    /// are pushed.
+    pub fn new(repo: &'repo Repository) -> HistoryRewriter<'repo, 'cb> {
+        HistoryRewriter {
+            repo,
+            refs: Vec::new(),
+            hidden: Vec::new(),
+            commit_cb: None,
+            path_cb: None,
+            blob_cb: None,
+            prune_empty: false,
+            backup: None,
+            message: "rewrite history".to_string(),
+        }
+    }
+
+    /// Rewrite the history of the reference `name`, e.g. `refs/heads/main`.
+    ///
+    /// Symbolic references are rewritten through the reference they point
+    /// to.
+    pub fn push_ref(&mut self, name: &str) -> Result<&mut Self, Error> {
+        let name = self.repo.resolve_reference_from_short_name(name)?;
+        let name = name.resolve()?;
+        if let Some(name) = name.name() {
+            if !self.refs.iter().any(|r| r == name) {
+                self.refs.push(name.to_string());
+            }
+        }
+        Ok(self)
+    }
+
+    /// Rewrite the history of every reference matching `glob`, e.g.
+    /// `refs/heads/*` or `refs/tags/*`.
+    pub fn push_glob(&mut self, glob: &str) -> Result<&mut Self, Error> {
+        let names = self
+            .repo
+            .references_glob(glob)?
+            .map(|r| Ok(r?.name().map(|s| s.to_string())))
+            .collect::<Result<Vec<_>, Error>>()?;
+        for name in names.into_iter().flatten() {
+            self.push_ref(&name)?;
+        }
+        Ok(self)
+    }
+
+    /// Leave `id` and its ancestors as they are, like the `A` of a `A..B`
+    /// range.
+    pub fn hide(&mut self, id: Oid) -> &mut Self {
+        self.hidden.push(id);
+        self
+    }
+
+    /// Set a callback to change the author, committer or message of each
+    /// commit, or to drop it.
+    pub fn commit_callback<F>(&mut self, cb: F) -> &mut Self
+    where
+        F: FnMut(&mut CommitRewrite<'_>) -> Result<(), Error> + 'cb,
+    {
+        self.commit_cb = Some(Box::new(cb) as Box<CommitCb<'cb>>);
+        self
+    }
+
+    /// Set a callback to move or remove the files of each tree.
+    ///
+    /// The callback receives the path of each file, symbolic link and
+    /// submodule, and returns its new path, or `None` to remove it. It is
+    /// called once per path.
+    pub fn path_callback<F>(&mut self, cb: F) -> &mut Self
+    where
+        F: FnMut(&Path) -> Option<PathBuf> + 'cb,
+    {
+        self.path_cb = Some(Box::new(cb) as Box<PathCb<'cb>>);
+        self
+    }
+
+    /// Set a callback to replace the contents of files.
+    ///
+    /// The callback receives the path of each file, after it is moved by the
+    /// path callback, and its contents, and returns new contents, or `None`
+    /// to keep them. It is called once per path and blob.
+    pub fn blob_callback<F>(&mut self, cb: F) -> &mut Self
+    where
+        F: FnMut(&Path, &Blob<'_>) -> Result<Option<Vec<u8>>, Error> + 'cb,
+    {
+        self.blob_cb = Some(Box::new(cb) as Box<BlobCb<'cb>>);
+        self
+    }
+
+    /// Drop commits which, once rewritten, no longer change anything, like
+    /// `git filter-branch --prune-empty`.
+    ///
+    /// Only commits with at most one parent are dropped. Defaults to `false`.
+    pub fn prune_empty(&mut self, prune: bool) -> &mut Self {
+        self.prune_empty = prune;
+        self
+    }
+
+    /// Keep the original target of each rewritten reference under `prefix`,
+    /// e.g. `refs/heads/main` under `refs/original/refs/heads/main` for a
+    /// prefix of `refs/original` like `git filter-branch` does.
+    pub fn backup(&mut self, prefix: &str) -> &mut Self {
+        self.backup = Some(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Set the message written to the reflog of the updated references.
+    pub fn reflog_message(&mut self, message: &str) -> &mut Self {
+        self.message = message.to_string();
+        self
+    }
+
+    /// Rewrite the history, update the references, and return how commits
+    /// were rewritten.
+    ///
+    /// References whose commits are all dropped are deleted. Annotated tags
+    /// are recreated to point to the rewritten commits.
+    pub fn rewrite(&mut self) -> Result<CommitMap, Error> {
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        let mut tips = Vec::new();
+        for name in self.refs.iter() {
+            let reference = self.repo.find_reference(name)?;
+            let target = reference.target().unwrap();
+            if let Ok(commit) = reference.peel_to_commit() {
+                walk.push(commit.id())?;
+                tips.push((name.clone(), target));
+            }
+        }
+        for id in self.hidden.iter() {
+            walk.hide(*id)?;
+        }
+
+        let mut rewritten = HashMap::new();
+        let mut map = CommitMap::default();
+        let mut trees = HashMap::new();
+        let mut paths = HashMap::new();
+        let mut blobs = HashMap::new();
+        for id in walk {
+            let commit = self.repo.find_commit(id?)?;
+            let mut parents = Vec::new();
+            for parent in commit.parent_ids() {
+                let new = match rewritten.get(&parent) {
+                    Some(Rewritten::Commit(new)) => vec![*new],
+                    Some(Rewritten::Dropped(parents)) => parents.clone(),
+                    None => vec![parent],
+                };
+                for new in new {
+                    if !parents.contains(&new) {
+                        parents.push(new);
+                    }
+                }
+            }
+
+            let tree = commit.tree()?;
+            let new_tree = match trees.get(&tree.id()) {
+                Some(new_tree) => *new_tree,
+                None => {
+                    let new_tree = self.rewrite_tree(&tree, &mut paths, &mut blobs)?;
+                    trees.insert(tree.id(), new_tree);
+                    new_tree
+                }
+            };
+
+            let mut rewrite = CommitRewrite {
+                commit: &commit,
+                author: commit.author().to_owned(),
+                committer: commit.committer().to_owned(),
+                message: commit.message_raw_bytes().to_vec(),
+                encoding: commit.message_encoding().map(str::to_string),
+                modified: false,
+                dropped: false,
+            };
+            if let Some(ref mut cb) = self.commit_cb {
+                cb(&mut rewrite)?;
+            }
+            let dropped = rewrite.dropped
+                || (self.prune_empty && parents.len() <= 1 && {
+                    let parent_tree = match parents.first() {
+                        Some(parent) => self.repo.find_commit(*parent)?.tree_id(),
+                        None => self.repo.treebuilder(None)?.write()?,
+                    };
+                    parent_tree == new_tree
+                });
+            if dropped {
+                map.insert(commit.id(), None);
+                rewritten.insert(commit.id(), Rewritten::Dropped(parents));
+                continue;
+            }
+
+            let unchanged = !rewrite.modified
+                && new_tree == tree.id()
+                && parents
+                    .iter()
+                    .eq(commit.parent_ids().collect::<Vec<_>>().iter());
+            let new = if unchanged {
+                commit.id()
+            } else {
+                let new_tree = self.repo.find_tree(new_tree)?;
+                let parents = parents
+                    .iter()
+                    .map(|id| self.repo.find_commit(*id))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                create_commit(self.repo, &rewrite, &new_tree, &parents)?
+            };
+            map.insert(commit.id(), Some(new));
+            rewritten.insert(commit.id(), Rewritten::Commit(new));
+        }
+
+        self.update_refs(&tips, &rewritten)?;
+        Ok(map)
+    }
+
+    /// Collect the files of `tree`, below `prefix`, with their ids and modes.
+    ///
+    /// Paths are built from the raw names of the entries, which need not be
+    /// UTF-8.
+    fn tree_files(
+        &self,
+        tree: &Tree<'_>,
+        prefix: &Path,
+        files: &mut Vec<(PathBuf, Oid, i32)>,
+    ) -> Result<(), Error> {
+        for entry in tree.iter() {
+            let path = prefix.join(util::bytes2path(entry.name_bytes()));
+            if entry.kind() == Some(ObjectType::Tree) {
+                let subtree = self.repo.find_tree(entry.id())?;
+                self.tree_files(&subtree, &path, files)?;
+            } else {
+                files.push((path, entry.id(), entry.filemode()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the path and blob callbacks to the files of `tree`.
+    fn rewrite_tree(
+        &mut self,
+        tree: &Tree<'_>,
+        paths: &mut HashMap<PathBuf, Option<PathBuf>>,
+        blobs: &mut HashMap<(PathBuf, Oid), Oid>,
+    ) -> Result<Oid, Error> {
+        if self.path_cb.is_none() && self.blob_cb.is_none() {
+            return Ok(tree.id());
+        }
+        let mut entries = Vec::new();
+        self.tree_files(tree, Path::new(""), &mut entries)?;
+
+        let mut removed = Vec::new();
+        let mut upserted = Vec::new();
+        for (path, id, mode) in entries {
+            let new_path = match self.path_cb {
+                Some(ref mut cb) => paths
+                    .entry(path.clone())
+                    .or_insert_with(|| cb(&path))
+                    .clone(),
+                None => Some(path.clone()),
+            };
+            let new_path = match new_path {
+                Some(new_path) => new_path,
+                None => {
+                    removed.push(path);
+                    continue;
+                }
+            };
+            let mut new_id = id;
+            let is_file = mode == i32::from(FileMode::Blob)
+                || mode == i32::from(FileMode::BlobExecutable)
+                || mode == i32::from(FileMode::BlobGroupWritable);
+            if let (true, Some(cb)) = (is_file, self.blob_cb.as_mut()) {
+                let key = (new_path.clone(), id);
+                new_id = match blobs.get(&key) {
+                    Some(new_id) => *new_id,
+                    None => {
+                        let blob = self.repo.find_blob(id)?;
+                        let new_id = match cb(&new_path, &blob)? {
+                            Some(contents) => self.repo.blob(&contents)?,
+                            None => id,
+                        };
+                        blobs.insert(key, new_id);
+                        new_id
+                    }
+                };
+            }
+            if new_path != path {
+                removed.push(path);
+            } else if new_id == id {
+                continue;
+            }
+            upserted.push((new_path, new_id, file_mode(mode)));
+        }
+        if removed.is_empty() && upserted.is_empty() {
+            return Ok(tree.id());
+        }
+
+        // Paths which are both removed and upserted are only upserted, as
+        // libgit2 does not support both.
+        let targets = upserted
+            .iter()
+            .map(|(path, _, _)| path.clone())
+            .collect::<HashSet<_>>();
+        let mut updates = TreeUpdateBuilder::new();
+        for path in removed.iter().filter(|p| !targets.contains(*p)) {
+            updates.remove(path.as_path());
+        }
+        for (path, id, mode) in upserted {
+            updates.upsert(path.as_path(), id, mode);
+        }
+        updates.create_updated(self.repo, tree)
+    }
+
+    /// Move the references to their rewritten commits in one transaction.
+    fn update_refs(
+        &self,
+        tips: &[(String, Oid)],
+        rewritten: &HashMap<Oid, Rewritten>,
+    ) -> Result<(), Error> {
+        let mut updates = Vec::new();
+        for (name, target) in tips {
+            let new = self.rewrite_ref_target(*target, rewritten)?;
+            if new != Some(*target) {
+                updates.push((name, *target, new));
+            }
+        }
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.repo.transaction()?;
+        for (name, old, new) in updates {
+            tx.lock_ref(name)?;
+            // The reference cannot move once locked, but may have moved
+            // since the history was rewritten.
+            let current = self.repo.find_reference(name)?;
+            if current.target() != Some(old) {
+                return Err(Error::new(
+                    ErrorCode::Modified,
+                    ErrorClass::Reference,
+                    format!("reference '{}' changed while rewriting history", name),
+                ));
+            }
+            if let Some(ref prefix) = self.backup {
+                let backup = format!("{}/{}", prefix, name);
+                tx.lock_ref(&backup)?;
+                tx.set_target(&backup, old, None, &self.message)?;
+            }
+            match new {
+                Some(new) => tx.set_target(name, new, None, &self.message)?,
+                None => tx.remove(name)?,
+            }
+        }
+        tx.commit()
+    }
+
+    /// The new target of a reference to `target`, recreating annotated
+    /// tags, or `None` if the reference is to be deleted.
+    fn rewrite_ref_target(
+        &self,
+        target: Oid,
+        rewritten: &HashMap<Oid, Rewritten>,
+    ) -> Result<Option<Oid>, Error> {
+        let object = self.repo.find_object(target, None)?;
+        let tag = match object.into_tag() {
+            Ok(tag) => tag,
+            Err(_) => {
+                return Ok(match rewritten.get(&target) {
+                    Some(Rewritten::Commit(new)) => Some(*new),
+                    Some(Rewritten::Dropped(parents)) => parents.first().cloned(),
+                    None => Some(target),
+                })
+            }
+        };
+        let new = match self.rewrite_ref_target(tag.target_id(), rewritten)? {
+            Some(new) => new,
+            None => return Ok(None),
+        };
+        if new == tag.target_id() {
+            return Ok(Some(target));
+        }
+        let new = self.repo.find_object(new, None)?;
+        let tagger = match tag.tagger() {
+            Some(tagger) => tagger.to_owned(),
+            None => self.repo.signature()?,
+        };
+        let name = tag.name().ok_or_else(|| {
+            Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Tag,
+                format!("tag {} has no name", target),
+            )
+        })?;
+        let message = tag.message().unwrap_or("");
+        self.repo
+            .tag_annotation_create(name, &new, &tagger, message)
+            .map(Some)
+    }
+}
+
+/// Create the commit described by `rewrite`, keeping its message as bytes
+/// along with their encoding.
+fn create_commit(
+    repo: &Repository,
+    rewrite: &CommitRewrite<'_>,
+    tree: &Tree<'_>,
+    parents: &[Commit<'_>],
+) -> Result<Oid, Error> {
+    let mut parent_ptrs = parents
+        .iter()
+        .map(|p| p.raw() as *const raw::git_commit)
+        .collect::<Vec<_>>();
+    let encoding = crate::opt_cstr(rewrite.encoding.as_deref())?;
+    let message = CString::new(&rewrite.message[..])?;
+    let mut raw = raw::git_oid {
+        id: [0; raw::GIT_OID_RAWSZ],
+    };
+    unsafe {
+        try_call!(raw::git_commit_create(
+            &mut raw,
+            repo.raw(),
+            ptr::null(),
+            rewrite.author.raw(),
+            rewrite.committer.raw(),
+            encoding,
+            message,
+            tree.raw(),
+            parents.len() as size_t,
+            parent_ptrs.as_mut_ptr()
+        ));
+        Ok(Binding::from_raw(&raw as *const _))
+    }
+}
+
+/// The `FileMode` of a tree entry with the raw mode `mode`.
+fn file_mode(mode: i32) -> FileMode {
+    match mode {
+        m if m == i32::from(FileMode::BlobExecutable) => FileMode::BlobExecutable,
+        m if m == i32::from(FileMode::BlobGroupWritable) => FileMode::BlobGroupWritable,
+        m if m == i32::from(FileMode::Link) => FileMode::Link,
+        m if m == i32::from(FileMode::Commit) => FileMode::Commit,
+        m if m == i32::from(FileMode::Tree) => FileMode::Tree,
+        _ => FileMode::Blob,
+    }
+}
+
+impl<'a> CommitRewrite<'a> {
+    /// The original commit.
+    pub fn original(&self) -> &Commit<'a> {
+        self.commit
+    }
+
+    /// The author of the rewritten commit.
+    pub fn author(&self) -> &Signature<'static> {
+        &self.author
+    }
+
+    /// Set the author of the rewritten commit.
+    pub fn set_author(&mut self, author: &Signature<'_>) -> &mut Self {
+        self.author = author.to_owned();
+        self.modified = true;
+        self
+    }
+
+    /// The committer of the rewritten commit.
+    pub fn committer(&self) -> &Signature<'static> {
+        &self.committer
+    }
+
+    /// Set the committer of the rewritten commit.
+    pub fn set_committer(&mut self, committer: &Signature<'_>) -> &mut Self {
+        self.committer = committer.to_owned();
+        self.modified = true;
+        self
+    }
+
+    /// The message of the rewritten commit.
+    ///
+    /// Returns `None` if the message is not valid utf-8, as may be the case
+    /// for a message in another [encoding](CommitRewrite::message_encoding).
+    pub fn message(&self) -> Option<&str> {
+        str::from_utf8(&self.message).ok()
+    }
+
+    /// The message of the rewritten commit, as bytes.
+    pub fn message_bytes(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// The encoding of the message of the rewritten commit, or `None` for
+    /// utf-8.
+    pub fn message_encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+
+    /// Set the message of the rewritten commit, which is then encoded in
+    /// utf-8.
+    pub fn set_message(&mut self, message: &str) -> &mut Self {
+        self.message = message.as_bytes().to_vec();
+        self.encoding = None;
+        self.modified = true;
+        self
+    }
+
+    /// Set the message of the rewritten commit as bytes in `encoding`, or
+    /// in utf-8 if it is `None`.
+    pub fn set_message_bytes(&mut self, message: &[u8], encoding: Option<&str>) -> &mut Self {
+        self.message = message.to_vec();
+        self.encoding = encoding.map(str::to_string);
+        self.modified = true;
+        self
+    }
+
+    /// Drop the commit from the history. Its children get its parents
+    /// instead.
+    pub fn drop_commit(&mut self) -> &mut Self {
+        self.dropped = true;
+        self
+    }
+}
+
+impl CommitMap {
+    fn insert(&mut self, old: Oid, new: Option<Oid>) {
+        self.positions.insert(old, self.entries.len());
+        self.entries.push((old, new));
+    }
+
+    /// How the commit `old` was rewritten: `None` if it was not part of the
+    /// rewritten history, `Some(None)` if it was dropped.
+    pub fn get(&self, old: Oid) -> Option<Option<Oid>> {
+        self.positions.get(&old).map(|i| self.entries[*i].1)
+    }
+
+    /// The original and rewritten commits, parents first.
+    pub fn iter(&self) -> impl Iterator<Item = (Oid, Option<Oid>)> + '_ {
+        self.entries.iter().cloned()
+    }
+
+    /// The number of commits which were rewritten.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no commit was rewritten.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the map to `path` in the `commit-map` format of
+    /// `git filter-repo`, where dropped commits map to the zero id.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut contents = format!("{:<40} new\n", "old");
+        for (old, new) in self.entries.iter() {
+            let new = new.unwrap_or_else(Oid::zero);
+            contents.push_str(&format!("{} {}\n", old, new));
+        }
+        fs::write(path, contents).map_err(|e| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to write commit map: {}", e),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::commit_files;
+    use crate::{HistoryRewriter, Oid, Repository, Signature};
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_files(&repo, &[("a", Some("a\n"))], "add a");
+        let secret = commit_files(&repo, &[("secret", Some("hunter2\n"))], "add secret");
+        let config = commit_files(
+            &repo,
+            &[("config", Some("password=hunter2\n"))],
+            "add config",
+        );
+        commit_files(&repo, &[("secret", None), ("b/c", Some("c\n"))], "add c");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tagger = repo.signature().unwrap();
+        repo.tag("v1", head.as_object(), &tagger, "v1", false)
+            .unwrap();
+        repo.tag_lightweight("old", &repo.find_object(a, None).unwrap(), false)
+            .unwrap();
+
+        let new_author = Signature::now("New", "new@example.com").unwrap();
+        let mut rewriter = HistoryRewriter::new(&repo);
+        rewriter
+            .push_ref("HEAD")
+            .unwrap()
+            .push_glob("refs/tags/*")
+            .unwrap()
+            .hide(base)
+            .prune_empty(true)
+            .backup("refs/original")
+            .path_callback(|path| match path.to_str() {
+                Some("secret") => None,
+                Some("b/c") => Some(PathBuf::from("c")),
+                _ => Some(path.to_path_buf()),
+            })
+            .blob_callback(|_, blob| {
+                let contents = String::from_utf8_lossy(blob.content());
+                Ok(Some(contents.replace("hunter2", "***").into_bytes())
+                    .filter(|c| c != blob.content()))
+            })
+            .commit_callback(|commit| {
+                if commit.original().id() == a {
+                    commit.set_author(&new_author);
+                }
+                Ok(())
+            });
+        let map = rewriter.rewrite().unwrap();
+
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(base), None);
+        assert_eq!(map.get(secret), Some(None));
+        let new_a = map.get(a).unwrap().unwrap();
+        assert_ne!(new_a, a);
+        assert_eq!(
+            repo.find_commit(new_a).unwrap().author().name(),
+            Some("New")
+        );
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(map.get(head.id()), None);
+        assert_eq!(head.summary(), Some("add c"));
+        let tree = head.tree().unwrap();
+        assert!(tree.get_name("secret").is_none());
+        assert!(tree.get_name("b").is_none());
+        assert!(tree.get_name("c").is_some());
+        let config_blob = tree.get_name("config").unwrap().id();
+        assert_eq!(
+            repo.find_blob(config_blob).unwrap().content(),
+            b"password=***\n"
+        );
+        let parent = head.parent(0).unwrap();
+        assert_eq!(map.get(config), Some(Some(parent.id())));
+        assert_eq!(parent.parent_id(0).unwrap(), new_a);
+
+        let backup = repo
+            .find_reference("refs/original/refs/heads/main")
+            .unwrap();
+        assert_ne!(backup.target(), Some(head.id()));
+        let tag = repo
+            .find_reference("refs/tags/v1")
+            .unwrap()
+            .peel_to_tag()
+            .unwrap();
+        assert_eq!(tag.target_id(), head.id());
+        assert_eq!(repo.refname_to_id("refs/tags/old").unwrap(), new_a);
+
+        let path = td.path().join("commit-map");
+        map.write(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(lines.next().unwrap(), format!("{:<40} new", "old"));
+        assert_eq!(lines.next().unwrap(), format!("{} {}", a, new_a));
+        assert_eq!(lines.next().unwrap(), format!("{} {}", secret, Oid::zero()));
+    }
+
+    #[test]
+    fn raw_messages_and_modes() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let odb = repo.odb().unwrap();
+        // libgit2 refuses to write the group writable mode of old git.
+        let blob = repo.blob(b"hunter2\n").unwrap();
+        let mut buf = b"100664 shared\0".to_vec();
+        buf.extend_from_slice(blob.as_bytes());
+        let tree = odb.write(crate::ObjectType::Tree, &buf).unwrap();
+        let mut buf = format!(
+            "tree {}\nparent {}\nauthor A <a@example.com> 0 +0000\n\
+             committer A <a@example.com> 0 +0000\nencoding ISO-8859-1\n\n",
+            tree,
+            head.id()
+        )
+        .into_bytes();
+        buf.extend_from_slice(b"caf\xe9\n");
+        let id = odb.write(crate::ObjectType::Commit, &buf).unwrap();
+        repo.reference("refs/heads/main", id, true, "").unwrap();
+
+        let new_author = Signature::now("New", "new@example.com").unwrap();
+        let mut rewriter = HistoryRewriter::new(&repo);
+        rewriter
+            .push_ref("refs/heads/main")
+            .unwrap()
+            .blob_callback(|_, _| Ok(Some(b"***\n".to_vec())))
+            .commit_callback(|commit| {
+                if commit.original().id() == id {
+                    assert_eq!(commit.message(), None);
+                    commit.set_author(&new_author);
+                }
+                Ok(())
+            });
+        let map = rewriter.rewrite().unwrap();
+
+        let new = repo.find_commit(map.get(id).unwrap().unwrap()).unwrap();
+        assert_eq!(new.author().name(), Some("New"));
+        assert_eq!(new.message_raw_bytes(), b"caf\xe9\n");
+        assert_eq!(new.message_encoding(), Some("ISO-8859-1"));
+        let entry = new.tree().unwrap().get_name("shared").unwrap().to_owned();
+        assert_eq!(repo.find_blob(entry.id()).unwrap().content(), b"***\n");
+    }
+
+    #[test]
+    fn unchanged() {
+        let (_td, repo) = crate::test::repo_init();
+        let a = commit_files(&repo, &[("a", Some("a\n"))], "add a");
+        let mut rewriter = HistoryRewriter::new(&repo);
+        rewriter.push_ref("refs/heads/main").unwrap();
+        let map = rewriter.rewrite().unwrap();
+        assert_eq!(map.get(a), Some(Some(a)));
+        assert_eq!(repo.head().unwrap().target(), Some(a));
+    }
+
+    #[test]
+    fn raw_paths_and_moved_refs() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let odb = repo.odb().unwrap();
+        let blob = repo.blob(b"hunter2\n").unwrap();
+        let mut buf = b"100644 secret\0".to_vec();
+        buf.extend_from_slice(blob.as_bytes());
+        let dir = odb.write(crate::ObjectType::Tree, &buf).unwrap();
+        let mut buf = b"40000 caf\xe9\0".to_vec();
+        buf.extend_from_slice(dir.as_bytes());
+        let tree = repo.find_tree(odb.write(crate::ObjectType::Tree, &buf).unwrap());
+        let tree = tree.unwrap();
+        let sig = repo.signature().unwrap();
+        let id = repo
+            .commit(Some("HEAD"), &sig, &sig, "raw", &tree, &[&head])
+            .unwrap();
+
+        // Paths which are not UTF-8 reach the callbacks as they are.
+        let mut seen = Vec::new();
+        let mut rewriter = HistoryRewriter::new(&repo);
+        rewriter
+            .push_ref("refs/heads/main")
+            .unwrap()
+            .hide(head.id())
+            .path_callback(|path| {
+                seen.push(path.to_path_buf());
+                Some(path.to_path_buf())
+            })
+            .blob_callback(|_, _| Ok(Some(b"***\n".to_vec())));
+        let map = rewriter.rewrite().unwrap();
+        drop(rewriter);
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let path = std::ffi::OsStr::from_bytes(b"caf\xe9/secret");
+            assert_eq!(seen, [PathBuf::from(path)]);
+        }
+        let new = map.get(id).unwrap().unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(new));
+        let tree = repo.find_commit(new).unwrap().tree().unwrap();
+        assert_eq!(tree.iter().next().unwrap().name_bytes(), b"caf\xe9");
+
+        // A reference moved while the history was rewritten is left alone.
+        let mut rewriter = HistoryRewriter::new(&repo);
+        rewriter
+            .push_ref("refs/heads/main")
+            .unwrap()
+            .hide(head.id())
+            .blob_callback(|_, _| {
+                let repo = Repository::open(repo.path()).unwrap();
+                repo.reference("refs/heads/main", head.id(), true, "moved")
+                    .unwrap();
+                Ok(Some(b"###\n".to_vec()))
+            });
+        let err = rewriter.rewrite().unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::Modified);
+        assert_eq!(repo.head().unwrap().target(), Some(head.id()));
+    }
+}
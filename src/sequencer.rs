@@ -0,0 +1,712 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::build::CheckoutBuilder;
+use crate::merge;
+use crate::{CherrypickOptions, Config, Error, ErrorClass, ErrorCode, MergeOptions, Oid};
+use crate::{Repository, RepositoryState, ResetType, RevertOptions};
+
+/// Options for cherry-picking or reverting a series of commits with a
+/// [`Sequencer`].
+pub struct SequencerOptions {
+    record_origin: bool,
+    mainline: u32,
+    keep_redundant_commits: bool,
+    merge_opts: Option<MergeOptions>,
+}
+
+/// A single commit to cherry-pick or revert.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequencerStep {
+    /// Cherry-pick the commit.
+    Pick(Oid),
+    /// Revert the commit.
+    Revert(Oid),
+}
+
+/// Why [`Sequencer::run`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequencerStatus {
+    /// Every commit has been applied and the sequence is over.
+    Complete,
+    /// The step could not be applied without conflicts. Resolve them in the
+    /// index and run again to commit the result, or skip the step.
+    Conflict(SequencerStep),
+    /// The step changes nothing, because its changes are already there.
+    /// Run again to commit it anyway, as an empty commit, or skip the step.
+    ///
+    /// Only returned if redundant commits are not kept.
+    Empty(SequencerStep),
+}
+
+/// A series of cherry-picks or reverts, like `git cherry-pick A..B` and
+/// `git revert A B`.
+///
+/// Created by [`Repository::cherrypick_commits`] and
+/// [`Repository::revert_commits`], or resumed with
+/// [`Repository::open_sequencer`]. The state of the sequence is kept in
+/// `.git/sequencer` in the format git uses, so a sequence stopped by a
+/// conflict can be continued by either this library or git.
+pub struct Sequencer<'repo> {
+    repo: &'repo Repository,
+    head: Oid,
+    todo: Vec<SequencerStep>,
+    /// The subjects of the commits of `todo`, which the todo file lists.
+    subjects: Vec<String>,
+    opts: SequencerOptions,
+}
+
+impl SequencerOptions {
+    /// Creates a default set of options.
+    pub fn new() -> SequencerOptions {
+        SequencerOptions {
+            record_origin: false,
+            mainline: 0,
+            keep_redundant_commits: false,
+            merge_opts: None,
+        }
+    }
+
+    /// Append a `(cherry picked from commit ...)` line to the message of
+    /// cherry-picked commits, like `git cherry-pick -x`.
+    ///
+    /// Defaults to `false`.
+    pub fn record_origin(&mut self, record: bool) -> &mut SequencerOptions {
+        self.record_origin = record;
+        self
+    }
+
+    /// Set the parent, starting at 1, relative to which merge commits are
+    /// cherry-picked or reverted.
+    pub fn mainline(&mut self, mainline: u32) -> &mut SequencerOptions {
+        self.mainline = mainline;
+        self
+    }
+
+    /// Commit the result of steps even if it does not change anything.
+    ///
+    /// By default the sequence stops at such steps with
+    /// [`SequencerStatus::Empty`].
+    pub fn keep_redundant_commits(&mut self, keep: bool) -> &mut SequencerOptions {
+        self.keep_redundant_commits = keep;
+        self
+    }
+
+    /// Set the options used to merge the changes of each commit.
+    ///
+    /// Unlike the other options, these are not recorded in
+    /// `.git/sequencer`, and are lost when the sequence is reopened.
+    pub fn merge_opts(&mut self, merge_opts: MergeOptions) -> &mut SequencerOptions {
+        self.merge_opts = Some(merge_opts);
+        self
+    }
+}
+
+impl Default for SequencerOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for SequencerOptions {
+    fn clone(&self) -> SequencerOptions {
+        SequencerOptions {
+            record_origin: self.record_origin,
+            mainline: self.mainline,
+            keep_redundant_commits: self.keep_redundant_commits,
+            merge_opts: self.merge_opts.clone(),
+        }
+    }
+}
+
+impl SequencerStep {
+    /// The commit applied by this step.
+    pub fn id(&self) -> Oid {
+        match *self {
+            SequencerStep::Pick(id) | SequencerStep::Revert(id) => id,
+        }
+    }
+}
+
+/// Formats the step as a line of a sequencer todo file, without the
+/// subject of the commit.
+impl fmt::Display for SequencerStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SequencerStep::Pick(id) => write!(f, "pick {}", id),
+            SequencerStep::Revert(id) => write!(f, "revert {}", id),
+        }
+    }
+}
+
+impl<'repo> Sequencer<'repo> {
+    /// Start a sequence of `todo` on top of `HEAD`.
+    pub(crate) fn start(
+        repo: &'repo Repository,
+        todo: Vec<SequencerStep>,
+        opts: Option<&SequencerOptions>,
+    ) -> Result<Sequencer<'repo>, Error> {
+        if repo.state() != RepositoryState::Clean || dir(repo).exists() {
+            return Err(Error::new(
+                ErrorCode::Locked,
+                ErrorClass::CherryPick,
+                "a cherry-pick or revert is already in progress",
+            ));
+        }
+        let head = repo.head()?.peel_to_commit()?.id();
+        let subjects = todo
+            .iter()
+            .map(|step| {
+                let commit = repo.find_commit(step.id())?;
+                Ok(commit.summary().unwrap_or("").to_string())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let sequencer = Sequencer {
+            repo,
+            head,
+            todo,
+            subjects,
+            opts: opts.cloned().unwrap_or_default(),
+        };
+        sequencer.write_state()?;
+        sequencer.write_abort_safety(head)?;
+        sequencer.write_opts()?;
+        Ok(sequencer)
+    }
+
+    /// Open the sequence in progress, started by this library or by git.
+    pub(crate) fn open(repo: &'repo Repository) -> Result<Sequencer<'repo>, Error> {
+        let dir = dir(repo);
+        if !dir.join("todo").exists() {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::CherryPick,
+                "no cherry-pick or revert in progress",
+            ));
+        }
+        let head = read(&dir.join("head"))?;
+        let head = Oid::from_str(head.trim())?;
+
+        let mut todo = Vec::new();
+        let mut subjects = Vec::new();
+        for line in read(&dir.join("todo"))?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.splitn(3, char::is_whitespace);
+            let command = words.next().unwrap_or("");
+            let id = words.next().unwrap_or("");
+            subjects.push(words.next().unwrap_or("").trim_start().to_string());
+            let id = repo.revparse_single(id)?.peel_to_commit()?.id();
+            todo.push(match command {
+                "pick" | "p" => SequencerStep::Pick(id),
+                "revert" | "r" => SequencerStep::Revert(id),
+                _ => {
+                    return Err(Error::new(
+                        ErrorCode::Invalid,
+                        ErrorClass::CherryPick,
+                        format!("invalid line in sequencer todo: '{}'", line),
+                    ))
+                }
+            });
+        }
+
+        let mut opts = SequencerOptions::new();
+        let path = dir.join("opts");
+        if path.exists() {
+            let config = Config::open(&path)?;
+            let get_bool = |name: &str| config.get_bool(name).unwrap_or(false);
+            opts.record_origin(get_bool("options.record-origin"));
+            opts.keep_redundant_commits(get_bool("options.keep-redundant-commits"));
+            opts.mainline(config.get_i32("options.mainline").unwrap_or(0) as u32);
+        }
+        Ok(Sequencer {
+            repo,
+            head,
+            todo,
+            subjects,
+            opts,
+        })
+    }
+
+    /// The steps which have not been applied yet, including the one which
+    /// stopped with conflicts, if any.
+    pub fn steps(&self) -> &[SequencerStep] {
+        &self.todo
+    }
+
+    /// The commit `HEAD` pointed to when the sequence started.
+    pub fn orig_head(&self) -> Oid {
+        self.head
+    }
+
+    /// Apply steps until the sequence is complete or a step conflicts or
+    /// changes nothing, like `git cherry-pick --continue`.
+    ///
+    /// If the sequence stopped at a step, the resolved index is committed
+    /// first with the message in `MERGE_MSG`, even if it changes nothing; it
+    /// is an error if conflicts remain. If the resolution was already committed, the
+    /// sequence simply moves on to the next step.
+    #[doc(alias = "continue")]
+    pub fn run(&mut self) -> Result<SequencerStatus, Error> {
+        self.resume()?;
+        while let Some(&step) = self.todo.first() {
+            if let Some(status) = self.apply(step)? {
+                self.write_state()?;
+                return Ok(status);
+            }
+            self.finish_step();
+            self.write_state()?;
+        }
+        self.remove_state()?;
+        Ok(SequencerStatus::Complete)
+    }
+
+    /// Discard the changes of the step which stopped with conflicts, then
+    /// continue with the next ones, like `git cherry-pick --skip`.
+    pub fn skip(&mut self) -> Result<SequencerStatus, Error> {
+        if self.pick_head()?.is_none() {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::CherryPick,
+                "there is no cherry-pick or revert to skip",
+            ));
+        }
+        // A hard reset would clean up the state of the sequence as well.
+        let head = self.repo.head()?.peel_to_commit()?;
+        let mut index = self.repo.index()?;
+        index.read_tree(&head.tree()?)?;
+        index.write()?;
+        self.repo
+            .checkout_index(Some(&mut index), Some(CheckoutBuilder::new().force()))?;
+        self.remove_pick_state()?;
+        if !self.todo.is_empty() {
+            self.finish_step();
+        }
+        self.write_state()?;
+        self.run()
+    }
+
+    /// Abandon the sequence, resetting `HEAD` to where it was when the
+    /// sequence started, like `git cherry-pick --abort`.
+    ///
+    /// This fails with [`ErrorCode::Modified`] if `HEAD` was moved since
+    /// the last step, in which case it is left alone.
+    pub fn abort(&mut self) -> Result<(), Error> {
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        if head != self.abort_safety()? {
+            return Err(Error::new(
+                ErrorCode::Modified,
+                ErrorClass::CherryPick,
+                "HEAD has moved since the last step of the sequence, not rewinding",
+            ));
+        }
+        let orig = self.repo.find_object(self.head, None)?;
+        self.repo.reset(&orig, ResetType::Hard, None)?;
+        self.remove_pick_state()?;
+        self.remove_state()
+    }
+
+    /// Commit the resolution of a conflicted step, if it has not been
+    /// committed already.
+    fn resume(&mut self) -> Result<(), Error> {
+        let step = match self.todo.first() {
+            Some(&step) => step,
+            None => return Ok(()),
+        };
+        if self.pick_head()?.is_some() {
+            if self.repo.index()?.has_conflicts() {
+                return Err(Error::new(
+                    ErrorCode::Unmerged,
+                    ErrorClass::CherryPick,
+                    "conflicts must be resolved before continuing",
+                ));
+            }
+            let message = read(&self.repo.path().join("MERGE_MSG"))?;
+            self.commit(step, &cleanup_message(&message), true)?;
+            self.remove_pick_state()?;
+        } else if self.repo.head()?.peel_to_commit()?.id() == self.abort_safety()? {
+            // The step has not been started yet.
+            return Ok(());
+        }
+        self.finish_step();
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        self.write_abort_safety(head)?;
+        self.write_state()
+    }
+
+    /// Drop the first step, which is done or skipped.
+    fn finish_step(&mut self) {
+        self.todo.remove(0);
+        self.subjects.remove(0);
+    }
+
+    /// Apply a step, returning why the sequence must stop, if it must.
+    fn apply(&mut self, step: SequencerStep) -> Result<Option<SequencerStatus>, Error> {
+        let commit = self.repo.find_commit(step.id())?;
+        let message = match step {
+            SequencerStep::Pick(id) => {
+                let mut opts = CherrypickOptions::new();
+                opts.mainline(self.opts.mainline);
+                if let Some(ref merge_opts) = self.opts.merge_opts {
+                    opts.merge_opts(merge_opts.clone());
+                }
+                self.repo.cherrypick(&commit, Some(&mut opts))?;
+                let message = commit.message_raw().unwrap_or("");
+                if self.opts.record_origin {
+                    record_origin(message, id)
+                } else {
+                    message.to_string()
+                }
+            }
+            SequencerStep::Revert(_) => {
+                let mut opts = RevertOptions::new();
+                opts.mainline(self.opts.mainline);
+                if let Some(ref merge_opts) = self.opts.merge_opts {
+                    opts.merge_opts(merge_opts.clone());
+                }
+                self.repo.revert(&commit, Some(&mut opts))?;
+                read(&self.repo.path().join("MERGE_MSG"))?
+            }
+        };
+
+        let index = self.repo.index()?;
+        if index.has_conflicts() {
+            let message = message + &merge::conflicts_comment(&index)?;
+            write(&self.repo.path().join("MERGE_MSG"), &message)?;
+            return Ok(Some(SequencerStatus::Conflict(step)));
+        }
+        if !self.commit(step, &message, self.opts.keep_redundant_commits)? {
+            write(&self.repo.path().join("MERGE_MSG"), &message)?;
+            return Ok(Some(SequencerStatus::Empty(step)));
+        }
+        self.remove_pick_state()?;
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        self.write_abort_safety(head)?;
+        Ok(None)
+    }
+
+    /// Commit the index for `step` on top of `HEAD`, unless it changes
+    /// nothing and `allow_empty` is false. Returns whether it was committed.
+    fn commit(&self, step: SequencerStep, message: &str, allow_empty: bool) -> Result<bool, Error> {
+        let mut index = self.repo.index()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let head = self.repo.head()?.peel_to_commit()?;
+        if tree.id() == head.tree_id() && !allow_empty {
+            return Ok(false);
+        }
+        let committer = self.repo.signature()?;
+        let author = match step {
+            SequencerStep::Pick(id) => self.repo.find_commit(id)?.author().to_owned(),
+            SequencerStep::Revert(_) => committer.clone(),
+        };
+        self.repo
+            .commit(Some("HEAD"), &author, &committer, message, &tree, &[&head])?;
+        Ok(true)
+    }
+
+    /// The commit being cherry-picked or reverted, if a step stopped.
+    fn pick_head(&self) -> Result<Option<Oid>, Error> {
+        for name in ["CHERRY_PICK_HEAD", "REVERT_HEAD"].iter() {
+            let path = self.repo.path().join(name);
+            if path.exists() {
+                return Ok(Some(Oid::from_str(read(&path)?.trim())?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The commit `HEAD` pointed to after the last step.
+    fn abort_safety(&self) -> Result<Oid, Error> {
+        let path = dir(self.repo).join("abort-safety");
+        match fs::read_to_string(path) {
+            Ok(id) => Oid::from_str(id.trim()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(self.head),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    fn write_abort_safety(&self, head: Oid) -> Result<(), Error> {
+        write(&dir(self.repo).join("abort-safety"), &format!("{}\n", head))
+    }
+
+    /// Record the original head and the remaining steps, with the subject of
+    /// their commits like git does.
+    fn write_state(&self) -> Result<(), Error> {
+        let dir = dir(self.repo);
+        fs::create_dir_all(&dir).map_err(io_error)?;
+        write(&dir.join("head"), &format!("{}\n", self.head))?;
+        let mut todo = String::new();
+        for (step, subject) in self.todo.iter().zip(&self.subjects) {
+            todo.push_str(&format!("{} {}\n", step, subject));
+        }
+        write(&dir.join("todo"), &todo)
+    }
+
+    /// Record the options in `.git/sequencer/opts`, a config file.
+    fn write_opts(&self) -> Result<(), Error> {
+        let path = dir(self.repo).join("opts");
+        write(&path, "")?;
+        let mut config = Config::open(&path)?;
+        if self.opts.record_origin {
+            config.set_bool("options.record-origin", true)?;
+        }
+        if self.opts.keep_redundant_commits {
+            config.set_bool("options.keep-redundant-commits", true)?;
+        }
+        if self.opts.mainline != 0 {
+            config.set_i32("options.mainline", self.opts.mainline as i32)?;
+        }
+        Ok(())
+    }
+
+    fn remove_pick_state(&self) -> Result<(), Error> {
+        for name in ["CHERRY_PICK_HEAD", "REVERT_HEAD", "MERGE_MSG"].iter() {
+            match fs::remove_file(self.repo.path().join(name)) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_state(&self) -> Result<(), Error> {
+        match fs::remove_dir_all(dir(self.repo)) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+}
+
+fn dir(repo: &Repository) -> PathBuf {
+    repo.path().join("sequencer")
+}
+
+fn read(path: &std::path::Path) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(io_error)
+}
+
+fn write(path: &std::path::Path, contents: &str) -> Result<(), Error> {
+    fs::write(path, contents).map_err(io_error)
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Os,
+        format!("failed to access sequencer state: {}", e),
+    )
+}
+
+/// Append the `(cherry picked from commit ...)` line of `git cherry-pick -x`
+/// to `message`, in the trailer block if it ends with one.
+fn record_origin(message: &str, id: Oid) -> String {
+    let mut message = message.trim_end().to_string();
+    let is_trailer = |line: &str| {
+        line.starts_with("(cherry picked from commit ")
+            || line
+                .split_once(": ")
+                .is_some_and(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+    };
+    let has_trailers = match message.rfind("\n\n") {
+        Some(i) => message[i + 2..].lines().all(is_trailer),
+        None => false,
+    };
+    message.push_str(if has_trailers { "\n" } else { "\n\n" });
+    message.push_str(&format!("(cherry picked from commit {})\n", id));
+    message
+}
+
+/// Strip the comments and trailing blank lines of a message edited by the
+/// user, like `git commit --cleanup=strip`.
+fn cleanup_message(message: &str) -> String {
+    let mut cleaned = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    cleaned.truncate(cleaned.trim_end().len());
+    cleaned.push('\n');
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::commit_files;
+    use crate::{Oid, Repository, RepositoryState, SequencerOptions, SequencerStatus};
+    use crate::{SequencerStep, Sort};
+    use std::fs;
+    use std::path::Path;
+
+    /// Create commits `a`, `b` and `c` on a `topic` branch and go back to
+    /// `main`, returning the commits of `main..topic`, oldest first.
+    fn topic(repo: &Repository) -> Vec<Oid> {
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("topic", &head, false).unwrap();
+        repo.set_head("refs/heads/topic").unwrap();
+        commit_files(repo, &[("a", Some("a\n"))], "add a");
+        commit_files(
+            repo,
+            &[("b", Some("b\n"))],
+            "add b\n\nSigned-off-by: A U Thor <a@example.com>",
+        );
+        commit_files(repo, &[("c", Some("c\n"))], "add c");
+        repo.set_head("refs/heads/main").unwrap();
+        repo.checkout_head(Some(crate::build::CheckoutBuilder::new().force()))
+            .unwrap();
+
+        let mut walk = repo.revwalk().unwrap();
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE).unwrap();
+        walk.push_range("main..topic").unwrap();
+        walk.collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let commits = topic(&repo);
+        let mut opts = SequencerOptions::new();
+        opts.record_origin(true);
+        let mut seq = repo.cherrypick_commits(&commits, Some(&opts)).unwrap();
+        assert_eq!(seq.steps().len(), 3);
+        assert_eq!(seq.run().unwrap(), SequencerStatus::Complete);
+        assert!(!repo.path().join("sequencer").exists());
+        assert_eq!(repo.state(), RepositoryState::Clean);
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(
+            head.message(),
+            Some(&*format!(
+                "add c\n\n(cherry picked from commit {})\n",
+                commits[2]
+            ))
+        );
+        let b = head.parent(0).unwrap();
+        assert_eq!(
+            b.message(),
+            Some(&*format!(
+                "add b\n\nSigned-off-by: A U Thor <a@example.com>\n\
+                 (cherry picked from commit {})\n",
+                commits[1]
+            ))
+        );
+
+        let picked = head.id();
+        let mut seq = repo.revert_commits(&[picked, b.id()], None).unwrap();
+        assert_eq!(seq.run().unwrap(), SequencerStatus::Complete);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.summary(), Some("Revert \"add b\""));
+        assert_eq!(head.parent(0).unwrap().summary(), Some("Revert \"add c\""));
+        assert!(head.tree().unwrap().get_name("b").is_none());
+        assert!(head.tree().unwrap().get_name("a").is_some());
+    }
+
+    #[test]
+    fn conflicts() {
+        let (td, repo) = crate::test::repo_init();
+        let commits = topic(&repo);
+        commit_files(&repo, &[("b", Some("main\n"))], "add b on main");
+
+        let mut seq = repo.cherrypick_commits(&commits, None).unwrap();
+        let status = seq.run().unwrap();
+        assert_eq!(
+            status,
+            SequencerStatus::Conflict(SequencerStep::Pick(commits[1]))
+        );
+        assert_eq!(repo.state(), RepositoryState::CherryPickSequence);
+        let todo = fs::read_to_string(repo.path().join("sequencer/todo")).unwrap();
+        assert_eq!(
+            todo,
+            format!("pick {} add b\npick {} add c\n", commits[1], commits[2])
+        );
+        assert!(seq.run().is_err());
+
+        // Continue with a reopened sequencer, as git would.
+        fs::write(td.path().join("b"), "resolved\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("b")).unwrap();
+        index.write().unwrap();
+        let mut seq = repo.open_sequencer().unwrap();
+        assert_eq!(seq.steps().len(), 2);
+        assert_eq!(seq.run().unwrap(), SequencerStatus::Complete);
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.summary(), Some("add c"));
+        let b = head.parent(0).unwrap();
+        assert_eq!(b.summary(), Some("add b"));
+        assert_eq!(
+            b.message(),
+            Some("add b\n\nSigned-off-by: A U Thor <a@example.com>\n")
+        );
+        assert_eq!(repo.state(), RepositoryState::Clean);
+    }
+
+    #[test]
+    fn empty() {
+        let (_td, repo) = crate::test::repo_init();
+        let commits = topic(&repo);
+        commit_files(&repo, &[("a", Some("a\n"))], "add a on main");
+        let main = repo.head().unwrap().target().unwrap();
+
+        // A step whose changes are already there stops the sequence, and
+        // running again commits it as it is.
+        let mut seq = repo.cherrypick_commits(&commits, None).unwrap();
+        let status = seq.run().unwrap();
+        assert_eq!(
+            status,
+            SequencerStatus::Empty(SequencerStep::Pick(commits[0]))
+        );
+        assert_eq!(repo.head().unwrap().target(), Some(main));
+        assert_eq!(seq.run().unwrap(), SequencerStatus::Complete);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let a = head.parent(0).unwrap().parent(0).unwrap();
+        assert_eq!(a.summary(), Some("add a"));
+        assert_eq!(a.tree_id(), repo.find_commit(main).unwrap().tree_id());
+
+        // Or it is skipped.
+        repo.reset(
+            &repo.find_object(main, None).unwrap(),
+            crate::ResetType::Hard,
+            None,
+        )
+        .unwrap();
+        let mut seq = repo.cherrypick_commits(&commits, None).unwrap();
+        assert!(matches!(seq.run().unwrap(), SequencerStatus::Empty(_)));
+        assert_eq!(seq.skip().unwrap(), SequencerStatus::Complete);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.parent(0).unwrap().summary(), Some("add b"));
+        assert_eq!(head.parent(0).unwrap().parent_id(0).unwrap(), main);
+        assert_eq!(repo.state(), RepositoryState::Clean);
+    }
+
+    #[test]
+    fn skip_and_abort() {
+        let (td, repo) = crate::test::repo_init();
+        let commits = topic(&repo);
+        commit_files(&repo, &[("b", Some("main\n"))], "add b on main");
+
+        let mut seq = repo.cherrypick_commits(&commits, None).unwrap();
+        assert!(matches!(seq.run().unwrap(), SequencerStatus::Conflict(_)));
+        assert_eq!(seq.skip().unwrap(), SequencerStatus::Complete);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.summary(), Some("add c"));
+        assert_eq!(head.parent(0).unwrap().summary(), Some("add a"));
+        assert_eq!(fs::read_to_string(td.path().join("b")).unwrap(), "main\n");
+
+        let head = head.id();
+        let mut seq = repo.cherrypick_commits(&commits[1..], None).unwrap();
+        assert!(repo.cherrypick_commits(&commits, None).is_err());
+        assert!(matches!(seq.run().unwrap(), SequencerStatus::Conflict(_)));
+        seq.abort().unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(head));
+        assert_eq!(repo.state(), RepositoryState::Clean);
+        assert!(!repo.path().join("sequencer").exists());
+        assert_eq!(fs::read_to_string(td.path().join("b")).unwrap(), "main\n");
+    }
+}
@@ -0,0 +1,1130 @@
+use libc::{c_char, c_int, c_void};
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, process, ptr};
+
+use crate::util::Binding;
+use crate::{panic, raw, Buf, Config, Error, ErrorClass, ErrorCode, Oid, Signature};
+
+/// A type which produces detached signatures of commit and tag objects.
+///
+/// Implementations are provided which sign with GnuPG ([`GpgSigner`]) and
+/// with SSH keys ([`SshSigner`]); [`Repository::signer`] picks one the way
+/// `git commit -S` does, according to the repository's configuration.
+///
+/// [`Repository::signer`]: crate::Repository::signer
+pub trait Signer {
+    /// Sign `content`, the serialized object without its signature, and
+    /// return the ASCII-armored signature to embed in the object.
+    fn sign(&self, content: &[u8]) -> Result<String, Error>;
+}
+
+/// A [`Signer`] which invokes `gpg` (or `gpgsm` for X.509 certificates).
+#[derive(Clone, Debug)]
+pub struct GpgSigner {
+    program: PathBuf,
+    key: Option<String>,
+}
+
+/// A [`Signer`] which invokes `ssh-keygen -Y sign`.
+#[derive(Clone, Debug)]
+pub struct SshSigner {
+    program: PathBuf,
+    key: String,
+}
+
+impl GpgSigner {
+    /// Creates a signer running `gpg` with its default secret key.
+    pub fn new() -> GpgSigner {
+        GpgSigner {
+            program: PathBuf::from("gpg"),
+            key: None,
+        }
+    }
+
+    /// Creates a signer from the `gpg.openpgp.program` (or `gpg.program`)
+    /// and `user.signingkey` configuration values.
+    pub fn from_config(config: &Config) -> Result<GpgSigner, Error> {
+        let mut signer = GpgSigner::new();
+        if let Some(program) = config_path(config, &["gpg.openpgp.program", "gpg.program"])? {
+            signer.program = program;
+        }
+        signer.key = config_string(config, "user.signingkey")?;
+        Ok(signer)
+    }
+
+    /// Creates a signer for X.509 certificates from the `gpg.x509.program`
+    /// and `user.signingkey` configuration values, running `gpgsm` by
+    /// default.
+    pub fn x509_from_config(config: &Config) -> Result<GpgSigner, Error> {
+        let mut signer = GpgSigner::new();
+        signer.program =
+            config_path(config, &["gpg.x509.program"])?.unwrap_or_else(|| PathBuf::from("gpgsm"));
+        signer.key = config_string(config, "user.signingkey")?;
+        Ok(signer)
+    }
+
+    /// Set the program to run instead of `gpg`.
+    pub fn program<P: AsRef<Path>>(&mut self, program: P) -> &mut GpgSigner {
+        self.program = program.as_ref().to_path_buf();
+        self
+    }
+
+    /// Set the key to sign with, as understood by `gpg --local-user`.
+    pub fn key(&mut self, key: &str) -> &mut GpgSigner {
+        self.key = Some(key.to_string());
+        self
+    }
+}
+
+impl Default for GpgSigner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signer for GpgSigner {
+    fn sign(&self, content: &[u8]) -> Result<String, Error> {
+        let mut cmd = Command::new(&self.program);
+        cmd.arg("--status-fd=2");
+        match self.key {
+            Some(ref key) => cmd.arg("-bsau").arg(key),
+            None => cmd.arg("-bsa"),
+        };
+        let (stdout, stderr) = run(&mut cmd, content)?;
+        if !stderr.contains("\n[GNUPG:] SIG_CREATED ")
+            && !stderr.starts_with("[GNUPG:] SIG_CREATED ")
+        {
+            return Err(signing_error(format!(
+                "{} failed to sign the data: {}",
+                self.program.display(),
+                stderr.trim()
+            )));
+        }
+        Ok(stdout)
+    }
+}
+
+impl SshSigner {
+    /// Creates a signer using the given key.
+    ///
+    /// The key is either the path of a private key, the path of a public key
+    /// whose private key is held by `ssh-agent`, or a public key itself,
+    /// optionally prefixed with `key::`, again held by the agent.
+    pub fn new(key: &str) -> SshSigner {
+        SshSigner {
+            program: PathBuf::from("ssh-keygen"),
+            key: key.to_string(),
+        }
+    }
+
+    /// Creates a signer from the `gpg.ssh.program` and `user.signingkey`
+    /// configuration values.
+    pub fn from_config(config: &Config) -> Result<SshSigner, Error> {
+        let key = config_string(config, "user.signingkey")?.ok_or_else(|| {
+            Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Config,
+                "user.signingkey needs to be set for ssh signing",
+            )
+        })?;
+        let mut signer = SshSigner::new(&key);
+        if let Some(program) = config_path(config, &["gpg.ssh.program"])? {
+            signer.program = program;
+        }
+        Ok(signer)
+    }
+
+    /// Set the program to run instead of `ssh-keygen`.
+    pub fn program<P: AsRef<Path>>(&mut self, program: P) -> &mut SshSigner {
+        self.program = program.as_ref().to_path_buf();
+        self
+    }
+}
+
+impl Signer for SshSigner {
+    fn sign(&self, content: &[u8]) -> Result<String, Error> {
+        let literal = self
+            .key
+            .strip_prefix("key::")
+            .or_else(|| Some(self.key.as_str()).filter(|k| k.starts_with("ssh-")));
+        let key_file = match literal {
+            Some(key) => Some(TempFile::new("pub", key.as_bytes())?),
+            None => None,
+        };
+
+        let mut cmd = Command::new(&self.program);
+        cmd.args(["-Y", "sign", "-n", "git", "-f"]);
+        match key_file {
+            Some(ref file) => cmd.arg(&file.path).arg("-U"),
+            None => cmd.arg(expand_home(&self.key)),
+        };
+        let (stdout, stderr) = run(&mut cmd, content)?;
+        if !stdout.contains("-----BEGIN SSH SIGNATURE-----") {
+            return Err(signing_error(format!(
+                "{} failed to sign the data: {}",
+                self.program.display(),
+                stderr.trim()
+            )));
+        }
+        Ok(stdout)
+    }
+}
+
+/// A type which checks the signatures of commit and tag objects.
+///
+/// Implementations are provided which check signatures with GnuPG
+/// ([`GpgVerifier`]) and against an SSH allowed signers file
+/// ([`SshVerifier`]); [`Repository::verifier`] combines them according to
+/// the repository's configuration.
+///
+/// [`Repository::verifier`]: crate::Repository::verifier
+pub trait Verifier {
+    /// Check the ASCII-armored `signature` of `content`, the serialized
+    /// object without its signature.
+    ///
+    /// An error is returned only if the signature could not be checked at
+    /// all; a signature which does not match is reported as
+    /// [`SignatureStatus::Bad`].
+    fn verify(&self, signature: &[u8], content: &[u8]) -> Result<SignatureVerification, Error>;
+}
+
+/// The outcome of checking the signature of an object, as returned by
+/// [`Commit::verify_signature`] and [`Tag::verify_signature`].
+///
+/// [`Commit::verify_signature`]: crate::Commit::verify_signature
+/// [`Tag::verify_signature`]: crate::Tag::verify_signature
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureVerification {
+    status: SignatureStatus,
+    signer: Option<String>,
+    fingerprint: Option<String>,
+}
+
+/// The status of a checked signature, similar to the `%G?` format of
+/// `git log`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The signature is valid and was made by a fully or ultimately trusted
+    /// key.
+    Good,
+    /// The signature is valid, but the key is not trusted or not allowed to
+    /// sign for anybody.
+    Untrusted,
+    /// The signature is valid, but it or its key has expired.
+    Expired,
+    /// The signature is valid, but its key has been revoked.
+    Revoked,
+    /// The signature does not match the signed content.
+    Bad,
+    /// The key which made the signature is not known, so the signature
+    /// cannot be checked.
+    UnknownKey,
+    /// The object is not signed.
+    Unsigned,
+}
+
+/// A [`Verifier`] which checks SSH signatures with `ssh-keygen -Y verify`
+/// against an allowed signers file, like `gpg.ssh.allowedSignersFile`.
+#[derive(Clone, Debug)]
+pub struct SshVerifier {
+    program: PathBuf,
+    allowed_signers: Option<PathBuf>,
+}
+
+/// A [`Verifier`] which checks signatures with `gpg --verify`, reading its
+/// `--status-fd` output.
+#[derive(Clone, Debug)]
+pub struct GpgVerifier {
+    program: PathBuf,
+}
+
+/// The verifier returned by `Repository::verifier`, dispatching on the kind
+/// of signature.
+struct ConfigVerifier {
+    openpgp: GpgVerifier,
+    x509: GpgVerifier,
+    ssh: SshVerifier,
+}
+
+const PGP_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+const PGP_MESSAGE: &str = "-----BEGIN PGP MESSAGE-----";
+const X509_SIGNATURE: &str = "-----BEGIN SIGNED MESSAGE-----";
+const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----";
+
+impl SignatureVerification {
+    /// Creates a verification result.
+    pub fn new(
+        status: SignatureStatus,
+        signer: Option<&str>,
+        fingerprint: Option<&str>,
+    ) -> SignatureVerification {
+        SignatureVerification {
+            status,
+            signer: signer.map(|s| s.to_string()),
+            fingerprint: fingerprint.map(|s| s.to_string()),
+        }
+    }
+
+    /// The result of checking an object which has no signature.
+    pub fn unsigned() -> SignatureVerification {
+        SignatureVerification::new(SignatureStatus::Unsigned, None, None)
+    }
+
+    /// The status of the signature.
+    pub fn status(&self) -> SignatureStatus {
+        self.status
+    }
+
+    /// Returns `true` if the signature is valid and trusted.
+    pub fn is_good(&self) -> bool {
+        self.status == SignatureStatus::Good
+    }
+
+    /// The identity of the signer, if known: the user id of a GnuPG key or
+    /// the principal of an SSH key.
+    pub fn signer(&self) -> Option<&str> {
+        self.signer.as_deref()
+    }
+
+    /// The fingerprint (or, if the key is unknown, the id) of the key which
+    /// made the signature.
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+}
+
+impl SshVerifier {
+    /// Creates a verifier checking signatures against the given allowed
+    /// signers file, in the format described in `ssh-keygen(1)`.
+    pub fn new<P: AsRef<Path>>(allowed_signers: P) -> SshVerifier {
+        SshVerifier {
+            program: PathBuf::from("ssh-keygen"),
+            allowed_signers: Some(allowed_signers.as_ref().to_path_buf()),
+        }
+    }
+
+    /// Creates a verifier from the `gpg.ssh.program` and
+    /// `gpg.ssh.allowedSignersFile` configuration values.
+    ///
+    /// Without an allowed signers file, valid signatures are reported as
+    /// [`SignatureStatus::Untrusted`].
+    pub fn from_config(config: &Config) -> Result<SshVerifier, Error> {
+        Ok(SshVerifier {
+            program: config_path(config, &["gpg.ssh.program"])?
+                .unwrap_or_else(|| PathBuf::from("ssh-keygen")),
+            allowed_signers: config_path(config, &["gpg.ssh.allowedSignersFile"])?,
+        })
+    }
+
+    /// Set the program to run instead of `ssh-keygen`.
+    pub fn program<P: AsRef<Path>>(&mut self, program: P) -> &mut SshVerifier {
+        self.program = program.as_ref().to_path_buf();
+        self
+    }
+}
+
+impl Verifier for SshVerifier {
+    fn verify(&self, signature: &[u8], content: &[u8]) -> Result<SignatureVerification, Error> {
+        let signature = TempFile::new("sig", signature)?;
+
+        // Find who may have made the signature, then check it for each of
+        // them, like git does.
+        let principals = match self.allowed_signers {
+            Some(ref allowed_signers) => {
+                let mut cmd = Command::new(&self.program);
+                cmd.args(["-Y", "find-principals", "-f"])
+                    .arg(allowed_signers)
+                    .arg("-s")
+                    .arg(&signature);
+                let (_, stdout, _) = run_unchecked(&mut cmd, &[])?;
+                stdout
+                    .lines()
+                    .map(|l| l.trim().to_string())
+                    .filter(|l| !l.is_empty())
+                    .collect::<Vec<_>>()
+            }
+            None => Vec::new(),
+        };
+        for principal in principals.iter() {
+            let mut cmd = Command::new(&self.program);
+            cmd.args(["-Y", "verify", "-n", "git", "-f"])
+                .arg(self.allowed_signers.as_ref().unwrap())
+                .arg("-I")
+                .arg(principal)
+                .arg("-s")
+                .arg(&signature);
+            let (success, stdout, stderr) = run_unchecked(&mut cmd, content)?;
+            let fingerprint = ssh_fingerprint(&stdout).or_else(|| ssh_fingerprint(&stderr));
+            if success {
+                return Ok(SignatureVerification::new(
+                    SignatureStatus::Good,
+                    Some(principal),
+                    fingerprint,
+                ));
+            }
+        }
+
+        // Nobody is allowed to make this signature; check that it is at
+        // least valid.
+        let mut cmd = Command::new(&self.program);
+        cmd.args(["-Y", "check-novalidate", "-n", "git", "-s"])
+            .arg(&signature);
+        let (success, stdout, stderr) = run_unchecked(&mut cmd, content)?;
+        let fingerprint = ssh_fingerprint(&stdout).or_else(|| ssh_fingerprint(&stderr));
+        let status = if success && principals.is_empty() {
+            SignatureStatus::Untrusted
+        } else {
+            SignatureStatus::Bad
+        };
+        Ok(SignatureVerification::new(status, None, fingerprint))
+    }
+}
+
+/// Extract the key fingerprint from the output of `ssh-keygen -Y verify`,
+/// e.g. `Good "git" signature for x with ED25519 key SHA256:...`.
+fn ssh_fingerprint(output: &str) -> Option<&str> {
+    output
+        .split_whitespace()
+        .find(|word| word.starts_with("SHA256:") || word.starts_with("MD5:"))
+}
+
+impl GpgVerifier {
+    /// Creates a verifier running `gpg`.
+    pub fn new() -> GpgVerifier {
+        GpgVerifier {
+            program: PathBuf::from("gpg"),
+        }
+    }
+
+    /// Creates a verifier from the `gpg.openpgp.program` (or `gpg.program`)
+    /// configuration value.
+    pub fn from_config(config: &Config) -> Result<GpgVerifier, Error> {
+        let mut verifier = GpgVerifier::new();
+        if let Some(program) = config_path(config, &["gpg.openpgp.program", "gpg.program"])? {
+            verifier.program = program;
+        }
+        Ok(verifier)
+    }
+
+    /// Creates a verifier for X.509 signatures from the `gpg.x509.program`
+    /// configuration value, running `gpgsm` by default.
+    pub fn x509_from_config(config: &Config) -> Result<GpgVerifier, Error> {
+        Ok(GpgVerifier {
+            program: config_path(config, &["gpg.x509.program"])?
+                .unwrap_or_else(|| PathBuf::from("gpgsm")),
+        })
+    }
+
+    /// Set the program to run instead of `gpg`.
+    pub fn program<P: AsRef<Path>>(&mut self, program: P) -> &mut GpgVerifier {
+        self.program = program.as_ref().to_path_buf();
+        self
+    }
+}
+
+impl Default for GpgVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Verifier for GpgVerifier {
+    fn verify(&self, signature: &[u8], content: &[u8]) -> Result<SignatureVerification, Error> {
+        let signature = TempFile::new("sig", signature)?;
+        let mut cmd = Command::new(&self.program);
+        cmd.args(["--keyid-format=long", "--status-fd=1", "--verify"])
+            .arg(&signature)
+            .arg("-");
+        let (_, stdout, stderr) = run_unchecked(&mut cmd, content)?;
+        parse_gpg_status(&stdout).ok_or_else(|| {
+            signing_error(format!(
+                "{} did not check the signature: {}",
+                self.program.display(),
+                stderr.trim()
+            ))
+        })
+    }
+}
+
+/// Interpret the `--status-fd` output of `gpg --verify`, or return `None`
+/// if it did not report on any signature.
+///
+/// Like git, output reporting on more than one signature is treated as a
+/// bad signature, and only full or ultimate trust makes a signature good.
+fn parse_gpg_status(output: &str) -> Option<SignatureVerification> {
+    let mut status = None;
+    let mut exclusive = 0;
+    let mut signer = None;
+    let mut key_id = None;
+    let mut fingerprint = None;
+    let mut trusted = false;
+    for line in output.lines() {
+        let line = match line.strip_prefix("[GNUPG:] ") {
+            Some(line) => line,
+            None => continue,
+        };
+        let mut words = line.splitn(3, ' ');
+        let keyword = words.next().unwrap_or("");
+        let arg = words.next();
+        let rest = words.next();
+        let found = match keyword {
+            "GOODSIG" => SignatureStatus::Untrusted,
+            "EXPSIG" | "EXPKEYSIG" => SignatureStatus::Expired,
+            "REVKEYSIG" => SignatureStatus::Revoked,
+            "BADSIG" => SignatureStatus::Bad,
+            "ERRSIG" => SignatureStatus::UnknownKey,
+            "VALIDSIG" => {
+                fingerprint = arg;
+                continue;
+            }
+            "TRUST_FULLY" | "TRUST_ULTIMATE" => {
+                trusted = true;
+                continue;
+            }
+            _ => continue,
+        };
+        exclusive += 1;
+        if status.is_none() {
+            status = Some(found);
+            key_id = arg;
+            if found != SignatureStatus::UnknownKey {
+                signer = rest;
+            }
+        }
+    }
+    let mut status = status?;
+    if exclusive > 1 {
+        return Some(SignatureVerification::new(SignatureStatus::Bad, None, None));
+    }
+    if status == SignatureStatus::Untrusted && trusted {
+        status = SignatureStatus::Good;
+    }
+    Some(SignatureVerification::new(
+        status,
+        signer,
+        fingerprint.or(key_id),
+    ))
+}
+
+impl Verifier for ConfigVerifier {
+    fn verify(&self, signature: &[u8], content: &[u8]) -> Result<SignatureVerification, Error> {
+        if signature.starts_with(SSH_SIGNATURE.as_bytes()) {
+            self.ssh.verify(signature, content)
+        } else if signature.starts_with(X509_SIGNATURE.as_bytes()) {
+            self.x509.verify(signature, content)
+        } else {
+            self.openpgp.verify(signature, content)
+        }
+    }
+}
+
+/// The verifier configured for `config`.
+pub(crate) fn verifier_from_config(config: &Config) -> Result<Box<dyn Verifier>, Error> {
+    Ok(Box::new(ConfigVerifier {
+        openpgp: GpgVerifier::from_config(config)?,
+        x509: GpgVerifier::x509_from_config(config)?,
+        ssh: SshVerifier::from_config(config)?,
+    }))
+}
+
+/// Split a signed tag object into its content and the signature appended to
+/// its message.
+pub(crate) fn split_signed_buffer(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut start = None;
+    let mut offset = 0;
+    for line in buf.split_inclusive(|b| *b == b'\n') {
+        let is_signature = [PGP_SIGNATURE, PGP_MESSAGE, X509_SIGNATURE, SSH_SIGNATURE]
+            .iter()
+            .any(|header| line.starts_with(header.as_bytes()));
+        if is_signature {
+            start = Some(offset);
+        }
+        offset += line.len();
+    }
+    start.map(|start| buf.split_at(start))
+}
+
+/// The signer configured for `config` by `gpg.format`.
+pub(crate) fn signer_from_config(config: &Config) -> Result<Box<dyn Signer>, Error> {
+    let format = config_string(config, "gpg.format")?;
+    match format.as_deref() {
+        None | Some("openpgp") => Ok(Box::new(GpgSigner::from_config(config)?)),
+        Some("x509") => Ok(Box::new(GpgSigner::x509_from_config(config)?)),
+        Some("ssh") => Ok(Box::new(SshSigner::from_config(config)?)),
+        Some(other) => Err(Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Config,
+            format!("invalid value for gpg.format: '{}'", other),
+        )),
+    }
+}
+
+/// Create a commit whose contents are signed by `signer`, without updating
+/// any reference.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn create_commit(
+    repo: *mut raw::git_repository,
+    author: *const raw::git_signature,
+    committer: *const raw::git_signature,
+    message_encoding: *const c_char,
+    message: *const c_char,
+    tree: *const raw::git_tree,
+    parents: &mut [*const raw::git_commit],
+    signer: &dyn Signer,
+) -> Result<Oid, Error> {
+    let buf = Buf::new();
+    try_call!(raw::git_commit_create_buffer(
+        buf.raw(),
+        repo,
+        author,
+        committer,
+        message_encoding,
+        message,
+        tree,
+        parents.len(),
+        parents.as_mut_ptr()
+    ));
+    let signature = CString::new(signer.sign(&buf)?)?;
+    let content = CString::new(&buf[..])?;
+    let mut raw = raw::git_oid {
+        id: [0; raw::GIT_OID_RAWSZ],
+    };
+    try_call!(raw::git_commit_create_with_signature(
+        &mut raw,
+        repo,
+        content,
+        signature,
+        ptr::null()
+    ));
+    Ok(Binding::from_raw(&raw as *const _))
+}
+
+/// Serialize a tag object whose message is followed by a signature made by
+/// `signer`.
+pub(crate) fn tag_buffer(
+    name: &str,
+    target: Oid,
+    kind: &str,
+    tagger: &Signature<'_>,
+    message: &str,
+    signer: &dyn Signer,
+) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(
+        format!("object {}\ntype {}\ntag {}\ntagger ", target, kind, name).as_bytes(),
+    );
+    buf.extend_from_slice(&signature_line(tagger));
+    buf.extend_from_slice(b"\n\n");
+    buf.extend_from_slice(message.as_bytes());
+    if !buf.ends_with(b"\n") {
+        buf.push(b'\n');
+    }
+    let signature = signer.sign(&buf)?;
+    buf.extend_from_slice(signature.as_bytes());
+    Ok(buf)
+}
+
+/// The `Name <email> time offset` form of a signature used in objects.
+fn signature_line(sig: &Signature<'_>) -> Vec<u8> {
+    let when = sig.when();
+    let offset = when.offset_minutes().abs();
+    let mut line = Vec::new();
+    line.extend_from_slice(sig.name_bytes());
+    line.extend_from_slice(b" <");
+    line.extend_from_slice(sig.email_bytes());
+    line.extend_from_slice(
+        format!(
+            "> {} {}{:02}{:02}",
+            when.seconds(),
+            when.sign(),
+            offset / 60,
+            offset % 60
+        )
+        .as_bytes(),
+    );
+    line
+}
+
+/// The `commit_create_cb` of a rebase, which signs commits with the signer
+/// stored behind `payload` if there is one.
+pub(crate) extern "C" fn rebase_commit_create_cb(
+    out: *mut raw::git_oid,
+    author: *const raw::git_signature,
+    committer: *const raw::git_signature,
+    message_encoding: *const c_char,
+    message: *const c_char,
+    tree: *const raw::git_tree,
+    parent_count: usize,
+    parents: *const *const raw::git_commit,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let slot = (*(payload as *const crate::rebase::SignerSlot)).get();
+        if slot.is_null() {
+            return raw::GIT_PASSTHROUGH as c_int;
+        }
+        let signer = *(slot as *const &dyn Signer);
+        let mut parents = slice::from_raw_parts(parents, parent_count).to_vec();
+        let result = create_commit(
+            raw::git_tree_owner(tree),
+            author,
+            committer,
+            message_encoding,
+            message,
+            tree,
+            &mut parents,
+            signer,
+        );
+        match result {
+            Ok(id) => {
+                *out = *id.raw();
+                0
+            }
+            Err(e) => {
+                let s = CString::new(e.message()).unwrap_or_default();
+                raw::git_error_set_str(e.class() as c_int, s.as_ptr());
+                e.raw_code() as c_int
+            }
+        }
+    })
+    .unwrap_or(-1)
+}
+
+fn config_string(config: &Config, name: &str) -> Result<Option<String>, Error> {
+    match config.get_string(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn config_path(config: &Config, names: &[&str]) -> Result<Option<PathBuf>, Error> {
+    for name in names {
+        match config.get_path(name) {
+            Ok(value) => return Ok(Some(value)),
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Run `cmd` with `input` on its standard input, returning its standard
+/// output and error, and failing if it does not exit successfully.
+fn run(cmd: &mut Command, input: &[u8]) -> Result<(String, String), Error> {
+    let (success, stdout, stderr) = run_unchecked(cmd, input)?;
+    if !success {
+        return Err(signing_error(format!(
+            "{} failed: {}",
+            Path::new(cmd.get_program()).display(),
+            stderr.trim()
+        )));
+    }
+    Ok((stdout, stderr))
+}
+
+/// Run `cmd` with `input` on its standard input, returning whether it
+/// exited successfully along with its standard output and error.
+fn run_unchecked(cmd: &mut Command, input: &[u8]) -> Result<(bool, String, String), Error> {
+    let program = cmd.get_program().to_os_string();
+    let spawn_error = |e: std::io::Error| {
+        signing_error(format!(
+            "failed to run {}: {}",
+            Path::new(&program).display(),
+            e
+        ))
+    };
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().map_err(spawn_error)?;
+    // The program may legitimately exit without reading all of its input.
+    let _ = writer.join();
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+fn signing_error(message: String) -> Error {
+    Error::new(ErrorCode::GenericError, ErrorClass::Os, message)
+}
+
+/// A file in the temporary directory which is removed when dropped.
+pub(crate) struct TempFile {
+    pub(crate) path: PathBuf,
+}
+
+impl TempFile {
+    /// Create a new file holding `contents`, never opening a file which
+    /// someone else created first under the same name.
+    pub(crate) fn new(extension: &str, contents: &[u8]) -> Result<TempFile, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir();
+        let mut attempts = 0;
+        loop {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            let name = format!(
+                "git2-{}-{}-{:08x}.{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst),
+                nanos,
+                extension
+            );
+            let path = dir.join(name);
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path);
+            let mut file = match file {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => {
+                    attempts += 1;
+                    continue;
+                }
+                Err(e) => {
+                    return Err(signing_error(format!(
+                        "failed to create {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            };
+            let temp = TempFile { path };
+            file.write_all(contents).map_err(|e| {
+                signing_error(format!("failed to write {}: {}", temp.path.display(), e))
+            })?;
+            return Ok(temp);
+        }
+    }
+}
+
+impl AsRef<OsStr> for TempFile {
+    fn as_ref(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use crate::{Repository, SignatureStatus, Signer, SshVerifier, Verifier};
+
+    /// Generate an SSH key in `dir` and configure `repo` to sign with it,
+    /// or return `None` if `ssh-keygen` is not available.
+    fn ssh_key(repo: &Repository, dir: &Path) -> Option<PathBuf> {
+        let key = dir.join("signing_key");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+            .arg(&key)
+            .status()
+            .ok()?;
+        if !status.success() {
+            return None;
+        }
+        let mut config = repo.config().unwrap();
+        config.set_str("gpg.format", "ssh").unwrap();
+        config
+            .set_str("user.signingkey", key.to_str().unwrap())
+            .unwrap();
+        Some(key)
+    }
+
+    #[test]
+    fn smoke_ssh() {
+        let (_td, repo) = crate::test::repo_init();
+        let keydir = tempfile::TempDir::new().unwrap();
+        if ssh_key(&repo, keydir.path()).is_none() {
+            return;
+        }
+        let signer = repo.signer().unwrap();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+
+        let id = repo
+            .commit_with_signer(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                "signed",
+                &tree,
+                &[&head],
+                &*signer,
+            )
+            .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(id));
+        let (signature, content) = repo.extract_signature(&id, None).unwrap();
+        let signature = signature.as_str().unwrap();
+        assert!(signature.starts_with("-----BEGIN SSH SIGNATURE-----"));
+        assert!(content.as_str().unwrap().ends_with("\n\nsigned"));
+
+        // A commit whose first parent is not the tip cannot update the ref.
+        assert!(repo
+            .commit_with_signer(Some("HEAD"), &sig, &sig, "stale", &tree, &[&head], &*signer)
+            .is_err());
+
+        let commit = repo.find_commit(id).unwrap();
+        let amended = commit
+            .amend_with_signer(
+                Some("HEAD"),
+                None,
+                None,
+                None,
+                Some("amended"),
+                None,
+                &*signer,
+            )
+            .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(amended));
+        let amended = repo.find_commit(amended).unwrap();
+        assert_eq!(amended.message(), Some("amended"));
+        assert_eq!(amended.parent_id(0).unwrap(), head.id());
+        assert!(repo.extract_signature(&amended.id(), None).is_ok());
+
+        let target = repo.find_object(amended.id(), None).unwrap();
+        let tag = repo
+            .tag_with_signer("v1", &target, &sig, "release", false, &*signer)
+            .unwrap();
+        let tag = repo.find_tag(tag).unwrap();
+        assert_eq!(tag.name(), Some("v1"));
+        assert_eq!(tag.target_id(), amended.id());
+        let message = tag.message().unwrap();
+        assert!(message.starts_with("release\n-----BEGIN SSH SIGNATURE-----"));
+        assert!(repo.find_reference("refs/tags/v1").is_ok());
+    }
+
+    #[test]
+    fn smoke_rebase() {
+        let (_td, repo) = crate::test::repo_init();
+        let keydir = tempfile::TempDir::new().unwrap();
+        if ssh_key(&repo, keydir.path()).is_none() {
+            return;
+        }
+        let signer = repo.signer().unwrap();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let base = repo
+            .commit(None, &sig, &sig, "base", &tree, &[&head])
+            .unwrap();
+        let mut builder = repo.treebuilder(Some(&tree)).unwrap();
+        let blob = repo.blob(b"tip\n").unwrap();
+        builder.insert("file", blob, 0o100644).unwrap();
+        let tip_tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let tip = repo
+            .commit(Some("HEAD"), &sig, &sig, "tip", &tip_tree, &[&head])
+            .unwrap();
+        assert_ne!(base, tip);
+
+        let branch = repo.find_annotated_commit(tip).unwrap();
+        let upstream = repo.find_annotated_commit(base).unwrap();
+        let mut opts = crate::RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+        rebase.next().unwrap().unwrap();
+        let err = rebase
+            .commit_with_signer(None, &sig, Some("tip"), &*signer)
+            .unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::Invalid);
+        rebase.abort().unwrap();
+
+        opts.sign_commits(true);
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+        drop(opts);
+        rebase.next().unwrap().unwrap();
+        let id = rebase
+            .commit_with_signer(None, &sig, Some("tip"), &*signer)
+            .unwrap();
+        assert!(rebase.next().is_none());
+        let (signature, _) = repo.extract_signature(&id, None).unwrap();
+        assert!(signature
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN SSH SIGNATURE-----"));
+        assert_eq!(repo.find_commit(id).unwrap().parent_id(0).unwrap(), base);
+    }
+
+    #[test]
+    fn custom_signer() {
+        struct Fixed;
+        impl Signer for Fixed {
+            fn sign(&self, _content: &[u8]) -> Result<String, crate::Error> {
+                Ok("fixed signature".to_string())
+            }
+        }
+
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let id = repo
+            .commit_with_signer(None, &sig, &sig, "signed", &tree, &[&head], &Fixed)
+            .unwrap();
+        let (signature, _) = repo.extract_signature(&id, None).unwrap();
+        assert_eq!(signature.as_str(), Some("fixed signature"));
+        assert_eq!(repo.head().unwrap().target(), Some(head.id()));
+
+        // Updating HEAD moves the branch and logs the commit in the reflogs
+        // of both.
+        let id = repo
+            .commit_with_signer(Some("HEAD"), &sig, &sig, "on head", &tree, &[&head], &Fixed)
+            .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(id));
+        for name in ["HEAD", "refs/heads/main"] {
+            let reflog = repo.reflog(name).unwrap();
+            let entry = reflog.get(0).unwrap();
+            assert_eq!(entry.id_new(), id);
+            assert_eq!(entry.id_old(), head.id());
+            assert_eq!(entry.message(), Some("commit: on head"));
+        }
+        let err = repo
+            .commit_with_signer(Some("HEAD"), &sig, &sig, "stale", &tree, &[&head], &Fixed)
+            .unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::Modified);
+        assert_eq!(repo.head().unwrap().target(), Some(id));
+    }
+
+    #[test]
+    fn verify_ssh() {
+        let (_td, repo) = crate::test::repo_init();
+        let keydir = tempfile::TempDir::new().unwrap();
+        let key = match ssh_key(&repo, keydir.path()) {
+            Some(key) => key,
+            None => return,
+        };
+        let public = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+        let allowed = keydir.path().join("allowed_signers");
+        std::fs::write(&allowed, format!("dev@example.com {}", public)).unwrap();
+
+        let signer = repo.signer().unwrap();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let id = repo
+            .commit_with_signer(None, &sig, &sig, "signed", &tree, &[&head], &*signer)
+            .unwrap();
+        let commit = repo.find_commit(id).unwrap();
+
+        let verifier = SshVerifier::new(&allowed);
+        let result = commit.verify_signature(&verifier).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Good);
+        assert!(result.is_good());
+        assert_eq!(result.signer(), Some("dev@example.com"));
+        assert!(result.fingerprint().unwrap().starts_with("SHA256:"));
+
+        // The configured verifier has no allowed signers yet.
+        let result = commit.verify_signature(&*repo.verifier().unwrap()).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Untrusted);
+        repo.config()
+            .unwrap()
+            .set_str("gpg.ssh.allowedSignersFile", allowed.to_str().unwrap())
+            .unwrap();
+        let result = commit.verify_signature(&*repo.verifier().unwrap()).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Good);
+
+        let (signature, content) = repo.extract_signature(&id, None).unwrap();
+        let mut tampered = content.to_vec();
+        tampered.extend_from_slice(b" tampered");
+        let result = verifier.verify(&signature, &tampered).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Bad);
+
+        let result = head.verify_signature(&verifier).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Unsigned);
+
+        let target = repo.find_object(id, None).unwrap();
+        let tag = repo
+            .tag_with_signer("v1", &target, &sig, "release\n", false, &*signer)
+            .unwrap();
+        let tag = repo.find_tag(tag).unwrap();
+        assert!(tag.verify_signature(&verifier).unwrap().is_good());
+        let tag = repo.tag("v2", &target, &sig, "plain\n", false).unwrap();
+        let tag = repo.find_tag(tag).unwrap();
+        assert_eq!(
+            tag.verify_signature(&verifier).unwrap().status(),
+            SignatureStatus::Unsigned
+        );
+    }
+
+    #[test]
+    fn gpg_status() {
+        let good = "[GNUPG:] NEWSIG\n\
+                    [GNUPG:] GOODSIG 0123456789ABCDEF A U Thor <author@example.com>\n\
+                    [GNUPG:] VALIDSIG FEDCBA98765432100123456789ABCDEF 2023-01-01 0 4 0 1 10 00 FEDCBA98765432100123456789ABCDEF\n\
+                    [GNUPG:] TRUST_ULTIMATE 0 pgp\n";
+        let result = super::parse_gpg_status(good).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Good);
+        assert_eq!(result.signer(), Some("A U Thor <author@example.com>"));
+        assert_eq!(
+            result.fingerprint(),
+            Some("FEDCBA98765432100123456789ABCDEF")
+        );
+
+        let untrusted = good.replace("TRUST_ULTIMATE", "TRUST_UNDEFINED");
+        let result = super::parse_gpg_status(&untrusted).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Untrusted);
+        let marginal = good.replace("TRUST_ULTIMATE", "TRUST_MARGINAL");
+        let result = super::parse_gpg_status(&marginal).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Untrusted);
+
+        // A second signature which does not check out spoils the first.
+        let two = format!(
+            "{}[GNUPG:] NEWSIG\n\
+             [GNUPG:] BADSIG 0123456789ABCDEF A U Thor <author@example.com>\n",
+            good
+        );
+        let result = super::parse_gpg_status(&two).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Bad);
+        assert_eq!(result.signer(), None);
+
+        let bad = "[GNUPG:] BADSIG 0123456789ABCDEF A U Thor <author@example.com>\n";
+        let result = super::parse_gpg_status(bad).unwrap();
+        assert_eq!(result.status(), SignatureStatus::Bad);
+        assert_eq!(result.fingerprint(), Some("0123456789ABCDEF"));
+
+        let unknown = "[GNUPG:] ERRSIG 0123456789ABCDEF 1 10 00 1672531200 9 -\n\
+                       [GNUPG:] NO_PUBKEY 0123456789ABCDEF\n";
+        let result = super::parse_gpg_status(unknown).unwrap();
+        assert_eq!(result.status(), SignatureStatus::UnknownKey);
+        assert_eq!(result.signer(), None);
+        assert_eq!(result.fingerprint(), Some("0123456789ABCDEF"));
+
+        assert!(super::parse_gpg_status("gpg: no valid OpenPGP data found.\n").is_none());
+    }
+}
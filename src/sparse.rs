@@ -0,0 +1,659 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::build::CheckoutBuilder;
+use crate::util;
+use crate::{raw, Error, ErrorClass, ErrorCode, Index, IndexEntry};
+use crate::{IndexTime, ObjectType, Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+
+/// Sparse checkout state of a repository, restricting the working directory
+/// to a set of directories.
+///
+/// Only cone mode is supported: the patterns in `.git/info/sparse-checkout`
+/// list directories, and a file is checked out if it is at the top level, in
+/// one of the listed directories or their subdirectories, or directly in a
+/// parent of a listed directory. Index entries of other files carry the
+/// skip-worktree bit.
+///
+/// While sparse checkout is enabled, `Repository::checkout_head` and
+/// `Repository::checkout_tree` keep the working directory within the cone.
+/// Checkouts limited with `CheckoutBuilder::path`, and other operations
+/// writing to the working directory such as `Repository::checkout_index`,
+/// `Repository::reset` and `Repository::merge`, do not know about the cone
+/// and may write files outside of it; call [`SparseCheckout::reapply`]
+/// afterwards to restrict the working directory again.
+///
+/// Created with [`Repository::sparse_checkout`].
+pub struct SparseCheckout<'repo> {
+    repo: &'repo Repository,
+}
+
+/// The directories of a cone mode sparse checkout.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Cone {
+    /// Directories whose whole contents are included.
+    recursive: BTreeSet<Vec<u8>>,
+    /// Strict ancestors of `recursive`, whose direct files are included.
+    parents: BTreeSet<Vec<u8>>,
+}
+
+impl<'repo> SparseCheckout<'repo> {
+    pub(crate) fn new(repo: &'repo Repository) -> SparseCheckout<'repo> {
+        SparseCheckout { repo }
+    }
+
+    /// Returns `true` if sparse checkout is enabled with
+    /// `core.sparseCheckout`.
+    pub fn is_enabled(&self) -> Result<bool, Error> {
+        is_enabled(self.repo)
+    }
+
+    /// Enable sparse checkout in cone mode, like `git sparse-checkout init
+    /// --cone`.
+    ///
+    /// If there are no patterns yet, only the files at the top level of the
+    /// working directory are kept.
+    pub fn init(&self) -> Result<(), Error> {
+        if !patterns_path(self.repo).exists() {
+            write_cone(self.repo, &Cone::default())?;
+        }
+        enable(self.repo, true)?;
+        self.reapply()
+    }
+
+    /// Replace the directories in the cone with `dirs`, like
+    /// `git sparse-checkout set`, enabling sparse checkout if needed.
+    pub fn set<I, T>(&self, dirs: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let cone = Cone::new(dirs.into_iter().map(|d| d.as_ref().as_bytes().to_vec()));
+        write_cone(self.repo, &cone)?;
+        enable(self.repo, true)?;
+        self.reapply()
+    }
+
+    /// Add `dirs` to the directories in the cone, like
+    /// `git sparse-checkout add`.
+    pub fn add<I, T>(&self, dirs: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let cone = cone(self.repo)?.ok_or_else(|| {
+            Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Config,
+                "sparse checkout is not enabled",
+            )
+        })?;
+        let dirs = cone
+            .recursive
+            .into_iter()
+            .chain(dirs.into_iter().map(|d| d.as_ref().as_bytes().to_vec()));
+        write_cone(self.repo, &Cone::new(dirs))?;
+        self.reapply()
+    }
+
+    /// List the directories in the cone, like `git sparse-checkout list`.
+    ///
+    /// Returns an empty list if sparse checkout is not enabled.
+    pub fn list(&self) -> Result<Vec<PathBuf>, Error> {
+        Ok(cone(self.repo)?
+            .map(|cone| {
+                cone.recursive
+                    .iter()
+                    .map(|dir| util::bytes2path(dir).to_path_buf())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Returns `true` if `path`, relative to the working directory, is
+    /// within the cone.
+    pub fn is_included(&self, path: &Path) -> Result<bool, Error> {
+        let path = util::path_to_repo_path(path)?;
+        Ok(cone(self.repo)?.is_none_or(|cone| cone.matches(path.as_bytes())))
+    }
+
+    /// Check out all files again and disable sparse checkout, like
+    /// `git sparse-checkout disable`.
+    ///
+    /// The patterns are kept for a later `init`.
+    pub fn disable(&self) -> Result<(), Error> {
+        apply(self.repo, None)?;
+        enable(self.repo, false)
+    }
+
+    /// Update the skip-worktree bits of the index and the working directory
+    /// to match the cone, like `git sparse-checkout reapply`.
+    ///
+    /// Files leaving the cone are removed unless they have local
+    /// modifications, and files entering it are checked out.
+    pub fn reapply(&self) -> Result<(), Error> {
+        apply(self.repo, cone(self.repo)?.as_ref())
+    }
+}
+
+impl Cone {
+    fn new<I: IntoIterator<Item = Vec<u8>>>(dirs: I) -> Cone {
+        let dirs = dirs
+            .into_iter()
+            .map(|dir| trim_slashes(&dir).to_vec())
+            .filter(|dir| !dir.is_empty())
+            .collect::<BTreeSet<_>>();
+        let mut cone = Cone::default();
+        for dir in &dirs {
+            // A directory inside another listed directory is already included.
+            if ancestors(dir).any(|ancestor| dirs.contains(ancestor)) {
+                continue;
+            }
+            cone.parents
+                .extend(ancestors(dir).map(|ancestor| ancestor.to_vec()));
+            cone.recursive.insert(dir.clone());
+        }
+        cone
+    }
+
+    /// Parse cone mode patterns, as written by `git sparse-checkout set`.
+    fn parse(patterns: &[u8]) -> Result<Cone, Error> {
+        let mut positive = BTreeSet::new();
+        let mut negative = BTreeSet::new();
+        for line in patterns.split(|b| *b == b'\n') {
+            let line = trim_end_whitespace(line);
+            if line.is_empty() || line.starts_with(b"#") || line == b"/*" || line == b"!/*/" {
+                continue;
+            }
+            if let Some(dir) = line
+                .strip_prefix(b"!/")
+                .and_then(|dir| dir.strip_suffix(b"/*/"))
+            {
+                negative.insert(unescape(dir));
+            } else if let Some(dir) = line
+                .strip_prefix(b"/")
+                .and_then(|dir| dir.strip_suffix(b"/"))
+                .filter(|dir| !dir.is_empty() && !dir.contains(&b'*'))
+            {
+                positive.insert(unescape(dir));
+            } else {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Config,
+                    format!(
+                        "sparse-checkout pattern '{}' is not in cone mode",
+                        String::from_utf8_lossy(line)
+                    ),
+                ));
+            }
+        }
+        Ok(Cone {
+            recursive: positive.difference(&negative).cloned().collect(),
+            parents: negative,
+        })
+    }
+
+    fn to_patterns(&self) -> Vec<u8> {
+        let mut out = b"/*\n!/*/\n".to_vec();
+        for dir in &self.parents {
+            let dir = escape(dir);
+            out.extend_from_slice(&[b"/", &dir[..], b"/\n!/", &dir[..], b"/*/\n"].concat());
+        }
+        for dir in &self.recursive {
+            out.extend_from_slice(&[b"/", &escape(dir)[..], b"/\n"].concat());
+        }
+        out
+    }
+
+    /// Returns `true` if the file at `path` is included in the cone.
+    pub(crate) fn matches(&self, path: &[u8]) -> bool {
+        let dir = match path.iter().rposition(|b| *b == b'/') {
+            Some(i) => &path[..i],
+            None => return true,
+        };
+        self.parents.contains(dir)
+            || self.recursive.contains(dir)
+            || ancestors(dir).any(|ancestor| self.recursive.contains(ancestor))
+    }
+}
+
+/// The strict ancestors of a `/` separated path, closest first.
+fn ancestors(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = path;
+    std::iter::from_fn(move || {
+        let i = rest.iter().rposition(|b| *b == b'/')?;
+        rest = &rest[..i];
+        Some(rest)
+    })
+}
+
+fn trim_slashes(path: &[u8]) -> &[u8] {
+    let start = path.iter().position(|b| *b != b'/').unwrap_or(path.len());
+    let end = path
+        .iter()
+        .rposition(|b| *b != b'/')
+        .map_or(start, |i| i + 1);
+    &path[start..end]
+}
+
+fn trim_end_whitespace(line: &[u8]) -> &[u8] {
+    let end = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    &line[..end]
+}
+
+fn escape(dir: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(dir.len());
+    for b in dir {
+        if matches!(b, b'*' | b'?' | b'[' | b'\\') {
+            out.push(b'\\');
+        }
+        out.push(*b);
+    }
+    out
+}
+
+fn unescape(dir: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(dir.len());
+    let mut bytes = dir.iter();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => out.extend(bytes.next()),
+            _ => out.push(*b),
+        }
+    }
+    out
+}
+
+fn patterns_path(repo: &Repository) -> PathBuf {
+    repo.path().join("info").join("sparse-checkout")
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::new(ErrorCode::GenericError, ErrorClass::Os, e.to_string())
+}
+
+fn write_cone(repo: &Repository, cone: &Cone) -> Result<(), Error> {
+    let path = patterns_path(repo);
+    fs::create_dir_all(path.parent().unwrap()).map_err(io_error)?;
+    fs::write(&path, cone.to_patterns()).map_err(io_error)
+}
+
+fn enable(repo: &Repository, enabled: bool) -> Result<(), Error> {
+    let mut config = repo.config()?;
+    config.set_bool("core.sparseCheckout", enabled)?;
+    if enabled {
+        config.set_bool("core.sparseCheckoutCone", true)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn is_enabled(repo: &Repository) -> Result<bool, Error> {
+    Ok(repo
+        .config()?
+        .snapshot()?
+        .get_bool("core.sparseCheckout")
+        .unwrap_or(false))
+}
+
+/// The cone of `repo`, or `None` if sparse checkout is disabled.
+pub(crate) fn cone(repo: &Repository) -> Result<Option<Cone>, Error> {
+    if repo.is_bare() || !is_enabled(repo)? {
+        return Ok(None);
+    }
+    let cone_mode = repo
+        .config()?
+        .snapshot()?
+        .get_bool("core.sparseCheckoutCone")
+        .unwrap_or(true);
+    if !cone_mode {
+        return Err(Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Config,
+            "only cone mode sparse checkout is supported",
+        ));
+    }
+    match fs::read(patterns_path(repo)) {
+        Ok(patterns) => Cone::parse(&patterns).map(Some),
+        // Without patterns git checks out everything.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+/// Returns `true` if a checkout with `opts` updates the whole working
+/// directory and index of the repository, and so should respect sparse
+/// checkout.
+///
+/// Checkouts limited to paths, or against a baseline given by the caller,
+/// are left as they are.
+pub(crate) fn affects(opts: &raw::git_checkout_options) -> bool {
+    opts.checkout_strategy & 0xf != raw::GIT_CHECKOUT_NONE
+        && opts.checkout_strategy & raw::GIT_CHECKOUT_DONT_UPDATE_INDEX == 0
+        && opts.target_directory.is_null()
+        && opts.paths.count == 0
+        && opts.baseline.is_null()
+        && opts.baseline_index.is_null()
+}
+
+/// Returns `true` if the file at `path` still has the contents of `entry`.
+fn is_unmodified(entry: &IndexEntry, path: &Path) -> bool {
+    let id = if entry.mode == 0o120000 {
+        match fs::read_link(path) {
+            Ok(target) => Oid::hash_object(
+                ObjectType::Blob,
+                target.to_string_lossy().replace('\\', "/").as_bytes(),
+            ),
+            Err(_) => return false,
+        }
+    } else {
+        Oid::hash_file(ObjectType::Blob, path)
+    };
+    id.is_ok_and(|id| id == entry.id)
+}
+
+/// Set the skip-worktree bits of the index according to `cone`, removing
+/// files leaving the cone and checking out files entering it.
+pub(crate) fn apply(repo: &Repository, cone: Option<&Cone>) -> Result<(), Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::from_str("cannot use sparse checkout in a bare repository"))?;
+    let mut index = repo.index()?;
+    let mut restore = Vec::new();
+    let mut removed = Vec::new();
+    for mut entry in index.iter().collect::<Vec<_>>() {
+        if entry.stage() != 0 {
+            continue;
+        }
+        let included = cone.is_none_or(|cone| cone.matches(&entry.path));
+        let skip = entry.is_skip_worktree();
+        if included && skip {
+            entry.set_skip_worktree(false);
+            index.add(&entry)?;
+            restore.push(entry.path);
+        } else if !included && !skip {
+            let path = workdir.join(util::bytes2path(&entry.path));
+            match fs::symlink_metadata(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(e)),
+                // Local modifications are left alone, as git does.
+                Ok(_) if !is_unmodified(&entry, &path) => continue,
+                Ok(_) => {
+                    fs::remove_file(&path).map_err(io_error)?;
+                    removed.push(path);
+                }
+            }
+            entry.set_skip_worktree(true);
+            index.add(&entry)?;
+        }
+    }
+
+    if !restore.is_empty() {
+        let mut checkout = CheckoutBuilder::new();
+        checkout.recreate_missing(true).disable_pathspec_match(true);
+        for path in restore {
+            checkout.path(path);
+        }
+        repo.checkout_index(Some(&mut index), Some(&mut checkout))?;
+    }
+    index.write()?;
+
+    // Remove the directories left empty, deepest first.
+    let mut dirs = removed
+        .iter()
+        .flat_map(|path| path.ancestors().skip(1))
+        .filter(|dir| dir.starts_with(workdir) && *dir != workdir)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in dirs {
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
+
+/// Build a baseline index for checking out `target`, in which the entries
+/// outside of `cone` are those of `target`.
+///
+/// Checking out against it leaves the working directory outside of the cone
+/// alone, as libgit2 would otherwise report a conflict for each file that is
+/// missing from the working directory but changed by the checkout.
+pub(crate) fn checkout_baseline(
+    repo: &Repository,
+    cone: &Cone,
+    target: &Tree<'_>,
+) -> Result<Index, Error> {
+    let mut baseline = Index::new()?;
+    if let Ok(head) = repo.head().and_then(|head| head.peel_to_tree()) {
+        baseline.read_tree(&head)?;
+    }
+    for entry in baseline.iter().collect::<Vec<_>>() {
+        if !cone.matches(&entry.path) {
+            baseline.remove(util::bytes2path(&entry.path), 0)?;
+        }
+    }
+    for entry in excluded_entries(cone, target)? {
+        baseline.add(&entry)?;
+    }
+    Ok(baseline)
+}
+
+/// Update the index entries outside of `cone` to those of `target`, with
+/// the skip-worktree bit set, after checking out `target`.
+pub(crate) fn checkout_finish(
+    repo: &Repository,
+    cone: &Cone,
+    target: &Tree<'_>,
+) -> Result<(), Error> {
+    let mut index = repo.index()?;
+    let entries = excluded_entries(cone, target)?;
+    let paths = entries
+        .iter()
+        .map(|entry| &entry.path[..])
+        .collect::<BTreeSet<_>>();
+    for entry in index.iter().collect::<Vec<_>>() {
+        if entry.stage() == 0 && !cone.matches(&entry.path) && !paths.contains(&entry.path[..]) {
+            index.remove(util::bytes2path(&entry.path), 0)?;
+        }
+    }
+    for mut entry in entries {
+        let current = index.get_path(util::bytes2path(&entry.path), 0);
+        if let Some(current) = current.filter(|e| e.id == entry.id && e.mode == entry.mode) {
+            entry = current;
+        }
+        entry.set_skip_worktree(true);
+        index.add(&entry)?;
+    }
+    index.write()?;
+    apply(repo, Some(cone))
+}
+
+/// The blobs of `tree` outside of `cone`, as index entries.
+fn excluded_entries(cone: &Cone, tree: &Tree<'_>) -> Result<Vec<IndexEntry>, Error> {
+    let mut entries = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return TreeWalkResult::Ok;
+        }
+        let path = [dir.as_bytes(), entry.name_bytes()].concat();
+        if !cone.matches(&path) {
+            entries.push(IndexEntry {
+                ctime: IndexTime::new(0, 0),
+                mtime: IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: entry.filemode() as u32,
+                uid: 0,
+                gid: 0,
+                file_size: 0,
+                id: entry.id(),
+                flags: 0,
+                flags_extended: 0,
+                path,
+            });
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::Cone;
+    use crate::build::{CheckoutBuilder, TreeUpdateBuilder};
+    use crate::{FileMode, Oid, Repository, Status};
+
+    fn commit(repo: &Repository, files: &[&str]) -> Oid {
+        let mut update = TreeUpdateBuilder::new();
+        for file in files {
+            let id = repo.blob(file.as_bytes()).unwrap();
+            update.upsert(file, id, FileMode::Blob);
+        }
+        let empty = repo.treebuilder(None).unwrap().write().unwrap();
+        let empty = repo.find_tree(empty).unwrap();
+        let tree = update.create_updated(repo, &empty).unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "files", &tree, &[&head])
+            .unwrap()
+    }
+
+    fn skipped(repo: &Repository) -> Vec<String> {
+        let index = repo.index().unwrap();
+        index
+            .iter()
+            .filter(|e| e.is_skip_worktree())
+            .map(|e| String::from_utf8(e.path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn cone() {
+        let cone = Cone::new(["a/b/", "/c", "a/b/d", "e/f/g"].map(|d| d.as_bytes().to_vec()));
+        let patterns = cone.to_patterns();
+        assert_eq!(
+            String::from_utf8_lossy(&patterns),
+            "/*\n!/*/\n/a/\n!/a/*/\n/e/\n!/e/*/\n/e/f/\n!/e/f/*/\n/a/b/\n/c/\n/e/f/g/\n"
+        );
+        assert_eq!(Cone::parse(&patterns).unwrap(), cone);
+        assert!(Cone::parse(b"*.txt\n").is_err());
+
+        for (path, included) in [
+            ("top", true),
+            ("a/file", true),
+            ("a/x/file", false),
+            ("a/b/file", true),
+            ("a/b/x/y/file", true),
+            ("c/file", true),
+            ("e/file", true),
+            ("e/f/file", true),
+            ("e/f/x/file", false),
+            ("e/f/g/file", true),
+            ("z/file", false),
+        ] {
+            assert_eq!(cone.matches(path.as_bytes()), included, "{}", path);
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        commit(&repo, &["top", "a/one", "a/b/two", "c/three"]);
+        let mut checkout = CheckoutBuilder::new();
+        repo.checkout_head(Some(checkout.force())).unwrap();
+        let sparse = repo.sparse_checkout();
+        assert!(!sparse.is_enabled().unwrap());
+        assert!(sparse.list().unwrap().is_empty());
+
+        sparse.init().unwrap();
+        assert!(sparse.is_enabled().unwrap());
+        assert_eq!(skipped(&repo), ["a/b/two", "a/one", "c/three"]);
+        assert!(td.path().join("top").exists());
+        assert!(!td.path().join("a").exists());
+        assert!(repo.statuses(None).unwrap().is_empty());
+
+        sparse.set(["a/b"]).unwrap();
+        assert_eq!(sparse.list().unwrap(), [Path::new("a/b")]);
+        assert_eq!(skipped(&repo), ["c/three"]);
+        assert_eq!(
+            fs::read_to_string(td.path().join("a/b/two")).unwrap(),
+            "a/b/two"
+        );
+        assert!(sparse.is_included(Path::new("a/one")).unwrap());
+        assert!(!sparse.is_included(Path::new("c/three")).unwrap());
+
+        // Modified files are not removed when leaving the cone.
+        fs::write(td.path().join("a/one"), "changed").unwrap();
+        sparse.set(["c"]).unwrap();
+        assert_eq!(skipped(&repo), ["a/b/two"]);
+        assert!(td.path().join("a/one").exists());
+        assert!(!td.path().join("a/b").exists());
+        assert!(td.path().join("c/three").exists());
+        fs::write(td.path().join("a/one"), "a/one").unwrap();
+        sparse.reapply().unwrap();
+
+        sparse.add(["a/b"]).unwrap();
+        assert_eq!(sparse.list().unwrap(), [Path::new("a/b"), Path::new("c")]);
+        assert!(skipped(&repo).is_empty());
+        assert!(repo.statuses(None).unwrap().is_empty());
+
+        sparse.set(["c"]).unwrap();
+        sparse.disable().unwrap();
+        assert!(!sparse.is_enabled().unwrap());
+        assert!(skipped(&repo).is_empty());
+        assert!(td.path().join("a/b/two").exists());
+    }
+
+    #[test]
+    fn checkout() {
+        let (td, repo) = crate::test::repo_init();
+        let first = commit(&repo, &["top", "a/one", "c/three"]);
+        let mut checkout = CheckoutBuilder::new();
+        repo.checkout_head(Some(checkout.force())).unwrap();
+        repo.sparse_checkout().set(["c"]).unwrap();
+
+        let second = commit(&repo, &["top", "a/changed", "c/changed", "d/new"]);
+        repo.set_head_detached(first).unwrap();
+        let target = repo.find_commit(second).unwrap();
+        repo.checkout_tree(target.as_object(), None).unwrap();
+        repo.set_head_detached(second).unwrap();
+
+        assert!(!td.path().join("a").exists());
+        assert!(!td.path().join("d").exists());
+        assert_eq!(
+            fs::read_to_string(td.path().join("c/changed")).unwrap(),
+            "c/changed"
+        );
+        assert!(!td.path().join("c/three").exists());
+        assert_eq!(skipped(&repo), ["a/changed", "d/new"]);
+        assert!(repo.statuses(None).unwrap().is_empty());
+        assert_eq!(
+            repo.status_file(Path::new("a/changed")).unwrap(),
+            Status::CURRENT
+        );
+
+        // Adding everything keeps the entries of missing skipped files.
+        let mut index = repo.index().unwrap();
+        index.update_all(["*"], None).unwrap();
+        assert_eq!(skipped(&repo), ["a/changed", "d/new"]);
+
+        // A checkout limited to a path leaves the other entries alone.
+        let third = commit(&repo, &["top", "a/other", "c/changed"]);
+        let target = repo.find_commit(third).unwrap();
+        let mut checkout = CheckoutBuilder::new();
+        checkout.path("c/changed");
+        repo.checkout_tree(target.as_object(), Some(&mut checkout))
+            .unwrap();
+        assert_eq!(skipped(&repo), ["a/changed", "d/new"]);
+        assert!(!td.path().join("a").exists());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::build::CheckoutBuilder;
 use crate::util::{self, Binding};
-use crate::{panic, raw, IntoCString, Oid, Signature, StashApplyProgress, StashFlags};
+use crate::{
+    panic, raw, Commit, IntoCString, Oid, Signature, StashApplyProgress, StashFlags, Time,
+};
 use libc::{c_char, c_int, c_void, size_t};
 use std::ffi::{c_uint, CStr, CString};
 use std::mem;
@@ -144,6 +146,67 @@ impl<'cb> StashApplyOptions<'cb> {
     }
 }
 
+/// A single entry of the stash list, as returned by
+/// [`Repository::stash_list`](crate::Repository::stash_list).
+#[derive(Clone)]
+pub struct StashEntry {
+    index: usize,
+    id: Oid,
+    base: Oid,
+    message: String,
+    author: Signature<'static>,
+    untracked: bool,
+}
+
+impl StashEntry {
+    pub(crate) fn new(index: usize, message: &str, commit: &Commit<'_>) -> StashEntry {
+        StashEntry {
+            index,
+            id: commit.id(),
+            base: commit.parent_id(0).unwrap_or_else(|_| Oid::zero()),
+            message: message.to_string(),
+            author: commit.author().to_owned(),
+            untracked: commit.parent_count() > 2,
+        }
+    }
+
+    /// The position of this entry in the stash list, 0 being the most recent
+    /// stash.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The id of the stash commit.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// The id of the commit that was `HEAD` when the stash was created.
+    pub fn base_id(&self) -> Oid {
+        self.base
+    }
+
+    /// The message of the stash, such as `WIP on main: 1234567 subject`.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The signature of the stasher.
+    pub fn author(&self) -> &Signature<'static> {
+        &self.author
+    }
+
+    /// The time at which the stash was created.
+    pub fn time(&self) -> Time {
+        self.author.when()
+    }
+
+    /// Returns `true` if untracked files were stashed too.
+    pub fn has_untracked(&self) -> bool {
+        self.untracked
+    }
+}
+
 #[allow(unused)]
 pub struct StashCbData<'a> {
     pub callback: &'a mut StashCb<'a>,
@@ -216,7 +279,7 @@ extern "C" fn stash_apply_progress_cb(
 mod tests {
     use crate::stash::{StashApplyOptions, StashSaveOptions};
     use crate::test::repo_init;
-    use crate::{IndexAddOption, Repository, StashFlags, Status};
+    use crate::{BranchType, Delta, Diff, IndexAddOption, Repository, StashFlags, Status};
     use std::fs;
     use std::path::{Path, PathBuf};
 
@@ -348,4 +411,137 @@ mod tests {
 
         assert_eq!(repo.statuses(None).unwrap().len(), 1);
     }
+
+    #[test]
+    fn stash_list_and_diff() {
+        let (_td, mut repo) = repo_init();
+        let signature = repo.signature().unwrap();
+
+        // A commit with a file, which the stashes leave alone.
+        create_file(&repo, "committed", "committed");
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("committed")).unwrap();
+        index.write().unwrap();
+        let head = {
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parent = repo.head().unwrap().peel_to_commit().unwrap();
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "base",
+                &tree,
+                &[&parent],
+            )
+            .unwrap()
+        };
+
+        create_file(&repo, "tracked", "one");
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked")).unwrap();
+        index.write().unwrap();
+        repo.stash_save(&signature, "first", None).unwrap();
+
+        create_file(&repo, "tracked", "two");
+        create_file(&repo, "committed", "changed");
+        create_file(&repo, "untracked", "three");
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked")).unwrap();
+        index.write().unwrap();
+        repo.stash_save(&signature, "second", Some(StashFlags::INCLUDE_UNTRACKED))
+            .unwrap();
+
+        let list = repo.stash_list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].index(), 0);
+        assert_eq!(list[0].message(), "On main: second");
+        assert_eq!(list[1].message(), "On main: first");
+        assert_eq!(list[0].base_id(), head);
+        assert_eq!(list[0].author().name(), signature.name());
+        assert!(list[0].has_untracked());
+        assert!(!list[1].has_untracked());
+
+        let changes = |diff: &Diff<'_>| {
+            diff.deltas()
+                .map(|d| (d.new_file().path().unwrap().to_path_buf(), d.status()))
+                .collect::<Vec<_>>()
+        };
+        let diff = repo.stash_diff(0, false, None).unwrap();
+        assert_eq!(
+            changes(&diff),
+            [
+                (PathBuf::from("committed"), Delta::Modified),
+                (PathBuf::from("tracked"), Delta::Added),
+            ]
+        );
+        let diff = repo.stash_diff(0, true, None).unwrap();
+        assert_eq!(
+            changes(&diff),
+            [
+                (PathBuf::from("committed"), Delta::Modified),
+                (PathBuf::from("tracked"), Delta::Added),
+                (PathBuf::from("untracked"), Delta::Added),
+            ]
+        );
+        let diff = repo.stash_diff(1, true, None).unwrap();
+        assert_eq!(changes(&diff), [(PathBuf::from("tracked"), Delta::Added)]);
+        assert!(repo.stash_diff(2, false, None).is_err());
+    }
+
+    #[test]
+    fn stash_branch() {
+        let (_td, mut repo) = repo_init();
+        let signature = repo.signature().unwrap();
+
+        create_file(&repo, "staged", "one");
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged")).unwrap();
+        index.write().unwrap();
+        repo.stash_save(&signature, "msg", None).unwrap();
+
+        repo.stash_branch("stashed", 0, None).unwrap();
+        assert_eq!(repo.head().unwrap().shorthand(), Some("stashed"));
+        assert_eq!(
+            repo.status_file(Path::new("staged")).unwrap(),
+            Status::INDEX_NEW
+        );
+        assert!(repo.stash_list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stash_branch_conflict() {
+        let (_td, mut repo) = repo_init();
+        let signature = repo.signature().unwrap();
+
+        create_file(&repo, "staged", "one");
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged")).unwrap();
+        index.write().unwrap();
+        repo.stash_save(&signature, "msg", None).unwrap();
+
+        // A change committed since, then modified in the working directory,
+        // which checking out the base of the stash would overwrite.
+        create_file(&repo, "file", "committed");
+        index.add_path(Path::new("file")).unwrap();
+        index.write().unwrap();
+        {
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let head = repo.head().unwrap().peel_to_commit().unwrap();
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "file",
+                &tree,
+                &[&head],
+            )
+            .unwrap();
+        }
+        create_file(&repo, "file", "modified");
+
+        assert!(repo.stash_branch("stashed", 0, None).is_err());
+        assert!(repo.find_branch("stashed", BranchType::Local).is_err());
+        assert_eq!(repo.head().unwrap().shorthand(), Some("main"));
+        assert_eq!(repo.stash_list().unwrap().len(), 1);
+    }
 }
@@ -1,12 +1,16 @@
 use libc::{c_char, c_uint, size_t};
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
 use std::marker;
 use std::mem;
 use std::ops::Range;
+use std::ptr;
 use std::str;
 
+use crate::untracked_cache::Untracked;
 use crate::util::{self, Binding};
-use crate::{raw, DiffDelta, IntoCString, Repository, Status};
+use crate::{raw, DiffDelta, Error, FsMonitor, Index, IndexEntry, IntoCString, Repository, Status};
+use crate::{IndexEntryExtendedFlag, IndexEntryFlag};
 
 /// Options that can be provided to `repo.statuses()` to control how the status
 /// information is gathered.
@@ -14,6 +18,7 @@ pub struct StatusOptions {
     raw: raw::git_status_options,
     pathspec: Vec<CString>,
     ptrs: Vec<*const c_char>,
+    fsmonitor: Option<Box<dyn FsMonitor>>,
 }
 
 /// Enumeration of possible methods of what can be shown through a status
@@ -39,11 +44,27 @@ pub enum StatusShow {
 /// allowing indexing, as well as providing an iterator.
 pub struct Statuses<'repo> {
     raw: *mut raw::git_status_list,
+    /// Positions of the entries to expose, along with the status to report
+    /// instead of the one computed by libgit2, or `None` for all of `raw`.
+    /// Positions past the end of `raw` are in `extra`.
+    entries: Option<Vec<(usize, Option<Status>)>>,
+    extra: Vec<ExtraEntry>,
+    /// Other status lists the entries in `extra` point into.
+    others: Vec<Statuses<'repo>>,
 
     // Hm, not currently present, but can't hurt?
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
+/// A status entry found without libgit2, or combined from entries of several
+/// lists, laid out like the ones libgit2 makes so that `StatusEntry` can
+/// point to it.
+struct ExtraEntry {
+    entry: Box<raw::git_status_entry>,
+    _delta: Option<Box<raw::git_diff_delta>>,
+    path: CString,
+}
+
 /// An iterator over the statuses in a `Statuses` instance.
 pub struct StatusIter<'statuses> {
     statuses: &'statuses Statuses<'statuses>,
@@ -55,6 +76,7 @@ pub struct StatusIter<'statuses> {
 /// Instances are created through the `.iter()` method or the `.get()` method.
 pub struct StatusEntry<'statuses> {
     raw: *const raw::git_status_entry,
+    status: Option<Status>,
     _marker: marker::PhantomData<&'statuses DiffDelta<'statuses>>,
 }
 
@@ -75,6 +97,7 @@ impl StatusOptions {
                 raw,
                 pathspec: Vec::new(),
                 ptrs: Vec::new(),
+                fsmonitor: None,
             }
         }
     }
@@ -113,6 +136,122 @@ impl StatusOptions {
         self
     }
 
+    /// Whether the untracked files these options ask for are the ones git
+    /// keeps in its untracked cache.
+    pub(crate) fn wants_untracked_cache(&self) -> bool {
+        let flags = self.raw.flags as raw::git_status_opt_t;
+        self.raw.show != raw::GIT_STATUS_SHOW_INDEX_ONLY
+            && self.pathspec.is_empty()
+            && flags & raw::GIT_STATUS_OPT_INCLUDE_UNTRACKED != 0
+            && flags
+                & (raw::GIT_STATUS_OPT_INCLUDE_IGNORED
+                    | raw::GIT_STATUS_OPT_RECURSE_UNTRACKED_DIRS
+                    | raw::GIT_STATUS_OPT_RENAMES_INDEX_TO_WORKDIR
+                    | raw::GIT_STATUS_OPT_SORT_CASE_INSENSITIVELY
+                    | raw::GIT_STATUS_OPT_INCLUDE_UNREADABLE_AS_UNTRACKED)
+                == 0
+    }
+
+    /// Ask `monitor` which files changed since the last status instead of
+    /// checking every file in the working directory.
+    ///
+    /// The monitor's token is kept in the index, in the `FSMN` extension git
+    /// keeps it in, along with which files were found modified, so the files
+    /// that need checking are the modified ones and the ones the monitor
+    /// reports. Without a token, and whenever the monitor cannot tell what
+    /// changed, every file is checked.
+    ///
+    /// As with git, the monitor only spares checking tracked files, so it is
+    /// consulted when untracked files are included only if they can come from
+    /// git's untracked cache. It is not consulted either with a pathspec, or
+    /// when ignored or unmodified files are included, or renames in the
+    /// working directory are detected.
+    pub fn fsmonitor<M: FsMonitor + 'static>(&mut self, monitor: M) -> &mut StatusOptions {
+        self.fsmonitor = Some(Box::new(monitor));
+        self
+    }
+
+    /// Take the monitor out of these options, for as long as it is in use.
+    pub(crate) fn take_fsmonitor(&mut self) -> Option<Box<dyn FsMonitor>> {
+        self.fsmonitor.take()
+    }
+
+    pub(crate) fn restore_fsmonitor(&mut self, monitor: Option<Box<dyn FsMonitor>>) {
+        self.fsmonitor = monitor;
+    }
+
+    /// Whether only some of the files need checking for these options, given
+    /// whether the untracked files come from the untracked cache.
+    pub(crate) fn can_use_fsmonitor(&self, untracked_cached: bool) -> bool {
+        let flags = self.raw.flags as raw::git_status_opt_t;
+        self.raw.show != raw::GIT_STATUS_SHOW_INDEX_ONLY
+            && self.pathspec.is_empty()
+            && (untracked_cached || flags & raw::GIT_STATUS_OPT_INCLUDE_UNTRACKED == 0)
+            && flags
+                & (raw::GIT_STATUS_OPT_INCLUDE_IGNORED
+                    | raw::GIT_STATUS_OPT_INCLUDE_UNMODIFIED
+                    | raw::GIT_STATUS_OPT_RENAMES_INDEX_TO_WORKDIR
+                    | raw::GIT_STATUS_OPT_RENAMES_FROM_REWRITES
+                    | raw::GIT_STATUS_OPT_INCLUDE_UNREADABLE
+                    | raw::GIT_STATUS_OPT_INCLUDE_UNREADABLE_AS_UNTRACKED)
+                == 0
+    }
+
+    /// Whether these options ask for the differences between HEAD and the
+    /// index.
+    pub(crate) fn shows_index(&self) -> bool {
+        self.raw.show != raw::GIT_STATUS_SHOW_WORKDIR_ONLY
+    }
+
+    /// These options restricted to the differences between HEAD and the
+    /// index.
+    pub(crate) fn index_only(&self) -> raw::git_status_options {
+        let pathspec = raw::git_strarray {
+            strings: ptr::null_mut(),
+            count: 0,
+        };
+        self.restricted(raw::GIT_STATUS_SHOW_INDEX_ONLY, 0, pathspec)
+    }
+
+    /// These options restricted to the tracked files at the literal `paths`
+    /// in the working directory, which the returned options point to.
+    pub(crate) fn workdir_only(&self, paths: &[*const c_char]) -> raw::git_status_options {
+        let pathspec = raw::git_strarray {
+            strings: paths.as_ptr() as *mut _,
+            count: paths.len() as size_t,
+        };
+        let literal = raw::GIT_STATUS_OPT_DISABLE_PATHSPEC_MATCH as c_uint;
+        self.restricted(raw::GIT_STATUS_SHOW_WORKDIR_ONLY, literal, pathspec)
+    }
+
+    /// These options without the untracked files, for when they are read
+    /// from git's untracked cache instead.
+    pub(crate) fn without_untracked(&self) -> raw::git_status_options {
+        let pathspec = raw::git_strarray {
+            strings: ptr::null_mut(),
+            count: 0,
+        };
+        // `wants_untracked_cache` requires an empty pathspec.
+        self.restricted(self.raw.show, 0, pathspec)
+    }
+
+    fn restricted(
+        &self,
+        show: raw::git_status_show_t,
+        flags: c_uint,
+        pathspec: raw::git_strarray,
+    ) -> raw::git_status_options {
+        let untracked = raw::GIT_STATUS_OPT_INCLUDE_UNTRACKED as c_uint;
+        raw::git_status_options {
+            version: self.raw.version,
+            show,
+            flags: self.raw.flags & !untracked | flags,
+            pathspec,
+            baseline: self.raw.baseline,
+            rename_threshold: self.raw.rename_threshold,
+        }
+    }
+
     /// Flag whether untracked files will be included.
     ///
     /// Untracked files will only be included if the workdir files are included
@@ -240,9 +379,17 @@ impl<'repo> Statuses<'repo> {
     ///
     /// Returns `None` if the index is out of bounds.
     pub fn get(&self, index: usize) -> Option<StatusEntry<'_>> {
+        let (index, status) = match &self.entries {
+            Some(entries) => *entries.get(index)?,
+            None => (index, None),
+        };
         unsafe {
-            let p = raw::git_status_byindex(self.raw, index as size_t);
-            Binding::from_raw_opt(p)
+            let p = match index.checked_sub(self.raw_len()) {
+                Some(extra) => &*self.extra.get(extra)?.entry,
+                None => raw::git_status_byindex(self.raw, index as size_t),
+            };
+            let entry: Option<StatusEntry<'_>> = Binding::from_raw_opt(p);
+            entry.map(|entry| StatusEntry { status, ..entry })
         }
     }
 
@@ -251,6 +398,13 @@ impl<'repo> Statuses<'repo> {
     /// If there are no changes in status (according to the options given
     /// when the status list was created), this should return 0.
     pub fn len(&self) -> usize {
+        match &self.entries {
+            Some(entries) => entries.len(),
+            None => self.raw_len(),
+        }
+    }
+
+    fn raw_len(&self) -> usize {
         unsafe { raw::git_status_list_entrycount(self.raw) as usize }
     }
 
@@ -266,6 +420,224 @@ impl<'repo> Statuses<'repo> {
             range: 0..self.len(),
         }
     }
+
+    /// Adjust the entries to the flags of their index entries, as found by
+    /// `index_entry`; see `adjust_status`.
+    pub(crate) fn adjust_to_index<F>(&mut self, mut index_entry: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<Option<IndexEntry>, Error>,
+    {
+        let mut entries = Vec::with_capacity(self.len());
+        let mut adjusted = false;
+        for i in 0..self.len() {
+            let (pos, status) = self.position(i);
+            let entry = self.get(i).unwrap();
+            let path = entry
+                .index_to_workdir()
+                .and_then(|delta| delta.old_file().path_bytes())
+                .or_else(|| {
+                    entry
+                        .head_to_index()
+                        .and_then(|delta| delta.new_file().path_bytes())
+                });
+            let index_entry = match path {
+                Some(path) => index_entry(path)?,
+                None => None,
+            };
+            let new_status = match &index_entry {
+                Some(index_entry) => adjust_status(entry.status(), index_entry),
+                None => Some(entry.status()),
+            };
+            match new_status {
+                Some(new_status) if new_status == entry.status() => entries.push((pos, status)),
+                Some(new_status) => {
+                    entries.push((pos, Some(new_status)));
+                    adjusted = true;
+                }
+                None => adjusted = true,
+            }
+        }
+        if adjusted {
+            self.entries = Some(entries);
+        }
+        Ok(())
+    }
+
+    /// Remove all entries.
+    pub(crate) fn clear(&mut self) {
+        self.entries = Some(Vec::new());
+    }
+
+    /// Add untracked files found without libgit2, keeping the entries sorted
+    /// by path.
+    pub(crate) fn add_untracked(&mut self, untracked: Vec<Untracked>) -> Result<(), Error> {
+        let mut extra = Vec::with_capacity(untracked.len());
+        for file in untracked {
+            extra.push(ExtraEntry::untracked(file)?);
+        }
+        extra.sort_by(|a, b| a.path.cmp(&b.path));
+        self.merge_extra(extra);
+        Ok(())
+    }
+
+    /// Combine this list, made for the index only, with `workdir`, made for
+    /// the working directory only, keeping the entries sorted by path.
+    pub(crate) fn add_workdir(&mut self, workdir: Statuses<'repo>) -> Result<(), Error> {
+        let mut extra = Vec::with_capacity(workdir.len());
+        let mut combined = HashSet::new();
+        {
+            let heads: HashMap<Vec<u8>, usize> = (0..self.len())
+                .map(|i| (self.get(i).unwrap().path_bytes().to_vec(), i))
+                .collect();
+            for entry in workdir.iter() {
+                let head = heads.get(entry.path_bytes()).map(|&i| {
+                    combined.insert(i);
+                    self.get(i).unwrap()
+                });
+                extra.push(ExtraEntry::combined(head.as_ref(), &entry)?);
+            }
+        }
+        if !combined.is_empty() {
+            let entries = (0..self.len())
+                .filter(|i| !combined.contains(i))
+                .map(|i| self.position(i))
+                .collect();
+            self.entries = Some(entries);
+        }
+        extra.sort_by(|a, b| a.path.cmp(&b.path));
+        self.merge_extra(extra);
+        self.others.push(workdir);
+        Ok(())
+    }
+
+    /// Add the `extra` entries, sorted by path, to the ones of this list.
+    fn merge_extra(&mut self, extra: Vec<ExtraEntry>) {
+        let first = self.raw_len() + self.extra.len();
+        let entries = match self.entries.take() {
+            Some(entries) => entries,
+            None => (0..self.raw_len()).map(|i| (i, None)).collect(),
+        };
+        let mut merged = Vec::with_capacity(entries.len() + extra.len());
+        let mut entries = entries.into_iter().peekable();
+        for (i, entry) in extra.iter().enumerate() {
+            let path = entry.path.as_bytes();
+            while let Some(&(pos, status)) = entries.peek() {
+                if self.get_raw(pos).path_bytes() > path {
+                    break;
+                }
+                merged.push((pos, status));
+                entries.next();
+            }
+            merged.push((first + i, None));
+        }
+        merged.extend(entries);
+        self.entries = Some(merged);
+        self.extra.extend(extra);
+    }
+
+    /// The position of the `index`th entry in `raw` or `extra`, along with
+    /// the status to report instead of the one it has.
+    fn position(&self, index: usize) -> (usize, Option<Status>) {
+        match &self.entries {
+            Some(entries) => entries[index],
+            None => (index, None),
+        }
+    }
+
+    fn get_raw(&self, pos: usize) -> StatusEntry<'_> {
+        unsafe {
+            let p = match pos.checked_sub(self.raw_len()) {
+                Some(extra) => &*self.extra[extra].entry,
+                None => raw::git_status_byindex(self.raw, pos as size_t),
+            };
+            Binding::from_raw(p)
+        }
+    }
+}
+
+impl ExtraEntry {
+    fn untracked(file: Untracked) -> Result<ExtraEntry, Error> {
+        let path = CString::new(file.path)?;
+        unsafe {
+            let mut delta: Box<raw::git_diff_delta> = Box::new(mem::zeroed());
+            delta.status = raw::GIT_DELTA_UNTRACKED;
+            delta.nfiles = 1;
+            delta.old_file.path = path.as_ptr();
+            delta.new_file.path = path.as_ptr();
+            delta.new_file.mode = file.mode;
+            let entry = Box::new(raw::git_status_entry {
+                status: raw::GIT_STATUS_WT_NEW,
+                head_to_index: ptr::null_mut(),
+                index_to_workdir: &mut *delta,
+            });
+            Ok(ExtraEntry {
+                entry,
+                _delta: Some(delta),
+                path,
+            })
+        }
+    }
+
+    /// An entry with the index status of `head`, if any, and the working
+    /// directory status of `workdir`, pointing to the deltas of both.
+    fn combined(
+        head: Option<&StatusEntry<'_>>,
+        workdir: &StatusEntry<'_>,
+    ) -> Result<ExtraEntry, Error> {
+        let path = CString::new(workdir.path_bytes())?;
+        unsafe {
+            let (status, head_to_index) = match head {
+                Some(head) => ((*head.raw).status, (*head.raw).head_to_index),
+                None => (0, ptr::null_mut()),
+            };
+            let entry = Box::new(raw::git_status_entry {
+                status: status | (*workdir.raw).status,
+                head_to_index,
+                index_to_workdir: (*workdir.raw).index_to_workdir,
+            });
+            Ok(ExtraEntry {
+                entry,
+                _delta: None,
+                path,
+            })
+        }
+    }
+}
+
+/// The status git reports for a file whose index entry is `entry`, given the
+/// status computed by libgit2, or `None` if the file should not be reported.
+///
+/// libgit2 reports a missing file as deleted even if its index entry has the
+/// skip-worktree or assume-unchanged bit, and an intent-to-add entry as a new
+/// empty file in the index.
+/// Whether `index` has entries whose status `adjust_status` changes.
+pub(crate) fn has_adjusted_entries(index: &Index) -> bool {
+    let extended = IndexEntryExtendedFlag::SKIP_WORKTREE | IndexEntryExtendedFlag::INTENT_TO_ADD;
+    index.has_flagged_entries(IndexEntryFlag::VALID.bits(), extended.bits())
+}
+
+pub(crate) fn adjust_status(status: Status, entry: &IndexEntry) -> Option<Status> {
+    let workdir = Status::WT_NEW
+        | Status::WT_MODIFIED
+        | Status::WT_DELETED
+        | Status::WT_TYPECHANGE
+        | Status::WT_RENAMED;
+    let mut adjusted = status;
+    if entry.is_skip_worktree() || entry.is_assume_unchanged() {
+        adjusted.remove(workdir);
+    }
+    if entry.is_intent_to_add() {
+        adjusted.remove(Status::INDEX_NEW);
+        if !adjusted.contains(Status::WT_DELETED) {
+            adjusted.remove(Status::WT_MODIFIED);
+            adjusted.insert(Status::WT_NEW);
+        }
+    }
+    if adjusted.is_empty() && !status.is_empty() {
+        None
+    } else {
+        Some(adjusted)
+    }
 }
 
 impl<'repo> Binding for Statuses<'repo> {
@@ -273,6 +645,9 @@ impl<'repo> Binding for Statuses<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_status_list) -> Statuses<'repo> {
         Statuses {
             raw,
+            entries: None,
+            extra: Vec::new(),
+            others: Vec::new(),
             _marker: marker::PhantomData,
         }
     }
@@ -335,13 +710,22 @@ impl<'statuses> StatusEntry<'statuses> {
 
     /// Access the status flags for this file
     pub fn status(&self) -> Status {
-        Status::from_bits_truncate(unsafe { (*self.raw).status as u32 })
+        self.status
+            .unwrap_or_else(|| Status::from_bits_truncate(unsafe { (*self.raw).status }))
     }
 
     /// Access detailed information about the differences between the file in
     /// HEAD and the file in the index.
     pub fn head_to_index(&self) -> Option<DiffDelta<'statuses>> {
-        unsafe { Binding::from_raw_opt((*self.raw).head_to_index) }
+        let index = Status::INDEX_NEW
+            | Status::INDEX_MODIFIED
+            | Status::INDEX_DELETED
+            | Status::INDEX_RENAMED
+            | Status::INDEX_TYPECHANGE;
+        match self.status {
+            Some(status) if !status.intersects(index) => None,
+            _ => unsafe { Binding::from_raw_opt((*self.raw).head_to_index) },
+        }
     }
 
     /// Access detailed information about the differences between the file in
@@ -357,6 +741,7 @@ impl<'statuses> Binding for StatusEntry<'statuses> {
     unsafe fn from_raw(raw: *const raw::git_status_entry) -> StatusEntry<'statuses> {
         StatusEntry {
             raw,
+            status: None,
             _marker: marker::PhantomData,
         }
     }
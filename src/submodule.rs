@@ -1,13 +1,16 @@
+use std::fs;
 use std::marker;
 use std::mem;
 use std::os::raw::c_int;
 use std::path::Path;
 use std::ptr;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 use crate::util::{self, Binding};
-use crate::{build::CheckoutBuilder, SubmoduleIgnore, SubmoduleUpdate};
-use crate::{raw, Error, FetchOptions, Oid, Repository};
+use crate::{build::CheckoutBuilder, SubmoduleIgnore, SubmoduleStatus, SubmoduleUpdate};
+use crate::{raw, ConfigLevel, Error, ErrorClass, ErrorCode, FetchOptions, Oid, Repository};
 
 /// A structure to represent a git [submodule][1]
 ///
@@ -246,6 +249,77 @@ impl<'repo> Submodule<'repo> {
         }
         Ok(())
     }
+
+    /// Unregister the submodule, like `git submodule deinit`.
+    ///
+    /// The contents of the submodule working tree are removed, leaving an
+    /// empty directory behind, and the `submodule.<name>` section is removed
+    /// from the configuration of the containing repository. The repository
+    /// under `.git/modules` is kept so that a later update does not need to
+    /// fetch again.
+    ///
+    /// Unless `force` is `true`, this fails if the submodule working tree has
+    /// local modifications or untracked files.
+    pub fn deinit(&mut self, force: bool) -> Result<(), Error> {
+        let repo = unsafe { Repository::borrow_raw(raw::git_submodule_owner(self.raw)) };
+        let name = str::from_utf8(self.name_bytes())
+            .map_err(|_| Error::from_str("submodule name is not valid utf-8"))?;
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| Error::from_str("cannot deinit a submodule in a bare repository"))?;
+        let path = workdir.join(self.path());
+
+        if path.join(".git").is_dir() {
+            return Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Submodule,
+                format!(
+                    "submodule '{}' has its git directory inside its working tree",
+                    name
+                ),
+            ));
+        }
+        if !force {
+            let dirty = SubmoduleStatus::WD_INDEX_MODIFIED
+                | SubmoduleStatus::WD_WD_MODIFIED
+                | SubmoduleStatus::WD_UNTRACKED;
+            if repo
+                .submodule_status(name, SubmoduleIgnore::None)?
+                .intersects(dirty)
+            {
+                return Err(Error::new(
+                    ErrorCode::Modified,
+                    ErrorClass::Submodule,
+                    format!("submodule '{}' contains local modifications", name),
+                ));
+            }
+        }
+
+        if let Ok(entries) = fs::read_dir(&path) {
+            for entry in entries {
+                let entry = entry.map_err(io_error)?;
+                let res = if entry.file_type().map_err(io_error)?.is_dir() {
+                    fs::remove_dir_all(entry.path())
+                } else {
+                    fs::remove_file(entry.path())
+                };
+                res.map_err(io_error)?;
+            }
+        }
+
+        let mut config = repo.config()?.open_level(ConfigLevel::Local)?;
+        let prefix = format!("submodule.{}.", name);
+        let mut keys = Vec::new();
+        config.entries(None)?.for_each(|entry| {
+            if let Some(key) = entry.name().filter(|key| key.starts_with(&prefix)) {
+                keys.push(key.to_string());
+            }
+        })?;
+        for key in keys {
+            config.remove(&key)?;
+        }
+        self.reload(true)
+    }
 }
 
 impl<'repo> Binding for Submodule<'repo> {
@@ -325,6 +399,245 @@ impl<'cb> Default for SubmoduleUpdateOptions<'cb> {
     }
 }
 
+/// Options for [`Repository::submodule_update_all`].
+pub struct SubmoduleUpdateAllOptions<'a> {
+    init: bool,
+    recursive: bool,
+    remote: bool,
+    jobs: usize,
+    fetch_options: Option<Box<dyn Fn() -> FetchOptions<'a> + Sync + 'a>>,
+}
+
+impl<'a> SubmoduleUpdateAllOptions<'a> {
+    /// Return default options.
+    pub fn new() -> Self {
+        SubmoduleUpdateAllOptions {
+            init: false,
+            recursive: false,
+            remote: false,
+            jobs: 1,
+            fetch_options: None,
+        }
+    }
+
+    /// Initialize submodules that have not been initialized yet, like
+    /// `--init`.
+    ///
+    /// Uninitialized submodules are skipped otherwise. Defaults to `false`.
+    pub fn init(&mut self, init: bool) -> &mut Self {
+        self.init = init;
+        self
+    }
+
+    /// Also update the submodules of each submodule, like `--recursive`.
+    ///
+    /// Defaults to `false`.
+    pub fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Check out the tip of the branch tracked by each submodule instead of
+    /// the commit recorded in the superproject, like `--remote`.
+    ///
+    /// The branch is taken from `submodule.<name>.branch`, where `.` names
+    /// the branch currently checked out in the superproject. Without it, the
+    /// `HEAD` of the remote is used. Defaults to `false`.
+    pub fn remote(&mut self, remote: bool) -> &mut Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Number of submodules to update in parallel, like `--jobs`.
+    ///
+    /// Defaults to 1.
+    pub fn jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Set a function creating the fetch options for each fetch.
+    ///
+    /// A function is needed as fetches may run on several threads at once,
+    /// each of which needs its own callbacks.
+    pub fn fetch_options<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() -> FetchOptions<'a> + Sync + 'a,
+    {
+        self.fetch_options = Some(Box::new(f));
+        self
+    }
+}
+
+impl<'a> Default for SubmoduleUpdateAllOptions<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn update_all(
+    repo: &Repository,
+    opts: &SubmoduleUpdateAllOptions<'_>,
+) -> Result<(), Error> {
+    let mut names = Vec::new();
+    {
+        let config = repo.config()?;
+        for mut submodule in repo.submodules()? {
+            if submodule.update_strategy() == SubmoduleUpdate::None {
+                continue;
+            }
+            let name = str::from_utf8(submodule.name_bytes())
+                .map_err(|_| Error::from_str("submodule name is not valid utf-8"))?
+                .to_string();
+            if opts.init {
+                submodule.init(false)?;
+            } else if config
+                .get_entry(&format!("submodule.{}.url", name))
+                .is_err()
+            {
+                continue;
+            }
+            names.push(name);
+        }
+    }
+
+    let jobs = opts.jobs.clamp(1, names.len().max(1));
+    if jobs == 1 {
+        for name in &names {
+            update_one(repo, name, opts)?;
+        }
+    } else {
+        // Each thread works on its own handle to the repository, picking the
+        // next submodule to update from the shared list.
+        let next = AtomicUsize::new(0);
+        let path = repo.path();
+        thread::scope(|scope| {
+            let handles = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), Error> {
+                        let repo = Repository::open(path)?;
+                        while let Some(name) = names.get(next.fetch_add(1, Ordering::SeqCst)) {
+                            update_one(&repo, name, opts)?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+        })?;
+    }
+
+    if opts.recursive {
+        for name in &names {
+            update_all(&repo.find_submodule(name)?.open()?, opts)?;
+        }
+    }
+    Ok(())
+}
+
+fn update_one(
+    repo: &Repository,
+    name: &str,
+    opts: &SubmoduleUpdateAllOptions<'_>,
+) -> Result<(), Error> {
+    let mut submodule = repo.find_submodule(name)?;
+    if submodule.open().is_err() {
+        reattach(repo, &submodule)?;
+    }
+    if !opts.remote || submodule.open().is_err() {
+        let mut update = SubmoduleUpdateOptions::new();
+        if let Some(f) = &opts.fetch_options {
+            update.fetch(f());
+        }
+        submodule.update(false, Some(&mut update))?;
+    }
+    if !opts.remote {
+        return Ok(());
+    }
+
+    let branch = match submodule.branch() {
+        Some(".") => repo
+            .head()?
+            .shorthand()
+            .ok_or_else(|| Error::from_str("superproject HEAD is not a branch"))?
+            .to_string(),
+        Some(branch) => branch.to_string(),
+        None => "HEAD".to_string(),
+    };
+    let subrepo = submodule.open()?;
+    let mut fetch = opts.fetch_options.as_ref().map(|f| f());
+    subrepo
+        .find_remote("origin")?
+        .fetch(&[&branch], fetch.as_mut(), None)?;
+    let target = subrepo.refname_to_id("FETCH_HEAD").map_err(|_| {
+        Error::new(
+            ErrorCode::NotFound,
+            ErrorClass::Submodule,
+            format!(
+                "remote branch '{}' not found for submodule '{}'",
+                branch, name
+            ),
+        )
+    })?;
+    let commit = subrepo.find_commit(target)?;
+    subrepo.checkout_tree(commit.as_object(), None)?;
+    subrepo.set_head_detached(commit.id())
+}
+
+/// Point an empty submodule working tree back at the repository kept in
+/// `.git/modules` by [`Submodule::deinit`] and check it out, as cloning into
+/// the existing repository would fail.
+fn reattach(repo: &Repository, submodule: &Submodule<'_>) -> Result<(), Error> {
+    let gitdir = repo
+        .path()
+        .join("modules")
+        .join(util::bytes2path(submodule.name_bytes()));
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.join(submodule.path()),
+        None => return Ok(()),
+    };
+    if !gitdir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(&workdir).map_err(io_error)?;
+    fs::write(
+        workdir.join(".git"),
+        format!("gitdir: {}\n", gitdir.display()),
+    )
+    .map_err(io_error)?;
+    let subrepo = submodule.open()?;
+    subrepo.checkout_head(Some(CheckoutBuilder::new().force()))
+}
+
+pub(crate) fn foreach(
+    repo: &Repository,
+    recursive: bool,
+    prefix: &Path,
+    callback: &mut dyn FnMut(&Path, &Submodule<'_>, &Repository) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for submodule in repo.submodules()? {
+        // Submodules that are not checked out are skipped, as git does.
+        let subrepo = match submodule.open() {
+            Ok(subrepo) => subrepo,
+            Err(_) => continue,
+        };
+        let path = prefix.join(submodule.path());
+        callback(&path, &submodule, &subrepo)?;
+        if recursive {
+            foreach(&subrepo, true, &path, callback)?;
+        }
+    }
+    Ok(())
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::new(ErrorCode::GenericError, ErrorClass::Os, e.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -333,7 +646,7 @@ mod tests {
     use url::Url;
 
     use crate::Repository;
-    use crate::SubmoduleUpdateOptions;
+    use crate::{SubmoduleUpdateAllOptions, SubmoduleUpdateOptions};
 
     #[test]
     fn smoke() {
@@ -468,4 +781,92 @@ mod tests {
         t!(child.repo_init(true));
         assert!(child.open().is_ok());
     }
+
+    fn add_submodule(parent: &Repository, child: &Repository, path: &str) {
+        let url = crate::test::path2url(child.workdir().unwrap());
+        let mut sub = parent.submodule(&url, Path::new(path), true).unwrap();
+        t!(sub.clone(None));
+        t!(sub.add_to_index(true));
+        t!(sub.add_finalize());
+        crate::test::commit(parent);
+    }
+
+    /// Clone a superproject with a nested submodule: top -> mid -> leaf.
+    fn nested() -> (Vec<TempDir>, Repository, Repository) {
+        let (td1, leaf) = crate::test::repo_init();
+        let (td2, mid) = crate::test::repo_init();
+        let (td3, top) = crate::test::repo_init();
+        add_submodule(&mid, &leaf, "leaf");
+        add_submodule(&top, &mid, "mid");
+
+        let td4 = TempDir::new().unwrap();
+        let url = crate::test::path2url(top.workdir().unwrap());
+        let clone = Repository::clone(&url, td4.path()).unwrap();
+        (vec![td1, td2, td3, td4], leaf, clone)
+    }
+
+    fn visited(repo: &Repository, recursive: bool) -> Vec<String> {
+        let mut paths = Vec::new();
+        t!(repo.submodule_foreach(recursive, |path, _, subrepo| {
+            assert!(subrepo.workdir().unwrap().ends_with(path));
+            paths.push(path.to_str().unwrap().to_string());
+            Ok(())
+        }));
+        paths
+    }
+
+    #[test]
+    fn update_all() {
+        let (_td, leaf, repo) = nested();
+        assert!(visited(&repo, true).is_empty());
+
+        // Nothing is initialized, so nothing is updated without `init`.
+        t!(repo.submodule_update_all(None));
+        assert!(visited(&repo, true).is_empty());
+
+        let mut opts = SubmoduleUpdateAllOptions::new();
+        opts.init(true).jobs(4);
+        t!(repo.submodule_update_all(Some(&opts)));
+        assert_eq!(visited(&repo, true), ["mid"]);
+
+        opts.recursive(true);
+        t!(repo.submodule_update_all(Some(&opts)));
+        assert_eq!(visited(&repo, true), ["mid", "mid/leaf"]);
+        assert_eq!(visited(&repo, false), ["mid"]);
+
+        let leaf_head = leaf.head().unwrap().target().unwrap();
+        let nested = Repository::open(repo.workdir().unwrap().join("mid/leaf")).unwrap();
+        assert_eq!(nested.head().unwrap().target(), Some(leaf_head));
+
+        // With `remote`, the tip of the remote branch is checked out instead.
+        let (tip, _) = crate::test::commit(&leaf);
+        opts.remote(true);
+        t!(repo.submodule_update_all(Some(&opts)));
+        assert_eq!(nested.head().unwrap().target(), Some(tip));
+    }
+
+    #[test]
+    fn deinit() {
+        let (_td, _leaf, repo) = nested();
+        let mut opts = SubmoduleUpdateAllOptions::new();
+        opts.init(true);
+        t!(repo.submodule_update_all(Some(&opts)));
+        let path = repo.workdir().unwrap().join("mid");
+
+        t!(fs::write(path.join("untracked"), "data"));
+        let mut sub = repo.find_submodule("mid").unwrap();
+        assert!(sub.deinit(false).is_err());
+        t!(sub.deinit(true));
+
+        assert!(path.is_dir());
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 0);
+        let config = repo.config().unwrap().snapshot().unwrap();
+        assert!(config.get_str("submodule.mid.url").is_err());
+        assert!(visited(&repo, true).is_empty());
+
+        // The submodule can be brought back without fetching it again.
+        t!(repo.submodule_update_all(Some(&opts)));
+        assert_eq!(visited(&repo, true), ["mid"]);
+        assert!(path.join(".gitmodules").is_file());
+    }
 }
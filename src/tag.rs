@@ -4,8 +4,9 @@ use std::mem;
 use std::ptr;
 use std::str;
 
+use crate::signing::{self, SignatureVerification, Verifier};
 use crate::util::Binding;
-use crate::{call, raw, signature, Error, Object, ObjectType, Oid, Signature};
+use crate::{call, raw, signature, Error, Object, ObjectType, Oid, Repository, Signature};
 
 /// A structure to represent a git [tag][1]
 ///
@@ -105,6 +106,24 @@ impl<'repo> Tag<'repo> {
         unsafe { ObjectType::from_raw(raw::git_tag_target_type(&*self.raw)) }
     }
 
+    /// Check the signature appended to the message of this tag with
+    /// `verifier`.
+    ///
+    /// A tag without a signature is reported as
+    /// [`SignatureStatus::Unsigned`](crate::SignatureStatus::Unsigned).
+    pub fn verify_signature(
+        &self,
+        verifier: &dyn Verifier,
+    ) -> Result<SignatureVerification, Error> {
+        let repo = unsafe { Repository::borrow_raw(raw::git_tag_owner(&*self.raw)) };
+        let odb = repo.odb()?;
+        let object = odb.read(self.id())?;
+        match signing::split_signed_buffer(object.data()) {
+            Some((content, signature)) => verifier.verify(signature, content),
+            None => Ok(SignatureVerification::unsigned()),
+        }
+    }
+
     /// Casts this Tag to be usable as an `Object`
     pub fn as_object(&self) -> &Object<'repo> {
         unsafe { &*(self as *const _ as *const Object<'repo>) }
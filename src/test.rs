@@ -1,4 +1,4 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::path::{Path, PathBuf};
 #[cfg(unix)]
@@ -52,6 +52,31 @@ pub fn commit(repo: &Repository) -> (Oid, Oid) {
     (commit, tree_id)
 }
 
+/// Commit `files` on top of HEAD through the working directory and index,
+/// removing those whose contents are `None`.
+pub fn commit_files(repo: &Repository, files: &[(&str, Option<&str>)], message: &str) -> Oid {
+    let root = repo.workdir().unwrap();
+    let mut index = t!(repo.index());
+    for (file, contents) in files {
+        match contents {
+            Some(contents) => {
+                t!(fs::create_dir_all(root.join(file).parent().unwrap()));
+                t!(fs::write(root.join(file), contents));
+                t!(index.add_path(Path::new(file)));
+            }
+            None => {
+                t!(fs::remove_file(root.join(file)));
+                t!(index.remove_path(Path::new(file)));
+            }
+        }
+    }
+    t!(index.write());
+    let tree = t!(repo.find_tree(t!(index.write_tree())));
+    let sig = t!(repo.signature());
+    let head = t!(t!(repo.head()).peel_to_commit());
+    t!(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&head]))
+}
+
 pub fn path2url(path: &Path) -> String {
     Url::from_file_path(path).unwrap().to_string()
 }
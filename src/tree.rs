@@ -9,7 +9,7 @@ use std::ptr;
 use std::str;
 
 use crate::util::{c_cmp_to_ordering, path_to_repo_path, Binding};
-use crate::{panic, raw, Error, Object, ObjectType, Oid, Repository};
+use crate::{panic, raw, Error, Object, ObjectType, Oid, Repository, TreeEditor};
 
 /// A structure to represent a git [tree][1]
 ///
@@ -194,6 +194,13 @@ impl<'repo> Tree<'repo> {
         }
     }
 
+    /// Create a [`TreeEditor`] to edit this tree by path.
+    ///
+    /// [`TreeEditor`]: crate::TreeEditor
+    pub fn editor(&self) -> Result<TreeEditor<'repo>, Error> {
+        TreeEditor::from_tree(self)
+    }
+
     /// Casts this Tree to be usable as an `Object`
     pub fn as_object(&self) -> &Object<'repo> {
         unsafe { &*(self as *const _ as *const Object<'repo>) }
@@ -0,0 +1,567 @@
+use std::collections::BTreeMap;
+use std::marker;
+use std::mem::ManuallyDrop;
+use std::path::Path;
+
+use crate::util::{self, path_to_repo_path, Binding};
+use crate::{raw, Error, ErrorClass, ErrorCode, FileMode, Oid, Repository, Tree};
+
+const TREE_MODE: i32 = 0o040000;
+
+/// An editor of a tree by path, which nests and unnests subtrees as needed.
+///
+/// Unlike [`TreeBuilder`], which edits a single tree, and
+/// [`build::TreeUpdateBuilder`], which applies upserts and removals in one go,
+/// the editor keeps the edited trees in memory. Intermediate trees are
+/// created for the paths inserted, and trees left empty by a removal are
+/// removed. Only the trees that changed are written, and the changes made can
+/// be listed before writing them.
+///
+/// [`TreeBuilder`]: crate::TreeBuilder
+/// [`build::TreeUpdateBuilder`]: crate::build::TreeUpdateBuilder
+pub struct TreeEditor<'repo> {
+    repo: ManuallyDrop<Repository>,
+    root: Dir,
+    _marker: marker::PhantomData<&'repo Repository>,
+}
+
+/// A change made by a [`TreeEditor`] to an entry of the tree it started from.
+///
+/// A tree inserted or removed as a whole is a single change; a tree edited
+/// through the editor is described by the changes to its entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeChange {
+    path: Vec<u8>,
+    old: Option<(Oid, i32)>,
+    new: Option<(Oid, i32)>,
+}
+
+/// A tree being edited.
+struct Dir {
+    /// The tree this one started as, if any.
+    base: Option<Oid>,
+    entries: BTreeMap<Vec<u8>, Node>,
+    modified: bool,
+}
+
+enum Node {
+    /// An entry not being edited, which may be a tree.
+    Entry(Oid, i32),
+    Dir(Dir),
+}
+
+impl<'repo> TreeEditor<'repo> {
+    /// Creates an editor starting from `tree`, or from an empty tree.
+    pub fn new(
+        repo: &'repo Repository,
+        tree: Option<&Tree<'_>>,
+    ) -> Result<TreeEditor<'repo>, Error> {
+        let repo = unsafe { Repository::borrow_raw(repo.raw()) };
+        let root = match tree {
+            Some(tree) => Dir::load(&repo, tree.id())?,
+            None => Dir::new(),
+        };
+        Ok(TreeEditor {
+            repo,
+            root,
+            _marker: marker::PhantomData,
+        })
+    }
+
+    pub(crate) fn from_tree(tree: &Tree<'repo>) -> Result<TreeEditor<'repo>, Error> {
+        let repo = unsafe { Repository::borrow_raw(raw::git_tree_owner(tree.raw())) };
+        let root = Dir::load(&repo, tree.id())?;
+        Ok(TreeEditor {
+            repo,
+            root,
+            _marker: marker::PhantomData,
+        })
+    }
+
+    /// Insert the object `id` at `path`, replacing what was there, and
+    /// creating the trees leading to it.
+    ///
+    /// A file in the way of one of the trees leading to `path` is replaced by
+    /// the tree. No attempt is made to ensure that `id` points to an object
+    /// of the type `filemode` calls for.
+    pub fn insert(&mut self, path: &Path, id: Oid, filemode: FileMode) -> Result<(), Error> {
+        let components = components(path)?;
+        let mode = i32::from(filemode);
+        self.root
+            .put(&self.repo, &components, Node::Entry(id, mode))
+    }
+
+    /// Insert `tree` at `path`, like `insert` does.
+    pub fn insert_tree(&mut self, path: &Path, tree: &Tree<'_>) -> Result<(), Error> {
+        self.insert(path, tree.id(), FileMode::Tree)
+    }
+
+    /// Remove the entry at `path`, along with the trees this leaves empty.
+    ///
+    /// The root tree is left in place, even if empty.
+    pub fn remove(&mut self, path: &Path) -> Result<(), Error> {
+        let components = components(path)?;
+        self.root.take(&self.repo, &components)?;
+        Ok(())
+    }
+
+    /// Move the entry at `from`, which may be a tree, to `to`, creating the
+    /// trees leading to it and removing the ones left empty.
+    ///
+    /// Fails if there is already an entry at `to`. As with `insert`, a file
+    /// in the way of one of the trees leading to `to` is replaced.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        let from = components(from)?;
+        let to = components(to)?;
+        if to.starts_with(&from) {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Tree,
+                format!("cannot move '{}' into itself", join(&from).escape_ascii()),
+            ));
+        }
+        if self.root.get(&self.repo, &to)?.is_some() {
+            return Err(Error::new(
+                ErrorCode::Exists,
+                ErrorClass::Tree,
+                format!("'{}' already exists", join(&to).escape_ascii()),
+            ));
+        }
+        let node = self.root.take(&self.repo, &from)?;
+        self.root.put(&self.repo, &to, node)
+    }
+
+    /// List the changes made so far, in path order.
+    pub fn changes(&self) -> Result<Vec<TreeChange>, Error> {
+        let mut changes = Vec::new();
+        self.root
+            .changes(&self.repo, self.root.base, &mut Vec::new(), &mut changes)?;
+        Ok(changes)
+    }
+
+    /// Write the trees that changed to the object database and return the
+    /// id of the root tree.
+    pub fn write(&self) -> Result<Oid, Error> {
+        self.root.write(&self.repo)
+    }
+}
+
+impl TreeChange {
+    /// The path of the entry.
+    pub fn path(&self) -> &Path {
+        util::bytes2path(&self.path)
+    }
+
+    /// The path of the entry, as bytes.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// The id of the entry before the change, if it existed.
+    pub fn old_id(&self) -> Option<Oid> {
+        self.old.map(|(id, _)| id)
+    }
+
+    /// The file mode of the entry before the change, if it existed.
+    pub fn old_filemode(&self) -> Option<i32> {
+        self.old.map(|(_, mode)| mode)
+    }
+
+    /// The id of the entry after the change, if it still exists.
+    pub fn new_id(&self) -> Option<Oid> {
+        self.new.map(|(id, _)| id)
+    }
+
+    /// The file mode of the entry after the change, if it still exists.
+    pub fn new_filemode(&self) -> Option<i32> {
+        self.new.map(|(_, mode)| mode)
+    }
+}
+
+impl Dir {
+    fn new() -> Dir {
+        Dir {
+            base: None,
+            entries: BTreeMap::new(),
+            modified: true,
+        }
+    }
+
+    fn load(repo: &Repository, id: Oid) -> Result<Dir, Error> {
+        let entries = entries(repo, id)?
+            .into_iter()
+            .map(|(name, (id, mode))| (name, Node::Entry(id, mode)))
+            .collect();
+        Ok(Dir {
+            base: Some(id),
+            entries,
+            modified: false,
+        })
+    }
+
+    /// The tree named `name` in this one, loading it if needed, or `None` if
+    /// there is no such tree.
+    fn subdir(&mut self, repo: &Repository, name: &[u8]) -> Result<Option<&mut Dir>, Error> {
+        let node = match self.entries.get_mut(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        if let Node::Entry(id, TREE_MODE) = *node {
+            *node = Node::Dir(Dir::load(repo, id)?);
+        }
+        match node {
+            Node::Dir(dir) => Ok(Some(dir)),
+            Node::Entry(..) => Ok(None),
+        }
+    }
+
+    fn get(&mut self, repo: &Repository, path: &[Vec<u8>]) -> Result<Option<&Node>, Error> {
+        let (name, rest) = path.split_first().unwrap();
+        if rest.is_empty() {
+            return Ok(self.entries.get(name));
+        }
+        match self.subdir(repo, name)? {
+            Some(dir) => dir.get(repo, rest),
+            None => Ok(None),
+        }
+    }
+
+    /// Put `node` at `path`, replacing what is there, and creating the trees
+    /// leading to it.
+    fn put(&mut self, repo: &Repository, path: &[Vec<u8>], node: Node) -> Result<(), Error> {
+        let (name, rest) = path.split_first().unwrap();
+        if rest.is_empty() {
+            self.entries.insert(name.to_vec(), node);
+            self.modified = true;
+            return Ok(());
+        }
+        if self.subdir(repo, name)?.is_none() {
+            self.entries.insert(name.to_vec(), Node::Dir(Dir::new()));
+        }
+        let dir = self.subdir(repo, name)?.unwrap();
+        dir.put(repo, rest, node)?;
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Remove the entry at `path` and return it, removing the trees left
+    /// empty.
+    fn take(&mut self, repo: &Repository, path: &[Vec<u8>]) -> Result<Node, Error> {
+        match self.take_from(repo, path)? {
+            Some(node) => Ok(node),
+            None => Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Tree,
+                format!("'{}' is not in the tree", join(path).escape_ascii()),
+            )),
+        }
+    }
+
+    fn take_from(&mut self, repo: &Repository, path: &[Vec<u8>]) -> Result<Option<Node>, Error> {
+        let (name, rest) = path.split_first().unwrap();
+        let node = if rest.is_empty() {
+            self.entries.remove(name)
+        } else {
+            match self.subdir(repo, name)? {
+                Some(dir) => {
+                    let node = dir.take_from(repo, rest)?;
+                    if node.is_some() && dir.entries.is_empty() {
+                        self.entries.remove(name);
+                    }
+                    node
+                }
+                None => None,
+            }
+        };
+        if node.is_some() {
+            self.modified = true;
+        }
+        Ok(node)
+    }
+
+    fn write(&self, repo: &Repository) -> Result<Oid, Error> {
+        if let (false, Some(base)) = (self.modified, self.base) {
+            return Ok(base);
+        }
+        let mut builder = repo.treebuilder(None)?;
+        for (name, node) in &self.entries {
+            let (id, mode) = match node {
+                Node::Entry(id, mode) => (*id, *mode),
+                Node::Dir(dir) => (dir.write(repo)?, TREE_MODE),
+            };
+            builder.insert(&name[..], id, mode)?;
+        }
+        builder.write()
+    }
+
+    /// Add the changes from the tree `old` to this one, found at `prefix`.
+    fn changes(
+        &self,
+        repo: &Repository,
+        old: Option<Oid>,
+        prefix: &mut Vec<u8>,
+        out: &mut Vec<TreeChange>,
+    ) -> Result<(), Error> {
+        if !self.modified && self.base == old {
+            return Ok(());
+        }
+        let old = match old {
+            Some(old) => entries(repo, old)?,
+            None => BTreeMap::new(),
+        };
+        let mut names: Vec<&Vec<u8>> = old.keys().chain(self.entries.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let len = prefix.len();
+            if !prefix.is_empty() {
+                prefix.push(b'/');
+            }
+            prefix.extend_from_slice(name);
+            let before = old.get(name).copied();
+            match self.entries.get(name) {
+                Some(Node::Dir(dir)) if dir.modified => {
+                    // An edited tree replacing a file is the file's removal
+                    // and the tree's entries being added.
+                    match before {
+                        Some((id, TREE_MODE)) => dir.changes(repo, Some(id), prefix, out)?,
+                        _ => {
+                            if before.is_some() {
+                                out.push(TreeChange {
+                                    path: prefix.clone(),
+                                    old: before,
+                                    new: None,
+                                });
+                            }
+                            dir.changes(repo, None, prefix, out)?;
+                        }
+                    }
+                }
+                after => {
+                    let after = match after {
+                        Some(Node::Entry(id, mode)) => Some((*id, *mode)),
+                        Some(Node::Dir(dir)) => dir.base.map(|id| (id, TREE_MODE)),
+                        None => None,
+                    };
+                    if before != after {
+                        out.push(TreeChange {
+                            path: prefix.clone(),
+                            old: before,
+                            new: after,
+                        });
+                    }
+                }
+            }
+            prefix.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+/// The entries of the tree `id`, by name.
+fn entries(repo: &Repository, id: Oid) -> Result<BTreeMap<Vec<u8>, (Oid, i32)>, Error> {
+    let tree = repo.find_tree(id)?;
+    Ok(tree
+        .iter()
+        .map(|entry| {
+            let name = entry.name_bytes().to_vec();
+            (name, (entry.id(), entry.filemode()))
+        })
+        .collect())
+}
+
+/// The components of `path`, which must name an entry in a tree.
+fn components(path: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let repo_path = path_to_repo_path(path)?;
+    let components: Vec<Vec<u8>> = repo_path
+        .as_bytes()
+        .split(|&b| b == b'/')
+        .map(|c| c.to_vec())
+        .collect();
+    let valid = components
+        .iter()
+        .all(|c| !c.is_empty() && c != b"." && c != b".." && !c.eq_ignore_ascii_case(b".git"));
+    if !valid {
+        return Err(Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Tree,
+            format!("invalid path '{}'", path.display()),
+        ));
+    }
+    Ok(components)
+}
+
+fn join(path: &[Vec<u8>]) -> Vec<u8> {
+    path.join(&b'/')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorCode, FileMode, Oid, Repository, TreeEditor};
+    use std::path::Path;
+
+    fn blob(repo: &Repository, data: &str) -> Oid {
+        t!(repo.blob(data.as_bytes()))
+    }
+
+    fn paths(repo: &Repository, id: Oid) -> Vec<String> {
+        let tree = t!(repo.find_tree(id));
+        let mut paths = Vec::new();
+        t!(tree.walk(crate::TreeWalkMode::PreOrder, |root, entry| {
+            paths.push(format!("{}{}", root, entry.name().unwrap()));
+            crate::TreeWalkResult::Ok
+        }));
+        paths
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let (_td, repo) = crate::test::repo_init();
+        let a = blob(&repo, "a");
+        let b = blob(&repo, "b");
+
+        let mut editor = t!(TreeEditor::new(&repo, None));
+        t!(editor.insert(Path::new("x/y/a"), a, FileMode::Blob));
+        t!(editor.insert(Path::new("x/b"), b, FileMode::BlobExecutable));
+        t!(editor.insert(Path::new("z/a"), a, FileMode::Blob));
+        let id = t!(editor.write());
+        assert_eq!(paths(&repo, id), ["x", "x/b", "x/y", "x/y/a", "z", "z/a"]);
+        let tree = t!(repo.find_tree(id));
+        let entry = t!(tree.get_path(Path::new("x/b")));
+        assert_eq!(entry.filemode(), i32::from(FileMode::BlobExecutable));
+
+        let mut editor = t!(tree.editor());
+        t!(editor.remove(Path::new("x/y/a")));
+        assert_eq!(
+            editor.remove(Path::new("x/y/a")).unwrap_err().code(),
+            ErrorCode::NotFound
+        );
+        assert_eq!(
+            editor
+                .insert(Path::new("x/../a"), a, FileMode::Blob)
+                .unwrap_err()
+                .code(),
+            ErrorCode::Invalid
+        );
+        let id2 = t!(editor.write());
+        assert_eq!(paths(&repo, id2), ["x", "x/b", "z", "z/a"]);
+
+        // The untouched subtree is shared between the two trees.
+        let tree2 = t!(repo.find_tree(id2));
+        let z = t!(tree.get_path(Path::new("z"))).id();
+        assert_eq!(t!(tree2.get_path(Path::new("z"))).id(), z);
+
+        // Removing everything leaves an empty root.
+        t!(editor.remove(Path::new("x")));
+        t!(editor.remove(Path::new("z/a")));
+        let empty = t!(t!(repo.treebuilder(None)).write());
+        assert_eq!(t!(editor.write()), empty);
+    }
+
+    #[test]
+    fn remove_missing() {
+        let (_td, repo) = crate::test::repo_init();
+        let empty = t!(t!(repo.treebuilder(None)).write());
+        let mut builder = t!(repo.treebuilder(None));
+        t!(builder.insert("empty", empty, 0o040000));
+        let base = t!(builder.write());
+
+        // A failed removal leaves the tree, empty subtrees included, alone.
+        let mut editor = t!(t!(repo.find_tree(base)).editor());
+        assert_eq!(
+            editor.remove(Path::new("empty/a")).unwrap_err().code(),
+            ErrorCode::NotFound
+        );
+        assert_eq!(t!(editor.write()), base);
+        t!(editor.insert(Path::new("a"), blob(&repo, "a"), FileMode::Blob));
+        assert_eq!(paths(&repo, t!(editor.write())), ["a", "empty"]);
+    }
+
+    #[test]
+    fn rename() {
+        let (_td, repo) = crate::test::repo_init();
+        let a = blob(&repo, "a");
+        let mut editor = t!(TreeEditor::new(&repo, None));
+        t!(editor.insert(Path::new("dir/sub/a"), a, FileMode::Blob));
+        t!(editor.insert(Path::new("dir/b"), a, FileMode::Blob));
+        let tree = t!(repo.find_tree(t!(editor.write())));
+
+        let mut editor = t!(tree.editor());
+        assert_eq!(
+            editor
+                .rename(Path::new("dir/sub/a"), Path::new("dir/b"))
+                .unwrap_err()
+                .code(),
+            ErrorCode::Exists
+        );
+        assert_eq!(
+            editor
+                .rename(Path::new("dir"), Path::new("dir/sub/dir"))
+                .unwrap_err()
+                .code(),
+            ErrorCode::Invalid
+        );
+        assert_eq!(
+            editor
+                .rename(Path::new("missing"), Path::new("found"))
+                .unwrap_err()
+                .code(),
+            ErrorCode::NotFound
+        );
+        t!(editor.rename(Path::new("dir/sub/a"), Path::new("new/a")));
+        t!(editor.rename(Path::new("dir"), Path::new("old")));
+        let id = t!(editor.write());
+        assert_eq!(paths(&repo, id), ["new", "new/a", "old", "old/b"]);
+    }
+
+    #[test]
+    fn insert_tree_and_changes() {
+        let (_td, repo) = crate::test::repo_init();
+        let a = blob(&repo, "a");
+        let b = blob(&repo, "b");
+        let mut editor = t!(TreeEditor::new(&repo, None));
+        t!(editor.insert(Path::new("lib/a"), a, FileMode::Blob));
+        t!(editor.insert(Path::new("src/a"), a, FileMode::Blob));
+        t!(editor.insert(Path::new("src/b"), b, FileMode::Blob));
+        let tree = t!(repo.find_tree(t!(editor.write())));
+        let lib = t!(tree.get_path(Path::new("lib"))).id();
+        let lib = t!(repo.find_tree(lib));
+
+        let mut editor = t!(tree.editor());
+        assert_eq!(t!(editor.changes()), []);
+        t!(editor.insert_tree(Path::new("vendor/lib"), &lib));
+        t!(editor.remove(Path::new("lib")));
+        t!(editor.insert(Path::new("src/a"), b, FileMode::Blob));
+        t!(editor.rename(Path::new("src/b"), Path::new("src/c")));
+        let changes = t!(editor.changes());
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.path().to_str().unwrap(), c.old_id(), c.new_id()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("lib", Some(lib.id()), None),
+                ("src/a", Some(a), Some(b)),
+                ("src/b", Some(b), None),
+                ("src/c", None, Some(b)),
+                ("vendor/lib", None, Some(lib.id())),
+            ]
+        );
+        assert_eq!(changes[0].old_filemode(), Some(0o040000));
+        assert_eq!(changes[4].new_filemode(), Some(0o040000));
+
+        let id = t!(editor.write());
+        assert_eq!(
+            paths(&repo, id),
+            [
+                "src",
+                "src/a",
+                "src/c",
+                "vendor",
+                "vendor/lib",
+                "vendor/lib/a"
+            ]
+        );
+    }
+}
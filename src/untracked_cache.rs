@@ -0,0 +1,585 @@
+//! git's untracked cache, the `UNTR` index extension.
+//!
+//! git records the untracked files of every directory it scans along with the
+//! stat data of the directory and the id of its `.gitignore`. As long as none
+//! of them changed, the directory need not be read again. libgit2 does not
+//! know about the cache, so `Repository::statuses` consults it before asking
+//! libgit2 for the rest of the status.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::index_file::{ewah_bits, IndexFile, Reader};
+use crate::util;
+use crate::{Error, ErrorClass, ErrorCode, Index, ObjectType, Oid, Repository};
+
+const HASH_SIZE: usize = 20;
+const UNTR: &[u8; 4] = b"UNTR";
+
+/// git's `DIR_SHOW_OTHER_DIRECTORIES | DIR_HIDE_EMPTY_DIRECTORIES`, the
+/// flags of `git status` with the default `--untracked-files=normal`, which
+/// lists untracked directories as a whole and leaves empty ones out, as
+/// libgit2 does without `recurse_untracked_dirs`.
+const NORMAL_DIR_FLAGS: u32 = 1 << 1 | 1 << 2;
+
+/// An untracked file or directory found in the cache.
+pub(crate) struct Untracked {
+    /// The path, ending with a slash for a directory.
+    pub(crate) path: Vec<u8>,
+    /// The mode libgit2 would report for it in the working directory.
+    pub(crate) mode: u16,
+}
+
+/// The parsed untracked cache.
+struct UntrackedCache {
+    ident: Vec<u8>,
+    info_exclude: Oid,
+    excludes_file: Oid,
+    dir_flags: u32,
+    exclude_per_dir: Vec<u8>,
+    /// The directories, in pre-order; the first one is the root.
+    dirs: Vec<Dir>,
+}
+
+struct Dir {
+    name: Vec<u8>,
+    untracked: Vec<Vec<u8>>,
+    dirs: Vec<usize>,
+    valid: bool,
+    check_only: bool,
+    stat: StatData,
+    exclude: Option<Oid>,
+}
+
+/// The parts of `struct stat` git compares by default, truncated to 32 bits.
+#[derive(Default, PartialEq, Eq)]
+struct StatData {
+    ctime: (u32, u32),
+    mtime: (u32, u32),
+    ino: u32,
+    uid: u32,
+    gid: u32,
+    size: u32,
+}
+
+/// The untracked files of the working directory of `repo` according to the
+/// untracked cache, or `None` if there is no usable cache.
+///
+/// The cache is only used if it is complete and up to date; otherwise git
+/// fills in the missing parts the next time it runs `git status`.
+pub(crate) fn untracked_files(repo: &Repository) -> Result<Option<Vec<Untracked>>, Error> {
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Ok(None),
+    };
+    let config = repo.config()?;
+    // `keep`, the default, uses the cache if there is one.
+    if config.get_bool("core.untrackedCache") == Ok(false)
+        || config.get_bool("core.ignoreCase") == Ok(true)
+    {
+        return Ok(None);
+    }
+    let index_path = repo.path().join("index");
+    let index_mtime = match fs::symlink_metadata(&index_path) {
+        Ok(meta) => StatData::from(&meta).mtime,
+        Err(_) => return Ok(None),
+    };
+    let file = IndexFile::read_merged(&index_path)?;
+    let cache = match file.extension(UNTR) {
+        Some(data) => UntrackedCache::parse(data)?,
+        None => return Ok(None),
+    };
+
+    // The cache only describes the index on disk.
+    let mut index = repo.index()?;
+    index.read(false)?;
+    if !file.matches(&index) || !cache.is_current(repo, &index, workdir, index_mtime)? {
+        return Ok(None);
+    }
+
+    let mut untracked = Vec::new();
+    cache.collect(0, &mut Vec::new(), &mut untracked);
+    let mut files = Vec::with_capacity(untracked.len());
+    for path in untracked {
+        let mode = if path.ends_with(b"/") {
+            // A directory with tracked files is not untracked as a whole.
+            if index.find_prefix(&path[..]).is_ok() {
+                return Ok(None);
+            }
+            0o040000
+        } else if index.get_path(util::bytes2path(&path), 0).is_some() {
+            continue;
+        } else {
+            match fs::symlink_metadata(workdir.join(util::bytes2path(&path))) {
+                Ok(meta) => file_mode(&meta),
+                Err(_) => return Ok(None),
+            }
+        };
+        files.push(Untracked { path, mode });
+    }
+    Ok(Some(files))
+}
+
+impl UntrackedCache {
+    fn parse(data: &[u8]) -> Result<UntrackedCache, Error> {
+        let mut reader = Reader::new(data);
+        let ident_len = reader.varint()? as usize;
+        let ident = reader.bytes(ident_len)?.to_vec();
+        let _info_exclude_stat = StatData::read(&mut reader)?;
+        let _excludes_file_stat = StatData::read(&mut reader)?;
+        let dir_flags = reader.u32()?;
+        let info_exclude = Oid::from_bytes(reader.bytes(HASH_SIZE)?)?;
+        let excludes_file = Oid::from_bytes(reader.bytes(HASH_SIZE)?)?;
+        let exclude_per_dir = reader.cstr()?.to_vec();
+        let mut cache = UntrackedCache {
+            ident,
+            info_exclude,
+            excludes_file,
+            dir_flags,
+            exclude_per_dir,
+            dirs: Vec::new(),
+        };
+        let count = reader.varint()? as usize;
+        if count == 0 {
+            return Ok(cache);
+        }
+        cache.read_dir(&mut reader)?;
+        if cache.dirs.len() != count {
+            return Err(corrupt("wrong number of directories"));
+        }
+
+        let valid = ewah_bits(&mut reader, count)?;
+        let check_only = ewah_bits(&mut reader, count)?;
+        let exclude_valid = ewah_bits(&mut reader, count)?;
+        for i in check_only {
+            cache.dir(i)?.check_only = true;
+        }
+        for i in valid {
+            let stat = StatData::read(&mut reader)?;
+            let dir = cache.dir(i)?;
+            dir.valid = true;
+            dir.stat = stat;
+        }
+        for i in exclude_valid {
+            let id = Oid::from_bytes(reader.bytes(HASH_SIZE)?)?;
+            cache.dir(i)?.exclude = Some(id);
+        }
+        Ok(cache)
+    }
+
+    fn read_dir(&mut self, reader: &mut Reader<'_>) -> Result<usize, Error> {
+        let untracked = reader.varint()? as usize;
+        let dirs = reader.varint()? as usize;
+        let name = reader.cstr()?.to_vec();
+        let untracked = (0..untracked)
+            .map(|_| reader.cstr().map(<[u8]>::to_vec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let index = self.dirs.len();
+        self.dirs.push(Dir {
+            name,
+            untracked,
+            dirs: Vec::new(),
+            valid: false,
+            check_only: false,
+            stat: StatData::default(),
+            exclude: None,
+        });
+        for _ in 0..dirs {
+            let child = self.read_dir(reader)?;
+            self.dirs[index].dirs.push(child);
+        }
+        Ok(index)
+    }
+
+    fn dir(&mut self, index: usize) -> Result<&mut Dir, Error> {
+        self.dirs
+            .get_mut(index)
+            .ok_or_else(|| corrupt("bitmap refers to a missing directory"))
+    }
+
+    /// Whether the cache was made for this working directory with the flags
+    /// libgit2 uses, and nothing it depends on changed since.
+    fn is_current(
+        &self,
+        repo: &Repository,
+        index: &Index,
+        workdir: &Path,
+        index_mtime: (u32, u32),
+    ) -> Result<bool, Error> {
+        let ident = self.ident.split(|&b| b == 0).next().unwrap_or(&[]);
+        if self.dirs.is_empty()
+            || self.dir_flags != NORMAL_DIR_FLAGS
+            || Some(ident) != ident_string(workdir).as_deref()
+            || self.info_exclude != blob_id(&repo.path().join("info").join("exclude"), None)?
+            || self.excludes_file
+                != excludes_file(repo).map_or(Ok(Oid::zero()), |p| blob_id(&p, None))?
+        {
+            return Ok(false);
+        }
+        self.is_dir_current(0, workdir, &mut Vec::new(), index, index_mtime)
+    }
+
+    /// Whether the directory at `pos`, found at `path` and at `prefix`
+    /// relative to the working directory, is unchanged since it was cached.
+    fn is_dir_current(
+        &self,
+        pos: usize,
+        path: &Path,
+        prefix: &mut Vec<u8>,
+        index: &Index,
+        index_mtime: (u32, u32),
+    ) -> Result<bool, Error> {
+        let dir = &self.dirs[pos];
+        if !dir.valid {
+            return Ok(false);
+        }
+        let stat = match fs::symlink_metadata(path) {
+            Ok(meta) => StatData::from(&meta),
+            Err(_) => return Ok(false),
+        };
+        // A directory changed in the same instant the index was written may
+        // change again without its mtime showing it.
+        if stat != dir.stat || stat.mtime >= index_mtime {
+            return Ok(false);
+        }
+        let exclude = path.join(util::bytes2path(&self.exclude_per_dir));
+        let exclude = match fs::metadata(&exclude) {
+            Ok(_) => {
+                let len = prefix.len();
+                prefix.extend_from_slice(&self.exclude_per_dir);
+                let tracked = index.get_path(util::bytes2path(prefix), 0);
+                prefix.truncate(len);
+                Some(blob_id(&exclude, tracked.map(|entry| entry.id))?)
+            }
+            Err(_) => None,
+        };
+        if exclude != dir.exclude {
+            return Ok(false);
+        }
+        for &child in &dir.dirs {
+            let name = &self.dirs[child].name;
+            let len = prefix.len();
+            prefix.extend_from_slice(name);
+            prefix.push(b'/');
+            let current = self.is_dir_current(
+                child,
+                &path.join(util::bytes2path(name)),
+                prefix,
+                index,
+                index_mtime,
+            )?;
+            prefix.truncate(len);
+            if !current {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Collect the untracked paths of the directory at `index`, whose path
+    /// relative to the working directory is `prefix`.
+    fn collect(&self, index: usize, prefix: &mut Vec<u8>, out: &mut Vec<Vec<u8>>) {
+        let dir = &self.dirs[index];
+        // A directory scanned only to tell whether it is empty is listed in
+        // its parent.
+        if dir.check_only {
+            return;
+        }
+        for name in &dir.untracked {
+            let mut path = prefix.clone();
+            path.extend_from_slice(name);
+            out.push(path);
+        }
+        for &child in &dir.dirs {
+            let len = prefix.len();
+            prefix.extend_from_slice(&self.dirs[child].name);
+            prefix.push(b'/');
+            self.collect(child, prefix, out);
+            prefix.truncate(len);
+        }
+    }
+}
+
+impl StatData {
+    fn read(reader: &mut Reader<'_>) -> Result<StatData, Error> {
+        let ctime = (reader.u32()?, reader.u32()?);
+        let mtime = (reader.u32()?, reader.u32()?);
+        let _dev = reader.u32()?;
+        Ok(StatData {
+            ctime,
+            mtime,
+            ino: reader.u32()?,
+            uid: reader.u32()?,
+            gid: reader.u32()?,
+            size: reader.u32()?,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl From<&fs::Metadata> for StatData {
+    fn from(meta: &fs::Metadata) -> StatData {
+        use std::os::unix::fs::MetadataExt;
+        StatData {
+            ctime: (meta.ctime() as u32, meta.ctime_nsec() as u32),
+            mtime: (meta.mtime() as u32, meta.mtime_nsec() as u32),
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl From<&fs::Metadata> for StatData {
+    // The cache is never current, as there is no `ident_string`.
+    fn from(_meta: &fs::Metadata) -> StatData {
+        StatData::default()
+    }
+}
+
+/// The string git identifies the working directory and system with.
+#[cfg(unix)]
+fn ident_string(workdir: &Path) -> Option<Vec<u8>> {
+    use std::ffi::CStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let sysname = unsafe {
+        let mut uts: libc::utsname = std::mem::zeroed();
+        if libc::uname(&mut uts) < 0 {
+            return None;
+        }
+        CStr::from_ptr(uts.sysname.as_ptr()).to_bytes().to_vec()
+    };
+    let workdir = workdir.as_os_str().as_bytes();
+    let workdir = workdir.strip_suffix(b"/").unwrap_or(workdir);
+    let mut ident = b"Location ".to_vec();
+    ident.extend_from_slice(workdir);
+    ident.extend_from_slice(b", system ");
+    ident.extend_from_slice(&sysname);
+    Some(ident)
+}
+
+#[cfg(not(unix))]
+fn ident_string(_workdir: &Path) -> Option<Vec<u8>> {
+    None
+}
+
+/// The global excludes file, `core.excludesFile` or its XDG default.
+fn excludes_file(repo: &Repository) -> Option<PathBuf> {
+    if let Ok(path) = repo.config().and_then(|c| c.get_path("core.excludesFile")) {
+        return Some(path);
+    }
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(config) => Some(PathBuf::from(config).join("git").join("ignore")),
+        None => std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join(".config")
+                .join("git")
+                .join("ignore")
+        }),
+    }
+}
+
+/// The id git records for the exclude file at `path`, or zero if it cannot
+/// be read.
+///
+/// A file matching its `tracked` index entry is recorded by that entry's id.
+/// Otherwise git hashes the contents with a newline appended.
+fn blob_id(path: &Path, tracked: Option<Oid>) -> Result<Oid, Error> {
+    let mut data = match fs::read(path) {
+        Ok(data) => data,
+        Err(_) => return Ok(Oid::zero()),
+    };
+    if data.is_empty() {
+        return Oid::hash_object(ObjectType::Blob, &data);
+    }
+    if let Some(id) = tracked {
+        if Oid::hash_object(ObjectType::Blob, &data)? == id {
+            return Ok(id);
+        }
+    }
+    data.push(b'\n');
+    Oid::hash_object(ObjectType::Blob, &data)
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u16 {
+    use std::os::unix::fs::PermissionsExt;
+    if meta.file_type().is_symlink() {
+        0o120000
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u16 {
+    if meta.file_type().is_symlink() {
+        0o120000
+    } else {
+        0o100644
+    }
+}
+
+fn corrupt(msg: &str) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Index,
+        format!("corrupt untracked cache: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    use tempfile::TempDir;
+
+    use super::untracked_files;
+    use crate::{Repository, StatusOptions};
+
+    fn git(repo: &Repository, args: &[&str]) -> bool {
+        Command::new("git")
+            .args(args)
+            .current_dir(repo.workdir().unwrap())
+            .output()
+            .is_ok_and(|o| o.status.success())
+    }
+
+    fn statuses(repo: &Repository) -> Vec<(String, crate::Status, u16)> {
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true)
+            .include_ignored(false)
+            .recurse_untracked_dirs(false);
+        let statuses = repo.statuses(Some(&mut opts)).unwrap();
+        // The options are left as they were.
+        assert!(opts.wants_untracked_cache());
+        statuses
+            .iter()
+            .map(|s| {
+                let mode = s
+                    .index_to_workdir()
+                    .map_or(0, |d| d.new_file().mode() as u16);
+                (s.path().unwrap().to_string(), s.status(), mode)
+            })
+            .collect()
+    }
+
+    /// Write the files of `files`, creating their directories.
+    fn write(root: &Path, files: &[(&str, &str)]) {
+        for (name, content) in files {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    /// Set up a repository with tracked and untracked files, and have git
+    /// record the untracked ones in the cache.
+    fn cached_repo() -> (TempDir, Repository) {
+        let (td, repo) = crate::test::repo_init();
+        write(
+            td.path(),
+            &[
+                ("src/a", "a"),
+                ("src/deep/b", "b"),
+                ("top", "top"),
+                (".gitignore", "*.log\n"),
+            ],
+        );
+        assert!(git(&repo, &["add", "."]));
+        write(
+            td.path(),
+            &[
+                ("untracked", "untracked"),
+                ("src/untracked", "untracked"),
+                ("src/ignored.log", "ignored"),
+                ("new/inner/file", "file"),
+            ],
+        );
+        fs::create_dir(td.path().join("empty")).unwrap();
+        fs::write(td.path().join("top"), "changed").unwrap();
+        assert!(untracked_files(&repo).unwrap().is_none());
+
+        assert!(git(&repo, &["config", "core.untrackedCache", "true"]));
+        assert!(git(&repo, &["update-index", "--untracked-cache"]));
+        // The first run records the directories, the second one trusts them.
+        assert!(git(&repo, &["status"]));
+        assert!(git(&repo, &["status"]));
+        (td, repo)
+    }
+
+    fn cached_paths(repo: &Repository) -> Option<Vec<String>> {
+        let cached = untracked_files(repo).unwrap()?;
+        Some(
+            cached
+                .into_iter()
+                .map(|u| String::from_utf8(u.path).unwrap())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = cached_repo();
+        let cached = cached_paths(&repo).expect("the cache is not used");
+        assert_eq!(cached, ["untracked", "new/", "src/untracked"]);
+
+        let with_cache = statuses(&repo);
+        assert_eq!(with_cache.len(), 7);
+        assert!(git(&repo, &["config", "core.untrackedCache", "false"]));
+        assert!(untracked_files(&repo).unwrap().is_none());
+        assert_eq!(statuses(&repo), with_cache);
+    }
+
+    #[test]
+    fn stale() {
+        let (td, repo) = cached_repo();
+        assert!(cached_paths(&repo).is_some());
+        let without_cache = |repo: &Repository| {
+            assert!(git(repo, &["config", "core.untrackedCache", "false"]));
+            let statuses = statuses(repo);
+            assert!(git(repo, &["config", "core.untrackedCache", "true"]));
+            statuses
+        };
+
+        // A new file changes its directory.
+        fs::write(td.path().join("src/another"), "another").unwrap();
+        assert!(cached_paths(&repo).is_none());
+        assert_eq!(statuses(&repo).len(), 8);
+        assert_eq!(statuses(&repo), without_cache(&repo));
+        assert!(git(&repo, &["status"]));
+        assert!(cached_paths(&repo)
+            .unwrap()
+            .contains(&"src/another".to_string()));
+
+        // So does a removed one, in a directory nested in a cached one.
+        fs::remove_file(td.path().join("new/inner/file")).unwrap();
+        assert!(cached_paths(&repo).is_none());
+        assert_eq!(statuses(&repo), without_cache(&repo));
+        assert!(git(&repo, &["status"]));
+        assert!(cached_paths(&repo).is_some());
+
+        // A changed ignore file changes what is untracked.
+        fs::write(td.path().join(".gitignore"), "*.log\nanother\n").unwrap();
+        assert!(cached_paths(&repo).is_none());
+        let statuses_now = statuses(&repo);
+        assert!(statuses_now.iter().all(|(path, ..)| path != "src/another"));
+        assert_eq!(statuses_now, without_cache(&repo));
+
+        // An index written by libgit2 leaves the cache out.
+        assert!(git(&repo, &["status"]));
+        assert!(cached_paths(&repo).is_some());
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("src/untracked")).unwrap();
+        index.write().unwrap();
+        assert!(cached_paths(&repo).is_none());
+        assert_eq!(statuses(&repo), without_cache(&repo));
+    }
+}
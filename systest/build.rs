@@ -14,6 +14,8 @@ fn main() {
         .header("git2/sys/mempack.h")
         .header("git2/sys/repository.h")
         .header("git2/sys/cred.h")
+        .header("git2/sys/commit_graph.h")
+        .header("git2/sys/midx.h")
         .header("git2/cred_helpers.h")
         .type_name(|s, _, _| s.to_string());
     cfg.field_name(|_, f| match f {